@@ -0,0 +1,79 @@
+//! Benchmarks for the collection of the prestate from the storage slots accessed by a transaction.
+//!
+//! The number of the storage reads is guarded by the unit tests of the tracer, so these only
+//! measure the time.
+
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use multivm::tracers::prestate_tracer::{
+    process_modified_storage_keys, PrestateTracerConfig, State, StateReader,
+};
+use zksync_types::{AccountTreeId, Address, StorageKey, H256};
+
+/// Numbers of the accounts and the slots per account accessed by the synthetic transaction.
+const SIZES: &[(u64, u64)] = &[(1, 100), (10, 10), (10, 100), (100, 10)];
+
+/// Snapshot of the state with all the account fields set to zero.
+struct ZeroReader;
+
+impl StateReader for ZeroReader {
+    fn read_value(&mut self, _key: &StorageKey) -> H256 {
+        H256::zero()
+    }
+
+    fn load_bytecode(&mut self, _hash: H256) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+fn modified_storage_keys(accounts: u64, slots: u64) -> HashMap<StorageKey, H256> {
+    (0..accounts)
+        .flat_map(|account| {
+            let account_id = AccountTreeId::new(Address::from_low_u64_be(account + 0x1_0000));
+            (0..slots).map(move |slot| {
+                let key = StorageKey::new(account_id, H256::from_low_u64_be(slot));
+                (key, H256::from_low_u64_be(slot + 1))
+            })
+        })
+        .collect()
+}
+
+fn collection_benches(criterion: &mut Criterion) {
+    // In diff mode, the same collection is performed for both the pre- and the post-state.
+    for diff_mode in [false, true] {
+        let group_name = if diff_mode {
+            "collect_prestate/diff_mode"
+        } else {
+            "collect_prestate"
+        };
+        let mut group = criterion.benchmark_group(group_name);
+        let config = PrestateTracerConfig::builder().diff_mode(diff_mode).build();
+        for &(accounts, slots) in SIZES {
+            let keys = modified_storage_keys(accounts, slots);
+            let states = if diff_mode { 2 } else { 1 };
+            group
+                .throughput(Throughput::Elements(accounts * slots))
+                .bench_with_input(
+                    BenchmarkId::new("accounts_x_slots", format!("{accounts}x{slots}")),
+                    &keys,
+                    |bencher, keys| {
+                        bencher.iter(|| {
+                            for _ in 0..states {
+                                process_modified_storage_keys(
+                                    State::new(),
+                                    keys,
+                                    &config,
+                                    &mut ZeroReader,
+                                );
+                            }
+                        })
+                    },
+                );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, collection_benches);
+criterion_main!(benches);
@@ -0,0 +1,4 @@
+# New inputs are written to the first corpus directory, the seeds are only read.
+mkdir -p corpus/process_modified_storage_keys
+cargo +nightly fuzz run --release process_modified_storage_keys \
+  corpus/process_modified_storage_keys seeds/process_modified_storage_keys
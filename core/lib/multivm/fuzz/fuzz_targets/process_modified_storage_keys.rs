@@ -0,0 +1,76 @@
+//! Runs the prestate collection on random modified storage keys and value lookups.
+//!
+//! The input is a flags byte selecting the config, followed by 34-byte entries:
+//! `[account, kind, value: 32 bytes]`. The `account` picks one of a few addresses, so that
+//! the keys of the accounts overlap, and the low 2 bits of `kind` pick a storage slot
+//! of the account or its nonce, code hash or balance kept by the system contracts.
+//! The entries with the high bit of `kind` set are returned by the value lookup instead
+//! of being modified.
+#![no_main]
+
+use std::collections::{HashMap, HashSet};
+
+use libfuzzer_sys::fuzz_target;
+use multivm::tracers::prestate_tracer::{prestate_from_modified_keys, PrestateTracerConfig};
+use zksync_types::{
+    get_code_key, get_nonce_key, utils::storage_key_for_eth_balance, AccountTreeId, Address,
+    StorageKey, H256,
+};
+
+const ENTRY_LEN: usize = 34;
+const ACCOUNT_COUNT: u64 = 4;
+
+fuzz_target!(|input: &[u8]| {
+    let Some((&flags, entries)) = input.split_first() else {
+        return;
+    };
+    let config = PrestateTracerConfig::builder()
+        .disable_storage(flags & 1 != 0)
+        .reuse_modified_values(flags & 2 != 0)
+        .prune_empty_accounts(flags & 4 != 0)
+        .exclude_system_contracts(flags & 8 != 0)
+        .build();
+
+    let mut modified_keys = HashMap::new();
+    let mut lookup_values = HashMap::new();
+    for entry in entries.chunks_exact(ENTRY_LEN) {
+        let address = Address::from_low_u64_be(u64::from(entry[0]) % ACCOUNT_COUNT + 1);
+        let kind = entry[1];
+        let key = match kind & 3 {
+            0 => StorageKey::new(
+                AccountTreeId::new(address),
+                H256::from_low_u64_be(u64::from((kind >> 2) & 0x1f)),
+            ),
+            1 => get_nonce_key(&address),
+            2 => get_code_key(&address),
+            _ => storage_key_for_eth_balance(&address),
+        };
+        let value = H256::from_slice(&entry[2..]);
+        if kind & 0x80 == 0 {
+            modified_keys.insert(key, value);
+        } else {
+            lookup_values.insert(key, value);
+        }
+    }
+
+    let state = prestate_from_modified_keys(&modified_keys, &config, |key| {
+        lookup_values.get(key).copied().unwrap_or_default()
+    });
+
+    let input_addresses: HashSet<_> = modified_keys
+        .keys()
+        .map(|key| *key.account().address())
+        .collect();
+    for address in state.keys() {
+        assert!(
+            input_addresses.contains(address),
+            "account {address:?} isn't owning any modified key"
+        );
+    }
+    let slot_count: usize = state
+        .values()
+        .filter_map(|account| account.storage.as_ref())
+        .map(HashMap::len)
+        .sum();
+    assert!(slot_count <= modified_keys.len());
+});
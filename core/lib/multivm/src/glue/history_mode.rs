@@ -4,21 +4,45 @@ pub trait HistoryMode:
     Default
     + GlueInto<Self::VmM6Mode>
     + GlueInto<Self::Vm1_3_2Mode>
-    + GlueInto<Self::VmVirtualBlocksMode>
-    + GlueInto<Self::VmVirtualBlocksRefundsEnhancement>
+    + VmVirtualBlocksHistoryMode
+    + VmRefundsEnhancementHistoryMode
     + GlueInto<Self::VmBoojumIntegration>
     + GlueInto<Self::Vm1_4_1>
     + GlueInto<Self::Vm1_4_2>
 {
     type VmM6Mode: crate::vm_m6::HistoryMode;
     type Vm1_3_2Mode: crate::vm_1_3_2::HistoryMode;
-    type VmVirtualBlocksMode: crate::vm_virtual_blocks::HistoryMode;
-    type VmVirtualBlocksRefundsEnhancement: crate::vm_refunds_enhancement::HistoryMode;
     type VmBoojumIntegration: crate::vm_boojum_integration::HistoryMode;
     type Vm1_4_1: crate::vm_1_4_1::HistoryMode;
     type Vm1_4_2: crate::vm_latest::HistoryMode;
 }
 
+/// Part of [`HistoryMode`] for `vm_virtual_blocks`, which is empty if the version is disabled.
+#[cfg(feature = "vm-virtual-blocks")]
+pub trait VmVirtualBlocksHistoryMode: GlueInto<Self::VmVirtualBlocksMode> {
+    type VmVirtualBlocksMode: crate::vm_virtual_blocks::HistoryMode;
+}
+
+#[cfg(not(feature = "vm-virtual-blocks"))]
+pub trait VmVirtualBlocksHistoryMode {}
+
+#[cfg(not(feature = "vm-virtual-blocks"))]
+impl<T> VmVirtualBlocksHistoryMode for T {}
+
+/// Part of [`HistoryMode`] for `vm_refunds_enhancement`, which is empty if the version is disabled.
+#[cfg(feature = "vm-refunds-enhancement")]
+pub trait VmRefundsEnhancementHistoryMode:
+    GlueInto<Self::VmVirtualBlocksRefundsEnhancement>
+{
+    type VmVirtualBlocksRefundsEnhancement: crate::vm_refunds_enhancement::HistoryMode;
+}
+
+#[cfg(not(feature = "vm-refunds-enhancement"))]
+pub trait VmRefundsEnhancementHistoryMode {}
+
+#[cfg(not(feature = "vm-refunds-enhancement"))]
+impl<T> VmRefundsEnhancementHistoryMode for T {}
+
 impl GlueFrom<crate::vm_latest::HistoryEnabled> for crate::vm_m6::HistoryEnabled {
     fn glue_from(_: crate::vm_latest::HistoryEnabled) -> Self {
         Self
@@ -31,12 +55,14 @@ impl GlueFrom<crate::vm_latest::HistoryEnabled> for crate::vm_1_3_2::HistoryEnab
     }
 }
 
+#[cfg(feature = "vm-virtual-blocks")]
 impl GlueFrom<crate::vm_latest::HistoryEnabled> for crate::vm_virtual_blocks::HistoryEnabled {
     fn glue_from(_: crate::vm_latest::HistoryEnabled) -> Self {
         Self
     }
 }
 
+#[cfg(feature = "vm-refunds-enhancement")]
 impl GlueFrom<crate::vm_latest::HistoryEnabled> for crate::vm_refunds_enhancement::HistoryEnabled {
     fn glue_from(_: crate::vm_latest::HistoryEnabled) -> Self {
         Self
@@ -67,12 +93,14 @@ impl GlueFrom<crate::vm_latest::HistoryDisabled> for crate::vm_1_3_2::HistoryDis
     }
 }
 
+#[cfg(feature = "vm-virtual-blocks")]
 impl GlueFrom<crate::vm_latest::HistoryDisabled> for crate::vm_virtual_blocks::HistoryDisabled {
     fn glue_from(_: crate::vm_latest::HistoryDisabled) -> Self {
         Self
     }
 }
 
+#[cfg(feature = "vm-refunds-enhancement")]
 impl GlueFrom<crate::vm_latest::HistoryDisabled>
     for crate::vm_refunds_enhancement::HistoryDisabled
 {
@@ -96,8 +124,6 @@ impl GlueFrom<crate::vm_latest::HistoryDisabled> for crate::vm_1_4_1::HistoryDis
 impl HistoryMode for crate::vm_latest::HistoryEnabled {
     type VmM6Mode = crate::vm_m6::HistoryEnabled;
     type Vm1_3_2Mode = crate::vm_1_3_2::HistoryEnabled;
-    type VmVirtualBlocksMode = crate::vm_virtual_blocks::HistoryEnabled;
-    type VmVirtualBlocksRefundsEnhancement = crate::vm_refunds_enhancement::HistoryEnabled;
     type VmBoojumIntegration = crate::vm_boojum_integration::HistoryEnabled;
     type Vm1_4_1 = crate::vm_1_4_1::HistoryEnabled;
     type Vm1_4_2 = crate::vm_latest::HistoryEnabled;
@@ -106,9 +132,27 @@ impl HistoryMode for crate::vm_latest::HistoryEnabled {
 impl HistoryMode for crate::vm_latest::HistoryDisabled {
     type VmM6Mode = crate::vm_m6::HistoryDisabled;
     type Vm1_3_2Mode = crate::vm_1_3_2::HistoryDisabled;
-    type VmVirtualBlocksMode = crate::vm_virtual_blocks::HistoryDisabled;
-    type VmVirtualBlocksRefundsEnhancement = crate::vm_refunds_enhancement::HistoryDisabled;
     type VmBoojumIntegration = crate::vm_boojum_integration::HistoryDisabled;
     type Vm1_4_1 = crate::vm_1_4_1::HistoryDisabled;
     type Vm1_4_2 = crate::vm_latest::HistoryDisabled;
 }
+
+#[cfg(feature = "vm-virtual-blocks")]
+impl VmVirtualBlocksHistoryMode for crate::vm_latest::HistoryEnabled {
+    type VmVirtualBlocksMode = crate::vm_virtual_blocks::HistoryEnabled;
+}
+
+#[cfg(feature = "vm-virtual-blocks")]
+impl VmVirtualBlocksHistoryMode for crate::vm_latest::HistoryDisabled {
+    type VmVirtualBlocksMode = crate::vm_virtual_blocks::HistoryDisabled;
+}
+
+#[cfg(feature = "vm-refunds-enhancement")]
+impl VmRefundsEnhancementHistoryMode for crate::vm_latest::HistoryEnabled {
+    type VmVirtualBlocksRefundsEnhancement = crate::vm_refunds_enhancement::HistoryEnabled;
+}
+
+#[cfg(feature = "vm-refunds-enhancement")]
+impl VmRefundsEnhancementHistoryMode for crate::vm_latest::HistoryDisabled {
+    type VmVirtualBlocksRefundsEnhancement = crate::vm_refunds_enhancement::HistoryDisabled;
+}
@@ -22,6 +22,10 @@
 //! For `MultiVMTracer` to be implemented, the Tracer must implement all N currently
 //! existing sub-traits.
 //!
+//! The sub-traits of the legacy versions which can be disabled by the crate features, e.g.
+//! `IntoVmVirtualBlocksTracer<S, H>`, are empty and implemented for any type if the version
+//! is disabled, so the tracers don't need an implementation for a version which isn't compiled.
+//!
 //! ## Adding a new VM version
 //!
 //! To add support for one more VM version to MultiVMTracer, one needs to:
@@ -56,18 +60,26 @@ pub trait IntoLatestTracer<S: WriteStorage, H: HistoryMode> {
     fn latest(&self) -> crate::vm_latest::TracerPointer<S, H::Vm1_4_2>;
 }
 
+#[cfg(feature = "vm-virtual-blocks")]
 pub trait IntoVmVirtualBlocksTracer<S: WriteStorage, H: HistoryMode> {
     fn vm_virtual_blocks(
         &self,
     ) -> crate::vm_virtual_blocks::TracerPointer<S, H::VmVirtualBlocksMode>;
 }
 
+#[cfg(not(feature = "vm-virtual-blocks"))]
+pub trait IntoVmVirtualBlocksTracer<S: WriteStorage, H: HistoryMode> {}
+
+#[cfg(feature = "vm-refunds-enhancement")]
 pub trait IntoVmRefundsEnhancementTracer<S: WriteStorage, H: HistoryMode> {
     fn vm_refunds_enhancement(
         &self,
     ) -> Box<dyn crate::vm_refunds_enhancement::VmTracer<S, H::VmVirtualBlocksRefundsEnhancement>>;
 }
 
+#[cfg(not(feature = "vm-refunds-enhancement"))]
+pub trait IntoVmRefundsEnhancementTracer<S: WriteStorage, H: HistoryMode> {}
+
 pub trait IntoVmBoojumIntegrationTracer<S: WriteStorage, H: HistoryMode> {
     fn vm_boojum_integration(
         &self,
@@ -101,6 +113,7 @@ where
     }
 }
 
+#[cfg(feature = "vm-virtual-blocks")]
 impl<S, T, H> IntoVmVirtualBlocksTracer<S, H> for T
 where
     S: WriteStorage,
@@ -114,6 +127,10 @@ where
     }
 }
 
+#[cfg(not(feature = "vm-virtual-blocks"))]
+impl<S: WriteStorage, H: HistoryMode, T> IntoVmVirtualBlocksTracer<S, H> for T {}
+
+#[cfg(feature = "vm-refunds-enhancement")]
 impl<S, T, H> IntoVmRefundsEnhancementTracer<S, H> for T
 where
     S: WriteStorage,
@@ -130,6 +147,9 @@ where
     }
 }
 
+#[cfg(not(feature = "vm-refunds-enhancement"))]
+impl<S: WriteStorage, H: HistoryMode, T> IntoVmRefundsEnhancementTracer<S, H> for T {}
+
 impl<S, T, H> IntoVmBoojumIntegrationTracer<S, H> for T
 where
     S: WriteStorage,
@@ -29,6 +29,7 @@ impl GlueFrom<crate::vm_1_3_2::utils::StorageLogQuery> for StorageLogQuery {
     }
 }
 
+#[cfg(feature = "vm-virtual-blocks")]
 impl GlueFrom<crate::vm_virtual_blocks::utils::logs::StorageLogQuery> for StorageLogQuery {
     fn glue_from(value: crate::vm_virtual_blocks::utils::logs::StorageLogQuery) -> Self {
         Self {
@@ -38,6 +39,7 @@ impl GlueFrom<crate::vm_virtual_blocks::utils::logs::StorageLogQuery> for Storag
     }
 }
 
+#[cfg(feature = "vm-refunds-enhancement")]
 impl GlueFrom<crate::vm_refunds_enhancement::utils::logs::StorageLogQuery> for StorageLogQuery {
     fn glue_from(value: crate::vm_refunds_enhancement::utils::logs::StorageLogQuery) -> Self {
         Self {
@@ -3,13 +3,20 @@
 #![warn(unused_imports)]
 
 pub use circuit_sequencer_api_1_4_1 as circuit_sequencer_api_latest;
+// Only used by the benchmarks.
+#[cfg(test)]
+use criterion as _;
 pub use zk_evm_1_4_1 as zk_evm_latest;
+// Only used by the tests of the `merkle_tree` feature.
+#[cfg(test)]
+use zksync_crypto as _;
 pub use zksync_types::vm_version::VmVersion;
 
-pub use self::versions::{
-    vm_1_3_2, vm_1_4_1, vm_boojum_integration, vm_latest, vm_m5, vm_m6, vm_refunds_enhancement,
-    vm_virtual_blocks,
-};
+#[cfg(feature = "vm-refunds-enhancement")]
+pub use self::versions::vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
+pub use self::versions::vm_virtual_blocks;
+pub use self::versions::{vm_1_3_2, vm_1_4_1, vm_boojum_integration, vm_latest, vm_m5, vm_m6};
 pub use crate::{
     glue::{
         history_mode::HistoryMode,
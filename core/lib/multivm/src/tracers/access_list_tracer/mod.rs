@@ -0,0 +1,149 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use once_cell::sync::OnceCell;
+use zksync_types::{
+    web3::types::{AccessList, AccessListItem},
+    Address, H256,
+};
+
+use crate::{glue::tracers::IntoOldVmTracer, tracers::PRECOMPILE_ADDRESSES};
+
+pub mod vm_1_4_1;
+pub mod vm_boojum_integration;
+pub mod vm_latest;
+#[cfg(feature = "vm-refunds-enhancement")]
+pub mod vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
+pub mod vm_virtual_blocks;
+
+/// Accounts to leave out of the access list. With the sender, the recipient and the precompiles
+/// excluded, the result matches the one of Geth's `eth_createAccessList`.
+#[derive(Debug, Clone, Default)]
+pub struct AccessListTracerConfig {
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub exclude_precompiles: bool,
+}
+
+impl AccessListTracerConfig {
+    fn is_excluded(&self, address: &Address) -> bool {
+        self.from.as_ref() == Some(address)
+            || self.to.as_ref() == Some(address)
+            || (self.exclude_precompiles && PRECOMPILE_ADDRESSES.contains(address))
+    }
+}
+
+/// Records the accounts called and the storage slots accessed during the execution
+/// and reports them as an EIP-2930 access list.
+#[derive(Debug, Clone)]
+pub struct AccessListTracer {
+    config: AccessListTracerConfig,
+    accessed: BTreeMap<Address, BTreeSet<H256>>,
+    pub result: Arc<OnceCell<AccessList>>,
+}
+
+impl AccessListTracer {
+    pub fn new(config: AccessListTracerConfig, result: Arc<OnceCell<AccessList>>) -> Self {
+        Self {
+            config,
+            accessed: BTreeMap::new(),
+            result,
+        }
+    }
+
+    fn record_account(&mut self, address: Address) {
+        if !self.config.is_excluded(&address) {
+            self.accessed.entry(address).or_default();
+        }
+    }
+
+    fn record_slot(&mut self, address: Address, key: H256) {
+        if !self.config.is_excluded(&address) {
+            self.accessed.entry(address).or_default().insert(key);
+        }
+    }
+
+    fn store_result(&mut self) {
+        let access_list = std::mem::take(&mut self.accessed)
+            .into_iter()
+            .map(|(address, storage_keys)| AccessListItem {
+                address,
+                storage_keys: storage_keys.into_iter().collect(),
+            })
+            .collect();
+        if self.result.set(access_list).is_err() {
+            tracing::error!("Access list tracer result is already set");
+        }
+    }
+}
+
+impl IntoOldVmTracer for AccessListTracer {}
+
+#[cfg(test)]
+mod tests {
+    use zksync_system_constants::SHA256_PRECOMPILE_ADDRESS;
+
+    use super::*;
+
+    #[test]
+    fn accessed_slots_are_deduplicated_per_address() {
+        let from = Address::repeat_byte(1);
+        let to = Address::repeat_byte(2);
+        let other = Address::repeat_byte(3);
+        let config = AccessListTracerConfig {
+            from: Some(from),
+            to: Some(to),
+            exclude_precompiles: true,
+        };
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = AccessListTracer::new(config, result.clone());
+
+        tracer.record_account(from);
+        tracer.record_slot(to, H256::repeat_byte(1));
+        tracer.record_account(SHA256_PRECOMPILE_ADDRESS);
+        tracer.record_slot(other, H256::repeat_byte(2));
+        tracer.record_account(other);
+        tracer.record_slot(other, H256::repeat_byte(1));
+        tracer.record_slot(other, H256::repeat_byte(2));
+        tracer.store_result();
+
+        let access_list = result.get().unwrap();
+        assert_eq!(
+            access_list,
+            &vec![AccessListItem {
+                address: other,
+                storage_keys: vec![H256::repeat_byte(1), H256::repeat_byte(2)],
+            }]
+        );
+        assert_eq!(
+            serde_json::to_value(access_list).unwrap(),
+            serde_json::json!([{
+                "address": format!("{:?}", other),
+                "storageKeys": [
+                    format!("{:?}", H256::repeat_byte(1)),
+                    format!("{:?}", H256::repeat_byte(2)),
+                ],
+            }])
+        );
+    }
+
+    #[test]
+    fn nothing_is_excluded_by_default() {
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = AccessListTracer::new(AccessListTracerConfig::default(), result.clone());
+
+        tracer.record_account(SHA256_PRECOMPILE_ADDRESS);
+        tracer.store_result();
+
+        assert_eq!(
+            result.get().unwrap(),
+            &vec![AccessListItem {
+                address: SHA256_PRECOMPILE_ADDRESS,
+                storage_keys: vec![],
+            }]
+        );
+    }
+}
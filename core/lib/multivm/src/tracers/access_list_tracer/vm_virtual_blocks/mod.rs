@@ -0,0 +1,63 @@
+use zk_evm_1_3_3::{
+    tracing::{AfterExecutionData, BeforeExecutionData, VmLocalStateData},
+    zkevm_opcode_defs::{LogOpcode, Opcode},
+};
+use zksync_state::{StoragePtr, WriteStorage};
+use zksync_utils::u256_to_h256;
+
+use crate::{
+    interface::{dyn_tracers::vm_1_3_3::DynTracer, tracer::VmExecutionStopReason},
+    tracers::access_list_tracer::AccessListTracer,
+    vm_virtual_blocks::{
+        BootloaderState, ExecutionEndTracer, ExecutionProcessing, HistoryMode, SimpleMemory,
+        VmTracer, ZkSyncVmState,
+    },
+};
+
+impl<S, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for AccessListTracer {
+    fn before_execution(
+        &mut self,
+        state: VmLocalStateData<'_>,
+        data: BeforeExecutionData,
+        _memory: &SimpleMemory<H>,
+        _storage: StoragePtr<S>,
+    ) {
+        if let Opcode::Log(LogOpcode::StorageRead | LogOpcode::StorageWrite) =
+            data.opcode.variant.opcode
+        {
+            self.record_slot(
+                state.vm_local_state.callstack.current.this_address,
+                u256_to_h256(data.src0_value.value),
+            );
+        }
+    }
+
+    fn after_execution(
+        &mut self,
+        state: VmLocalStateData<'_>,
+        data: AfterExecutionData,
+        _memory: &SimpleMemory<H>,
+        _storage: StoragePtr<S>,
+    ) {
+        if let Opcode::FarCall(_) = data.opcode.variant.opcode {
+            let current = state.vm_local_state.callstack.current;
+            self.record_account(current.this_address);
+            self.record_account(current.code_address);
+        }
+    }
+}
+
+impl<H: HistoryMode> ExecutionEndTracer<H> for AccessListTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> ExecutionProcessing<S, H> for AccessListTracer {
+    fn after_vm_execution(
+        &mut self,
+        _state: &mut ZkSyncVmState<S, H>,
+        _bootloader_state: &BootloaderState,
+        _stop_reason: VmExecutionStopReason,
+    ) {
+        self.store_result();
+    }
+}
+
+impl<S: WriteStorage, H: HistoryMode> VmTracer<S, H> for AccessListTracer {}
@@ -1,24 +1,113 @@
 use std::sync::Arc;
 
 use once_cell::sync::OnceCell;
-use zksync_types::vm_trace::Call;
+use serde::Serialize;
+use zksync_state::WriteStorage;
+use zksync_types::{
+    vm_trace::{Call, CallType},
+    web3::types::Bytes,
+    zk_evm_types::FarCallOpcode,
+    Address, U256,
+};
 
-use crate::{glue::tracers::IntoOldVmTracer, tracers::call_tracer::metrics::CALL_METRICS};
+use crate::{
+    glue::tracers::IntoOldVmTracer,
+    tracers::{call_tracer::metrics::CALL_METRICS, NamedTracer},
+    HistoryMode, MultiVMTracer, MultiVmTracerPointer,
+};
+
+/// Error of the call summarizing the frames deeper than [`CallTracer::with_max_depth()`].
+pub const MAX_DEPTH_EXCEEDED_ERROR: &str = "max call depth exceeded";
 
 mod metrics;
 pub mod vm_1_4_1;
 pub mod vm_boojum_integration;
 pub mod vm_latest;
+#[cfg(feature = "vm-refunds-enhancement")]
 pub mod vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
 pub mod vm_virtual_blocks;
 
+/// Type of a call in Geth's `callTracer` output, see [`CallTracer::get_result()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum GethCallType {
+    Call,
+    DelegateCall,
+    StaticCall,
+    Create,
+}
+
+/// Call in the format of Geth's `callTracer`. Unlike the [`Call`] traces, it tells apart
+/// the static calls.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GethCall {
+    pub r#type: GethCallType,
+    pub from: Address,
+    pub to: Address,
+    pub gas: U256,
+    pub gas_used: U256,
+    pub value: U256,
+    pub output: Bytes,
+    pub input: Bytes,
+    pub error: Option<String>,
+    pub revert_reason: Option<String>,
+    pub calls: Vec<GethCall>,
+}
+
+impl GethCall {
+    /// Converts the `call` tree, taking whether the calls are static from `static_flags`
+    /// in the depth-first order.
+    fn new(call: &Call, static_flags: &mut impl Iterator<Item = bool>) -> Self {
+        let is_static = static_flags.next().unwrap_or(false);
+        let r#type = match call.r#type {
+            CallType::Call(FarCallOpcode::Delegate) => GethCallType::DelegateCall,
+            CallType::Call(_) if is_static => GethCallType::StaticCall,
+            CallType::Call(_) => GethCallType::Call,
+            CallType::Create => GethCallType::Create,
+            CallType::NearCall => unreachable!("near calls aren't reported by the call tracer"),
+        };
+        let calls = call
+            .calls
+            .iter()
+            .map(|call| Self::new(call, static_flags))
+            .collect();
+        Self {
+            r#type,
+            from: call.from,
+            to: call.to,
+            gas: call.gas.into(),
+            gas_used: call.gas_used.into(),
+            value: call.value,
+            output: call.output.clone().into(),
+            input: call.input.clone().into(),
+            error: call.error.clone(),
+            revert_reason: call.revert_reason.clone(),
+            calls,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CallTracer {
     stack: Vec<FarcallAndNearCallCount>,
     result: Arc<OnceCell<Vec<Call>>>,
+    /// Whether the reported far calls are static, in the order they were made. That's
+    /// the depth-first order of the result, since the calls are attached to their parents
+    /// once they return.
+    static_flags: Vec<bool>,
+    static_flags_result: Arc<OnceCell<Vec<bool>>>,
 
     max_stack_depth: usize,
     max_near_calls: usize,
+
+    max_depth: usize,
+    /// Number of the far calls on the stack which haven't returned yet.
+    open_far_calls: usize,
+    /// Number of the frames entered after the call truncated because of `max_depth`,
+    /// including the call itself, which haven't returned yet.
+    skipped_frames: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -40,11 +129,43 @@ impl CallTracer {
         Self {
             stack: vec![],
             result,
+            static_flags: vec![],
+            static_flags_result: Arc::new(OnceCell::new()),
             max_stack_depth: 0,
             max_near_calls: 0,
+            max_depth: usize::MAX,
+            open_far_calls: 0,
+            skipped_frames: 0,
         }
     }
 
+    /// Reports at most `max_depth` levels of nested far calls, e.g. of a recursive contract.
+    /// A call deeper than that is reported without its subcalls, input and output,
+    /// with the [`MAX_DEPTH_EXCEEDED_ERROR`] error.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Returns the calls in the format of Geth's `callTracer`, if they have already been stored.
+    ///
+    /// The static calls are only told apart by the VM versions starting from
+    /// `vm_virtual_blocks`, and are reported as normal calls otherwise.
+    pub fn get_result(&self) -> Option<Vec<GethCall>> {
+        let calls = self.result.get()?;
+        let mut static_flags = self
+            .static_flags_result
+            .get()
+            .into_iter()
+            .flatten()
+            .copied();
+        let calls = calls
+            .iter()
+            .map(|call| GethCall::new(call, &mut static_flags))
+            .collect();
+        Some(calls)
+    }
+
     fn extract_result(&mut self) -> Vec<Call> {
         std::mem::take(&mut self.stack)
             .into_iter()
@@ -56,6 +177,8 @@ impl CallTracer {
         let result = self.extract_result();
         let cell = self.result.as_ref();
         cell.set(result).unwrap();
+        let static_flags = std::mem::take(&mut self.static_flags);
+        self.static_flags_result.set(static_flags).unwrap();
     }
 
     fn push_call_and_update_stats(&mut self, farcall: Call, near_calls_after: usize) {
@@ -79,7 +202,53 @@ impl CallTracer {
         self.max_near_calls = self.max_near_calls.max(near_calls_after);
     }
 
+    /// Pushes the far call to the stack, or summarizes it in the parent call
+    /// if it is deeper than `max_depth`.
+    fn push_far_call(&mut self, farcall: Call, is_static: bool) {
+        if self.skipped_frames > 0 {
+            self.skipped_frames += 1;
+        } else if self.open_far_calls < self.max_depth {
+            self.open_far_calls += 1;
+            self.static_flags.push(is_static);
+            self.push_call_and_update_stats(farcall, 0);
+        } else {
+            self.skipped_frames = 1;
+            if let Some(parent_call) = self.stack.last_mut() {
+                self.static_flags.push(is_static);
+                parent_call.farcall.calls.push(Call {
+                    input: vec![],
+                    error: Some(MAX_DEPTH_EXCEEDED_ERROR.to_owned()),
+                    ..farcall
+                });
+            }
+        }
+    }
+
+    /// Handles the return from the frame if it was skipped because of `max_depth`.
+    /// Returns `false` if the frame is reported and should be handled as usual.
+    fn pop_skipped_frame(&mut self, ergs_remaining: u32) -> bool {
+        if self.skipped_frames == 0 {
+            return false;
+        }
+        self.skipped_frames -= 1;
+        if self.skipped_frames == 0 {
+            // The truncated call itself has returned, so its gas usage is known.
+            let truncated_call = self
+                .stack
+                .last_mut()
+                .and_then(|parent_call| parent_call.farcall.calls.last_mut());
+            if let Some(call) = truncated_call {
+                call.gas_used = call.parent_gas.saturating_sub(ergs_remaining);
+            }
+        }
+        true
+    }
+
     fn increase_near_call_count(&mut self) {
+        if self.skipped_frames > 0 {
+            self.skipped_frames += 1;
+            return;
+        }
         if let Some(last) = self.stack.last_mut() {
             last.near_calls_after += 1;
             last.stack_depth_on_prefix += 1;
@@ -95,3 +264,73 @@ impl IntoOldVmTracer for CallTracer {
         crate::tracers::old_tracers::OldTracers::CallTracer(self.result.clone())
     }
 }
+
+impl<S: WriteStorage, H: HistoryMode> NamedTracer<S, H> for CallTracer {
+    fn tracer_pointers(&self) -> Vec<MultiVmTracerPointer<S, H>> {
+        vec![self.clone().into_tracer_pointer()]
+    }
+
+    fn json_result(&self) -> Option<serde_json::Value> {
+        let calls = self.get_result()?;
+        Some(serde_json::to_value(calls).expect("failed to serialize call tracer result"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(r#type: CallType, to: u8, calls: Vec<Call>) -> Call {
+        Call {
+            r#type,
+            to: Address::repeat_byte(to),
+            calls,
+            ..Call::default()
+        }
+    }
+
+    #[test]
+    fn calls_have_geth_types() {
+        // 1 -> (static 2 -> 3, delegatecall 4, create 5) and 6.
+        let static_call = call(
+            CallType::Call(FarCallOpcode::Normal),
+            2,
+            vec![call(CallType::Call(FarCallOpcode::Normal), 3, vec![])],
+        );
+        let root = call(
+            CallType::Call(FarCallOpcode::Mimic),
+            1,
+            vec![
+                static_call,
+                call(CallType::Call(FarCallOpcode::Delegate), 4, vec![]),
+                call(CallType::Create, 5, vec![]),
+            ],
+        );
+        let other_root = call(CallType::Call(FarCallOpcode::Normal), 6, vec![]);
+
+        let tracer = CallTracer::new(Arc::new(OnceCell::new()));
+        tracer.result.set(vec![root, other_root]).unwrap();
+        let static_flags = vec![false, true, false, false, false, false];
+        tracer.static_flags_result.set(static_flags).unwrap();
+
+        let calls = tracer.get_result().unwrap();
+        let types: Vec<_> = calls[0].calls.iter().map(|call| call.r#type).collect();
+        assert_eq!(
+            types,
+            [
+                GethCallType::StaticCall,
+                GethCallType::DelegateCall,
+                GethCallType::Create
+            ]
+        );
+        // The calls made by a static call are only static if they are made as such.
+        assert_eq!(calls[0].calls[0].calls[0].r#type, GethCallType::Call);
+        assert_eq!(calls[0].r#type, GethCallType::Call);
+        assert_eq!(calls[1].r#type, GethCallType::Call);
+
+        let json = serde_json::to_value(&calls[0].calls[1]).unwrap();
+        assert_eq!(json["type"], "DELEGATECALL");
+        assert_eq!(json["to"], "0x0404040404040404040404040404040404040404");
+        assert_eq!(json["gasUsed"], "0x0");
+    }
+}
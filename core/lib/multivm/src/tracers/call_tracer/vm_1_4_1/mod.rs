@@ -2,7 +2,7 @@ use zk_evm_1_4_1::{
     tracing::{AfterExecutionData, VmLocalStateData},
     zkevm_opcode_defs::{
         FarCallABI, FatPointer, Opcode, RetOpcode, CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER,
-        RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER,
+        FAR_CALL_STATIC_FLAG_IDX, RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER,
     },
 };
 use zksync_state::{StoragePtr, WriteStorage};
@@ -54,7 +54,8 @@ impl<S, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for CallTracer {
                 };
 
                 self.handle_far_call_op_code_vm_1_4_1(state, memory, &mut current_call);
-                self.push_call_and_update_stats(current_call, 0);
+                let is_static = data.opcode.variant.flags[FAR_CALL_STATIC_FLAG_IDX];
+                self.push_far_call(current_call, is_static);
             }
             Opcode::Ret(ret_code) => {
                 self.handle_ret_op_code_vm_1_4_1(state, memory, ret_code);
@@ -181,6 +182,10 @@ impl CallTracer {
         memory: &SimpleMemory<H>,
         ret_opcode: RetOpcode,
     ) {
+        if self.pop_skipped_frame(state.vm_local_state.callstack.current.ergs_remaining) {
+            return;
+        }
+
         let Some(mut current_call) = self.stack.pop() else {
             return;
         };
@@ -190,6 +195,7 @@ impl CallTracer {
             self.push_call_and_update_stats(current_call.farcall, current_call.near_calls_after);
             return;
         }
+        self.open_far_calls = self.open_far_calls.saturating_sub(1);
 
         current_call.farcall.gas_used = current_call
             .farcall
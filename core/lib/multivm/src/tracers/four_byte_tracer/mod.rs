@@ -0,0 +1,105 @@
+use std::{collections::HashMap, sync::Arc};
+
+use once_cell::sync::OnceCell;
+use zksync_state::WriteStorage;
+use zksync_types::Address;
+
+use crate::{
+    glue::tracers::IntoOldVmTracer,
+    tracers::{NamedTracer, PRECOMPILE_ADDRESSES},
+    HistoryMode, MultiVMTracer, MultiVmTracerPointer,
+};
+
+pub mod vm_1_4_1;
+pub mod vm_boojum_integration;
+pub mod vm_latest;
+#[cfg(feature = "vm-refunds-enhancement")]
+pub mod vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
+pub mod vm_virtual_blocks;
+
+const SELECTOR_LEN: usize = 4;
+
+/// Counts the calls made by the transaction by function selector and calldata size,
+/// in the format of Geth's `4byteTracer`, e.g. `{"0x27dc297e-128": 1}`.
+///
+/// Only calls with a selector are counted. Deployments and calls to precompiles are ignored.
+#[derive(Debug, Clone)]
+pub struct FourByteTracer {
+    counts: HashMap<String, u64>,
+    pub result: Arc<OnceCell<HashMap<String, u64>>>,
+}
+
+impl FourByteTracer {
+    pub fn new(result: Arc<OnceCell<HashMap<String, u64>>>) -> Self {
+        Self {
+            counts: HashMap::new(),
+            result,
+        }
+    }
+
+    /// Records a call to `callee`. Only the `selector` part of the calldata is passed,
+    /// since the rest of it is not needed.
+    fn record_call(&mut self, callee: Address, selector: &[u8], calldata_len: usize) {
+        if calldata_len < SELECTOR_LEN || PRECOMPILE_ADDRESSES.contains(&callee) {
+            return;
+        }
+        let key = format!(
+            "0x{}-{}",
+            hex::encode(&selector[..SELECTOR_LEN]),
+            calldata_len - SELECTOR_LEN
+        );
+        *self.counts.entry(key).or_default() += 1;
+    }
+
+    fn store_result(&mut self) {
+        if self.result.set(std::mem::take(&mut self.counts)).is_err() {
+            tracing::error!("Four byte tracer result is already set");
+        }
+    }
+}
+
+impl IntoOldVmTracer for FourByteTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> NamedTracer<S, H> for FourByteTracer {
+    fn tracer_pointers(&self) -> Vec<MultiVmTracerPointer<S, H>> {
+        vec![self.clone().into_tracer_pointer()]
+    }
+
+    fn json_result(&self) -> Option<serde_json::Value> {
+        let counts = self.result.get()?;
+        Some(serde_json::to_value(counts).expect("failed to serialize 4byte tracer result"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_system_constants::ECRECOVER_PRECOMPILE_ADDRESS;
+
+    use super::*;
+
+    #[test]
+    fn calls_are_counted_by_selector_and_size() {
+        let contract = Address::repeat_byte(0x11);
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = FourByteTracer::new(result.clone());
+
+        tracer.record_call(contract, &[0x27, 0xdc, 0x29, 0x7e], 132);
+        tracer.record_call(contract, &[0x27, 0xdc, 0x29, 0x7e], 132);
+        tracer.record_call(contract, &[0x27, 0xdc, 0x29, 0x7e], 36);
+        tracer.record_call(contract, &[0x6d, 0x4c, 0xe6, 0x3c], 4);
+        // Calls without a selector and calls to precompiles are ignored.
+        tracer.record_call(contract, &[0x01, 0x02], 2);
+        tracer.record_call(ECRECOVER_PRECOMPILE_ADDRESS, &[0x01, 0x02, 0x03, 0x04], 128);
+        tracer.store_result();
+
+        assert_eq!(
+            result.get().unwrap(),
+            &HashMap::from([
+                ("0x27dc297e-128".to_string(), 2),
+                ("0x27dc297e-32".to_string(), 1),
+                ("0x6d4ce63c-0".to_string(), 1),
+            ])
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use zk_evm_1_4_1::{
+    tracing::{AfterExecutionData, VmLocalStateData},
+    zkevm_opcode_defs::{
+        FarCallABI, FarCallOpcode, Opcode, CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER,
+    },
+};
+use zksync_state::{StoragePtr, WriteStorage};
+use zksync_system_constants::CONTRACT_DEPLOYER_ADDRESS;
+
+use crate::{
+    interface::{tracer::VmExecutionStopReason, traits::tracers::dyn_tracers::vm_1_4_1::DynTracer},
+    tracers::four_byte_tracer::{FourByteTracer, SELECTOR_LEN},
+    vm_latest::{BootloaderState, HistoryMode, SimpleMemory, VmTracer, ZkSyncVmState},
+};
+
+impl<S, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for FourByteTracer {
+    fn after_execution(
+        &mut self,
+        state: VmLocalStateData<'_>,
+        data: AfterExecutionData,
+        memory: &SimpleMemory<H>,
+        _storage: StoragePtr<S>,
+    ) {
+        let Opcode::FarCall(far_call) = data.opcode.variant.opcode else {
+            return;
+        };
+        let callstack = &state.vm_local_state.callstack;
+        let current = callstack.current;
+        // Constructors are called by the deployer via mimic calls.
+        let is_deployment = matches!(far_call, FarCallOpcode::Mimic)
+            && callstack.inner.last().map(|call| call.this_address)
+                == Some(CONTRACT_DEPLOYER_ADDRESS);
+        // If the callee has no code or no gas, its calldata is not available.
+        if is_deployment || current.code_page.0 == 0 || current.ergs_remaining == 0 {
+            return;
+        }
+
+        let packed_abi =
+            state.vm_local_state.registers[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize];
+        if !packed_abi.is_pointer {
+            return;
+        }
+        let calldata_ptr = FarCallABI::from_u256(packed_abi.value).memory_quasi_fat_pointer;
+        let calldata_len = calldata_ptr.length as usize;
+        let selector = memory.read_unaligned_bytes(
+            calldata_ptr.memory_page as usize,
+            calldata_ptr.start as usize,
+            calldata_len.min(SELECTOR_LEN),
+        );
+        self.record_call(current.this_address, &selector, calldata_len);
+    }
+}
+
+impl<S: WriteStorage, H: HistoryMode> VmTracer<S, H> for FourByteTracer {
+    fn after_vm_execution(
+        &mut self,
+        _state: &mut ZkSyncVmState<S, H>,
+        _bootloader_state: &BootloaderState,
+        _stop_reason: VmExecutionStopReason,
+    ) {
+        self.store_result();
+    }
+}
@@ -1,9 +1,46 @@
+use zksync_system_constants::{
+    ECRECOVER_PRECOMPILE_ADDRESS, EC_ADD_PRECOMPILE_ADDRESS, EC_MUL_PRECOMPILE_ADDRESS,
+    KECCAK256_PRECOMPILE_ADDRESS, SHA256_PRECOMPILE_ADDRESS,
+};
+use zksync_types::Address;
+
+pub mod access_list_tracer;
 pub mod call_tracer;
+pub mod four_byte_tracer;
 mod multivm_dispatcher;
+pub mod mux_tracer;
+pub mod noop_tracer;
 pub mod old_tracers;
+pub mod prestate_tracer;
+mod registry;
 pub mod storage_invocation;
+pub mod struct_logger;
 pub mod validator;
 
+pub use access_list_tracer::AccessListTracer;
 pub use call_tracer::CallTracer;
+pub use four_byte_tracer::FourByteTracer;
 pub use multivm_dispatcher::TracerDispatcher;
+pub use mux_tracer::MuxTracer;
+pub use noop_tracer::NoopTracer;
+pub use prestate_tracer::PrestateTracer;
+pub use registry::{
+    tracer_from_name, tracer_from_name_with_max_depth, FlatCallTracer, NamedTracer,
+};
 pub use storage_invocation::StorageInvocations;
+pub use struct_logger::StructLogger;
+
+/// Returns whether the `address` belongs to the kernel address space, where the bootloader,
+/// the system contracts and the precompiles are deployed.
+pub(crate) fn is_system_contract(address: &Address) -> bool {
+    *address <= Address::from_low_u64_be(0xffff)
+}
+
+/// Addresses of the precompiles, which are not reported by the Geth-like tracers.
+pub(crate) const PRECOMPILE_ADDRESSES: [Address; 5] = [
+    ECRECOVER_PRECOMPILE_ADDRESS,
+    SHA256_PRECOMPILE_ADDRESS,
+    KECCAK256_PRECOMPILE_ADDRESS,
+    EC_ADD_PRECOMPILE_ADDRESS,
+    EC_MUL_PRECOMPILE_ADDRESS,
+];
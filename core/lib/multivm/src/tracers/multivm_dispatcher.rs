@@ -59,6 +59,7 @@ impl<S: WriteStorage, H: HistoryMode> From<TracerDispatcher<S, H>>
     }
 }
 
+#[cfg(feature = "vm-refunds-enhancement")]
 impl<S: WriteStorage, H: HistoryMode> From<TracerDispatcher<S, H>>
     for crate::vm_refunds_enhancement::TracerDispatcher<S, H::VmVirtualBlocksRefundsEnhancement>
 {
@@ -73,6 +74,7 @@ impl<S: WriteStorage, H: HistoryMode> From<TracerDispatcher<S, H>>
     }
 }
 
+#[cfg(feature = "vm-virtual-blocks")]
 impl<S: WriteStorage, H: HistoryMode> From<TracerDispatcher<S, H>>
     for crate::vm_virtual_blocks::TracerDispatcher<S, H::VmVirtualBlocksMode>
 {
@@ -0,0 +1,92 @@
+use zksync_state::WriteStorage;
+
+use crate::{
+    tracers::{tracer_from_name_with_max_depth, NamedTracer},
+    HistoryMode, MultiVmTracerPointer,
+};
+
+/// Runs several tracers in one VM execution and reports their results keyed by the tracer name,
+/// like Geth's `muxTracer`, e.g. `{"prestateTracer": {..}, "4byteTracer": {..}}`.
+///
+/// The VM callbacks are fanned out to the children by the `TracerDispatcher` of the VM version,
+/// so all of them observe the same VM state and storage.
+pub struct MuxTracer<S, H> {
+    tracers: Vec<(String, Box<dyn NamedTracer<S, H>>)>,
+}
+
+impl<S: WriteStorage, H: HistoryMode> MuxTracer<S, H> {
+    pub fn new(tracers: Vec<(String, Box<dyn NamedTracer<S, H>>)>) -> Self {
+        Self { tracers }
+    }
+
+    /// Creates the child tracers by their names, see
+    /// [`tracer_from_name`](crate::tracers::tracer_from_name).
+    /// Returns `None` if any of the names is unknown.
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        Self::from_names_with_max_depth(names, usize::MAX)
+    }
+
+    /// Same as [`Self::from_names()`], but all the children are limited to `max_depth` levels
+    /// of nested calls, see [`tracer_from_name_with_max_depth`].
+    pub fn from_names_with_max_depth<'a>(
+        names: impl IntoIterator<Item = &'a str>,
+        max_depth: usize,
+    ) -> Option<Self> {
+        let tracers = names
+            .into_iter()
+            .map(|name| {
+                let tracer = tracer_from_name_with_max_depth(name, max_depth)?;
+                Some((name.to_owned(), tracer))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self::new(tracers))
+    }
+}
+
+impl<S: WriteStorage, H: HistoryMode> NamedTracer<S, H> for MuxTracer<S, H> {
+    fn tracer_pointers(&self) -> Vec<MultiVmTracerPointer<S, H>> {
+        self.tracers
+            .iter()
+            .flat_map(|(_, tracer)| tracer.tracer_pointers())
+            .collect()
+    }
+
+    /// Returns the results of all the children, or `None` if any of them is missing.
+    fn json_result(&self) -> Option<serde_json::Value> {
+        let results = self
+            .tracers
+            .iter()
+            .map(|(name, tracer)| Some((name.clone(), tracer.json_result()?)))
+            .collect::<Option<serde_json::Map<_, _>>>()?;
+        Some(results.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_state::{InMemoryStorage, StorageView};
+
+    use super::*;
+    use crate::vm_latest::HistoryEnabled;
+
+    type Tracer = MuxTracer<StorageView<InMemoryStorage>, HistoryEnabled>;
+
+    #[test]
+    fn results_are_keyed_by_name() {
+        let tracer = Tracer::from_names(["noopTracer", "4byteTracer"]).unwrap();
+        assert_eq!(tracer.tracer_pointers().len(), 2);
+        // The 4byte tracer hasn't run yet, so there is no result.
+        assert_eq!(tracer.json_result(), None);
+
+        let tracer = Tracer::from_names(["noopTracer"]).unwrap();
+        assert_eq!(
+            tracer.json_result(),
+            Some(serde_json::json!({ "noopTracer": {} }))
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert!(Tracer::from_names(["noopTracer", "foo"]).is_none());
+    }
+}
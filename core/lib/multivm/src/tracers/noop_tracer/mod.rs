@@ -0,0 +1,30 @@
+use zksync_state::WriteStorage;
+
+use crate::{
+    glue::tracers::IntoOldVmTracer, tracers::NamedTracer, HistoryMode, MultiVMTracer,
+    MultiVmTracerPointer,
+};
+
+pub mod vm_1_4_1;
+pub mod vm_boojum_integration;
+pub mod vm_latest;
+#[cfg(feature = "vm-refunds-enhancement")]
+pub mod vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
+pub mod vm_virtual_blocks;
+
+/// Tracer that does nothing, an equivalent of Geth's `noopTracer`. Its result is an empty object.
+#[derive(Debug, Clone, Default)]
+pub struct NoopTracer;
+
+impl IntoOldVmTracer for NoopTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> NamedTracer<S, H> for NoopTracer {
+    fn tracer_pointers(&self) -> Vec<MultiVmTracerPointer<S, H>> {
+        vec![self.clone().into_tracer_pointer()]
+    }
+
+    fn json_result(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({}))
+    }
+}
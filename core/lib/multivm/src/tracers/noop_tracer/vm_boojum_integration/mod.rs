@@ -0,0 +1,11 @@
+use zksync_state::WriteStorage;
+
+use crate::{
+    interface::traits::tracers::dyn_tracers::vm_1_4_0::DynTracer,
+    tracers::noop_tracer::NoopTracer,
+    vm_boojum_integration::{HistoryMode, SimpleMemory, VmTracer},
+};
+
+impl<S, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for NoopTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> VmTracer<S, H> for NoopTracer {}
@@ -0,0 +1,11 @@
+use zksync_state::WriteStorage;
+
+use crate::{
+    interface::traits::tracers::dyn_tracers::vm_1_4_1::DynTracer,
+    tracers::noop_tracer::NoopTracer,
+    vm_latest::{HistoryMode, SimpleMemory, VmTracer},
+};
+
+impl<S, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for NoopTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> VmTracer<S, H> for NoopTracer {}
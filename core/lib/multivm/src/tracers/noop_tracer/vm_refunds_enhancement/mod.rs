@@ -0,0 +1,11 @@
+use zksync_state::WriteStorage;
+
+use crate::{
+    interface::traits::tracers::dyn_tracers::vm_1_3_3::DynTracer,
+    tracers::noop_tracer::NoopTracer,
+    vm_refunds_enhancement::{HistoryMode, SimpleMemory, VmTracer},
+};
+
+impl<S, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for NoopTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> VmTracer<S, H> for NoopTracer {}
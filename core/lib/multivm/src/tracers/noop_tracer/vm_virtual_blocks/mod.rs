@@ -0,0 +1,17 @@
+use zksync_state::WriteStorage;
+
+use crate::{
+    interface::dyn_tracers::vm_1_3_3::DynTracer,
+    tracers::noop_tracer::NoopTracer,
+    vm_virtual_blocks::{
+        ExecutionEndTracer, ExecutionProcessing, HistoryMode, SimpleMemory, VmTracer,
+    },
+};
+
+impl<S, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for NoopTracer {}
+
+impl<H: HistoryMode> ExecutionEndTracer<H> for NoopTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> ExecutionProcessing<S, H> for NoopTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> VmTracer<S, H> for NoopTracer {}
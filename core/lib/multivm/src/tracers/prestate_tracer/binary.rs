@@ -0,0 +1,349 @@
+//! Compact binary encoding of the prestate, complementing the human-readable JSON one.
+//!
+//! Hashes and 256-bit numbers are encoded as fixed 32-byte arrays rather than hex strings,
+//! and maps are encoded as lists of pairs.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use zksync_types::{web3::signing::keccak256, Address, H256, U256};
+
+use crate::tracers::prestate_tracer::{
+    Account, BalanceChange, BalanceDelta, ProtocolRole, SlotPreimage, State, TouchReason,
+};
+
+type Bytes32 = [u8; 32];
+
+#[derive(Serialize, Deserialize)]
+struct BinaryAccount {
+    balance: Option<Bytes32>,
+    code: Option<Vec<u8>>,
+    code_hash: Option<Bytes32>,
+    code_size: Option<u64>,
+    nonce: Option<Bytes32>,
+    deployment_nonce: Option<Bytes32>,
+    storage: Option<Vec<(Bytes32, Bytes32)>>,
+    token_balances: Option<Vec<([u8; 20], Bytes32)>>,
+    storage_preimages: Option<Vec<(Bytes32, Bytes32, Bytes32)>>,
+    storage_gas_remaining: Option<Vec<(Bytes32, u32)>>,
+    gas_used: Option<u64>,
+    storage_root: Option<Bytes32>,
+    code_source: Option<[u8; 20]>,
+    storage_decoded: Option<Vec<(Bytes32, String)>>,
+    init_code: Option<Vec<u8>>,
+    omitted_slots: Option<u64>,
+    protocol_role: Option<ProtocolRole>,
+    balance_wei_normalized: Option<Bytes32>,
+    storage_refunds: Option<Vec<(Bytes32, u32)>>,
+    touch_reasons: Option<BTreeSet<TouchReason>>,
+    /// Value and fee deltas, each as an amount and whether it's negative.
+    balance_change: Option<[(Bytes32, bool); 2]>,
+    code_unavailable: Option<bool>,
+    code_truncated: Option<bool>,
+}
+
+impl BinaryAccount {
+    /// Sorts the maps of the account, so that its encoding doesn't depend on their iteration order.
+    fn sort(&mut self) {
+        if let Some(storage) = &mut self.storage {
+            storage.sort_unstable();
+        }
+        if let Some(balances) = &mut self.token_balances {
+            balances.sort_unstable();
+        }
+        if let Some(preimages) = &mut self.storage_preimages {
+            preimages.sort_unstable();
+        }
+        if let Some(gas) = &mut self.storage_gas_remaining {
+            gas.sort_unstable();
+        }
+        if let Some(decoded) = &mut self.storage_decoded {
+            decoded.sort_unstable();
+        }
+        if let Some(refunds) = &mut self.storage_refunds {
+            refunds.sort_unstable();
+        }
+    }
+}
+
+fn u256_to_bytes(value: U256) -> Bytes32 {
+    let mut bytes = [0_u8; 32];
+    value.to_big_endian(&mut bytes);
+    bytes
+}
+
+impl From<&Account> for BinaryAccount {
+    fn from(account: &Account) -> Self {
+        Self {
+            balance: account.balance.map(u256_to_bytes),
+            code: account.code.clone(),
+            code_hash: account.code_hash.map(|hash| hash.0),
+            code_size: account.code_size.map(|size| size as u64),
+            nonce: account.nonce.map(u256_to_bytes),
+            deployment_nonce: account.deployment_nonce.map(u256_to_bytes),
+            storage: account.storage.as_ref().map(|storage| {
+                storage
+                    .iter()
+                    .map(|(slot, value)| (slot.0, value.0))
+                    .collect()
+            }),
+            token_balances: account.token_balances.as_ref().map(|balances| {
+                balances
+                    .iter()
+                    .map(|(token, balance)| (token.0, u256_to_bytes(*balance)))
+                    .collect()
+            }),
+            storage_preimages: account.storage_preimages.as_ref().map(|preimages| {
+                preimages
+                    .iter()
+                    .map(|(slot, preimage)| (slot.0, preimage.base_slot.0, preimage.key.0))
+                    .collect()
+            }),
+            storage_gas_remaining: account.storage_gas_remaining.as_ref().map(|gas| {
+                gas.iter()
+                    .map(|(slot, gas_remaining)| (slot.0, *gas_remaining))
+                    .collect()
+            }),
+            gas_used: account.gas_used,
+            storage_root: account.storage_root.map(|root| root.0),
+            code_source: account.code_source.map(|address| address.0),
+            storage_decoded: account.storage_decoded.as_ref().map(|decoded| {
+                decoded
+                    .iter()
+                    .map(|(slot, value)| (slot.0, value.clone()))
+                    .collect()
+            }),
+            init_code: account.init_code.clone(),
+            omitted_slots: account.omitted_slots.map(|count| count as u64),
+            protocol_role: account.protocol_role,
+            balance_wei_normalized: account.balance_wei_normalized.map(u256_to_bytes),
+            storage_refunds: account.storage_refunds.as_ref().map(|refunds| {
+                refunds
+                    .iter()
+                    .map(|(slot, refund)| (slot.0, *refund))
+                    .collect()
+            }),
+            touch_reasons: account.touch_reasons.clone(),
+            balance_change: account.balance_change.map(|change| {
+                [change.value_delta, change.fee_delta]
+                    .map(|delta| (u256_to_bytes(delta.amount), delta.is_negative))
+            }),
+            code_unavailable: account.code_unavailable,
+            code_truncated: account.code_truncated,
+        }
+    }
+}
+
+impl From<BinaryAccount> for Account {
+    fn from(account: BinaryAccount) -> Self {
+        let to_u256 = |bytes: Bytes32| U256::from_big_endian(&bytes);
+        Self {
+            balance: account.balance.map(to_u256),
+            code: account.code,
+            code_hash: account.code_hash.map(H256),
+            code_size: account.code_size.map(|size| size as usize),
+            nonce: account.nonce.map(to_u256),
+            deployment_nonce: account.deployment_nonce.map(to_u256),
+            storage: account.storage.map(|storage| {
+                storage
+                    .into_iter()
+                    .map(|(slot, value)| (H256(slot), H256(value)))
+                    .collect()
+            }),
+            token_balances: account.token_balances.map(|balances| {
+                balances
+                    .into_iter()
+                    .map(|(token, balance)| (Address::from(token), to_u256(balance)))
+                    .collect()
+            }),
+            storage_preimages: account.storage_preimages.map(|preimages| {
+                preimages
+                    .into_iter()
+                    .map(|(slot, base_slot, key)| {
+                        let preimage = SlotPreimage {
+                            base_slot: H256(base_slot),
+                            key: H256(key),
+                        };
+                        (H256(slot), preimage)
+                    })
+                    .collect()
+            }),
+            storage_gas_remaining: account.storage_gas_remaining.map(|gas| {
+                gas.into_iter()
+                    .map(|(slot, gas_remaining)| (H256(slot), gas_remaining))
+                    .collect()
+            }),
+            gas_used: account.gas_used,
+            storage_root: account.storage_root.map(H256),
+            code_source: account.code_source.map(Address::from),
+            storage_decoded: account.storage_decoded.map(|decoded| {
+                decoded
+                    .into_iter()
+                    .map(|(slot, value)| (H256(slot), value))
+                    .collect()
+            }),
+            init_code: account.init_code,
+            omitted_slots: account.omitted_slots.map(|count| count as usize),
+            protocol_role: account.protocol_role,
+            balance_wei_normalized: account.balance_wei_normalized.map(to_u256),
+            storage_refunds: account.storage_refunds.map(|refunds| {
+                refunds
+                    .into_iter()
+                    .map(|(slot, refund)| (H256(slot), refund))
+                    .collect()
+            }),
+            touch_reasons: account.touch_reasons,
+            balance_change: account.balance_change.map(|[value_delta, fee_delta]| {
+                let to_delta = |(amount, is_negative)| {
+                    if is_negative {
+                        BalanceDelta::decrease(to_u256(amount))
+                    } else {
+                        BalanceDelta::increase(to_u256(amount))
+                    }
+                };
+                BalanceChange {
+                    value_delta: to_delta(value_delta),
+                    fee_delta: to_delta(fee_delta),
+                }
+            }),
+            code_unavailable: account.code_unavailable,
+            code_truncated: account.code_truncated,
+        }
+    }
+}
+
+/// Encodes the `state` with `bincode`.
+pub fn state_to_bincode(state: &State) -> Result<Vec<u8>, bincode::Error> {
+    let accounts: Vec<_> = state
+        .iter()
+        .map(|(address, account)| (address.0, BinaryAccount::from(account)))
+        .collect();
+    bincode::serialize(&accounts)
+}
+
+/// Decodes the state encoded with [`state_to_bincode()`].
+pub fn state_from_bincode(bytes: &[u8]) -> Result<State, bincode::Error> {
+    let accounts: Vec<([u8; 20], BinaryAccount)> = bincode::deserialize(bytes)?;
+    Ok(accounts
+        .into_iter()
+        .map(|(address, account)| (Address::from(address), account.into()))
+        .collect())
+}
+
+/// Returns the hash of the `state` which only depends on its contents, i.e. the logically equal
+/// states always have the same hash regardless of the iteration order of their maps.
+///
+/// The hash covers all the account fields, including the ones which aren't a part of Geth's output.
+pub fn canonical_hash(state: &State) -> H256 {
+    let mut accounts: Vec<_> = state
+        .iter()
+        .map(|(address, account)| {
+            let mut account = BinaryAccount::from(account);
+            account.sort();
+            (address.0, account)
+        })
+        .collect();
+    accounts.sort_unstable_by_key(|(address, _)| *address);
+    let bytes = bincode::serialize(&accounts).expect("failed to encode state");
+    H256(keccak256(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn account(seed: u8) -> Account {
+        let slots = (0..10).map(|_| (H256::random(), H256::random()));
+        Account {
+            balance: Some(U256::exp10(seed.into())),
+            code: Some(vec![seed; 64]),
+            code_hash: Some(H256::repeat_byte(seed)),
+            code_size: Some(64),
+            nonce: Some(seed.into()),
+            deployment_nonce: Some(U256::one()),
+            storage: Some(slots.collect()),
+            token_balances: Some(HashMap::from([(Address::repeat_byte(seed), U256::MAX)])),
+            storage_preimages: Some(HashMap::from([(
+                H256::random(),
+                SlotPreimage {
+                    base_slot: H256::zero(),
+                    key: H256::repeat_byte(seed),
+                },
+            )])),
+            storage_gas_remaining: Some(HashMap::from([(H256::random(), 1_000)])),
+            gas_used: Some(seed.into()),
+            storage_root: Some(H256::random()),
+            code_source: Some(Address::repeat_byte(seed)),
+            storage_decoded: Some(HashMap::from([(H256::random(), seed.to_string())])),
+            init_code: Some(vec![seed; 32]),
+            omitted_slots: Some(seed.into()),
+            protocol_role: Some(ProtocolRole::Bootloader),
+            balance_wei_normalized: Some(U256::exp10(usize::from(seed) + 12)),
+            storage_refunds: Some(HashMap::from([(H256::random(), 50)])),
+            touch_reasons: Some(BTreeSet::from([TouchReason::ReadOnly])),
+            balance_change: Some(BalanceChange {
+                value_delta: BalanceDelta::increase(seed.into()),
+                fee_delta: BalanceDelta::decrease(U256::MAX),
+            }),
+            code_unavailable: Some(true),
+            code_truncated: Some(true),
+        }
+    }
+
+    #[test]
+    fn state_round_trips_through_bincode() {
+        let state: State = (1..=3)
+            .map(|seed| (Address::repeat_byte(seed), account(seed)))
+            .chain([(Address::repeat_byte(0xff), Account::default())])
+            .collect();
+
+        let bytes = state_to_bincode(&state).unwrap();
+        assert_eq!(state_from_bincode(&bytes).unwrap(), state);
+
+        let json = serde_json::to_vec(&state).unwrap();
+        assert!(
+            bytes.len() * 3 < json.len() * 2,
+            "binary: {}, JSON: {}",
+            bytes.len(),
+            json.len()
+        );
+    }
+
+    #[test]
+    fn canonical_hash_ignores_iteration_order() {
+        let accounts: Vec<_> = (1..=3)
+            .map(|seed| (Address::repeat_byte(seed), account(seed)))
+            .collect();
+        let state: State = accounts.iter().cloned().collect();
+        // Rebuild the same state with every map filled in the reverse order.
+        let reversed: State = accounts
+            .into_iter()
+            .rev()
+            .map(|(address, account)| {
+                let storage = account.storage.clone().unwrap();
+                let mut slots: Vec<_> = storage.into_iter().collect();
+                slots.reverse();
+                let account = Account {
+                    storage: Some(slots.into_iter().collect()),
+                    ..account
+                };
+                (address, account)
+            })
+            .collect();
+
+        assert_eq!(reversed, state);
+        assert_eq!(canonical_hash(&reversed), canonical_hash(&state));
+
+        let mut changed = state.clone();
+        changed.get_mut(&Address::repeat_byte(1)).unwrap().nonce = Some(U256::zero());
+        assert_ne!(canonical_hash(&changed), canonical_hash(&state));
+    }
+
+    #[test]
+    fn invalid_bincode_is_rejected() {
+        let bytes = state_to_bincode(&State::from([(Address::zero(), account(1))])).unwrap();
+        assert!(state_from_bincode(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,1000 @@
+//! Prestate of a whole block, like the one reported by Geth's `debug_traceBlockByNumber`.
+
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::Hash,
+    sync::Arc,
+};
+
+use once_cell::sync::OnceCell;
+use zksync_state::{StoragePtr, WriteStorage};
+use zksync_system_constants::DEFAULT_L2_TX_GAS_PER_PUBDATA_BYTE;
+use zksync_types::{
+    fee::Fee, l2::L2Tx, Address, Nonce, PackedEthSignature, Transaction, H256, U256,
+};
+
+use crate::{
+    interface::{
+        L1BatchEnv, SystemEnv, TxExecutionMode, VmExecutionMode, VmExecutionResultAndLogs,
+        VmInterface,
+    },
+    tracers::{
+        prestate_tracer::{
+            annotate_storage_roots, is_nonexistent, retain_changes, Account, BlockContext,
+            PostState, PreState, PrestateResult, PrestateTracer, PrestateTracerConfig,
+            PrestateTracerError, State, StateCounts, TracerVmVersion,
+        },
+        TracerDispatcher,
+    },
+    vm_latest::{constants::ETH_CALL_GAS_LIMIT, utils::fee::get_batch_base_fee, HistoryDisabled},
+    HistoryMode, MultiVMTracer, VmInstance,
+};
+
+/// Traces a single L2 `transaction` executed on top of the `storage`, in the batch with
+/// the `batch_env`. The VM version is chosen by the protocol version of the `system_env`,
+/// and the tracer is built from the `config` for it, see
+/// [`PrestateTracerConfig::for_system_env()`]. The returned result includes the execution
+/// outcome and the hash of the transaction.
+///
+/// Fails if the tracer couldn't collect the result, e.g. if the `storage` is borrowed
+/// at the end of the execution.
+pub fn trace_prestate<S: WriteStorage>(
+    transaction: Transaction,
+    storage: StoragePtr<S>,
+    batch_env: L1BatchEnv,
+    system_env: SystemEnv,
+    config: &PrestateTracerConfig,
+) -> Result<PrestateResult, PrestateTracerError> {
+    let tx_hash = transaction.hash();
+    let config = config.for_system_env(&system_env);
+    let vm = VmInstance::new(batch_env, system_env, storage);
+    // The VM is created for the transaction, so it's the first one in the batch.
+    Ok(trace_in_new_vm(vm, transaction, &config)?.with_transaction(tx_hash, 0))
+}
+
+/// Same as [`trace_prestate()`], but executes the transaction in the VM of the `vm_version`
+/// regardless of the protocol version of the `system_env`. The base system contracts
+/// of the `system_env` must be supported by that VM.
+pub fn trace_prestate_with_version<S: WriteStorage>(
+    transaction: Transaction,
+    storage: StoragePtr<S>,
+    batch_env: L1BatchEnv,
+    system_env: SystemEnv,
+    vm_version: TracerVmVersion,
+    config: &PrestateTracerConfig,
+) -> Result<PrestateResult, PrestateTracerError> {
+    let tx_hash = transaction.hash();
+    let config = config.for_system_env(&system_env);
+    let vm =
+        VmInstance::new_with_specific_version(batch_env, system_env, storage, vm_version.into());
+    Ok(trace_in_new_vm(vm, transaction, &config)?.with_transaction(tx_hash, 0))
+}
+
+/// Message call executed without a signed transaction, as the one of `eth_call`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallMessage {
+    pub from: Address,
+    pub to: Address,
+    pub data: Vec<u8>,
+    pub value: U256,
+    /// Gas limit of the call, [`ETH_CALL_GAS_LIMIT`] by default.
+    pub gas: Option<U256>,
+}
+
+/// Same as [`trace_prestate()`], but traces a bare `call` like `debug_traceCall` does. The call
+/// is executed in the `eth_call` mode regardless of the mode of the `system_env`, so that
+/// neither the signature nor the nonce of the sender is checked, and the nonce isn't
+/// incremented. The fee is priced at the base fee of the batch.
+///
+/// The call has no hash, so the returned result doesn't identify a transaction.
+pub fn trace_call_prestate<S: WriteStorage>(
+    call: CallMessage,
+    storage: StoragePtr<S>,
+    batch_env: L1BatchEnv,
+    mut system_env: SystemEnv,
+    config: &PrestateTracerConfig,
+) -> Result<PrestateResult, PrestateTracerError> {
+    let fee = Fee {
+        gas_limit: call.gas.unwrap_or_else(|| ETH_CALL_GAS_LIMIT.into()),
+        max_fee_per_gas: get_batch_base_fee(&batch_env).into(),
+        max_priority_fee_per_gas: U256::zero(),
+        gas_per_pubdata_limit: DEFAULT_L2_TX_GAS_PER_PUBDATA_BYTE.into(),
+    };
+    let mut transaction = L2Tx::new(
+        call.to,
+        call.data,
+        Nonce(0),
+        fee,
+        call.from,
+        call.value,
+        None,
+        Default::default(),
+    );
+    // The signature isn't validated, but the bootloader still expects a well-formed one.
+    transaction.common_data.signature = PackedEthSignature::default().serialize_packed().into();
+    system_env.execution_mode = TxExecutionMode::EthCall;
+    let config = config.for_system_env(&system_env);
+    let vm = VmInstance::new(batch_env, system_env, storage);
+    trace_in_new_vm(vm, transaction.into(), &config)
+}
+
+fn trace_in_new_vm<S: WriteStorage>(
+    mut vm: VmInstance<S, HistoryDisabled>,
+    transaction: Transaction,
+    config: &PrestateTracerConfig,
+) -> Result<PrestateResult, PrestateTracerError> {
+    vm.push_transaction(transaction);
+    let (execution, result) = inspect_transaction(&mut vm, config);
+    let result = result.ok_or(PrestateTracerError::ResultNotStored)?;
+    Ok(result
+        .with_execution_result(&execution.result)
+        .with_execution_statistics(&execution.statistics))
+}
+
+/// Executes the transaction pushed to the `vm` last, tracing it with a prestate tracer built
+/// from the `config`. The returned result includes the execution outcome.
+///
+/// Fails if the tracer hasn't stored its result, e.g. if it couldn't borrow the storage.
+pub fn trace_transaction_prestate<S, H, V>(
+    vm: &mut V,
+    config: &PrestateTracerConfig,
+) -> Result<(VmExecutionResultAndLogs, PrestateResult), PrestateTracerError>
+where
+    S: WriteStorage,
+    H: HistoryMode,
+    V: VmInterface<S, H>,
+{
+    let (execution, result) = inspect_transaction(vm, config);
+    let result = result
+        .ok_or(PrestateTracerError::ResultNotStored)?
+        .with_execution_result(&execution.result)
+        .with_execution_statistics(&execution.statistics);
+    Ok((execution, result))
+}
+
+/// Executes the transaction pushed to the `vm` last with a prestate tracer, returning its result
+/// as is, or `None` if it wasn't stored.
+fn inspect_transaction<S, H, V>(
+    vm: &mut V,
+    config: &PrestateTracerConfig,
+) -> (VmExecutionResultAndLogs, Option<PrestateResult>)
+where
+    S: WriteStorage,
+    H: HistoryMode,
+    V: VmInterface<S, H>,
+{
+    let tracer = PrestateTracer::from_config(config.clone());
+    let tracers: TracerDispatcher<S, H> = vec![tracer.clone().into_tracer_pointer()].into();
+    let execution = vm.inspect(tracers.into(), VmExecutionMode::OneTx);
+    (execution, tracer.take_result())
+}
+
+/// Executes the transaction pushed to the `vm` last with Geth's `prestateTracer` and returns
+/// the response of `debug_traceTransaction`. The `tracer_config` is the `tracerConfig` parameter
+/// of the request, see [`PrestateTracerConfig::from_json()`].
+pub fn debug_trace_transaction_prestate<S, H, V>(
+    vm: &mut V,
+    tracer_config: &serde_json::Value,
+) -> Result<serde_json::Value, PrestateTracerError>
+where
+    S: WriteStorage,
+    H: HistoryMode,
+    V: VmInterface<S, H>,
+{
+    let config = PrestateTracerConfig::from_json(tracer_config)?;
+    let (_, result) = trace_transaction_prestate(vm, &config)?;
+    Ok(result.to_json(&config))
+}
+
+/// Executes the `transactions` of a block one by one, tracing each of them with a prestate tracer
+/// built from the `config`, and merges the results with [`merge_prestate_results()`].
+pub fn trace_block_prestate<S, H, V>(
+    vm: &mut V,
+    transactions: impl IntoIterator<Item = Transaction>,
+    config: &PrestateTracerConfig,
+) -> PrestateResult
+where
+    S: WriteStorage,
+    H: HistoryMode,
+    V: VmInterface<S, H>,
+{
+    merge_prestate_results(trace_transactions(vm, transactions, config))
+}
+
+/// Results of the transactions of a block, with the bytecodes of all of them reported once.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockPrestateResults {
+    /// Results of the transactions in the execution order, without their own `factory_deps`.
+    /// The code of the accounts is left out as well if it's in the shared `factory_deps`,
+    /// so it's only referenced by the `code_hash`.
+    pub results: Vec<PrestateResult>,
+    /// Sorted hashes of the bytecodes in `factory_deps` needed by each transaction, in the same
+    /// order as the `results`.
+    pub factory_dep_hashes: Vec<Vec<H256>>,
+    /// Bytecodes of the contracts of all the transactions, keyed by their versioned hash.
+    pub factory_deps: HashMap<H256, Vec<u8>>,
+}
+
+/// Executes the `transactions` of a block like [`trace_block_prestate()`], but reports
+/// the result of every transaction rather than merging them, see [`share_factory_deps()`].
+/// The factory dependencies are collected regardless of the `config`.
+pub fn trace_block_prestate_per_transaction<S, H, V>(
+    vm: &mut V,
+    transactions: impl IntoIterator<Item = Transaction>,
+    config: &PrestateTracerConfig,
+) -> BlockPrestateResults
+where
+    S: WriteStorage,
+    H: HistoryMode,
+    V: VmInterface<S, H>,
+{
+    let config = PrestateTracerConfig {
+        collect_factory_deps: true,
+        ..config.clone()
+    };
+    share_factory_deps(trace_transactions(vm, transactions, &config))
+}
+
+/// Moves the factory dependencies of the `results` of the transactions to a single map,
+/// so that a bytecode used by several transactions is reported once.
+pub fn share_factory_deps(
+    results: impl IntoIterator<Item = PrestateResult>,
+) -> BlockPrestateResults {
+    let mut block_results = BlockPrestateResults::default();
+    for mut result in results {
+        let factory_deps = result.factory_deps.take().unwrap_or_default();
+        let mut hashes: Vec<_> = factory_deps.keys().copied().collect();
+        hashes.sort_unstable();
+        block_results.factory_deps.extend(factory_deps);
+
+        let shared_deps = &block_results.factory_deps;
+        let post_accounts = result.post.iter_mut().flat_map(|post| post.values_mut());
+        for account in result.pre.values_mut().chain(post_accounts) {
+            if account
+                .code_hash
+                .map_or(false, |code_hash| shared_deps.contains_key(&code_hash))
+            {
+                account.code = None;
+            }
+        }
+        block_results.results.push(result);
+        block_results.factory_dep_hashes.push(hashes);
+    }
+    block_results
+}
+
+/// Traces the `transactions` one by one, returning the result of each of them with the hash
+/// of the transaction and its index among the `transactions`.
+fn trace_transactions<S, H, V>(
+    vm: &mut V,
+    transactions: impl IntoIterator<Item = Transaction>,
+    config: &PrestateTracerConfig,
+) -> Vec<PrestateResult>
+where
+    S: WriteStorage,
+    H: HistoryMode,
+    V: VmInterface<S, H>,
+{
+    let mut results = vec![];
+    let mut tracer = PrestateTracer::with_config(config.clone(), Arc::new(OnceCell::new()));
+    for (i, tx) in transactions.into_iter().enumerate() {
+        if i > 0 {
+            tracer.reset(Arc::new(OnceCell::new()));
+        }
+        let tracers: TracerDispatcher<S, H> = vec![tracer.clone().into_tracer_pointer()].into();
+        let tx_hash = tx.hash();
+        vm.push_transaction(tx);
+        let execution = vm.inspect(tracers.into(), VmExecutionMode::OneTx);
+        results.extend(tracer.get_result().map(|result| {
+            result
+                .with_execution_result(&execution.result)
+                .with_execution_statistics(&execution.statistics)
+                .with_transaction(tx_hash, i)
+        }));
+    }
+    results
+}
+
+/// Merges the results for the transactions of a block, in the execution order, into the result
+/// for the whole block.
+///
+/// The first seen value of every account field and slot wins for the pre-state, and the last one
+/// wins for the post-state. In diff mode, the values first seen in the post-state of a transaction
+/// were not present at the block start, so e.g. the accounts created by the block have no code
+/// in its pre-state.
+///
+/// If the results include the storage roots, they are computed for the merged storage.
+/// The touch orders are concatenated, keeping the first touch of every reported account,
+/// and the factory dependencies and the accessed slots of all the transactions are reported
+/// together. The block context is the one of the first transaction, with the block hashes
+/// read by all of them. The fees credited to the recipient add up, while the effective gas price
+/// is only reported if it's the same for all the transactions, and so is the price of the pubdata,
+/// while the published pubdata adds up. The storage collisions are the ones of the individual
+/// transactions, in their order, and the latest values of the modified keys win.
+///
+/// In diff mode, the accounts deleted by a transaction are deleted in the block post-state until
+/// they are written again, and the ones both created and deleted by the block are left out.
+///
+/// The block is marked as failed if any of the transactions has failed, with the revert reason
+/// of the first failed one. The return data is the one of the last transaction which has it,
+/// while the transaction hash and index aren't reported for the block.
+pub fn merge_prestate_results(results: impl IntoIterator<Item = PrestateResult>) -> PrestateResult {
+    let mut merged = PrestateResult {
+        pre: PreState::default(),
+        post: None,
+        truncated: false,
+        failed: false,
+        revert_reason: None,
+        return_data: None,
+        tx_hash: None,
+        tx_index: None,
+        vm_version: None,
+        touch_order: None,
+        factory_deps: None,
+        access_profile: None,
+        op_counts: None,
+        replay_safe: true,
+        block_context: None,
+        effective_gas_price: None,
+        fee_recipient_delta: None,
+        gas_per_pubdata: None,
+        pubdata_published: None,
+        storage_collisions: None,
+        modified_keys: None,
+        created_addresses: Vec::new(),
+        counts: Default::default(),
+    };
+    let mut include_storage_roots = false;
+    let mut deleted_accounts = HashSet::new();
+    let mut touched_accounts = HashSet::new();
+    let mut gas_prices = HashSet::new();
+    let mut pubdata_prices = HashSet::new();
+    for result in results {
+        merged.truncated |= result.truncated;
+        merged.replay_safe &= result.replay_safe;
+        merged.vm_version = merged.vm_version.or(result.vm_version);
+        if let Some(touch_order) = result.touch_order {
+            let merged_order = merged.touch_order.get_or_insert_with(Vec::new);
+            let new_accounts = touch_order
+                .into_iter()
+                .filter(|address| touched_accounts.insert(*address));
+            merged_order.extend(new_accounts);
+        }
+        if let Some(factory_deps) = result.factory_deps {
+            merged
+                .factory_deps
+                .get_or_insert_with(HashMap::new)
+                .extend(factory_deps);
+        }
+        if let Some(access_profile) = result.access_profile {
+            let merged_profile = merged.access_profile.get_or_insert_with(HashMap::new);
+            for (address, accesses) in access_profile {
+                let merged_accesses = merged_profile.entry(address).or_default();
+                merged_accesses.read_slots.extend(accesses.read_slots);
+                merged_accesses.write_slots.extend(accesses.write_slots);
+            }
+        }
+        if let Some(op_counts) = result.op_counts {
+            *merged.op_counts.get_or_insert_with(Default::default) += op_counts;
+        }
+        if let Some(block_context) = result.block_context {
+            let merged_context = merged.block_context.get_or_insert_with(|| BlockContext {
+                block_hashes: BTreeMap::new(),
+                ..block_context.clone()
+            });
+            merged_context
+                .block_hashes
+                .extend(block_context.block_hashes);
+        }
+        if let Some(gas_price) = result.effective_gas_price {
+            gas_prices.insert(gas_price);
+        }
+        if let Some(delta) = result.fee_recipient_delta {
+            let merged_delta = merged
+                .fee_recipient_delta
+                .get_or_insert_with(Default::default);
+            *merged_delta = *merged_delta + delta;
+        }
+        if let Some(gas_per_pubdata) = result.gas_per_pubdata {
+            pubdata_prices.insert(gas_per_pubdata);
+        }
+        if let Some(pubdata_published) = result.pubdata_published {
+            *merged.pubdata_published.get_or_insert(0) += pubdata_published;
+        }
+        if let Some(collisions) = result.storage_collisions {
+            merged
+                .storage_collisions
+                .get_or_insert_with(Vec::new)
+                .extend(collisions);
+        }
+        if let Some(modified_keys) = result.modified_keys {
+            merged
+                .modified_keys
+                .get_or_insert_with(HashMap::new)
+                .extend(modified_keys);
+        }
+        merged.created_addresses.extend(result.created_addresses);
+        include_storage_roots |= result
+            .pre
+            .values()
+            .chain(result.post.iter().flat_map(|post| post.values()))
+            .any(|account| account.storage_root.is_some());
+        if result.return_data.is_some() {
+            merged.return_data = result.return_data;
+        }
+        if result.failed && !merged.failed {
+            merged.failed = true;
+            merged.revert_reason = result.revert_reason;
+        }
+        match result.post {
+            Some(post) => {
+                let merged_post = merged.post.get_or_insert_with(PostState::default);
+                merge_diff(
+                    &mut merged.pre,
+                    merged_post,
+                    &mut deleted_accounts,
+                    result.pre.into_inner(),
+                    post.into_inner(),
+                );
+            }
+            None => merge_full(&mut merged.pre, result.pre.into_inner()),
+        }
+    }
+    if let Some(post) = &mut merged.post {
+        // Like in the diff of a transaction, the deleted accounts keep their full prestate.
+        // The ones which didn't exist before the block are a no-op.
+        let deleted_accounts: State = deleted_accounts
+            .iter()
+            .filter_map(|address| merged.pre.remove_entry(address))
+            .filter(|(_, account)| !is_nonexistent(account))
+            .collect();
+        retain_changes(&mut merged.pre, post);
+        merged.pre.extend(deleted_accounts);
+    }
+    if let Some(touch_order) = &mut merged.touch_order {
+        let (pre, post) = (&merged.pre, &merged.post);
+        touch_order.retain(|address| {
+            pre.contains_key(address)
+                || post
+                    .as_ref()
+                    .map_or(false, |post| post.contains_key(address))
+        });
+    }
+    // The roots of the transactions don't commit to the merged storage, so they are recomputed.
+    if include_storage_roots {
+        annotate_storage_roots(&mut merged.pre);
+        if let Some(post) = &mut merged.post {
+            annotate_storage_roots(post);
+        }
+    }
+    if gas_prices.len() == 1 {
+        merged.effective_gas_price = gas_prices.into_iter().next();
+    }
+    if pubdata_prices.len() == 1 {
+        merged.gas_per_pubdata = pubdata_prices.into_iter().next();
+    }
+    merged.counts = StateCounts::new(&merged.pre, merged.post.as_deref());
+    merged
+}
+
+/// Merges the results for the transactions of an L1 batch, grouped by its L2 `blocks`, into
+/// the net result for the whole batch. The transactions are merged in the execution order
+/// across the blocks, like by [`merge_prestate_results()`].
+pub fn merge_batch_prestate_results<B>(blocks: impl IntoIterator<Item = B>) -> PrestateResult
+where
+    B: IntoIterator<Item = PrestateResult>,
+{
+    merge_prestate_results(blocks.into_iter().flatten())
+}
+
+/// Merges the full state of the accounts before a transaction into the block pre-state.
+/// The accounts are fully read on the first access, so only the slots seen for the first time
+/// are added to the ones already known.
+fn merge_full(block_pre: &mut State, pre: State) {
+    for (address, account) in pre {
+        match block_pre.entry(address) {
+            Entry::Vacant(entry) => {
+                entry.insert(account);
+            }
+            Entry::Occupied(mut entry) => {
+                let block_account = entry.get_mut();
+                merge_map(&mut block_account.storage, &mut None, account.storage, None);
+                merge_map(
+                    &mut block_account.token_balances,
+                    &mut None,
+                    account.token_balances,
+                    None,
+                );
+                merge_map(
+                    &mut block_account.storage_preimages,
+                    &mut None,
+                    account.storage_preimages,
+                    None,
+                );
+                merge_map(
+                    &mut block_account.storage_gas_remaining,
+                    &mut None,
+                    account.storage_gas_remaining,
+                    None,
+                );
+                merge_map(
+                    &mut block_account.storage_decoded,
+                    &mut None,
+                    account.storage_decoded,
+                    None,
+                );
+            }
+        }
+    }
+}
+
+/// Merges the changes made by a transaction into the block pre- and post-state. The accounts
+/// only present in the pre-state of the transaction are deleted by it, and are collected
+/// into `deleted_accounts`.
+fn merge_diff(
+    block_pre: &mut State,
+    block_post: &mut State,
+    deleted_accounts: &mut HashSet<Address>,
+    mut pre: State,
+    mut post: State,
+) {
+    let addresses: HashSet<_> = pre.keys().chain(post.keys()).copied().collect();
+    for address in addresses {
+        let pre = pre.remove(&address).unwrap_or_default();
+        let mut post = post.remove(&address);
+        if let Some(post) = &mut post {
+            restore_zero_slots(&pre, post);
+        }
+        let is_deleted = post.is_none();
+        merge_account(
+            block_pre.entry(address).or_default(),
+            block_post.entry(address).or_default(),
+            pre,
+            post.unwrap_or_default(),
+        );
+        // The deleted account is absent from the post-state, like in the diff of a transaction.
+        if is_deleted {
+            block_post.remove(&address);
+            deleted_accounts.insert(address);
+        } else {
+            deleted_accounts.remove(&address);
+        }
+    }
+}
+
+/// Adds the changed slots omitted from the `post`-state of an account, since they were set
+/// to zero. Otherwise, a slot set to zero would keep the value written by an earlier transaction.
+fn restore_zero_slots(pre: &Account, post: &mut Account) {
+    let Some(pre_storage) = &pre.storage else {
+        return;
+    };
+    let post_storage = post.storage.get_or_insert_with(HashMap::new);
+    for slot in pre_storage.keys() {
+        post_storage.entry(*slot).or_insert_with(H256::zero);
+    }
+}
+
+fn merge_account(block_pre: &mut Account, block_post: &mut Account, pre: Account, post: Account) {
+    merge_field(
+        &mut block_pre.balance,
+        &mut block_post.balance,
+        pre.balance,
+        post.balance,
+    );
+    merge_field(
+        &mut block_pre.code,
+        &mut block_post.code,
+        pre.code,
+        post.code,
+    );
+    merge_field(
+        &mut block_pre.code_hash,
+        &mut block_post.code_hash,
+        pre.code_hash,
+        post.code_hash,
+    );
+    merge_field(
+        &mut block_pre.code_size,
+        &mut block_post.code_size,
+        pre.code_size,
+        post.code_size,
+    );
+    merge_field(
+        &mut block_pre.code_unavailable,
+        &mut block_post.code_unavailable,
+        pre.code_unavailable,
+        post.code_unavailable,
+    );
+    merge_field(
+        &mut block_pre.code_truncated,
+        &mut block_post.code_truncated,
+        pre.code_truncated,
+        post.code_truncated,
+    );
+    merge_field(
+        &mut block_pre.nonce,
+        &mut block_post.nonce,
+        pre.nonce,
+        post.nonce,
+    );
+    merge_field(
+        &mut block_pre.deployment_nonce,
+        &mut block_post.deployment_nonce,
+        pre.deployment_nonce,
+        post.deployment_nonce,
+    );
+    merge_map(
+        &mut block_pre.storage,
+        &mut block_post.storage,
+        pre.storage,
+        post.storage,
+    );
+    merge_map(
+        &mut block_pre.token_balances,
+        &mut block_post.token_balances,
+        pre.token_balances,
+        post.token_balances,
+    );
+    merge_map(
+        &mut block_pre.storage_preimages,
+        &mut block_post.storage_preimages,
+        pre.storage_preimages,
+        post.storage_preimages,
+    );
+    merge_map(
+        &mut block_pre.storage_gas_remaining,
+        &mut block_post.storage_gas_remaining,
+        pre.storage_gas_remaining,
+        post.storage_gas_remaining,
+    );
+    merge_map(
+        &mut block_pre.storage_decoded,
+        &mut block_post.storage_decoded,
+        pre.storage_decoded,
+        post.storage_decoded,
+    );
+    merge_map(
+        &mut block_pre.storage_refunds,
+        &mut block_post.storage_refunds,
+        pre.storage_refunds,
+        post.storage_refunds,
+    );
+    merge_field(
+        &mut block_pre.gas_used,
+        &mut block_post.gas_used,
+        pre.gas_used,
+        post.gas_used,
+    );
+    merge_field(
+        &mut block_pre.code_source,
+        &mut block_post.code_source,
+        pre.code_source,
+        post.code_source,
+    );
+    merge_field(
+        &mut block_pre.init_code,
+        &mut block_post.init_code,
+        pre.init_code,
+        post.init_code,
+    );
+    merge_field(
+        &mut block_pre.omitted_slots,
+        &mut block_post.omitted_slots,
+        pre.omitted_slots,
+        post.omitted_slots,
+    );
+    merge_field(
+        &mut block_pre.protocol_role,
+        &mut block_post.protocol_role,
+        pre.protocol_role,
+        post.protocol_role,
+    );
+    merge_field(
+        &mut block_pre.balance_wei_normalized,
+        &mut block_post.balance_wei_normalized,
+        pre.balance_wei_normalized,
+        post.balance_wei_normalized,
+    );
+    // The account is touched by the block for the reasons of all its transactions.
+    for (block_reasons, reasons) in [
+        (&mut block_pre.touch_reasons, pre.touch_reasons),
+        (&mut block_post.touch_reasons, post.touch_reasons),
+    ] {
+        if let Some(reasons) = reasons {
+            block_reasons
+                .get_or_insert_with(BTreeSet::new)
+                .extend(reasons);
+        }
+    }
+    // The values transferred and the fees paid by the transactions add up.
+    if let Some(change) = post.balance_change {
+        block_post.balance_change = Some(match block_post.balance_change {
+            Some(block_change) => block_change + change,
+            None => change,
+        });
+    }
+}
+
+/// Takes the pre-state value of the field only if it wasn't seen by the earlier transactions.
+fn merge_field<T>(
+    block_pre: &mut Option<T>,
+    block_post: &mut Option<T>,
+    pre: Option<T>,
+    post: Option<T>,
+) {
+    if block_pre.is_none() && block_post.is_none() {
+        *block_pre = pre;
+    }
+    if post.is_some() {
+        *block_post = post;
+    }
+}
+
+/// Same as [`merge_field()`], but for every key of the map.
+fn merge_map<K: Eq + Hash, V>(
+    block_pre: &mut Option<HashMap<K, V>>,
+    block_post: &mut Option<HashMap<K, V>>,
+    pre: Option<HashMap<K, V>>,
+    post: Option<HashMap<K, V>>,
+) {
+    for (key, value) in pre.into_iter().flatten() {
+        let seen_in_post = block_post
+            .as_ref()
+            .map_or(false, |block_post| block_post.contains_key(&key));
+        if !seen_in_post {
+            block_pre
+                .get_or_insert_with(HashMap::new)
+                .entry(key)
+                .or_insert(value);
+        }
+    }
+    for (key, value) in post.into_iter().flatten() {
+        block_post
+            .get_or_insert_with(HashMap::new)
+            .insert(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_types::{Address, U256};
+
+    use super::*;
+
+    fn account(balance: u64, storage: &[(u64, u64)]) -> Account {
+        let storage = storage
+            .iter()
+            .map(|&(slot, value)| (H256::from_low_u64_be(slot), H256::from_low_u64_be(value)));
+        Account {
+            balance: Some(balance.into()),
+            storage: Some(storage.collect()),
+            ..Account::default()
+        }
+    }
+
+    #[test]
+    fn first_seen_values_win_in_default_mode() {
+        let address = Address::repeat_byte(0x01);
+        let tx_results = [
+            PrestateResult {
+                pre: State::from([(address, account(100, &[(0, 1)]))]).into(),
+                post: None,
+                truncated: false,
+                failed: false,
+                revert_reason: None,
+                return_data: None,
+                tx_hash: None,
+                tx_index: None,
+                vm_version: None,
+                touch_order: None,
+                factory_deps: None,
+                access_profile: None,
+                op_counts: None,
+                replay_safe: false,
+                block_context: None,
+                effective_gas_price: None,
+                fee_recipient_delta: None,
+                gas_per_pubdata: None,
+                pubdata_published: None,
+                storage_collisions: None,
+                modified_keys: None,
+                created_addresses: Vec::new(),
+                counts: Default::default(),
+            },
+            PrestateResult {
+                pre: State::from([(address, account(50, &[(0, 2), (1, 3)]))]).into(),
+                post: None,
+                truncated: true,
+                failed: false,
+                revert_reason: None,
+                return_data: None,
+                tx_hash: None,
+                tx_index: None,
+                vm_version: None,
+                touch_order: None,
+                factory_deps: None,
+                access_profile: None,
+                op_counts: None,
+                replay_safe: false,
+                block_context: None,
+                effective_gas_price: None,
+                fee_recipient_delta: None,
+                gas_per_pubdata: None,
+                pubdata_published: None,
+                storage_collisions: None,
+                modified_keys: None,
+                created_addresses: Vec::new(),
+                counts: Default::default(),
+            },
+        ];
+
+        let merged = merge_prestate_results(tx_results);
+        assert!(merged.truncated);
+        assert_eq!(merged.post, None);
+        assert_eq!(merged.pre[&address], account(100, &[(0, 1), (1, 3)]));
+    }
+
+    #[test]
+    fn diff_mode_takes_first_pre_and_last_post_values() {
+        let address = Address::repeat_byte(0x01);
+        let created = Address::repeat_byte(0x02);
+        let code_hash = H256::repeat_byte(0xcc);
+        let tx_results = [
+            PrestateResult::from((
+                State::from([(address, account(100, &[(0, 1)]))]),
+                State::from([
+                    (address, account(90, &[(0, 2)])),
+                    (
+                        created,
+                        Account {
+                            code_hash: Some(code_hash),
+                            ..Account::default()
+                        },
+                    ),
+                ]),
+            )),
+            PrestateResult::from((
+                State::from([
+                    (address, account(90, &[(1, 0)])),
+                    (
+                        created,
+                        Account {
+                            code_hash: Some(code_hash),
+                            nonce: Some(U256::zero()),
+                            ..Account::default()
+                        },
+                    ),
+                ]),
+                State::from([
+                    (address, account(80, &[(1, 5)])),
+                    (
+                        created,
+                        Account {
+                            nonce: Some(U256::one()),
+                            ..Account::default()
+                        },
+                    ),
+                ]),
+            )),
+        ];
+
+        let merged = merge_prestate_results(tx_results);
+        let post = merged.post.unwrap();
+        assert_eq!(merged.pre[&address], account(100, &[(0, 1), (1, 0)]));
+        assert_eq!(post[&address], account(80, &[(0, 2), (1, 5)]));
+        // The account was created by the block, so it has no code before it.
+        assert_eq!(
+            merged.pre[&created],
+            Account {
+                nonce: Some(U256::zero()),
+                ..Account::default()
+            }
+        );
+        assert_eq!(post[&created].code_hash, Some(code_hash));
+    }
+
+    #[test]
+    fn slot_reset_to_zero_is_merged() {
+        let address = Address::repeat_byte(0x01);
+        let tx_results = [
+            PrestateResult::from((
+                State::from([(address, account(100, &[(0, 0)]))]),
+                State::from([(address, account(100, &[(0, 5)]))]),
+            )),
+            // The slot set back to zero is omitted from the post-state.
+            PrestateResult::from((
+                State::from([(address, account(100, &[(0, 5)]))]),
+                State::from([(address, account(100, &[]))]),
+            )),
+        ];
+
+        let merged = merge_prestate_results(tx_results);
+        assert!(merged.pre.is_empty());
+        assert_eq!(merged.post, Some(PostState::default()));
+    }
+
+    #[test]
+    fn batch_diff_nets_out_accounts_created_and_deleted_by_it() {
+        let address = Address::repeat_byte(0x01);
+        let short_lived = Address::repeat_byte(0x02);
+        let code_hash = H256::repeat_byte(0xcc);
+        let contract = Account {
+            code_hash: Some(code_hash),
+            ..account(10, &[(0, 7)])
+        };
+        let first_block = [PrestateResult::from((
+            State::from([
+                (address, account(100, &[(0, 1)])),
+                (short_lived, account(0, &[(0, 0)])),
+            ]),
+            State::from([
+                (address, account(90, &[(0, 2)])),
+                (short_lived, contract.clone()),
+            ]),
+        ))];
+        // The deleted account keeps its full prestate and is absent from the post-state.
+        let second_block = [PrestateResult::from((
+            State::from([(address, account(90, &[(0, 2)])), (short_lived, contract)]),
+            State::from([(address, account(80, &[(0, 3)]))]),
+        ))];
+
+        let merged = merge_batch_prestate_results([first_block, second_block]);
+        let post = merged.post.unwrap();
+        assert_eq!(
+            merged.pre,
+            State::from([(address, account(100, &[(0, 1)]))])
+        );
+        assert_eq!(post, State::from([(address, account(80, &[(0, 3)]))]));
+
+        // The account existing before the batch is reported as deleted.
+        let deleted = Address::repeat_byte(0x03);
+        let tx_results = [
+            PrestateResult::from((
+                State::from([(deleted, account(10, &[(0, 7)]))]),
+                State::from([(deleted, account(10, &[(0, 8)]))]),
+            )),
+            PrestateResult::from((
+                State::from([(deleted, account(10, &[(0, 8)]))]),
+                State::new(),
+            )),
+        ];
+        let merged = merge_batch_prestate_results([tx_results]);
+        assert_eq!(merged.pre[&deleted], account(10, &[(0, 7)]));
+        assert_eq!(merged.post, Some(PostState::default()));
+    }
+
+    #[test]
+    fn factory_deps_are_shared_by_transactions() {
+        let address = Address::repeat_byte(0x01);
+        let (code_hash, code) = (H256::repeat_byte(0xcc), vec![0xaa; 32]);
+        let other_code_hash = H256::repeat_byte(0xdd);
+        let contract = Account {
+            code: Some(code.clone()),
+            code_hash: Some(code_hash),
+            ..account(100, &[(0, 1)])
+        };
+        let tx_result = |factory_deps: &[H256]| PrestateResult {
+            factory_deps: Some(
+                factory_deps
+                    .iter()
+                    .map(|hash| (*hash, vec![hash[0]; 32]))
+                    .chain([(code_hash, code.clone())])
+                    .collect(),
+            ),
+            ..PrestateResult::from((State::from([(address, contract.clone())]), State::new()))
+        };
+
+        let block_results = share_factory_deps([tx_result(&[]), tx_result(&[other_code_hash])]);
+        assert_eq!(block_results.factory_deps.len(), 2);
+        assert_eq!(block_results.factory_deps[&code_hash], code);
+        assert_eq!(
+            block_results.factory_dep_hashes,
+            [vec![code_hash], vec![code_hash, other_code_hash]]
+        );
+        for result in &block_results.results {
+            assert_eq!(result.factory_deps, None);
+            assert_eq!(result.pre[&address].code, None);
+            assert_eq!(result.pre[&address].code_hash, Some(code_hash));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Conversion of a captured state into a sequence of Foundry cheatcodes, so that the prestate
+//! of a real transaction can be recreated in a local Solidity test.
+
+use std::fmt::Write as _;
+
+use crate::tracers::prestate_tracer::{to_checksum_address, State};
+
+pub(super) fn to_cheatcode_script(state: &State) -> String {
+    let mut accounts: Vec<_> = state.iter().collect();
+    accounts.sort_unstable_by_key(|(address, _)| **address);
+
+    let mut script = String::new();
+    for (address, account) in accounts {
+        // Address literals must be checksummed in Solidity.
+        let address = to_checksum_address(address);
+        if let Some(balance) = account.balance {
+            writeln!(script, "vm.deal({address}, {balance});").unwrap();
+        }
+        if let Some(nonce) = account.nonce {
+            writeln!(script, "vm.setNonce({address}, {nonce});").unwrap();
+        }
+        if let Some(code) = &account.code {
+            let code = hex::encode(code);
+            writeln!(script, "vm.etch({address}, hex\"{code}\");").unwrap();
+        }
+        let mut slots: Vec<_> = account.storage.iter().flatten().collect();
+        slots.sort_unstable();
+        for (slot, value) in slots {
+            writeln!(
+                script,
+                "vm.store({address}, bytes32({slot:?}), bytes32({value:?}));"
+            )
+            .unwrap();
+        }
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use zksync_types::{Address, H256, U256};
+
+    use super::*;
+    use crate::tracers::prestate_tracer::{Account, StateExt};
+
+    #[test]
+    fn script_recreates_state() {
+        let contract = Address::repeat_byte(0x01);
+        let eoa = Address::repeat_byte(0x02);
+        let slot = |slot: u64| H256::from_low_u64_be(slot);
+        let state = State::from([
+            (
+                contract,
+                Account {
+                    balance: Some(U256::zero()),
+                    code: Some(vec![0x00, 0x01]),
+                    storage: Some(HashMap::from([(slot(2), slot(3)), (slot(0), slot(1))])),
+                    ..Account::default()
+                },
+            ),
+            (
+                eoa,
+                Account {
+                    balance: Some(1_000.into()),
+                    nonce: Some(5.into()),
+                    ..Account::default()
+                },
+            ),
+        ]);
+
+        let script = state.to_cheatcode_script();
+        let contract = to_checksum_address(&contract);
+        let eoa = to_checksum_address(&eoa);
+        let store = |key: u64, value: u64| {
+            format!(
+                "vm.store({contract}, bytes32({:?}), bytes32({:?}));",
+                slot(key),
+                slot(value)
+            )
+        };
+        let expected_lines = [
+            format!("vm.deal({contract}, 0);"),
+            format!("vm.etch({contract}, hex\"0001\");"),
+            store(0, 1),
+            store(2, 3),
+            format!("vm.deal({eoa}, 1000);"),
+            format!("vm.setNonce({eoa}, 5);"),
+        ];
+        assert_eq!(script.lines().collect::<Vec<_>>(), expected_lines);
+    }
+}
@@ -0,0 +1,117 @@
+//! Self-consistency checks of a captured prestate before it's used to replay the transaction.
+
+use thiserror::Error;
+use zksync_types::{Address, H256};
+
+use crate::tracers::prestate_tracer::PrestateResult;
+
+/// Gap in a captured prestate, found by [`PrestateResult::validate()`].
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CompletenessError {
+    /// Some of the touched accounts or slots weren't collected because of the limits
+    /// of the config.
+    #[error("prestate is truncated")]
+    Truncated,
+    /// The account has code, e.g. it's a call target, but the code isn't captured.
+    #[error("code of {address:?} with hash {code_hash:?} is not captured")]
+    MissingCode { address: Address, code_hash: H256 },
+    /// Some of the accessed slots of the account were left out by `max_slots_per_account`.
+    #[error("{count} accessed slots of {address:?} are omitted")]
+    OmittedSlots { address: Address, count: usize },
+    /// The factory dependencies are collected, but the bytecode of the account is missing.
+    #[error("factory dependencies lack the bytecode of {address:?} with hash {code_hash:?}")]
+    MissingFactoryDep { address: Address, code_hash: H256 },
+}
+
+impl PrestateResult {
+    /// Checks that the pre-state is complete enough to replay the transaction, e.g. that
+    /// the code of every contract is captured. The gaps are sorted by the account address.
+    ///
+    /// Meant for the results collected outside diff mode, since the unchanged code isn't reported
+    /// in diff mode.
+    pub fn validate(&self) -> Result<(), Vec<CompletenessError>> {
+        let mut errors = vec![];
+        if self.truncated {
+            errors.push(CompletenessError::Truncated);
+        }
+
+        let mut accounts: Vec<_> = self.pre.iter().collect();
+        accounts.sort_unstable_by_key(|(address, _)| **address);
+        for (&address, account) in accounts {
+            let code_hash = account.code_hash.filter(|hash| !hash.is_zero());
+            if let (Some(code_hash), None) = (code_hash, &account.code) {
+                errors.push(CompletenessError::MissingCode { address, code_hash });
+            }
+            if let Some(count) = account.omitted_slots.filter(|&count| count > 0) {
+                errors.push(CompletenessError::OmittedSlots { address, count });
+            }
+            if let (Some(code_hash), Some(factory_deps)) = (code_hash, &self.factory_deps) {
+                if !factory_deps.contains_key(&code_hash) {
+                    errors.push(CompletenessError::MissingFactoryDep { address, code_hash });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::tracers::prestate_tracer::{Account, State};
+
+    #[test]
+    fn missing_call_target_code_is_reported() {
+        let caller = Address::repeat_byte(0x01);
+        let callee = Address::repeat_byte(0x02);
+        let eoa = Address::repeat_byte(0x03);
+        let contract = |code_hash, code| Account {
+            balance: Some(0.into()),
+            code_hash: Some(code_hash),
+            code,
+            storage: Some(HashMap::new()),
+            ..Account::default()
+        };
+        let complete = State::from([
+            (
+                caller,
+                contract(H256::repeat_byte(0xaa), Some(vec![0xaa; 32])),
+            ),
+            (eoa, contract(H256::zero(), None)),
+        ]);
+        let result = PrestateResult::from((complete.clone(), State::new()));
+        assert_eq!(result.validate(), Ok(()));
+
+        // The call target was touched, but its code wasn't captured.
+        let mut incomplete = complete;
+        incomplete.insert(callee, contract(H256::repeat_byte(0xbb), None));
+        let mut result = PrestateResult::from((incomplete, State::new()));
+        let missing_code = CompletenessError::MissingCode {
+            address: callee,
+            code_hash: H256::repeat_byte(0xbb),
+        };
+        assert_eq!(result.validate(), Err(vec![missing_code.clone()]));
+
+        result.factory_deps = Some(HashMap::from([(H256::repeat_byte(0xaa), vec![0xaa; 32])]));
+        result.truncated = true;
+        let missing_dep = CompletenessError::MissingFactoryDep {
+            address: callee,
+            code_hash: H256::repeat_byte(0xbb),
+        };
+        assert_eq!(
+            result.validate(),
+            Err(vec![
+                CompletenessError::Truncated,
+                missing_code,
+                missing_dep
+            ])
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Differences between two captured states, e.g. the prestates of two points of a block.
+
+use std::collections::HashMap;
+
+use zksync_types::{AccountTreeId, Address, H256};
+
+use crate::tracers::prestate_tracer::{
+    cheatcodes::to_cheatcode_script, genesis::to_genesis_alloc, retain_changes, storage_root,
+    Account, AccountTreeState, GenesisAlloc, State,
+};
+
+/// Changes between two states, in the same format as the diff mode of the tracer.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateDiff {
+    /// Accounts present only in the new state.
+    pub added: State,
+    /// Accounts present only in the old state.
+    pub removed: State,
+    /// Changed fields and slots of the accounts present in both states, before the change.
+    pub pre: State,
+    /// Changed fields and slots of the accounts present in both states, after the change.
+    pub post: State,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.pre.is_empty()
+            && self.post.is_empty()
+    }
+}
+
+/// Extension methods of [`State`].
+pub trait StateExt {
+    /// Returns the changes from `self` to the `other` state. Unchanged fields, slots and accounts
+    /// are dropped. A slot present in only one of the states is treated as zero in the other one.
+    ///
+    /// Gas, storage root, code source, decoded storage, init code, omitted slot, protocol role,
+    /// refund, touch reason, balance change and unavailable code annotations of the accounts
+    /// are not a part of the state, so they are ignored.
+    fn diff(&self, other: &State) -> StateDiff;
+
+    /// Converts the state into an allocation of accounts, which can be used to seed a fresh VM.
+    /// The missing balances and nonces are set to zero, and the missing storage is empty.
+    fn to_genesis_alloc(&self) -> GenesisAlloc;
+
+    /// Converts the state to the one keyed by the account IDs.
+    fn to_account_tree_state(&self) -> AccountTreeState;
+
+    /// Returns the Foundry cheatcodes (`vm.deal`, `vm.setNonce`, `vm.etch` and `vm.store`)
+    /// recreating the balances, nonces, code and storage of the state, one call per line.
+    /// The accounts and slots are sorted, so the script is deterministic.
+    ///
+    /// Deployment nonces have no cheatcode, so they are left out. The code is zkEVM bytecode,
+    /// so it can only be executed by a zkSync-enabled Foundry.
+    fn to_cheatcode_script(&self) -> String;
+
+    /// Returns the state with only the slots matching the `predicate`, e.g. the ones of a single
+    /// storage variable across all the accounts. The accounts left without slots are dropped,
+    /// while the rest keep their balances, nonces and code.
+    ///
+    /// The annotations of the dropped slots are removed as well, and the storage roots are
+    /// recomputed for the remaining slots.
+    fn project(&self, predicate: impl Fn(&Address, &H256) -> bool) -> State;
+}
+
+impl StateExt for State {
+    fn diff(&self, other: &State) -> StateDiff {
+        let mut diff = StateDiff::default();
+        for (address, account) in self {
+            if let Some(other_account) = other.get(address) {
+                let (pre, post) = zero_missing_slots(account, other_account);
+                diff.pre.insert(*address, pre);
+                diff.post.insert(*address, post);
+            } else {
+                diff.removed.insert(*address, account.clone());
+            }
+        }
+        diff.added = other
+            .iter()
+            .filter(|(address, _)| !self.contains_key(address))
+            .map(|(address, account)| (*address, account.clone()))
+            .collect();
+        retain_changes(&mut diff.pre, &mut diff.post);
+        diff
+    }
+
+    fn to_genesis_alloc(&self) -> GenesisAlloc {
+        to_genesis_alloc(self)
+    }
+
+    fn to_account_tree_state(&self) -> AccountTreeState {
+        self.iter()
+            .map(|(address, account)| (AccountTreeId::new(*address), account.clone()))
+            .collect()
+    }
+
+    fn to_cheatcode_script(&self) -> String {
+        to_cheatcode_script(self)
+    }
+
+    fn project(&self, predicate: impl Fn(&Address, &H256) -> bool) -> State {
+        self.iter()
+            .filter_map(|(address, account)| {
+                let matches = |slot: &H256| predicate(address, slot);
+                let storage: HashMap<_, _> = account
+                    .storage
+                    .iter()
+                    .flatten()
+                    .filter(|(slot, _)| matches(slot))
+                    .map(|(slot, value)| (*slot, *value))
+                    .collect();
+                if storage.is_empty() {
+                    return None;
+                }
+                let mut account = account.clone();
+                retain_slots(&mut account.storage_preimages, matches);
+                retain_slots(&mut account.storage_gas_remaining, matches);
+                retain_slots(&mut account.storage_decoded, matches);
+                retain_slots(&mut account.storage_refunds, matches);
+                if account.storage_root.is_some() {
+                    account.storage_root = Some(storage_root(&storage));
+                }
+                account.storage = Some(storage);
+                Some((*address, account))
+            })
+            .collect()
+    }
+}
+
+fn retain_slots<T>(slots: &mut Option<HashMap<H256, T>>, matches: impl Fn(&H256) -> bool) {
+    if let Some(slots) = slots {
+        slots.retain(|slot, _| matches(slot));
+    }
+}
+
+/// Converts the state keyed by the account IDs back to the one keyed by the addresses,
+/// e.g. to serialize it to JSON.
+pub fn state_from_account_tree_state(state: AccountTreeState) -> State {
+    state
+        .into_iter()
+        .map(|(account_id, account)| (*account_id.address(), account))
+        .collect()
+}
+
+/// Returns the copies of the accounts without the gas, storage root, code source, decoded
+/// storage, init code, omitted slot, protocol role, refund, touch reason, balance change
+/// and unavailable code annotations, with the slots missing from one of them set to zero.
+fn zero_missing_slots(pre: &Account, post: &Account) -> (Account, Account) {
+    let strip_annotations = |account: &Account| Account {
+        storage_gas_remaining: None,
+        gas_used: None,
+        storage_root: None,
+        code_source: None,
+        storage_decoded: None,
+        init_code: None,
+        omitted_slots: None,
+        protocol_role: None,
+        storage_refunds: None,
+        touch_reasons: None,
+        balance_change: None,
+        code_unavailable: None,
+        code_truncated: None,
+        ..account.clone()
+    };
+    let (mut pre, mut post) = (strip_annotations(pre), strip_annotations(post));
+    if pre.storage.is_none() && post.storage.is_none() {
+        return (pre, post);
+    }
+
+    let pre_storage = pre.storage.get_or_insert_with(Default::default);
+    let post_storage = post.storage.get_or_insert_with(Default::default);
+    for slot in pre_storage.keys() {
+        post_storage.entry(*slot).or_insert_with(H256::zero);
+    }
+    for slot in post_storage.keys() {
+        pre_storage.entry(*slot).or_insert_with(H256::zero);
+    }
+    (pre, post)
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_types::U256;
+
+    use super::*;
+
+    fn account(slots: &[(u64, u64)]) -> Account {
+        let storage = slots
+            .iter()
+            .map(|&(slot, value)| (H256::from_low_u64_be(slot), H256::from_low_u64_be(value)));
+        Account {
+            balance: Some(100.into()),
+            nonce: Some(U256::one()),
+            storage: Some(storage.collect()),
+            ..Account::default()
+        }
+    }
+
+    #[test]
+    fn diff_contains_only_changed_slot() {
+        let address = Address::repeat_byte(0x01);
+        let unchanged = Address::repeat_byte(0x02);
+        let old = State::from([
+            (address, account(&[(0, 1), (1, 2)])),
+            (unchanged, account(&[(0, 1)])),
+        ]);
+        let new = State::from([
+            (address, account(&[(0, 1), (1, 3)])),
+            (unchanged, account(&[(0, 1)])),
+        ]);
+
+        let diff = old.diff(&new);
+        let slot = H256::from_low_u64_be(1);
+        let expected_pre = Account {
+            storage: Some(HashMap::from([(slot, H256::from_low_u64_be(2))])),
+            ..Account::default()
+        };
+        let expected_post = Account {
+            storage: Some(HashMap::from([(slot, H256::from_low_u64_be(3))])),
+            ..Account::default()
+        };
+        assert_eq!(diff.pre, State::from([(address, expected_pre)]));
+        assert_eq!(diff.post, State::from([(address, expected_post)]));
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn missing_slots_are_treated_as_zero() {
+        let address = Address::repeat_byte(0x01);
+        let old = State::from([(address, account(&[(0, 0), (1, 1)]))]);
+        let new = State::from([(address, account(&[(2, 0)]))]);
+
+        let diff = old.diff(&new);
+        let slot = H256::from_low_u64_be(1);
+        assert_eq!(
+            diff.pre[&address].storage,
+            Some(HashMap::from([(slot, H256::from_low_u64_be(1))]))
+        );
+        // Slots set to zero are omitted from the post-state, like in the diff mode of the tracer.
+        assert_eq!(diff.post[&address].storage, Some(HashMap::new()));
+    }
+
+    #[test]
+    fn state_round_trips_through_account_tree_ids() {
+        let state = State::from([
+            (Address::repeat_byte(0x01), account(&[(0, 1)])),
+            (Address::repeat_byte(0x02), account(&[])),
+        ]);
+
+        let tree_state = state.to_account_tree_state();
+        let account_id = AccountTreeId::new(Address::repeat_byte(0x01));
+        assert_eq!(tree_state[&account_id], state[&Address::repeat_byte(0x01)]);
+        assert_eq!(tree_state.len(), 2);
+        assert_eq!(state_from_account_tree_state(tree_state), state);
+    }
+
+    #[test]
+    fn projection_keeps_only_matching_slots() {
+        let address = Address::repeat_byte(0x01);
+        let other = Address::repeat_byte(0x02);
+        let without_slot = Address::repeat_byte(0x03);
+        let state = State::from([
+            (address, account(&[(0, 1), (1, 2)])),
+            (other, account(&[(1, 3)])),
+            (without_slot, account(&[(0, 4)])),
+        ]);
+
+        let slot = H256::from_low_u64_be(1);
+        let projected = state.project(|_, key| *key == slot);
+        let expected_account = |value: u64| Account {
+            storage: Some(HashMap::from([(slot, H256::from_low_u64_be(value))])),
+            ..account(&[])
+        };
+        let expected = State::from([(address, expected_account(2)), (other, expected_account(3))]);
+        assert_eq!(projected, expected);
+
+        let projected = state.project(|account, key| *account == other && *key == slot);
+        assert_eq!(projected, State::from([(other, expected_account(3))]));
+    }
+
+    #[test]
+    fn added_and_removed_accounts() {
+        let old = State::from([(Address::repeat_byte(0x01), account(&[]))]);
+        let new = State::from([(Address::repeat_byte(0x02), account(&[]))]);
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.removed, old);
+        assert_eq!(diff.added, new);
+        assert!(diff.pre.is_empty() && diff.post.is_empty());
+    }
+}
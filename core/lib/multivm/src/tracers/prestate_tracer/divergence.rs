@@ -0,0 +1,237 @@
+//! Explanation of the differences between two results of the tracer, e.g. of the same transaction
+//! traced by two VM versions.
+
+use std::collections::{BTreeSet, HashMap};
+
+use zksync_types::{Address, H256};
+
+use crate::tracers::prestate_tracer::{Account, PrestateResult, State};
+
+/// State of a result which the divergence was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DivergentState {
+    Pre,
+    Post,
+}
+
+/// Part of an account which differs between the results.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DivergencePath {
+    /// The account is only reported in one of the results.
+    Account,
+    /// Field of the account, named like in the JSON output, e.g. `balance`.
+    Field(String),
+    /// Storage slot of the account.
+    Slot(H256),
+}
+
+/// Single difference between two results. The values are formatted like in the JSON output,
+/// and are `None` if the account, field or slot isn't reported in the corresponding result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub state: DivergentState,
+    pub address: Address,
+    pub path: DivergencePath,
+    pub this: Option<serde_json::Value>,
+    pub other: Option<serde_json::Value>,
+}
+
+impl PrestateResult {
+    /// Lists the differences between the accounts of `self` and the `other` result, sorted
+    /// by the state, the address and the path. The post-state of a result without one is treated
+    /// as empty.
+    ///
+    /// Only the states are compared: the VM version and the rest of the metadata are expected
+    /// to differ, e.g. for the traces of the same transaction by two VM versions.
+    pub fn explain_diff(&self, other: &PrestateResult) -> Vec<Divergence> {
+        let mut divergences = vec![];
+        compare_states(DivergentState::Pre, &self.pre, &other.pre, &mut divergences);
+        let empty = State::new();
+        compare_states(
+            DivergentState::Post,
+            self.post.as_deref().unwrap_or(&empty),
+            other.post.as_deref().unwrap_or(&empty),
+            &mut divergences,
+        );
+        divergences
+            .sort_by(|a, b| (a.state, a.address, &a.path).cmp(&(b.state, b.address, &b.path)));
+        divergences
+    }
+}
+
+pub(super) fn compare_states(
+    state: DivergentState,
+    this: &State,
+    other: &State,
+    divergences: &mut Vec<Divergence>,
+) {
+    let addresses: BTreeSet<_> = this.keys().chain(other.keys()).collect();
+    for address in addresses {
+        match (this.get(address), other.get(address)) {
+            (Some(this), Some(other)) => {
+                compare_accounts(state, *address, this, other, divergences);
+            }
+            (this, other) => divergences.push(Divergence {
+                state,
+                address: *address,
+                path: DivergencePath::Account,
+                this: this.map(account_to_json),
+                other: other.map(account_to_json),
+            }),
+        }
+    }
+}
+
+fn compare_accounts(
+    state: DivergentState,
+    address: Address,
+    this: &Account,
+    other: &Account,
+    divergences: &mut Vec<Divergence>,
+) {
+    // The fields are compared via the JSON output, so that the new fields are covered as well.
+    let fields = |account: &Account| match account_to_json(account) {
+        serde_json::Value::Object(fields) => fields,
+        _ => unreachable!("account is serialized as an object"),
+    };
+    let (mut this_fields, mut other_fields) = (fields(this), fields(other));
+    let names: BTreeSet<_> = this_fields
+        .keys()
+        .chain(other_fields.keys())
+        .filter(|name| *name != "storage")
+        .cloned()
+        .collect();
+    for name in names {
+        let (this_value, other_value) = (this_fields.remove(&name), other_fields.remove(&name));
+        if this_value != other_value {
+            divergences.push(Divergence {
+                state,
+                address,
+                path: DivergencePath::Field(name),
+                this: this_value,
+                other: other_value,
+            });
+        }
+    }
+
+    // The slots are compared one by one, so that a single changed slot doesn't hide the rest.
+    let empty = HashMap::new();
+    let this_storage = this.storage.as_ref().unwrap_or(&empty);
+    let other_storage = other.storage.as_ref().unwrap_or(&empty);
+    let slots: BTreeSet<_> = this_storage.keys().chain(other_storage.keys()).collect();
+    for slot in slots {
+        let (this_value, other_value) = (this_storage.get(slot), other_storage.get(slot));
+        if this_value != other_value {
+            divergences.push(Divergence {
+                state,
+                address,
+                path: DivergencePath::Slot(*slot),
+                this: this_value.map(|value| serde_json::json!(value)),
+                other: other_value.map(|value| serde_json::json!(value)),
+            });
+        }
+    }
+    if this.storage.is_some() != other.storage.is_some() {
+        // E.g. an empty storage is reported for a contract, but not for an EOA.
+        divergences.push(Divergence {
+            state,
+            address,
+            path: DivergencePath::Field("storage".to_owned()),
+            this: this.storage.is_some().then(|| serde_json::json!({})),
+            other: other.storage.is_some().then(|| serde_json::json!({})),
+        });
+    }
+}
+
+fn account_to_json(account: &Account) -> serde_json::Value {
+    serde_json::to_value(account).expect("failed to serialize account")
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_types::U256;
+
+    use super::*;
+
+    #[test]
+    fn divergences_are_enumerated() {
+        let same = Address::repeat_byte(0x01);
+        let changed = Address::repeat_byte(0x02);
+        let missing = Address::repeat_byte(0x03);
+        let slot = |slot: u64| H256::from_low_u64_be(slot);
+        let account = Account {
+            balance: Some(100.into()),
+            nonce: Some(U256::one()),
+            storage: Some(HashMap::from([(slot(0), slot(1)), (slot(1), slot(2))])),
+            ..Account::default()
+        };
+        let changed_account = Account {
+            balance: Some(101.into()),
+            code_hash: Some(H256::repeat_byte(0xcc)),
+            storage: Some(HashMap::from([(slot(0), slot(1)), (slot(2), slot(3))])),
+            ..account.clone()
+        };
+        let this = PrestateResult::from((
+            State::from([
+                (same, account.clone()),
+                (changed, account.clone()),
+                (missing, account.clone()),
+            ]),
+            State::from([(same, account.clone())]),
+        ));
+        let other = PrestateResult::from((
+            State::from([(same, account.clone()), (changed, changed_account)]),
+            State::from([(same, account.clone())]),
+        ));
+
+        let divergence = |path, this, other| Divergence {
+            state: DivergentState::Pre,
+            address: changed,
+            path,
+            this,
+            other,
+        };
+        let expected = vec![
+            divergence(
+                DivergencePath::Field("balance".to_owned()),
+                Some(serde_json::json!("0x64")),
+                Some(serde_json::json!("0x65")),
+            ),
+            divergence(
+                DivergencePath::Field("codeHash".to_owned()),
+                None,
+                Some(serde_json::json!(H256::repeat_byte(0xcc))),
+            ),
+            divergence(
+                DivergencePath::Slot(slot(1)),
+                Some(serde_json::json!(slot(2))),
+                None,
+            ),
+            divergence(
+                DivergencePath::Slot(slot(2)),
+                None,
+                Some(serde_json::json!(slot(3))),
+            ),
+            Divergence {
+                state: DivergentState::Pre,
+                address: missing,
+                path: DivergencePath::Account,
+                this: Some(account_to_json(&account)),
+                other: None,
+            },
+        ];
+        assert_eq!(this.explain_diff(&other), expected);
+        assert!(this.explain_diff(&this).is_empty());
+
+        // A result without the post-state diverges in all the post-state accounts.
+        let without_post = PrestateResult {
+            post: None,
+            ..this.clone()
+        };
+        let divergences = without_post.explain_diff(&this);
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].state, DivergentState::Post);
+        assert_eq!(divergences[0].path, DivergencePath::Account);
+        assert_eq!(divergences[0].this, None);
+    }
+}
@@ -0,0 +1,179 @@
+//! Split of the balance change of the fee payer into the transferred value and the fee,
+//! which are combined in the reported balance.
+
+use std::{fmt, ops};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use zksync_types::{Address, U256};
+
+use crate::tracers::prestate_tracer::State;
+
+/// Signed change of a balance. Serialized as a hex quantity with an optional minus sign,
+/// e.g. `-0x5`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BalanceDelta {
+    pub amount: U256,
+    /// Whether the balance has decreased. Always `false` for a zero `amount`.
+    pub is_negative: bool,
+}
+
+impl BalanceDelta {
+    /// Returns the change from the `old` balance to the `new` one.
+    pub fn between(old: U256, new: U256) -> Self {
+        if new >= old {
+            Self::increase(new - old)
+        } else {
+            Self::decrease(old - new)
+        }
+    }
+
+    pub fn increase(amount: U256) -> Self {
+        Self {
+            amount,
+            is_negative: false,
+        }
+    }
+
+    pub fn decrease(amount: U256) -> Self {
+        Self {
+            amount,
+            is_negative: !amount.is_zero(),
+        }
+    }
+}
+
+/// Saturates if the sum doesn't fit into 256 bits.
+impl ops::Add for BalanceDelta {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        if self.is_negative == other.is_negative {
+            let amount = self.amount.saturating_add(other.amount);
+            return Self {
+                amount,
+                is_negative: self.is_negative,
+            };
+        }
+        let (positive, negative) = if self.is_negative {
+            (other.amount, self.amount)
+        } else {
+            (self.amount, other.amount)
+        };
+        Self::between(negative, positive)
+    }
+}
+
+impl fmt::Display for BalanceDelta {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.is_negative { "-" } else { "" };
+        write!(formatter, "{sign}{:#x}", self.amount)
+    }
+}
+
+impl Serialize for BalanceDelta {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BalanceDelta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let (is_negative, hex) = match value.strip_prefix('-') {
+            Some(hex) => (true, hex),
+            None => (false, value.as_str()),
+        };
+        let hex = hex
+            .strip_prefix("0x")
+            .ok_or_else(|| de::Error::custom(format!("missing 0x prefix in {value:?}")))?;
+        let amount = U256::from_str_radix(hex, 16).map_err(de::Error::custom)?;
+        Ok(if is_negative {
+            Self::decrease(amount)
+        } else {
+            Self::increase(amount)
+        })
+    }
+}
+
+/// Balance change of the fee payer, split into the fee and the rest of it, e.g. the value
+/// transferred by the transaction. The reported balance is the sum of both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceChange {
+    /// Change of the balance excluding the fee.
+    pub value_delta: BalanceDelta,
+    /// Change of the balance caused by the fee, net of the refund. Never positive.
+    pub fee_delta: BalanceDelta,
+}
+
+impl ops::Add for BalanceChange {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            value_delta: self.value_delta + other.value_delta,
+            fee_delta: self.fee_delta + other.fee_delta,
+        }
+    }
+}
+
+/// Annotates the post-state account of the `payer` with the split of its balance change,
+/// given the `fee` it has paid. The payer is skipped if its balance isn't reported.
+pub(super) fn annotate_fee(pre: &State, post: &mut State, payer: &Address, fee: U256) {
+    let Some(account) = post.get_mut(payer) else {
+        return;
+    };
+    let Some(post_balance) = account.balance else {
+        return;
+    };
+    // The unchanged balance is only reported in one of the states.
+    let pre_balance = pre
+        .get(payer)
+        .and_then(|account| account.balance)
+        .unwrap_or(post_balance);
+    account.balance_change = Some(BalanceChange {
+        value_delta: BalanceDelta::between(pre_balance, post_balance.saturating_add(fee)),
+        fee_delta: BalanceDelta::decrease(fee),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracers::prestate_tracer::Account;
+
+    #[test]
+    fn balance_change_is_split_into_value_and_fee() {
+        let payer = Address::repeat_byte(0x01);
+        let account = |balance: u64| Account {
+            balance: Some(balance.into()),
+            ..Account::default()
+        };
+        let pre = State::from([(payer, account(1_000))]);
+        let mut post = State::from([(payer, account(850))]);
+
+        annotate_fee(&pre, &mut post, &payer, 50.into());
+        let change = post[&payer].balance_change.unwrap();
+        assert_eq!(change.value_delta, BalanceDelta::decrease(100.into()));
+        assert_eq!(change.fee_delta, BalanceDelta::decrease(50.into()));
+        let json = serde_json::to_value(change).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "valueDelta": "-0x64", "feeDelta": "-0x32" })
+        );
+        assert_eq!(
+            serde_json::from_value::<BalanceChange>(json).unwrap(),
+            change
+        );
+
+        // The changes of several transactions add up.
+        let received = BalanceChange {
+            value_delta: BalanceDelta::increase(150.into()),
+            fee_delta: BalanceDelta::default(),
+        };
+        let total = change + received;
+        assert_eq!(total.value_delta, BalanceDelta::increase(50.into()));
+        assert_eq!(total.fee_delta, BalanceDelta::decrease(50.into()));
+        assert_eq!(BalanceDelta::increase(5.into()).to_string(), "0x5");
+    }
+}
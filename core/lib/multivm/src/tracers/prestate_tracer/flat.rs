@@ -0,0 +1,164 @@
+//! Flat representation of the diff mode result, listing the changed slots and fields
+//! without the nested account objects, e.g. for the bandwidth-sensitive clients.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Serialize;
+use zksync_types::{Address, H256};
+
+use crate::tracers::prestate_tracer::{Account, PrestateResult};
+
+/// Fields of an account listed in the flat changes, named like in the JSON output
+/// and sorted by the name.
+pub(super) const FLAT_FIELDS: [&str; 5] =
+    ["balance", "code", "codeHash", "deploymentNonce", "nonce"];
+
+/// Change of a storage slot: `(address, slot, old, new)`. The missing values are zero.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SlotChange(pub Address, pub H256, pub H256, pub H256);
+
+/// Change of an account field: `(address, field, old, new)`. The values are formatted like
+/// in the JSON output, and are `None` if the account didn't exist before or after the change.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange(
+    pub Address,
+    pub String,
+    pub Option<serde_json::Value>,
+    pub Option<serde_json::Value>,
+);
+
+/// Changes of a diff mode result, see [`PrestateResult::to_flat_changes()`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FlatChanges {
+    pub slots: Vec<SlotChange>,
+    pub fields: Vec<FieldChange>,
+}
+
+impl PrestateResult {
+    /// Lists the changed slots and the changed balances, nonces and code of the accounts,
+    /// sorted by the address and then by the slot or the field. Returns `None` if the result
+    /// has no post-state, i.e. it wasn't collected in diff mode.
+    ///
+    /// The annotations of the accounts, e.g. the gas or the touch reasons, aren't listed.
+    pub fn to_flat_changes(&self) -> Option<FlatChanges> {
+        let post = self.post.as_ref()?;
+        let mut changes = FlatChanges::default();
+        let addresses: BTreeSet<_> = self.pre.keys().chain(post.keys()).collect();
+        for address in addresses {
+            let (pre, post) = (self.pre.get(address), post.get(address));
+            push_field_changes(*address, pre, post, &mut changes.fields);
+
+            let empty = HashMap::new();
+            let storage = |account: Option<&Account>| {
+                account
+                    .and_then(|account| account.storage.as_ref())
+                    .unwrap_or(&empty)
+            };
+            let (pre_storage, post_storage) = (storage(pre), storage(post));
+            let slots: BTreeSet<_> = pre_storage.keys().chain(post_storage.keys()).collect();
+            for slot in slots {
+                let old = pre_storage.get(slot).copied().unwrap_or_default();
+                let new = post_storage.get(slot).copied().unwrap_or_default();
+                if old != new {
+                    changes.slots.push(SlotChange(*address, *slot, old, new));
+                }
+            }
+        }
+        Some(changes)
+    }
+}
+
+fn push_field_changes(
+    address: Address,
+    pre: Option<&Account>,
+    post: Option<&Account>,
+    changes: &mut Vec<FieldChange>,
+) {
+    let fields = |account: Option<&Account>| {
+        let json = account
+            .map(|account| serde_json::to_value(account).expect("failed to serialize account"));
+        match json {
+            Some(serde_json::Value::Object(fields)) => fields,
+            Some(_) => unreachable!("account is serialized as an object"),
+            None => serde_json::Map::new(),
+        }
+    };
+    let (mut pre_fields, mut post_fields) = (fields(pre), fields(post));
+    for name in FLAT_FIELDS {
+        let (old, new) = (pre_fields.remove(name), post_fields.remove(name));
+        // In diff mode, a field is only reported in both states if it has changed.
+        if old != new {
+            changes.push(FieldChange(address, name.to_owned(), old, new));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracers::prestate_tracer::State;
+
+    #[test]
+    fn one_slot_change_is_single_tuple() {
+        let address = Address::repeat_byte(0x01);
+        let slot = H256::from_low_u64_be(1);
+        let account = |value: u64| Account {
+            storage: Some(HashMap::from([(slot, H256::from_low_u64_be(value))])),
+            ..Account::default()
+        };
+        let result = PrestateResult::from((
+            State::from([(address, account(1))]),
+            State::from([(address, account(2))]),
+        ));
+
+        let changes = result.to_flat_changes().unwrap();
+        let expected = SlotChange(
+            address,
+            slot,
+            H256::from_low_u64_be(1),
+            H256::from_low_u64_be(2),
+        );
+        assert_eq!(changes.slots, [expected.clone()]);
+        assert!(changes.fields.is_empty());
+        assert_eq!(
+            serde_json::to_value(&changes).unwrap(),
+            serde_json::json!({ "slots": [expected], "fields": [] })
+        );
+        assert_eq!(
+            serde_json::to_value(&expected).unwrap(),
+            serde_json::json!([
+                address,
+                slot,
+                H256::from_low_u64_be(1),
+                H256::from_low_u64_be(2)
+            ])
+        );
+
+        // A field is listed with the missing value for an account created by the transaction.
+        let created = PrestateResult::from((
+            State::new(),
+            State::from([(
+                address,
+                Account {
+                    balance: Some(5.into()),
+                    ..Account::default()
+                },
+            )]),
+        ));
+        let changes = created.to_flat_changes().unwrap();
+        assert_eq!(
+            changes.fields,
+            [FieldChange(
+                address,
+                "balance".to_owned(),
+                None,
+                Some(serde_json::json!("0x5"))
+            )]
+        );
+        let without_post = PrestateResult {
+            post: None,
+            ..result
+        };
+        assert_eq!(without_post.to_flat_changes(), None);
+    }
+}
@@ -0,0 +1,126 @@
+//! Conversion of a captured state into an allocation of accounts, like the `alloc` section
+//! of a Geth genesis file, so that the traced transactions can be replayed on a fresh VM.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use zksync_types::{Address, H256, U256};
+
+use crate::tracers::prestate_tracer::{AccountOverride, State};
+
+/// Allocation of the accounts, keyed by the account address.
+pub type GenesisAlloc = HashMap<Address, GenesisAccount>;
+
+/// Account of a [`GenesisAlloc`]. Unlike [`Account`](super::Account), all the fields except for
+/// the code are always set, with the missing ones defaulting to zero or empty values.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    pub balance: U256,
+    /// Transaction nonce of the account.
+    pub nonce: U256,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "super::bytecode_as_hex"
+    )]
+    pub code: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub storage: HashMap<H256, H256>,
+}
+
+/// Seeds the account with [`apply_state_override()`](super::apply_state_override).
+/// Only the allocated slots are written, the rest of the account storage is left intact.
+impl From<GenesisAccount> for AccountOverride {
+    fn from(account: GenesisAccount) -> Self {
+        Self {
+            balance: Some(account.balance),
+            nonce: Some(account.nonce),
+            code: account.code,
+            state: None,
+            state_diff: Some(account.storage),
+        }
+    }
+}
+
+pub(super) fn to_genesis_alloc(state: &State) -> GenesisAlloc {
+    state
+        .iter()
+        .map(|(address, account)| {
+            let genesis_account = GenesisAccount {
+                balance: account.balance.unwrap_or_default(),
+                nonce: account.nonce.unwrap_or_default(),
+                code: account.code.clone(),
+                storage: account.storage.clone().unwrap_or_default(),
+            };
+            (*address, genesis_account)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_state::{InMemoryStorage, ReadStorage, StorageView};
+    use zksync_types::{get_nonce_key, AccountTreeId, StorageKey, L2_ETH_TOKEN_ADDRESS};
+    use zksync_utils::{h256_to_u256, u256_to_h256};
+
+    use super::*;
+    use crate::tracers::prestate_tracer::{
+        apply_state_override, decode_nonce, get_balance_key, Account, StateExt, StateOverride,
+    };
+
+    #[test]
+    fn state_is_converted_to_alloc() {
+        let contract = Address::repeat_byte(0x01);
+        let eoa = Address::repeat_byte(0x02);
+        let code = vec![0; 32];
+        let slot = H256::from_low_u64_be(1);
+        let state = State::from([
+            (
+                contract,
+                Account {
+                    balance: Some(100.into()),
+                    nonce: Some(U256::zero()),
+                    code: Some(code.clone()),
+                    code_hash: Some(H256::repeat_byte(0xcc)),
+                    storage: Some(HashMap::from([(slot, H256::from_low_u64_be(2))])),
+                    ..Account::default()
+                },
+            ),
+            (
+                eoa,
+                Account {
+                    balance: Some(5.into()),
+                    nonce: Some(3.into()),
+                    ..Account::default()
+                },
+            ),
+        ]);
+
+        let alloc = state.to_genesis_alloc();
+        assert_eq!(alloc[&contract].balance, 100.into());
+        assert_eq!(alloc[&contract].code, Some(code));
+        assert_eq!(alloc[&contract].storage[&slot], H256::from_low_u64_be(2));
+        assert_eq!(alloc[&eoa].nonce, 3.into());
+        assert_eq!(alloc[&eoa].code, None);
+        assert!(alloc[&eoa].storage.is_empty());
+        let json = serde_json::to_value(&alloc[&eoa]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "balance": "0x5", "nonce": "0x3" })
+        );
+
+        // The allocation seeds the storage of a fresh VM.
+        let overrides: StateOverride = alloc
+            .into_iter()
+            .map(|(address, account)| (address, account.into()))
+            .collect();
+        let mut storage = StorageView::new(InMemoryStorage::default());
+        apply_state_override(&mut storage, &overrides, L2_ETH_TOKEN_ADDRESS).unwrap();
+        let balance = storage.read_value(&get_balance_key(&AccountTreeId::new(contract)));
+        assert_eq!(h256_to_u256(balance), 100.into());
+        let nonce = storage.read_value(&get_nonce_key(&eoa));
+        assert_eq!(decode_nonce(h256_to_u256(nonce)), (3, 0));
+        let key = StorageKey::new(AccountTreeId::new(contract), slot);
+        assert_eq!(storage.read_value(&key), u256_to_h256(2.into()));
+    }
+}
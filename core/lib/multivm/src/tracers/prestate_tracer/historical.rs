@@ -0,0 +1,111 @@
+//! Prestate of a transaction traced against the historical state of the chain, i.e. as it was
+//! executed in its original block rather than on top of the current state.
+
+use zksync_state::{ReadStorage, StoragePtr, StorageView};
+use zksync_types::{block::MiniblockExecutionData, L1BatchNumber, MiniblockNumber, Transaction};
+
+use crate::{
+    interface::{L1BatchEnv, L2BlockEnv, SystemEnv, VmExecutionMode, VmInterface},
+    tracers::prestate_tracer::{
+        trace_transaction_prestate, PrestateResult, PrestateTracerConfig, PrestateTracerError,
+    },
+    HistoryMode,
+};
+
+/// Height of the chain state, i.e. the state right after the given L2 block or L1 batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StateHeight {
+    L2Block(MiniblockNumber),
+    L1Batch(L1BatchNumber),
+}
+
+impl StateHeight {
+    /// Returns the height of the state which the block or the batch was executed on top of,
+    /// i.e. the state after the previous one. Returns `None` for the genesis.
+    pub fn parent(self) -> Option<Self> {
+        match self {
+            Self::L2Block(number) => Some(Self::L2Block(MiniblockNumber(number.0.checked_sub(1)?))),
+            Self::L1Batch(number) => Some(Self::L1Batch(L1BatchNumber(number.0.checked_sub(1)?))),
+        }
+    }
+}
+
+/// Source of the snapshots of the historical state, e.g. the storage logs in Postgres.
+pub trait HistoricalStorage {
+    type Snapshot: ReadStorage;
+
+    /// Returns the state right after the block or the batch at the `height` is applied.
+    fn snapshot_at(&self, height: StateHeight) -> anyhow::Result<Self::Snapshot>;
+}
+
+/// Traces the `transaction` as it was executed in the `block` (an L2 block or an L1 batch),
+/// with a prestate tracer built from the `config` for the `system_env`, see
+/// [`PrestateTracerConfig::for_system_env()`].
+///
+/// The VM is created with the environment of the block on top of the state after the previous
+/// block, so the balances, nonces and code are read as of that height. The `preceding_l2_blocks`
+/// are the L2 blocks of the block up to the one of the `transaction`, with the transactions
+/// preceding it. They are executed without tracing in their original order: the first one in
+/// the `first_l2_block` of the `batch_env`, and each next one after starting it in the VM.
+/// An L2 `block` consists of the first L2 block only.
+pub fn trace_historical_transaction_prestate<T, H, V>(
+    source: &T,
+    block: StateHeight,
+    preceding_l2_blocks: impl IntoIterator<Item = MiniblockExecutionData>,
+    transaction: Transaction,
+    batch_env: L1BatchEnv,
+    system_env: SystemEnv,
+    config: &PrestateTracerConfig,
+) -> Result<PrestateResult, PrestateTracerError>
+where
+    T: HistoricalStorage,
+    H: HistoryMode,
+    V: VmInterface<StorageView<T::Snapshot>, H>,
+{
+    let height = block
+        .parent()
+        .ok_or(PrestateTracerError::NoParentState(block))?;
+    let snapshot =
+        source
+            .snapshot_at(height)
+            .map_err(|err| PrestateTracerError::SnapshotUnavailable {
+                height,
+                reason: format!("{err:#}"),
+            })?;
+    let storage: StoragePtr<_> = StorageView::new(snapshot).to_rc_ptr();
+    let config = config.for_system_env(&system_env);
+    let mut vm = V::new(batch_env, system_env, storage);
+    for (i, l2_block) in preceding_l2_blocks.into_iter().enumerate() {
+        if i > 0 {
+            if let StateHeight::L2Block(_) = block {
+                return Err(PrestateTracerError::UnexpectedL2Block(l2_block.number));
+            }
+            vm.start_new_l2_block(L2BlockEnv::from_miniblock_data(&l2_block));
+        }
+        for tx in l2_block.txs {
+            vm.push_transaction(tx);
+            vm.execute(VmExecutionMode::OneTx);
+        }
+    }
+    vm.push_transaction(transaction);
+    let (_, result) = trace_transaction_prestate(&mut vm, &config)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parent_height_is_previous_block() {
+        assert_eq!(
+            StateHeight::L2Block(MiniblockNumber(5)).parent(),
+            Some(StateHeight::L2Block(MiniblockNumber(4)))
+        );
+        assert_eq!(
+            StateHeight::L1Batch(L1BatchNumber(1)).parent(),
+            Some(StateHeight::L1Batch(L1BatchNumber(0)))
+        );
+        assert_eq!(StateHeight::L2Block(MiniblockNumber(0)).parent(), None);
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use vise::{Buckets, Counter, Histogram, Metrics};
+
+#[derive(Debug, Metrics)]
+#[metrics(prefix = "vm_prestate_tracer")]
+pub struct PrestateTracerMetrics {
+    /// Number of accounts collected by the tracer.
+    pub accounts_processed: Counter,
+    /// Number of storage slots collected for a transaction.
+    #[metrics(buckets = Buckets::exponential(1.0..=4096.0, 2.0))]
+    pub slots_per_transaction: Histogram<usize>,
+    /// Time spent on collecting the state of the accounts.
+    #[metrics(buckets = Buckets::LATENCIES)]
+    pub collection_duration: Histogram<Duration>,
+}
+
+#[vise::register]
+pub static PRESTATE_TRACER_METRICS: vise::Global<PrestateTracerMetrics> = vise::Global::new();
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use zksync_types::{AccountTreeId, Address, StorageKey, H256};
+
+    use super::PRESTATE_TRACER_METRICS;
+    use crate::tracers::prestate_tracer::{
+        process_modified_storage_keys, testing::fixtures::MockReader, PrestateTracerConfig, State,
+    };
+
+    #[test]
+    fn metrics_are_reported_when_enabled() {
+        let modified_storage_keys: HashMap<_, _> = (1..=3)
+            .map(|account| {
+                let key = StorageKey::new(
+                    AccountTreeId::new(Address::repeat_byte(account)),
+                    H256::zero(),
+                );
+                (key, H256::zero())
+            })
+            .collect();
+        let accounts_processed = || PRESTATE_TRACER_METRICS.accounts_processed.get();
+
+        let before = accounts_processed();
+        process_modified_storage_keys(
+            State::new(),
+            &modified_storage_keys,
+            &PrestateTracerConfig::default(),
+            &mut MockReader::default(),
+        );
+        assert_eq!(accounts_processed(), before);
+
+        let config = PrestateTracerConfig::builder().metrics(true).build();
+        process_modified_storage_keys(
+            State::new(),
+            &modified_storage_keys,
+            &config,
+            &mut MockReader::default(),
+        );
+        assert_eq!(accounts_processed(), before + 3);
+    }
+}
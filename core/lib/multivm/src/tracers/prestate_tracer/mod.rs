@@ -0,0 +1,3812 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt, ops,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Instant,
+};
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zk_evm_1_4_1::zkevm_opcode_defs::system_params::INITIAL_STORAGE_WRITE_PUBDATA_BYTES;
+use zksync_state::{StoragePtr, WriteStorage};
+use zksync_system_constants::{
+    ACCOUNT_CODE_STORAGE_ADDRESS, BOOTLOADER_ADDRESS, CURRENT_VIRTUAL_BLOCK_INFO_POSITION,
+    SYSTEM_CONTEXT_ADDRESS, SYSTEM_CONTEXT_BASE_FEE_POSITION, SYSTEM_CONTEXT_BLOCK_HASH_POSITION,
+    SYSTEM_CONTEXT_BLOCK_INFO_POSITION, SYSTEM_CONTEXT_CURRENT_L2_BLOCK_HASHES_POSITION,
+    SYSTEM_CONTEXT_CURRENT_TX_ROLLING_HASH_POSITION, SYSTEM_CONTEXT_GAS_PRICE_POSITION,
+    SYSTEM_CONTEXT_STORED_L2_BLOCK_HASHES, SYSTEM_CONTEXT_TX_ORIGIN_POSITION,
+};
+use zksync_types::{
+    block::unpack_block_info,
+    get_code_key, get_nonce_key,
+    utils::{decompose_full_nonce, nonces_to_full_nonce},
+    web3::signing::keccak256,
+    writes::{compression::compress_with_best_strategy, BYTES_PER_ENUMERATION_INDEX},
+    AccountTreeId, Address, L2ChainId, MiniblockNumber, StorageKey, StorageValue, VmVersion, H256,
+    L2_ETH_TOKEN_ADDRESS, U256,
+};
+use zksync_utils::{
+    address_to_h256, be_words_to_bytes, bytecode::bytecode_len_in_bytes, h256_to_account_address,
+    h256_to_u256, u256_to_h256,
+};
+
+#[cfg(any(test, feature = "test-util"))]
+pub use self::testing::{assert_diff_subset_of_default, assert_state_eq};
+#[cfg(feature = "merkle_tree")]
+pub use self::witness::TreeProver;
+pub use self::{
+    binary::{canonical_hash, state_from_bincode, state_to_bincode},
+    block::{
+        debug_trace_transaction_prestate, merge_batch_prestate_results, merge_prestate_results,
+        share_factory_deps, trace_block_prestate, trace_block_prestate_per_transaction,
+        trace_call_prestate, trace_prestate, trace_prestate_with_version,
+        trace_transaction_prestate, BlockPrestateResults, CallMessage,
+    },
+    completeness::CompletenessError,
+    diff::{state_from_account_tree_state, StateDiff, StateExt},
+    divergence::{Divergence, DivergencePath, DivergentState},
+    fees::{BalanceChange, BalanceDelta},
+    flat::{FieldChange, FlatChanges, SlotChange},
+    genesis::{GenesisAccount, GenesisAlloc},
+    historical::{trace_historical_transaction_prestate, HistoricalStorage, StateHeight},
+    oracle::{EnumerationIndexSource, OracleStorageEntry},
+    overrides::{apply_state_override, AccountOverride, StateOverride, StateOverrideError},
+    token_layout::{TokenSlot, TokenStandard},
+    witness::{
+        EthAccountProof, EthStorageProof, LeafProof, PrestateWitness, StorageProof, StorageProver,
+    },
+};
+use crate::{
+    glue::tracers::IntoOldVmTracer,
+    interface::{ExecutionResult, SystemEnv, VmExecutionStatistics, VmRevertReason},
+    tracers::{
+        is_system_contract,
+        prestate_tracer::{fees::annotate_fee, metrics::PRESTATE_TRACER_METRICS},
+        NamedTracer,
+    },
+    HistoryMode, MultiVMTracer, MultiVmTracerPointer,
+};
+
+mod binary;
+mod block;
+mod cheatcodes;
+mod completeness;
+mod diff;
+mod divergence;
+mod fees;
+mod flat;
+mod genesis;
+mod historical;
+mod metrics;
+mod oracle;
+mod overrides;
+mod rlp_encoding;
+mod schema;
+#[cfg(any(test, feature = "test-util"))]
+mod testing;
+mod token_layout;
+mod versions;
+mod witness;
+
+/// State of the accounts touched by a transaction, keyed by the account address.
+pub type State = HashMap<Address, Account>;
+
+/// [`State`] keyed by the account IDs, like in the rest of zkSync code. Converted with
+/// [`StateExt::to_account_tree_state()`] and [`state_from_account_tree_state()`].
+pub type AccountTreeState = HashMap<AccountTreeId, Account>;
+
+/// [`State`] of the accounts before the transaction, see [`PrestateResult::pre`].
+///
+/// The pre- and post-states are distinct types, so that one can't be passed for the other:
+///
+/// ```compile_fail
+/// use multivm::tracers::prestate_tracer::{PostState, PreState};
+///
+/// fn expects_pre(_: &PreState) {}
+/// expects_pre(&PreState::default());
+/// expects_pre(&PostState::default());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PreState(pub State);
+
+/// [`State`] of the accounts after the transaction, see [`PrestateResult::post`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PostState(pub State);
+
+macro_rules! impl_state_wrapper {
+    ($wrapper:ident) => {
+        impl $wrapper {
+            /// Returns the wrapped state.
+            pub fn into_inner(self) -> State {
+                self.0
+            }
+        }
+
+        impl From<State> for $wrapper {
+            fn from(state: State) -> Self {
+                Self(state)
+            }
+        }
+
+        impl PartialEq<State> for $wrapper {
+            fn eq(&self, other: &State) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl std::ops::Deref for $wrapper {
+            type Target = State;
+
+            fn deref(&self) -> &State {
+                &self.0
+            }
+        }
+
+        impl std::ops::DerefMut for $wrapper {
+            fn deref_mut(&mut self) -> &mut State {
+                &mut self.0
+            }
+        }
+
+        impl<'a> IntoIterator for &'a $wrapper {
+            type Item = (&'a Address, &'a Account);
+            type IntoIter = std::collections::hash_map::Iter<'a, Address, Account>;
+
+            fn into_iter(self) -> Self::IntoIter {
+                self.0.iter()
+            }
+        }
+    };
+}
+
+impl_state_wrapper!(PreState);
+impl_state_wrapper!(PostState);
+
+/// Account state in the format used by Geth's `prestateTracer`.
+///
+/// All the fields are optional, and the missing ones are omitted from the serialized output.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    /// Bytecode of the account. zkSync has no EIP-7702 delegation of the EOA code: the code
+    /// of every account, including the custom accounts of the account abstraction, is deployed
+    /// under its code hash. So an account with code is always captured like a contract.
+    ///
+    /// The storage only holds the code hash, so the bytecode is resolved by the hash among
+    /// the factory dependencies known to the VM. See [`Self::code_unavailable`] if it isn't found.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "bytecode_as_hex"
+    )]
+    pub code: Option<Vec<u8>>,
+    /// Versioned hash of the account bytecode. Not a part of Geth's output.
+    #[serde(default, rename = "codeHash", skip_serializing_if = "Option::is_none")]
+    pub code_hash: Option<H256>,
+    /// Length of the account bytecode in bytes. Not a part of Geth's output.
+    #[serde(default, rename = "codeSize", skip_serializing_if = "Option::is_none")]
+    pub code_size: Option<usize>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "nonce_as_number"
+    )]
+    pub nonce: Option<U256>,
+    /// Number of contracts deployed by the account. Not a part of Geth's output.
+    ///
+    /// It's packed into the same slot of the nonce holder as `nonce`, but both are decoded,
+    /// so in diff mode they are reported independently, e.g. only the deployment nonce
+    /// of a factory deploying contracts.
+    #[serde(
+        default,
+        rename = "deploymentNonce",
+        skip_serializing_if = "Option::is_none",
+        with = "nonce_as_number"
+    )]
+    pub deployment_nonce: Option<U256>,
+    /// Persistent storage slots. None of the supported VM versions have transient storage
+    /// (EIP-1153), so there are no transient slots to report separately.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<HashMap<H256, H256>>,
+    /// Balances of the tokens requested in the config. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "tokenBalances",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub token_balances: Option<HashMap<Address, U256>>,
+    /// Preimages of the reported storage slots, if they were supplied in the config.
+    /// Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "storagePreimages",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub storage_preimages: Option<HashMap<H256, SlotPreimage>>,
+    /// Gas remaining in the call frame when the reported slots were last written.
+    /// Only reported with the gas attribution enabled. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "storageGasRemaining",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub storage_gas_remaining: Option<HashMap<H256, u32>>,
+    /// Gas consumed while the account code was executing. Only reported with the gas attribution
+    /// enabled. Not a part of Geth's output.
+    #[serde(default, rename = "gasUsed", skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<u64>,
+    /// Commitment to the reported storage slots, see [`storage_root()`]. Only reported with
+    /// `include_storage_root` enabled. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "storageRoot",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub storage_root: Option<H256>,
+    /// Address of the contract whose code wrote the storage of the account via a delegate call,
+    /// e.g. the implementation of a proxy. Only reported with `track_code_source` enabled.
+    /// Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "codeSource",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub code_source: Option<Address>,
+    /// Reported storage slots rendered according to the slot types supplied in the config,
+    /// see [`SlotType`]. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "storageDecoded",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub storage_decoded: Option<HashMap<H256, String>>,
+    /// Bytecode executed by the constructor of a contract deployed by the transaction.
+    /// `None` for the contracts which existed before it. Not a part of Geth's output.
+    ///
+    /// zkEVM has no separate init code: the constructor is a part of the deployed bytecode,
+    /// which is executed with the code hash marked as constructing. So this is the same bytecode
+    /// as the deployed one, but it's also reported in the prestate, where the account has no code.
+    #[serde(
+        default,
+        rename = "initCode",
+        skip_serializing_if = "Option::is_none",
+        with = "bytecode_as_hex"
+    )]
+    pub init_code: Option<Vec<u8>>,
+    /// Number of the accessed slots left out of `storage` because of the `max_slots_per_account`
+    /// limit of the config. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "omittedSlots",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub omitted_slots: Option<usize>,
+    /// Role of a protocol-level account in the transaction. Only reported in diff mode
+    /// with [`ProtocolAccounts::Tag`]. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "protocolRole",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub protocol_role: Option<ProtocolRole>,
+    /// Balance scaled from the decimals of the base token to the 18 decimals of wei. Only reported
+    /// with `normalize_balances` enabled, while `balance` stays raw. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "balanceWeiNormalized",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub balance_wei_normalized: Option<U256>,
+    /// Pubdata bytes refunded for clearing the slots, i.e. setting them to zero. The cleared slots
+    /// themselves are omitted from the post-state storage. Only reported in diff mode
+    /// with `track_refunds` enabled. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "storageRefunds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub storage_refunds: Option<HashMap<H256, u32>>,
+    /// Why the transaction touched the account. Only reported with `record_touch_reasons`
+    /// enabled. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "touchReasons",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub touch_reasons: Option<BTreeSet<TouchReason>>,
+    /// Split of the balance change into the fee and the rest of it. Only reported in diff mode
+    /// for the [fee payer](PrestateTracerConfigBuilder::fee_payer()) in the post-state.
+    /// Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "balanceChange",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub balance_change: Option<BalanceChange>,
+    /// Set if the account has a code hash, but its bytecode isn't among the factory dependencies
+    /// known to the VM, so only the hash is reported. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "codeUnavailable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub code_unavailable: Option<bool>,
+    /// Set if `code` is cut to the `max_code_bytes` limit of the config, while `code_hash`
+    /// and `code_size` describe the full bytecode. Not a part of Geth's output.
+    #[serde(
+        default,
+        rename = "codeTruncated",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub code_truncated: Option<bool>,
+}
+
+/// Describes how a slot of a Solidity mapping is computed, i.e. `keccak256(key ++ base_slot)`.
+///
+/// For nested mappings, `base_slot` is the slot of the inner mapping. E.g., the slot of
+/// ERC-20 `allowance[owner][spender]` has `spender` as the `key` and the slot of
+/// `allowance[owner]` as the `base_slot`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotPreimage {
+    /// Slot of the mapping.
+    pub base_slot: H256,
+    /// Mapping key, padded to 32 bytes.
+    pub key: H256,
+}
+
+impl SlotPreimage {
+    /// Returns the slot described by the preimage.
+    pub fn slot(&self) -> H256 {
+        self.slot_with(&SlotHasher::default())
+    }
+
+    /// Returns the slot described by the preimage, hashing it with the `hasher`.
+    pub fn slot_with(&self, hasher: &SlotHasher) -> H256 {
+        let mut bytes = [0_u8; 64];
+        bytes[..32].copy_from_slice(self.key.as_bytes());
+        bytes[32..].copy_from_slice(self.base_slot.as_bytes());
+        hasher.hash(&bytes)
+    }
+}
+
+/// Hash function deriving the slots of the Solidity mappings, see [`SlotPreimage`].
+/// Defaults to `keccak256`; another implementation must compute the same hashes, e.g. it can be
+/// hardware-accelerated or instrumented.
+#[derive(Clone, Default)]
+pub struct SlotHasher(Option<Arc<dyn Fn(&[u8]) -> H256 + Send + Sync>>);
+
+impl SlotHasher {
+    pub fn new(hash: impl Fn(&[u8]) -> H256 + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(hash)))
+    }
+
+    pub fn hash(&self, preimage: &[u8]) -> H256 {
+        match &self.0 {
+            Some(hash) => hash(preimage),
+            None => H256(keccak256(preimage)),
+        }
+    }
+}
+
+impl fmt::Debug for SlotHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SlotHasher").finish_non_exhaustive()
+    }
+}
+
+/// Type of the value stored in a slot, used to render it in a human-friendly way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlotType {
+    /// Rendered as a 20-byte hex address.
+    Address,
+    /// Rendered as a decimal number.
+    Uint256,
+    /// Rendered as `true` or `false`.
+    Bool,
+    /// Rendered as a 32-byte hex word, the same as the raw value.
+    Bytes32,
+}
+
+/// Role of an account whose balance is moved by the protocol rather than by the user,
+/// e.g. when the fee is paid by a paymaster to the bootloader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProtocolRole {
+    Bootloader,
+    Paymaster,
+}
+
+/// Reason why an account is reported, see [`Account::touch_reasons`]. An account can be touched
+/// for several reasons, e.g. both read and written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TouchReason {
+    /// Some of its slots, or its balance, were written.
+    Written,
+    /// Some of its slots were only read, e.g. so that the transaction can be replayed.
+    ReadOnly,
+    /// Its code was executed, e.g. as the target of a call.
+    CallTarget,
+    /// Its code hash was queried, e.g. by `EXTCODESIZE` or `EXTCODEHASH`.
+    CodeRead,
+    /// Its nonce was incremented, e.g. as the sender of the transaction or a contract deployer.
+    Sender,
+    /// It was deployed by the transaction.
+    Created,
+}
+
+/// How the protocol-level accounts, i.e. the bootloader and the paymaster of the transaction,
+/// are reported in diff mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProtocolAccounts {
+    /// Reported like any other account.
+    #[default]
+    Report,
+    /// Reported with their [`Account::protocol_role`].
+    Tag,
+    /// Not reported, so that the diff only contains the effects of the transaction on the user.
+    Exclude,
+}
+
+impl SlotType {
+    /// Renders the `value` stored in a slot of this type. Values which aren't valid
+    /// for the type, e.g. an address with non-zero upper bytes, are rendered raw.
+    pub fn render(self, value: H256) -> String {
+        match self {
+            Self::Address if value.as_bytes()[..12].iter().all(|byte| *byte == 0) => {
+                format!("{:?}", h256_to_account_address(&value))
+            }
+            Self::Uint256 => h256_to_u256(value).to_string(),
+            Self::Bool if value == H256::zero() => "false".to_owned(),
+            Self::Bool if value == H256::from_low_u64_be(1) => "true".to_owned(),
+            Self::Address | Self::Bool | Self::Bytes32 => format!("{:?}", value),
+        }
+    }
+}
+
+impl Account {
+    /// Returns an account with none of the fields reported, which is serialized as `{}`.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Returns a builder of an account with the Geth fields set one by one, e.g. for the expected
+    /// accounts in the tests. The rest of the fields can be set on the built account.
+    pub fn builder() -> AccountBuilder {
+        AccountBuilder::default()
+    }
+
+    /// Folds the changed slots into the reported storage, with the `delta` values overwriting
+    /// the known ones. The storage is reported afterwards even if it wasn't before.
+    pub fn apply_storage_delta(&mut self, delta: &HashMap<H256, H256>) {
+        let storage = self.storage.get_or_insert_with(HashMap::new);
+        storage.extend(delta.iter().map(|(slot, value)| (*slot, *value)));
+    }
+
+    /// Overwrites the reported balance. The normalized balance is dropped, since the decimals
+    /// of the base token aren't known to the account.
+    pub fn apply_balance(&mut self, balance: U256) {
+        self.balance = Some(balance);
+        self.balance_wei_normalized = None;
+    }
+
+    /// Overwrites the reported transaction nonce. The deployment nonce is left intact.
+    pub fn apply_nonce(&mut self, nonce: U256) {
+        self.nonce = Some(nonce);
+    }
+
+    /// Checks whether the account reports no change in a diff, i.e. none of its balance, code,
+    /// code hash, nonces and token balances is reported, and its storage is empty or missing.
+    /// The annotations, e.g. the [touch reasons](Self::touch_reasons), aren't changes.
+    ///
+    /// Such accounts are dropped from the diffs.
+    pub fn is_empty_change(&self) -> bool {
+        self.balance.is_none()
+            && self.code.is_none()
+            && self.code_hash.is_none()
+            && self.nonce.is_none()
+            && self.deployment_nonce.is_none()
+            && self.token_balances.is_none()
+            && self.storage.as_ref().map_or(true, HashMap::is_empty)
+    }
+
+    /// Compares the accounts like `==`, but treats the missing storage as the empty one
+    /// and the missing balance as the zero one, e.g. so that the expected accounts in the tests
+    /// don't depend on whether the absent data is reported.
+    pub fn semantically_eq(&self, other: &Self) -> bool {
+        let normalize = |account: &Self| Self {
+            balance: account.balance.filter(|balance| !balance.is_zero()),
+            storage: account
+                .storage
+                .clone()
+                .filter(|storage| !storage.is_empty()),
+            ..account.clone()
+        };
+        normalize(self) == normalize(other)
+    }
+}
+
+/// Slot of an [`Account`] set to different values, see [`AccountBuilder::build()`].
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Storage slot {slot:?} is set to both {value:?} and {other_value:?}")]
+pub struct ConflictingSlotError {
+    pub slot: H256,
+    pub value: H256,
+    pub other_value: H256,
+}
+
+/// Builder of an [`Account`], see [`Account::builder()`].
+#[derive(Debug, Default)]
+pub struct AccountBuilder {
+    account: Account,
+    error: Option<ConflictingSlotError>,
+}
+
+impl AccountBuilder {
+    pub fn balance(mut self, balance: impl Into<U256>) -> Self {
+        self.account.balance = Some(balance.into());
+        self
+    }
+
+    pub fn code(mut self, code: Vec<u8>) -> Self {
+        self.account.code = Some(code);
+        self
+    }
+
+    pub fn nonce(mut self, nonce: impl Into<U256>) -> Self {
+        self.account.nonce = Some(nonce.into());
+        self
+    }
+
+    /// Reports the `storage`, adding to the slots which are already set.
+    pub fn storage(self, storage: HashMap<H256, H256>) -> Self {
+        storage
+            .into_iter()
+            .fold(self, |builder, (slot, value)| builder.slot(slot, value))
+    }
+
+    /// Reports the `slot` with the `value`. Setting the same slot to different values
+    /// fails the [`build()`](Self::build()).
+    pub fn slot(mut self, slot: H256, value: H256) -> Self {
+        let storage = self.account.storage.get_or_insert_with(HashMap::new);
+        let known_value = *storage.entry(slot).or_insert(value);
+        if known_value != value && self.error.is_none() {
+            self.error = Some(ConflictingSlotError {
+                slot,
+                value: known_value,
+                other_value: value,
+            });
+        }
+        self
+    }
+
+    /// Returns the account, or the first slot set to different values.
+    pub fn build(self) -> Result<Account, ConflictingSlotError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.account),
+        }
+    }
+}
+
+/// Formats the account as pretty-printed JSON, the same as the serialized one.
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string_pretty(self).map_err(|_| fmt::Error)?;
+        f.write_str(&json)
+    }
+}
+
+/// Geth reports nonces as plain JSON numbers rather than hex strings.
+mod nonce_as_number {
+    use serde::{ser::Error, Deserialize, Deserializer, Serializer};
+    use zksync_types::U256;
+
+    pub(super) fn serialize<S: Serializer>(
+        nonce: &Option<U256>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match nonce {
+            Some(nonce) if *nonce > U256::from(u64::MAX) => {
+                Err(S::Error::custom("nonce does not fit into u64"))
+            }
+            Some(nonce) => serializer.serialize_u64(nonce.as_u64()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<U256>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(U256::from))
+    }
+}
+
+/// Bytecodes are reported as `0x`-prefixed hex strings.
+mod bytecode_as_hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        code: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match code {
+            Some(code) => serializer.serialize_str(&format!("0x{}", hex::encode(code))),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|code| hex::decode(code.trim_start_matches("0x")).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// VM version whose adapter produced a trace, mirroring the VM version modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TracerVmVersion {
+    Latest,
+    Vm1_4_1,
+    BoojumIntegration,
+    RefundsEnhancement,
+    VirtualBlocks,
+}
+
+impl TracerVmVersion {
+    /// All the versions supported by the tracer, from the latest one.
+    pub const ALL: [Self; 5] = [
+        Self::Latest,
+        Self::Vm1_4_1,
+        Self::BoojumIntegration,
+        Self::RefundsEnhancement,
+        Self::VirtualBlocks,
+    ];
+}
+
+/// Selects the VM of the version module, e.g. for [`trace_prestate_with_version()`].
+impl From<TracerVmVersion> for VmVersion {
+    fn from(version: TracerVmVersion) -> Self {
+        match version {
+            TracerVmVersion::Latest => Self::Vm1_4_2,
+            TracerVmVersion::Vm1_4_1 => Self::Vm1_4_1,
+            TracerVmVersion::BoojumIntegration => Self::VmBoojumIntegration,
+            TracerVmVersion::RefundsEnhancement => Self::VmVirtualBlocksRefundsEnhancement,
+            TracerVmVersion::VirtualBlocks => Self::VmVirtualBlocks,
+        }
+    }
+}
+
+/// Result of the prestate tracer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrestateResult {
+    /// State of the accounts before the transaction.
+    pub pre: PreState,
+    /// State of the accounts after the transaction. Only reported in diff mode.
+    pub post: Option<PostState>,
+    /// Whether the collection was stopped because the limits of the config were exceeded,
+    /// so that some of the touched accounts or slots are missing.
+    pub truncated: bool,
+    /// Whether the transaction has failed. Set by [`Self::with_execution_result()`].
+    pub failed: bool,
+    /// Reason of the transaction revert, if it was reverted with the standard `Error(string)`.
+    pub revert_reason: Option<String>,
+    /// Output of the transaction, or the revert payload if it was reverted. It's the data returned
+    /// by the account's `executeTransaction` to the bootloader, so it depends on the account
+    /// forwarding the output of the called contract. Set by [`Self::with_execution_result()`].
+    pub return_data: Option<Vec<u8>>,
+    /// Hash of the traced transaction. Set by the entry points executing the transactions,
+    /// e.g. [`trace_prestate()`], or by [`Self::with_transaction()`].
+    pub tx_hash: Option<H256>,
+    /// Index of the traced transaction, e.g. among the transactions of the traced block.
+    /// Set like `tx_hash`.
+    pub tx_index: Option<usize>,
+    /// VM version which executed the transaction, or `None` if the result wasn't produced
+    /// by a VM, e.g. if it was deserialized.
+    pub vm_version: Option<TracerVmVersion>,
+    /// Reported accounts in the order they were first touched by the transaction.
+    /// Only reported with `record_touch_order` enabled.
+    pub touch_order: Option<Vec<Address>>,
+    /// Bytecodes of the reported and executed contracts, keyed by their versioned hash, so that
+    /// they can be supplied to the VM replaying the transaction. Unlike [`Account::code`],
+    /// the bytecodes of the unchanged contracts are included in diff mode as well.
+    /// Only reported with `collect_factory_deps` enabled.
+    pub factory_deps: Option<HashMap<H256, Vec<u8>>>,
+    /// Slots read and written by the transaction. Only reported with `access_profile` enabled.
+    pub access_profile: Option<AccessProfile>,
+    /// Numbers of the storage operations executed by the transaction.
+    /// Only reported with `op_counts` enabled.
+    pub op_counts: Option<StorageOpCounts>,
+    /// Whether the pre-state is expected to be complete enough to replay the transaction,
+    /// i.e. the config captures all the accessed state (see
+    /// [`PrestateTracerConfig::is_replay_safe()`]) and the result isn't truncated.
+    /// [`Self::validate()`] checks the captured accounts themselves.
+    pub replay_safe: bool,
+    /// Block properties read by the transaction. Only reported with `block_context` enabled.
+    pub block_context: Option<BlockContext>,
+    /// Gas price the fee of the transaction was charged at, as set by the bootloader according
+    /// to the fee model of the batch. Only reported in diff mode with `capture_fees` enabled.
+    pub effective_gas_price: Option<U256>,
+    /// Change of the balance of the bootloader, which collects the fees of the transactions
+    /// on behalf of the operator, i.e. the fee net of the refund. Should match the fee debited
+    /// from the payer. Only reported in diff mode with `capture_fees` enabled.
+    pub fee_recipient_delta: Option<BalanceDelta>,
+    /// Gas charged per byte of the published data, as set by the bootloader according
+    /// to the fee model of the batch and the limit of the transaction. The pubdata is charged
+    /// separately from the execution, so it explains the part of the fee which the execution gas
+    /// doesn't account for. Only reported with `capture_pubdata` enabled.
+    pub gas_per_pubdata: Option<u32>,
+    /// Bytes of the data published to L1 by the transaction, i.e. its storage writes, L2-to-L1
+    /// logs and messages, and published bytecodes, as counted by the VM. Only reported
+    /// with `capture_pubdata` enabled, and only set by [`Self::with_execution_statistics()`].
+    pub pubdata_published: Option<u32>,
+    /// Slots of the proxies written by more than one implementation, sorted by the address
+    /// and the slot. Only reported with `detect_storage_collisions` enabled.
+    pub storage_collisions: Option<Vec<StorageCollision>>,
+    /// Unprocessed snapshot of the storage keys modified in the VM storage at the end
+    /// of the transaction, i.e. by all the transactions executed by the VM so far, with
+    /// their values. Only reported with `record_modified_keys` enabled.
+    pub modified_keys: Option<HashMap<StorageKey, StorageValue>>,
+    /// Addresses of the contracts deployed by the transaction, in the order of the deployments.
+    /// The contracts destructed by the same transaction are left out.
+    pub created_addresses: Vec<Address>,
+    /// Counts of the reported accounts and slots, computed once the states are collected.
+    counts: StateCounts,
+}
+
+/// Storage slots accessed by the transaction, keyed by the address of the account they belong to.
+/// Unlike the states, it's about the access patterns rather than the values, so it covers
+/// all the accessed slots, including the ones of the system contracts storing the balances,
+/// nonces and code hashes.
+pub type AccessProfile = HashMap<Address, AccountAccesses>;
+
+/// Slots of an account accessed by the transaction, see [`AccessProfile`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountAccesses {
+    /// Slots read by the transaction. The slots which are both read and written are in both sets.
+    pub read_slots: HashSet<H256>,
+    pub write_slots: HashSet<H256>,
+}
+
+/// Numbers of the storage operations executed by the transaction, see
+/// [`PrestateResult::op_counts`]. The operations of the reverted frames are counted as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageOpCounts {
+    pub sloads: u64,
+    pub sstores: u64,
+    /// Accesses to the slots which weren't accessed by the transaction before.
+    pub cold_accesses: u64,
+    /// Repeated accesses to the slots, already accessed by the transaction.
+    pub warm_accesses: u64,
+    /// Slots whose value after the transaction differs from the one before it.
+    pub changed_slots: u64,
+}
+
+impl ops::AddAssign for StorageOpCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.sloads += other.sloads;
+        self.sstores += other.sstores;
+        self.cold_accesses += other.cold_accesses;
+        self.warm_accesses += other.warm_accesses;
+        self.changed_slots += other.changed_slots;
+    }
+}
+
+/// Block properties available to the transaction, see [`PrestateResult::block_context`].
+///
+/// On zkSync they are stored by the `SystemContext` system contract rather than supplied
+/// by the environment, so they are read from its storage.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockContext {
+    /// Number of the block returned by the `NUMBER` opcode, i.e. of the virtual block
+    /// for the VMs supporting them.
+    pub number: u64,
+    pub timestamp: u64,
+    pub base_fee: U256,
+    /// Hashes of the previous blocks read by the transaction, e.g. via `BLOCKHASH`,
+    /// keyed by the block number.
+    pub block_hashes: BTreeMap<u64, H256>,
+}
+
+/// Slot of a proxy written by the code of several implementations via delegate calls, which
+/// may be an unintended overlap of their storage layouts, see
+/// [`PrestateResult::storage_collisions`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageCollision {
+    /// Account owning the slot, i.e. the proxy.
+    pub address: Address,
+    pub slot: H256,
+    /// Sorted addresses of the code which has written the slot.
+    pub implementations: Vec<Address>,
+}
+
+/// Numbers of the distinct accounts and storage slots in the pre- and post-states of a result.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct StateCounts {
+    accounts: usize,
+    slots: usize,
+}
+
+impl StateCounts {
+    fn new(pre: &State, post: Option<&State>) -> Self {
+        let states = || std::iter::once(pre).chain(post);
+        let accounts: HashSet<_> = states().flat_map(State::keys).collect();
+        let slots: HashSet<_> = states()
+            .flat_map(|state| state.iter())
+            .flat_map(|(address, account)| {
+                let slots = account.storage.iter().flat_map(HashMap::keys);
+                slots.map(move |slot| (address, slot))
+            })
+            .collect();
+        Self {
+            accounts: accounts.len(),
+            slots: slots.len(),
+        }
+    }
+}
+
+impl From<(State, State)> for PrestateResult {
+    fn from((pre, post): (State, State)) -> Self {
+        let counts = StateCounts::new(&pre, Some(&post));
+        Self {
+            pre: PreState(pre),
+            post: Some(PostState(post)),
+            truncated: false,
+            failed: false,
+            revert_reason: None,
+            return_data: None,
+            tx_hash: None,
+            tx_index: None,
+            vm_version: None,
+            touch_order: None,
+            factory_deps: None,
+            access_profile: None,
+            op_counts: None,
+            // The post-state is only reported in diff mode.
+            replay_safe: false,
+            block_context: None,
+            effective_gas_price: None,
+            fee_recipient_delta: None,
+            gas_per_pubdata: None,
+            pubdata_published: None,
+            storage_collisions: None,
+            modified_keys: None,
+            created_addresses: Vec::new(),
+            counts,
+        }
+    }
+}
+
+impl PrestateResult {
+    /// Records the outcome of the traced transaction, which is only known to the VM caller.
+    ///
+    /// The changes made by a reverted transaction are rolled back before the result is collected,
+    /// so in diff mode only the fee payment and the nonce increment are reported for it.
+    pub fn with_execution_result(mut self, result: &ExecutionResult) -> Self {
+        self.failed = result.is_failed();
+        self.revert_reason = match result {
+            ExecutionResult::Revert {
+                output: VmRevertReason::General { msg, .. },
+            } => Some(msg.clone()),
+            _ => None,
+        };
+        self.return_data = match result {
+            ExecutionResult::Success { output } => Some(output.clone()),
+            ExecutionResult::Revert { output } => Some(output.encoded_data()),
+            ExecutionResult::Halt { .. } => None,
+        };
+        self
+    }
+
+    /// Records the pubdata published by the traced transaction, which is counted by the VM
+    /// rather than the tracer. Only applied if the tracer has captured the price of the pubdata,
+    /// i.e. with `capture_pubdata` enabled.
+    pub fn with_execution_statistics(mut self, statistics: &VmExecutionStatistics) -> Self {
+        if self.gas_per_pubdata.is_some() {
+            self.pubdata_published = Some(statistics.pubdata_published);
+        }
+        self
+    }
+
+    /// Records the hash of the traced transaction and its index, so that the result can be
+    /// correlated with it, e.g. when the results are stored in a database.
+    pub fn with_transaction(mut self, tx_hash: H256, tx_index: usize) -> Self {
+        self.tx_hash = Some(tx_hash);
+        self.tx_index = Some(tx_index);
+        self
+    }
+
+    /// Returns the number of the distinct reported accounts. In diff mode, an account is counted
+    /// once even if it's reported in both the pre- and post-states.
+    ///
+    /// The count is computed when the result is collected, so it doesn't reflect the changes
+    /// made to the states afterwards.
+    pub fn account_count(&self) -> usize {
+        self.counts.accounts
+    }
+
+    /// Returns the number of the distinct reported storage slots of all the accounts. In diff mode,
+    /// a slot is counted once even if it's reported in both the pre- and post-states.
+    ///
+    /// Like [`Self::account_count()`], the count is computed when the result is collected.
+    pub fn slot_count(&self) -> usize {
+        self.counts.slots
+    }
+
+    /// Estimates the length of [`Self::to_json()`] serialized without whitespace, e.g. to reject
+    /// the responses exceeding a size limit before serializing them.
+    ///
+    /// The fields reported by Geth, the code hash and size and the deployment nonce are counted,
+    /// while the rest of the annotations aren't. The addresses are counted in the default format.
+    pub fn estimated_json_size(&self) -> usize {
+        match &self.post {
+            // `{"pre":`, `,"post":` and `}`.
+            Some(post) => {
+                16 + estimated_state_json_size(&self.pre) + estimated_state_json_size(post)
+            }
+            None => estimated_state_json_size(&self.pre),
+        }
+    }
+
+    /// Formats the result like Geth's `prestateTracer`: the state before the transaction,
+    /// or both `pre` and `post` states in diff mode.
+    ///
+    /// Like in Geth, all the hex values are lowercase, and the storage slots and values are padded
+    /// to 32 bytes. Account addresses are formatted with the `address_formatter` of the `config`
+    /// if it is set, and are EIP-55 checksummed if `checksum_addresses` is enabled.
+    pub fn to_json(&self, config: &PrestateTracerConfig) -> serde_json::Value {
+        match &self.post {
+            Some(post) => serde_json::json!({
+                "pre": state_to_json(&self.pre, config),
+                "post": state_to_json(post, config),
+            }),
+            None => state_to_json(&self.pre, config),
+        }
+    }
+
+    /// Same as [`Self::to_json()`], but serialized with the accounts, fields and slots sorted
+    /// by their keys, so that equal results are always serialized to the same bytes,
+    /// e.g. to cache the traces by their hash.
+    pub fn to_sorted_json(&self, config: &PrestateTracerConfig) -> String {
+        let json = sort_json_keys(self.to_json(config));
+        serde_json::to_string(&json).expect("failed to serialize prestate tracer result")
+    }
+
+    /// Writes the same JSON as [`Self::to_json()`] to the `writer` directly, without building
+    /// an intermediate JSON value, e.g. to stream the response of an RPC request.
+    pub fn write_json(
+        &self,
+        writer: impl std::io::Write,
+        config: &PrestateTracerConfig,
+    ) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        struct JsonDiff<'a> {
+            pre: JsonState<'a>,
+            post: JsonState<'a>,
+        }
+
+        let pre = JsonState::new(&self.pre, config);
+        match &self.post {
+            Some(post) => {
+                let post = JsonState::new(post, config);
+                serde_json::to_writer(writer, &JsonDiff { pre, post })
+            }
+            None => serde_json::to_writer(writer, &pre),
+        }
+    }
+}
+
+/// State serialized with the accounts keyed by the address strings, lowercase like in Geth
+/// or formatted with the `address_formatter` and `checksum_addresses` options of the config,
+/// see [`PrestateResult::to_json()`].
+#[derive(Debug, Clone, Copy)]
+pub struct JsonState<'a> {
+    state: &'a State,
+    config: &'a PrestateTracerConfig,
+}
+
+impl<'a> JsonState<'a> {
+    pub fn new(state: &'a State, config: &'a PrestateTracerConfig) -> Self {
+        Self { state, config }
+    }
+}
+
+impl Serialize for JsonState<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let accounts = self
+            .state
+            .iter()
+            .map(|(address, account)| (format_address(address, self.config), account));
+        serializer.collect_map(accounts)
+    }
+}
+
+/// Sorts the keys of all the nested objects. `serde_json` maps are only sorted without
+/// the `preserve_order` feature, which may be enabled by any other crate in the build.
+fn sort_json_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            let entries = entries
+                .into_iter()
+                .map(|(key, value)| (key, sort_json_keys(value)));
+            serde_json::Value::Object(entries.collect())
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_json_keys).collect())
+        }
+        value => value,
+    }
+}
+
+fn state_to_json(state: &State, config: &PrestateTracerConfig) -> serde_json::Value {
+    serde_json::to_value(JsonState::new(state, config))
+        .expect("failed to serialize prestate tracer result")
+}
+
+fn estimated_state_json_size(state: &State) -> usize {
+    // `"0x<address>":` and `"0x<hash>"`.
+    const ADDRESS_KEY_SIZE: usize = 45;
+    const HASH_SIZE: usize = 68;
+    let decimal_size = |value: U256| value.to_string().len();
+    let hex_size = |value: U256| 4 + ((value.bits() + 3) / 4).max(1);
+
+    let accounts = state.values().map(|account| {
+        let fields = [
+            account
+                .balance
+                .map(|balance| "\"balance\":".len() + hex_size(balance)),
+            account
+                .nonce
+                .map(|nonce| "\"nonce\":".len() + decimal_size(nonce)),
+            account
+                .deployment_nonce
+                .map(|nonce| "\"deploymentNonce\":".len() + decimal_size(nonce)),
+            account
+                .code
+                .as_ref()
+                .map(|code| "\"code\":".len() + 4 + 2 * code.len()),
+            account.code_hash.map(|_| "\"codeHash\":".len() + HASH_SIZE),
+            account
+                .code_size
+                .map(|size| "\"codeSize\":".len() + decimal_size(size.into())),
+            account.storage.as_ref().map(|storage| {
+                // `"<slot>":"<value>"` pairs separated by commas.
+                let pairs = storage.len() * (2 * HASH_SIZE + 1) + storage.len().saturating_sub(1);
+                "\"storage\":{}".len() + pairs
+            }),
+        ];
+        let (size, count) = fields
+            .into_iter()
+            .flatten()
+            .fold((0, 0), |(size, count), field| (size + field, count + 1));
+        // The braces of the account and the commas between its fields.
+        ADDRESS_KEY_SIZE + 2 + size + count.saturating_sub(1)
+    });
+    // The braces of the state and the commas between the accounts.
+    2 + accounts.sum::<usize>() + state.len().saturating_sub(1)
+}
+
+fn format_address(address: &Address, config: &PrestateTracerConfig) -> String {
+    let formatted_address = config
+        .address_formatter
+        .as_ref()
+        .and_then(|formatter| formatter.format(address));
+    formatted_address.unwrap_or_else(|| {
+        if config.checksum_addresses {
+            to_checksum_address(address)
+        } else {
+            format!("{:?}", address)
+        }
+    })
+}
+
+/// Formats the `address` with the EIP-55 mixed-case checksum.
+pub fn to_checksum_address(address: &Address) -> String {
+    let hex_address = hex::encode(address.as_bytes());
+    let hash = keccak256(hex_address.as_bytes());
+    let checksummed: String = hex_address
+        .chars()
+        .enumerate()
+        .map(|(i, char)| {
+            // Letters are uppercased if the corresponding nibble of the hash is at least 8.
+            let hash_byte = hash[i / 2];
+            let nibble = if i % 2 == 0 {
+                hash_byte >> 4
+            } else {
+                hash_byte & 0xf
+            };
+            if nibble >= 8 {
+                char.to_ascii_uppercase()
+            } else {
+                char
+            }
+        })
+        .collect();
+    format!("0x{checksummed}")
+}
+
+/// Selector of `transferFromTo(address,address,uint256)` of the base token contract.
+const TRANSFER_FROM_TO_SELECTOR: [u8; 4] = [0x57, 0x99, 0x52, 0xfc];
+/// Selector of `mint(address,uint256)` of the base token contract.
+const MINT_SELECTOR: [u8; 4] = [0x40, 0xc1, 0x0f, 0x19];
+/// Length of the base token calldata prefix holding the selector and up to two addresses.
+const BASE_TOKEN_CALLDATA_LEN: usize = 4 + 2 * 32;
+
+#[derive(Debug, Clone)]
+pub struct PrestateTracer {
+    pub pre: State,
+    pub post: State,
+    pub config: PrestateTracerConfig,
+    pub result: Arc<OnceCell<(State, State)>>,
+
+    // Values of the slots accessed by the transaction, as they were before the first access.
+    initial_values: HashMap<StorageKey, H256>,
+    // Storage slots written by the transaction.
+    written_keys: HashSet<StorageKey>,
+    // Storage slots read by the transaction. Only collected with `access_profile`.
+    read_keys: HashSet<StorageKey>,
+    // Shared with the clones of the tracer, like the result.
+    truncated: Arc<AtomicBool>,
+    // Set by the VM version adapter at the end of the execution. Shared like the result.
+    vm_version: Arc<OnceCell<TracerVmVersion>>,
+    // Gas remaining when the slots were last written. Only collected with the gas attribution.
+    write_gas_remaining: HashMap<StorageKey, u32>,
+    // Gas consumed by the code of each account. Only collected with the gas attribution.
+    gas_by_code_address: HashMap<Address, u64>,
+    // Code address, call stack depth and remaining gas on the previous VM step.
+    last_step: Option<(Address, usize, u32)>,
+    // Contracts whose code last wrote the storage of the accounts via a delegate call.
+    // Only collected with `track_code_source`.
+    code_sources: HashMap<Address, Address>,
+    // Contracts whose code was executed, including the targets of the delegate calls.
+    executed_code_addresses: HashSet<Address>,
+    // Senders and recipients of the base token transfers and mints.
+    transfer_parties: HashSet<Address>,
+    // Touched accounts in the order of the first touch, and the same accounts as a set.
+    // Only collected with `record_touch_order`.
+    touch_order: Vec<Address>,
+    touched_accounts: HashSet<Address>,
+    // Reported accounts from `touch_order`. Shared like the result.
+    touch_order_result: Arc<OnceCell<Vec<Address>>>,
+    // Only collected with `collect_factory_deps`. Shared like the result.
+    factory_deps_result: Arc<OnceCell<HashMap<H256, Vec<u8>>>>,
+    // Only collected with `access_profile`. Shared like the result.
+    access_profile_result: Arc<OnceCell<AccessProfile>>,
+    // Changes of the collected state in the order they were made, so that they can be undone.
+    journal: Vec<JournalEntry>,
+    // Snapshots taken on the entry to the frames being executed.
+    // Only collected with `rollback_reverted_frames`.
+    frame_snapshots: Vec<TracerSnapshot>,
+    // Only collected with `op_counts`, except for `changed_slots` computed with the result.
+    op_counts: StorageOpCounts,
+    // Slots accessed by the storage opcodes. Only collected with `op_counts`.
+    warm_keys: HashSet<StorageKey>,
+    // Only collected with `op_counts`. Shared like the result.
+    op_counts_result: Arc<OnceCell<StorageOpCounts>>,
+    // Only collected with `block_context`. Shared like the result.
+    block_context_result: Arc<OnceCell<BlockContext>>,
+    // Effective gas price and the balance change of the fee recipient.
+    // Only collected with `capture_fees`. Shared like the result.
+    fees_result: Arc<OnceCell<(U256, BalanceDelta)>>,
+    // Last nonzero price of the pubdata set in the VM. Only collected with `capture_pubdata`.
+    gas_per_pubdata: u32,
+    // Only collected with `capture_pubdata`. Shared like the result.
+    gas_per_pubdata_result: Arc<OnceCell<u32>>,
+    // Code which has written the slots via delegate calls.
+    // Only collected with `detect_storage_collisions`.
+    delegate_writers: HashMap<StorageKey, BTreeSet<Address>>,
+    // Only collected with `detect_storage_collisions`. Shared like the result.
+    storage_collisions_result: Arc<OnceCell<Vec<StorageCollision>>>,
+    // Only collected with `record_modified_keys`. Shared like the result.
+    modified_keys_result: Arc<OnceCell<HashMap<StorageKey, StorageValue>>>,
+    // Shared like the result.
+    created_addresses_result: Arc<OnceCell<Vec<Address>>>,
+    // Values of the slots known from the `baseline` of the config.
+    baseline_values: HashMap<StorageKey, H256>,
+}
+
+/// Position in the history of a [`PrestateTracer`], see [`PrestateTracer::snapshot()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TracerSnapshot(usize);
+
+/// Change of the state collected by the tracer, see [`PrestateTracer::rollback_to()`].
+#[derive(Debug, Clone)]
+enum JournalEntry {
+    InitialValue(StorageKey),
+    Write(StorageKey),
+    Read(StorageKey),
+    ExecutedCode(Address),
+    Touch(Address),
+    TransferParty(Address),
+}
+
+impl PrestateTracer {
+    pub fn new(diff_mode: bool, result: Arc<OnceCell<(State, State)>>) -> Self {
+        Self::with_config(
+            PrestateTracerConfig::builder().diff_mode(diff_mode).build(),
+            result,
+        )
+    }
+
+    pub fn with_config(
+        config: PrestateTracerConfig,
+        result: Arc<OnceCell<(State, State)>>,
+    ) -> Self {
+        let baseline_values = config
+            .baseline
+            .as_ref()
+            .map(|baseline| baseline_storage_values(baseline, &config))
+            .unwrap_or_default();
+        Self {
+            pre: Default::default(),
+            post: Default::default(),
+            baseline_values,
+            config,
+            result,
+            initial_values: Default::default(),
+            written_keys: Default::default(),
+            read_keys: Default::default(),
+            truncated: Default::default(),
+            vm_version: Default::default(),
+            write_gas_remaining: Default::default(),
+            gas_by_code_address: Default::default(),
+            last_step: None,
+            code_sources: Default::default(),
+            executed_code_addresses: Default::default(),
+            transfer_parties: Default::default(),
+            touch_order: Default::default(),
+            touched_accounts: Default::default(),
+            touch_order_result: Default::default(),
+            factory_deps_result: Default::default(),
+            access_profile_result: Default::default(),
+            journal: Default::default(),
+            frame_snapshots: Default::default(),
+            op_counts: Default::default(),
+            warm_keys: Default::default(),
+            op_counts_result: Default::default(),
+            block_context_result: Default::default(),
+            fees_result: Default::default(),
+            gas_per_pubdata: 0,
+            gas_per_pubdata_result: Default::default(),
+            delegate_writers: Default::default(),
+            storage_collisions_result: Default::default(),
+            modified_keys_result: Default::default(),
+            created_addresses_result: Default::default(),
+        }
+    }
+
+    /// Creates a diff mode tracer with the default config otherwise and its own result cell.
+    pub fn diff() -> Self {
+        Self::from_config(PrestateTracerConfig::builder().diff_mode(true).build())
+    }
+
+    /// Creates a tracer with its own result cell. The result can then be obtained with
+    /// [`Self::take_result()`] once the traced transaction is executed.
+    pub fn from_config(config: PrestateTracerConfig) -> Self {
+        Self::with_config(config, Arc::new(OnceCell::new()))
+    }
+
+    /// Prepares the tracer for tracing another transaction, storing its result to `new_result`.
+    ///
+    /// All the collected state is cleared, so a single tracer can be reused e.g. for all
+    /// the transactions of a replayed block. The config is left intact.
+    pub fn reset(&mut self, new_result: Arc<OnceCell<(State, State)>>) {
+        *self = Self::with_config(self.config.clone(), new_result);
+    }
+
+    /// Returns the current position in the history of the collected state, e.g. on the entry
+    /// to a call frame, which the tracer can be rolled back to.
+    pub fn snapshot(&self) -> TracerSnapshot {
+        TracerSnapshot(self.journal.len())
+    }
+
+    /// Forgets the slots, the executed code and the parties of the base token transfers which
+    /// were captured after the `snapshot`, e.g. when the frame they were accessed in is reverted.
+    /// The snapshots taken after this one are invalidated.
+    ///
+    /// The gas attribution, the code sources of the writes and the implementations which have
+    /// written the slots aren't rolled back.
+    pub fn rollback_to(&mut self, snapshot: TracerSnapshot) {
+        let undone = self.journal.split_off(snapshot.0.min(self.journal.len()));
+        for entry in undone.into_iter().rev() {
+            match entry {
+                JournalEntry::InitialValue(key) => {
+                    self.initial_values.remove(&key);
+                }
+                JournalEntry::Write(key) => {
+                    self.written_keys.remove(&key);
+                }
+                JournalEntry::Read(key) => {
+                    self.read_keys.remove(&key);
+                }
+                JournalEntry::ExecutedCode(address) => {
+                    self.executed_code_addresses.remove(&address);
+                }
+                JournalEntry::Touch(address) => {
+                    // The touches are undone in the reverse order, so it's the last one.
+                    self.touched_accounts.remove(&address);
+                    self.touch_order.pop();
+                }
+                JournalEntry::TransferParty(address) => {
+                    self.transfer_parties.remove(&address);
+                }
+            }
+        }
+    }
+
+    /// Returns the result of the tracer, if it has already been stored.
+    pub fn get_result(&self) -> Option<PrestateResult> {
+        let (pre, post) = self.result.get()?.clone();
+        Some(self.build_result(pre, post))
+    }
+
+    /// Consumes the tracer, returning its result if it has already been stored.
+    ///
+    /// The result is moved out of the cell if the clones passed to the VM are already dropped,
+    /// which is the case once the VM execution returns.
+    pub fn take_result(mut self) -> Option<PrestateResult> {
+        let (pre, post) = match Arc::try_unwrap(std::mem::take(&mut self.result)) {
+            Ok(result) => result.into_inner()?,
+            Err(result) => result.get()?.clone(),
+        };
+        Some(self.build_result(pre, post))
+    }
+
+    fn build_result(&self, pre: State, post: State) -> PrestateResult {
+        let post = self.config.diff_mode.then_some(post);
+        PrestateResult {
+            counts: StateCounts::new(&pre, post.as_ref()),
+            pre: PreState(pre),
+            post: post.map(PostState),
+            truncated: self.truncated.load(Ordering::Relaxed),
+            failed: false,
+            revert_reason: None,
+            return_data: None,
+            tx_hash: None,
+            tx_index: None,
+            vm_version: self.vm_version.get().copied(),
+            touch_order: self.touch_order_result.get().cloned(),
+            factory_deps: self.factory_deps_result.get().cloned(),
+            access_profile: self.access_profile_result.get().cloned(),
+            op_counts: self.op_counts_result.get().copied(),
+            replay_safe: self.is_replay_safe(),
+            block_context: self.block_context_result.get().cloned(),
+            effective_gas_price: self.fees_result.get().map(|(price, _)| *price),
+            fee_recipient_delta: self.fees_result.get().map(|(_, delta)| *delta),
+            gas_per_pubdata: self.gas_per_pubdata_result.get().copied(),
+            pubdata_published: None,
+            storage_collisions: self.storage_collisions_result.get().cloned(),
+            modified_keys: self.modified_keys_result.get().cloned(),
+            created_addresses: self
+                .created_addresses_result
+                .get()
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+
+    fn is_replay_safe(&self) -> bool {
+        self.config.is_replay_safe() && !self.truncated.load(Ordering::Relaxed)
+    }
+
+    /// Restricts the output to the given accounts. The rest of the touched accounts are skipped.
+    pub fn with_only_addresses(mut self, addresses: HashSet<Address>) -> Self {
+        self.config.only_addresses = Some(addresses);
+        self
+    }
+
+    /// Additionally reports the balances of the given tokens for every touched account.
+    pub fn with_token_balances(mut self, tokens: Vec<Address>) -> Self {
+        self.config.tokens = tokens;
+        self
+    }
+
+    /// Handles a VM step executing the code of `code_address` with `ergs_remaining` gas left
+    /// in the frame. Called by all VM versions before every opcode is executed.
+    ///
+    /// The gas spent between two steps within the same frame is attributed to the code.
+    /// The gas passed to and returned from the subcalls isn't attributed to anyone.
+    fn on_step(&mut self, code_address: Address, callstack_depth: usize, ergs_remaining: u32) {
+        let step = (code_address, callstack_depth, ergs_remaining);
+        let last_step = self.last_step.replace(step);
+        // The code address only changes on calls and returns, so the set is rarely touched.
+        if last_step.map(|(last_address, ..)| last_address) != Some(code_address) {
+            if self.executed_code_addresses.insert(code_address) {
+                self.journal.push(JournalEntry::ExecutedCode(code_address));
+            }
+            self.record_touch(code_address);
+        }
+        if !self.config.gas_attribution {
+            return;
+        }
+        if let Some((last_address, last_depth, last_ergs)) = last_step {
+            if last_address == code_address && last_depth == callstack_depth {
+                let spent = last_ergs.saturating_sub(ergs_remaining);
+                *self.gas_by_code_address.entry(code_address).or_default() += u64::from(spent);
+            }
+        }
+    }
+
+    /// Handles a VM step like [`Self::on_step()`] unless the frame is nested deeper than
+    /// `max_depth` far calls. Counting the far calls is linear in the callstack depth,
+    /// so `far_call_depth` is only evaluated if the depth is limited.
+    fn on_frame_step(
+        &mut self,
+        code_address: Address,
+        callstack_depth: usize,
+        far_call_depth: impl FnOnce() -> usize,
+        ergs_remaining: u32,
+    ) {
+        if self.config.max_depth != usize::MAX && far_call_depth() > self.config.max_depth {
+            return;
+        }
+        self.on_step(code_address, callstack_depth, ergs_remaining);
+    }
+
+    /// Remembers the price of the pubdata in the VM state. It's zero until the bootloader
+    /// sets it for the transaction, so only the nonzero prices are kept.
+    fn on_pubdata_price(&mut self, gas_per_pubdata: u32) {
+        if self.config.capture_pubdata && gas_per_pubdata != 0 {
+            self.gas_per_pubdata = gas_per_pubdata;
+        }
+    }
+
+    /// Checks whether the calls to the `address` are handled by [`Self::on_base_token_call()`].
+    fn is_base_token(&self, address: &Address) -> bool {
+        *address == self.config.base_token
+    }
+
+    /// Handles a far call to the base token contract with the `calldata`, truncated
+    /// to [`BASE_TOKEN_CALLDATA_LEN`]. The parties of the transfers and mints are remembered,
+    /// so that their balance changes are reported even if they don't execute any code,
+    /// e.g. the beneficiary of the balance of a destructed account.
+    fn on_base_token_call(&mut self, calldata: &[u8]) {
+        let (selector, args) = calldata.split_at(calldata.len().min(4));
+        let address_count = if selector == TRANSFER_FROM_TO_SELECTOR {
+            2
+        } else if selector == MINT_SELECTOR {
+            1
+        } else {
+            return;
+        };
+        let parties = args
+            .chunks_exact(32)
+            .take(address_count)
+            .map(|word| Address::from_slice(&word[12..]));
+        for address in parties {
+            if self.transfer_parties.insert(address) {
+                self.journal.push(JournalEntry::TransferParty(address));
+            }
+        }
+    }
+
+    /// Handles the entry to a near or far call frame.
+    fn on_frame_entry(&mut self) {
+        if self.config.rollback_reverted_frames {
+            let snapshot = self.snapshot();
+            self.frame_snapshots.push(snapshot);
+        }
+    }
+
+    /// Handles the return from the current frame, rolling back the state captured in it
+    /// if the frame is `reverted`.
+    fn on_frame_exit(&mut self, reverted: bool) {
+        if !self.config.rollback_reverted_frames {
+            return;
+        }
+        if let Some(snapshot) = self.frame_snapshots.pop() {
+            if reverted {
+                self.rollback_to(snapshot);
+            }
+        }
+    }
+
+    /// Handles a storage read or write by the `address` contract executing the code of
+    /// `code_address`, with `ergs_remaining` gas left in the frame. Called by all VM versions
+    /// before the corresponding opcode is executed.
+    fn on_storage_opcode<S: WriteStorage>(
+        &mut self,
+        address: Address,
+        code_address: Address,
+        slot: U256,
+        is_write: bool,
+        ergs_remaining: u32,
+        storage: &StoragePtr<S>,
+    ) {
+        let key = StorageKey::new(AccountTreeId::new(address), u256_to_h256(slot));
+        if self.config.op_counts {
+            self.count_storage_op(key, is_write);
+        }
+        self.record_storage_access(key, is_write, storage);
+        if is_write && self.config.gas_attribution {
+            self.write_gas_remaining.insert(key, ergs_remaining);
+        }
+        // The code runs in the context of another account only for delegate calls.
+        if is_write && self.config.track_code_source && code_address != address {
+            self.code_sources.insert(address, code_address);
+        }
+        if is_write && self.config.detect_storage_collisions && code_address != address {
+            let writers = self.delegate_writers.entry(key).or_default();
+            writers.insert(code_address);
+        }
+    }
+
+    fn count_storage_op(&mut self, key: StorageKey, is_write: bool) {
+        let counts = &mut self.op_counts;
+        if is_write {
+            counts.sstores += 1;
+        } else {
+            counts.sloads += 1;
+        }
+        if self.warm_keys.insert(key) {
+            counts.cold_accesses += 1;
+        } else {
+            counts.warm_accesses += 1;
+        }
+    }
+
+    /// Collects and stores the result. Called by all VM versions after the execution.
+    /// Tracers cannot return errors to the VM, so they are only logged.
+    fn on_execution_end<S: WriteStorage>(
+        &mut self,
+        storage: &StoragePtr<S>,
+        known_bytecodes: &HashMap<U256, Vec<U256>>,
+        vm_version: TracerVmVersion,
+    ) {
+        // Only the first execution is traced, like for the result itself.
+        self.vm_version.get_or_init(|| vm_version);
+        if self.config.capture_pubdata {
+            self.gas_per_pubdata_result
+                .get_or_init(|| self.gas_per_pubdata);
+        }
+        if let Err(err) = self.store_result(storage, known_bytecodes) {
+            tracing::error!("Failed to store prestate tracer result: {}", err);
+        }
+    }
+
+    /// Remembers the value of the slot before the VM accesses it for the first time.
+    /// Must be called before the access is actually performed. The later writes don't overwrite
+    /// the remembered value, so in diff mode the slot is compared to the one at the start
+    /// of the transaction, regardless of the intermediate writes.
+    ///
+    /// New slots are ignored once `max_storage_slots` are collected, so that the memory used
+    /// by the tracer is bounded.
+    fn record_storage_access<S: WriteStorage>(
+        &mut self,
+        key: StorageKey,
+        is_write: bool,
+        storage: &StoragePtr<S>,
+    ) {
+        if !is_write && self.config.access_profile && self.read_keys.insert(key) {
+            self.journal.push(JournalEntry::Read(key));
+        }
+        if self.config.exclude_bootloader_scratch && is_bootloader_scratch_slot(&key) {
+            return;
+        }
+        if !is_write && self.config.lazy_pre && self.config.diff_mode {
+            // Only the values which can end up in the diff are captured.
+            return;
+        }
+        if !self.initial_values.contains_key(&key) {
+            if self.initial_values.len() >= self.config.max_storage_slots {
+                self.truncated.store(true, Ordering::Relaxed);
+                return;
+            }
+            let value = match self.baseline_values.get(&key) {
+                Some(value) => *value,
+                None => storage.borrow_mut().read_value(&key),
+            };
+            self.initial_values.insert(key, value);
+            self.journal.push(JournalEntry::InitialValue(key));
+            let address = *key.account().address();
+            if address == ACCOUNT_CODE_STORAGE_ADDRESS {
+                // The code hash is reported for the account it belongs to.
+                self.record_touch(h256_to_account_address(key.key()));
+            }
+            self.record_touch(address);
+        }
+        if is_write && self.written_keys.insert(key) {
+            self.journal.push(JournalEntry::Write(key));
+        }
+    }
+
+    fn record_touch(&mut self, address: Address) {
+        if self.config.record_touch_order && self.touched_accounts.insert(address) {
+            self.touch_order.push(address);
+            self.journal.push(JournalEntry::Touch(address));
+        }
+    }
+
+    /// Checks whether the account had code before the transaction and was left
+    /// with neither code nor balance after it, i.e. it was destructed by the transaction.
+    pub fn is_account_deleted<S: WriteStorage>(
+        &self,
+        storage: &StoragePtr<S>,
+        address: &Address,
+    ) -> bool {
+        self.account_deleted(&mut *storage.borrow_mut(), address)
+    }
+
+    fn account_deleted<S: WriteStorage>(&self, storage: &mut S, address: &Address) -> bool {
+        let code_key = get_code_key(address);
+        let balance_key = self.config.balance_key(&AccountTreeId::new(*address));
+        let code_before = self
+            .initial_values
+            .get(&code_key)
+            .copied()
+            .unwrap_or_else(|| storage.read_value(&code_key));
+
+        code_before != H256::zero()
+            && storage.read_value(&code_key) == H256::zero()
+            && storage.read_value(&balance_key) == H256::zero()
+    }
+
+    /// Returns why the reported accounts were touched by the transaction.
+    fn touch_reasons(
+        &self,
+        created_accounts: &HashSet<Address>,
+    ) -> HashMap<Address, BTreeSet<TouchReason>> {
+        let mut written_owners = HashSet::new();
+        let mut read_only_owners = HashSet::new();
+        let mut code_read_accounts = HashSet::new();
+        for key in self.initial_values.keys() {
+            let owner = *key.account().address();
+            if self.written_keys.contains(key) {
+                written_owners.insert(owner);
+            } else {
+                read_only_owners.insert(owner);
+            }
+            if owner == ACCOUNT_CODE_STORAGE_ADDRESS {
+                code_read_accounts.insert(h256_to_account_address(key.key()));
+            }
+        }
+
+        let addresses: HashSet<_> = self.pre.keys().chain(self.post.keys()).copied().collect();
+        addresses
+            .into_iter()
+            .map(|address| {
+                let balance_key = self.config.balance_key(&AccountTreeId::new(address));
+                let reasons = [
+                    (
+                        TouchReason::Written,
+                        written_owners.contains(&address)
+                            || self.written_keys.contains(&balance_key),
+                    ),
+                    (TouchReason::ReadOnly, read_only_owners.contains(&address)),
+                    (
+                        TouchReason::CallTarget,
+                        self.executed_code_addresses.contains(&address),
+                    ),
+                    (TouchReason::CodeRead, code_read_accounts.contains(&address)),
+                    (
+                        TouchReason::Sender,
+                        self.written_keys.contains(&get_nonce_key(&address)),
+                    ),
+                    (TouchReason::Created, created_accounts.contains(&address)),
+                ];
+                let reasons = reasons
+                    .into_iter()
+                    .filter_map(|(reason, applies)| applies.then_some(reason))
+                    .collect();
+                (address, reasons)
+            })
+            .collect()
+    }
+
+    /// Checks whether the account had code before or after the transaction.
+    fn has_code<S: WriteStorage>(&self, storage: &mut S, address: &Address) -> bool {
+        let code_key = get_code_key(address);
+        let code_before = self
+            .initial_values
+            .get(&code_key)
+            .copied()
+            .unwrap_or_else(|| storage.read_value(&code_key));
+        code_before != H256::zero() || storage.read_value(&code_key) != H256::zero()
+    }
+
+    /// Returns the addresses of the contracts deployed by the transaction, i.e. the ones
+    /// that had no code before the transaction and have it after.
+    fn created_accounts<S: WriteStorage>(&self, storage: &mut S) -> HashSet<Address> {
+        self.written_keys
+            .iter()
+            .filter(|key| *key.account().address() == ACCOUNT_CODE_STORAGE_ADDRESS)
+            .filter(|key| {
+                self.initial_values[*key] == H256::zero() && storage.read_value(key) != H256::zero()
+            })
+            .map(|key| h256_to_account_address(key.key()))
+            .filter(|address| is_address_included(&self.config, address))
+            .collect()
+    }
+
+    /// Returns the addresses of the contracts upgraded by the transaction, i.e. the ones
+    /// which had code before the transaction and have a different code after it, e.g. after
+    /// a force deployment. The upgrade itself only writes the slot of the code storage.
+    fn upgraded_accounts<S: WriteStorage>(&self, storage: &mut S) -> HashSet<Address> {
+        self.written_keys
+            .iter()
+            .filter(|key| *key.account().address() == ACCOUNT_CODE_STORAGE_ADDRESS)
+            .filter(|key| {
+                let (code_before, code_after) =
+                    (self.initial_values[*key], storage.read_value(key));
+                code_before != H256::zero()
+                    && code_after != H256::zero()
+                    && code_after != code_before
+            })
+            .map(|key| h256_to_account_address(key.key()))
+            .filter(|address| is_address_included(&self.config, address))
+            .collect()
+    }
+
+    /// Returns the addresses of the accounts created and destroyed by the transaction, i.e.
+    /// the ones whose code was written, but which had no code both before and after it.
+    fn short_lived_accounts<S: WriteStorage>(&self, storage: &mut S) -> HashSet<Address> {
+        self.written_keys
+            .iter()
+            .filter(|key| *key.account().address() == ACCOUNT_CODE_STORAGE_ADDRESS)
+            .filter(|key| {
+                self.initial_values[*key] == H256::zero() && storage.read_value(key) == H256::zero()
+            })
+            .map(|key| h256_to_account_address(key.key()))
+            .collect()
+    }
+
+    /// Returns the addresses of the accounts whose code was accessed by the transaction,
+    /// i.e. the ones whose code hash was queried (e.g. by `EXTCODESIZE` or `EXTCODEHASH`)
+    /// and the ones whose code was executed (e.g. as the target of a delegate call).
+    /// zkEVM has no `EXTCODECOPY`, so the code cannot be accessed otherwise.
+    fn code_accessed_accounts(&self) -> impl Iterator<Item = Address> + '_ {
+        self.initial_values
+            .keys()
+            .filter(|key| *key.account().address() == ACCOUNT_CODE_STORAGE_ADDRESS)
+            .map(|key| h256_to_account_address(key.key()))
+            .chain(self.executed_code_addresses.iter().copied())
+            .filter(|address| is_address_included(&self.config, address))
+    }
+
+    /// Returns the senders and recipients of the base token transfers and mints made
+    /// by the transaction, see [`Self::on_base_token_call()`].
+    fn transfer_parties(&self) -> impl Iterator<Item = Address> + '_ {
+        self.transfer_parties
+            .iter()
+            .copied()
+            .filter(|address| is_address_included(&self.config, address))
+    }
+
+    /// Returns the accounts touched by the transaction whose nonce or balance was written,
+    /// e.g. the sender and the recipient of a transfer.
+    ///
+    /// Nonces and balances are stored by the system contracts under hashed slots, so the accounts
+    /// cannot be recovered from the written keys themselves. Instead, the keys of the accounts
+    /// whose code was executed or accessed, and of the parties of the base token transfers
+    /// are checked.
+    fn value_changed_accounts(&self) -> HashSet<Address> {
+        self.code_accessed_accounts()
+            .chain(self.transfer_parties())
+            .filter(|address| {
+                let balance_key = self.config.balance_key(&AccountTreeId::new(*address));
+                self.written_keys.contains(&get_nonce_key(address))
+                    || self.written_keys.contains(&balance_key)
+            })
+            .collect()
+    }
+
+    fn store_result<S: WriteStorage>(
+        &mut self,
+        storage: &StoragePtr<S>,
+        known_bytecodes: &HashMap<U256, Vec<U256>>,
+    ) -> Result<(), PrestateTracerError> {
+        // Fails rather than panics if the storage is borrowed, e.g. by a tracer composed
+        // with this one.
+        let mut storage = storage
+            .try_borrow_mut()
+            .map_err(|_| PrestateTracerError::StorageBorrowed)?;
+        self.store_result_with_storage(&mut *storage, known_bytecodes)
+    }
+
+    /// Collects and stores the result reading the already borrowed `storage`, for the tracers
+    /// which are composed with this one and hold the storage borrow themselves.
+    /// The `known_bytecodes` are the bytecodes known to the VM decommitter.
+    pub fn store_result_with_storage<S: WriteStorage>(
+        &mut self,
+        storage: &mut S,
+        known_bytecodes: &HashMap<U256, Vec<U256>>,
+    ) -> Result<(), PrestateTracerError> {
+        let created_accounts = self.created_accounts(&mut *storage);
+        let config = &self.config;
+
+        // Read-only accesses don't change the state, so they are only reported outside of
+        // diff mode, where the prestate must be complete enough to replay the transaction.
+        let pre_values: HashMap<_, _> = if config.diff_mode {
+            self.written_keys
+                .iter()
+                .map(|key| (*key, self.initial_values[key]))
+                .collect()
+        } else {
+            self.initial_values
+                .iter()
+                .filter(|(key, value)| {
+                    config.include_empty_reads
+                        || !value.is_zero()
+                        || self.written_keys.contains(key)
+                })
+                .map(|(key, value)| (*key, *value))
+                .collect()
+        };
+        let mut pre_accounts = created_accounts.clone();
+        // The accounts whose nonce, balance or code hash has changed (e.g. the transaction sender
+        // or an upgraded contract) are reported in diff mode even if none of their own slots
+        // are written.
+        let value_changed_accounts = if config.diff_mode && !config.storage_only {
+            let mut accounts = self.value_changed_accounts();
+            accounts.extend(self.upgraded_accounts(&mut *storage));
+            accounts
+        } else {
+            HashSet::new()
+        };
+        pre_accounts.extend(&value_changed_accounts);
+        if !config.diff_mode && !config.storage_only {
+            pre_accounts.extend(self.code_accessed_accounts());
+            pre_accounts.extend(self.transfer_parties());
+        }
+        let mut pre_reader = VmStateReader {
+            storage: &mut *storage,
+            known_bytecodes,
+            initial_values: Some(&self.initial_values),
+        };
+        self.pre =
+            process_modified_storage_keys(State::new(), &pre_values, config, &mut pre_reader);
+        // Deployed contracts and the ones whose code was accessed don't have to own any
+        // of the accessed slots, but are still reported.
+        for address in &pre_accounts {
+            if config.is_cancelled() {
+                break;
+            }
+            self.pre
+                .entry(*address)
+                .or_insert_with(|| read_account(address, HashMap::new(), config, &mut pre_reader));
+        }
+
+        if config.diff_mode {
+            // Like in Geth, the accounts created and destroyed by the transaction are a no-op,
+            // even if their storage or balance were left behind.
+            let short_lived_accounts = self.short_lived_accounts(&mut *storage);
+            self.pre
+                .retain(|address, _| !short_lived_accounts.contains(address));
+            // Deleted accounts keep their full prestate and are absent from the post-state.
+            let deleted_accounts: State = self
+                .pre
+                .iter()
+                .filter(|(address, _)| self.account_deleted(&mut *storage, address))
+                .map(|(address, account)| (*address, account.clone()))
+                .collect();
+
+            let post_values: HashMap<_, _> = self
+                .written_keys
+                .iter()
+                .filter(|key| {
+                    let address = key.account().address();
+                    !deleted_accounts.contains_key(address)
+                        && !short_lived_accounts.contains(address)
+                })
+                .map(|key| (*key, storage.read_value(key)))
+                .collect();
+            self.pre
+                .retain(|address, _| !deleted_accounts.contains_key(address));
+            let mut post_reader = VmStateReader {
+                storage: &mut *storage,
+                known_bytecodes,
+                initial_values: None,
+            };
+            self.post =
+                process_modified_storage_keys(State::new(), &post_values, config, &mut post_reader);
+            for address in created_accounts.iter().chain(&value_changed_accounts) {
+                if config.is_cancelled() {
+                    break;
+                }
+                if deleted_accounts.contains_key(address) || short_lived_accounts.contains(address)
+                {
+                    continue;
+                }
+                self.post.entry(*address).or_insert_with(|| {
+                    read_account(address, HashMap::new(), config, &mut post_reader)
+                });
+            }
+            if config.is_cancelled() {
+                // The accounts which are only collected for one of the states would be reported
+                // as changed, so they are dropped from both.
+                let post = &self.post;
+                self.pre.retain(|address, _| post.contains_key(address));
+                let pre = &self.pre;
+                self.post.retain(|address, _| pre.contains_key(address));
+            }
+            let emptied_accounts = if config.track_existence && !config.storage_only {
+                apply_existence_transitions(&mut self.pre, &mut self.post)
+            } else {
+                State::new()
+            };
+            retain_changes(&mut self.pre, &mut self.post);
+            self.pre.extend(deleted_accounts);
+            self.pre.extend(emptied_accounts);
+            handle_protocol_accounts(&mut self.pre, &mut self.post, config);
+            if config.track_refunds {
+                annotate_refunds(&mut self.post, &post_values, &self.initial_values);
+            }
+            if config.fee_payer.is_some() || config.capture_fees {
+                // The fee is paid to the bootloader, which refunds the unused part of it
+                // before the end of the transaction.
+                let bootloader_balance_key =
+                    config.balance_key(&AccountTreeId::new(BOOTLOADER_ADDRESS));
+                let bootloader_balance_before = self
+                    .initial_values
+                    .get(&bootloader_balance_key)
+                    .copied()
+                    .unwrap_or_else(|| storage.read_value(&bootloader_balance_key));
+                let bootloader_balance_after = storage.read_value(&bootloader_balance_key);
+                let (before, after) = (
+                    h256_to_u256(bootloader_balance_before),
+                    h256_to_u256(bootloader_balance_after),
+                );
+                if let Some(payer) = &config.fee_payer {
+                    annotate_fee(
+                        &self.pre,
+                        &mut self.post,
+                        payer,
+                        after.saturating_sub(before),
+                    );
+                }
+                if config.capture_fees {
+                    // Set by the bootloader for every transaction before executing it.
+                    let gas_price_key = StorageKey::new(
+                        AccountTreeId::new(SYSTEM_CONTEXT_ADDRESS),
+                        SYSTEM_CONTEXT_GAS_PRICE_POSITION,
+                    );
+                    let gas_price = h256_to_u256(storage.read_value(&gas_price_key));
+                    let delta = BalanceDelta::between(before, after);
+                    self.fees_result.get_or_init(|| (gas_price, delta));
+                }
+            }
+        }
+        if config.contracts_only {
+            let eoas: HashSet<_> = self
+                .pre
+                .keys()
+                .chain(self.post.keys())
+                .filter(|address| !self.has_code(&mut *storage, address))
+                .copied()
+                .collect();
+            self.pre.retain(|address, _| !eoas.contains(address));
+            self.post.retain(|address, _| !eoas.contains(address));
+        }
+        if truncate_accounts(&mut self.pre, &mut self.post, config.max_accounts)
+            || config.is_cancelled()
+        {
+            self.truncated.store(true, Ordering::Relaxed);
+        }
+        if config.gas_attribution {
+            // The written values are only reported in the post-state in diff mode.
+            let state = if config.diff_mode {
+                &mut self.post
+            } else {
+                &mut self.pre
+            };
+            annotate_gas(state, &self.write_gas_remaining, &self.gas_by_code_address);
+        }
+        if config.track_code_source {
+            // Like the gas, the sources are attributed to the written values.
+            let state = if config.diff_mode {
+                &mut self.post
+            } else {
+                &mut self.pre
+            };
+            for (address, account) in state.iter_mut() {
+                account.code_source = self.code_sources.get(address).copied();
+            }
+        }
+        if !config.disable_code && !config.storage_only {
+            // Deployed contracts are only reported in the post-state in diff mode.
+            let state = if config.diff_mode {
+                &mut self.post
+            } else {
+                &mut self.pre
+            };
+            let mut reader = VmStateReader {
+                storage: &mut *storage,
+                known_bytecodes,
+                initial_values: None,
+            };
+            for address in &created_accounts {
+                if let Some(account) = state.get_mut(address) {
+                    let code_hash = reader.read_value(&get_code_key(address));
+                    account.init_code = reader.load_bytecode(code_hash);
+                }
+            }
+        }
+        if config.include_storage_root {
+            annotate_storage_roots(&mut self.pre);
+            annotate_storage_roots(&mut self.post);
+        }
+        if !config.slot_types.is_empty() {
+            annotate_decoded_storage(&mut self.pre, &config.slot_types);
+            annotate_decoded_storage(&mut self.post, &config.slot_types);
+        }
+        if config.record_touch_order {
+            let (pre, post) = (&self.pre, &self.post);
+            let touch_order = self
+                .touch_order
+                .iter()
+                .filter(|address| pre.contains_key(address) || post.contains_key(address))
+                .copied()
+                .collect();
+            self.touch_order_result.get_or_init(|| touch_order);
+        }
+        if config.record_touch_reasons {
+            let touch_reasons = self.touch_reasons(&created_accounts);
+            for (address, account) in self.pre.iter_mut().chain(self.post.iter_mut()) {
+                account.touch_reasons = touch_reasons.get(address).cloned();
+            }
+        }
+        if config.collect_factory_deps {
+            let mut reader = VmStateReader {
+                storage: &mut *storage,
+                known_bytecodes,
+                initial_values: None,
+            };
+            // The system contracts are a part of every VM, so they aren't needed for the replay.
+            let executed_code_hashes: Vec<_> = self
+                .executed_code_addresses
+                .iter()
+                .filter(|address| !is_system_contract(address))
+                .map(|address| reader.read_value(&get_code_key(address)))
+                .collect();
+            let factory_deps = self
+                .pre
+                .values()
+                .chain(self.post.values())
+                .filter_map(|account| account.code_hash)
+                .chain(executed_code_hashes)
+                .filter(|code_hash| !code_hash.is_zero())
+                .filter_map(|code_hash| Some((code_hash, reader.load_bytecode(code_hash)?)))
+                .collect();
+            self.factory_deps_result.get_or_init(|| factory_deps);
+        }
+        if config.access_profile {
+            let mut access_profile = AccessProfile::new();
+            for (keys, is_write) in [(&self.read_keys, false), (&self.written_keys, true)] {
+                for key in keys {
+                    let accesses = access_profile.entry(*key.account().address()).or_default();
+                    let slots = if is_write {
+                        &mut accesses.write_slots
+                    } else {
+                        &mut accesses.read_slots
+                    };
+                    slots.insert(*key.key());
+                }
+            }
+            self.access_profile_result.get_or_init(|| access_profile);
+        }
+        if config.op_counts {
+            let changed_slots = self
+                .written_keys
+                .iter()
+                .filter(|key| {
+                    let initial_value = self.initial_values.get(key);
+                    initial_value.map_or(false, |value| *value != storage.read_value(key))
+                })
+                .count();
+            let op_counts = StorageOpCounts {
+                changed_slots: changed_slots as u64,
+                ..self.op_counts
+            };
+            self.op_counts_result.get_or_init(|| op_counts);
+        }
+        if config.block_context {
+            let block_context = self.block_context(&mut *storage);
+            self.block_context_result.get_or_init(|| block_context);
+        }
+        if config.detect_storage_collisions {
+            let mut collisions: Vec<_> = self
+                .delegate_writers
+                .iter()
+                .filter(|(_, writers)| writers.len() > 1)
+                .map(|(key, writers)| StorageCollision {
+                    address: *key.account().address(),
+                    slot: *key.key(),
+                    implementations: writers.iter().copied().collect(),
+                })
+                .collect();
+            collisions.sort_unstable_by_key(|collision| (collision.address, collision.slot));
+            self.storage_collisions_result.get_or_init(|| collisions);
+        }
+        if config.record_modified_keys {
+            let modified_keys = storage.modified_storage_keys().clone();
+            self.modified_keys_result.get_or_init(|| modified_keys);
+        }
+        // The code hash of a deployed contract is first written when the deployment starts,
+        // so the journal has the deployments in order.
+        let created_addresses = self
+            .journal
+            .iter()
+            .filter_map(|entry| match entry {
+                JournalEntry::Write(key)
+                    if *key.account().address() == ACCOUNT_CODE_STORAGE_ADDRESS =>
+                {
+                    Some(h256_to_account_address(key.key()))
+                }
+                _ => None,
+            })
+            .filter(|address| created_accounts.contains(address))
+            .collect();
+        self.created_addresses_result
+            .get_or_init(|| created_addresses);
+        if let Some(processor) = &config.post_process {
+            processor.process(&mut self.pre);
+            processor.process(&mut self.post);
+        }
+
+        self.result
+            .set((self.pre.clone(), self.post.clone()))
+            .map_err(|_| PrestateTracerError::ResultAlreadySet)
+    }
+
+    /// Reads the block properties from the storage of `SystemContext`, taking the values
+    /// before the transaction for the slots it has accessed.
+    fn block_context<S: WriteStorage>(&self, storage: &mut S) -> BlockContext {
+        let system_context = AccountTreeId::new(SYSTEM_CONTEXT_ADDRESS);
+        let mut read = |position: H256| {
+            let key = StorageKey::new(system_context, position);
+            let initial_value = self.initial_values.get(&key).copied();
+            h256_to_u256(initial_value.unwrap_or_else(|| storage.read_value(&key)))
+        };
+        let mut block_info = read(CURRENT_VIRTUAL_BLOCK_INFO_POSITION);
+        if block_info.is_zero() {
+            // The VMs before the virtual blocks only store the batch info.
+            block_info = read(SYSTEM_CONTEXT_BLOCK_INFO_POSITION);
+        }
+        let (number, timestamp) = unpack_block_info(block_info);
+        let base_fee = read(SYSTEM_CONTEXT_BASE_FEE_POSITION);
+
+        // `BLOCKHASH` is only defined for the 256 most recent blocks, the hashes of which are
+        // stored either in the mapping keyed by the number or in the ring buffer of the L2 blocks.
+        let mut block_hashes = BTreeMap::new();
+        for block_number in number.saturating_sub(256)..number {
+            let mapping_slot = SlotPreimage {
+                base_slot: SYSTEM_CONTEXT_BLOCK_HASH_POSITION,
+                key: u256_to_h256(block_number.into()),
+            }
+            .slot_with(&self.config.slot_hasher);
+            let ring_slot = h256_to_u256(SYSTEM_CONTEXT_CURRENT_L2_BLOCK_HASHES_POSITION)
+                + block_number % u64::from(SYSTEM_CONTEXT_STORED_L2_BLOCK_HASHES);
+            for slot in [mapping_slot, u256_to_h256(ring_slot)] {
+                let key = StorageKey::new(system_context, slot);
+                match self.initial_values.get(&key) {
+                    Some(hash) if !hash.is_zero() => {
+                        block_hashes.insert(block_number, *hash);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        BlockContext {
+            number,
+            timestamp,
+            base_fee,
+            block_hashes,
+        }
+    }
+}
+
+/// Tracer with the default config and its own result cell, see [`PrestateTracer::from_config()`].
+impl Default for PrestateTracer {
+    fn default() -> Self {
+        Self::from_config(PrestateTracerConfig::default())
+    }
+}
+
+impl IntoOldVmTracer for PrestateTracer {}
+
+impl<S: WriteStorage, H: HistoryMode> NamedTracer<S, H> for PrestateTracer {
+    fn tracer_pointers(&self) -> Vec<MultiVmTracerPointer<S, H>> {
+        vec![self.clone().into_tracer_pointer()]
+    }
+
+    /// Reports the state before the transaction, or both `pre` and `post` states in diff mode.
+    fn json_result(&self) -> Option<serde_json::Value> {
+        Some(self.get_result()?.to_json(&self.config))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum PrestateTracerError {
+    /// The result has already been stored, e.g. because the tracer was reused.
+    #[error("Prestate tracer result is already set")]
+    ResultAlreadySet,
+    /// The storage is borrowed by another component, e.g. another tracer. The result can be
+    /// collected with [`PrestateTracer::store_result_with_storage()`] by the borrower instead.
+    #[error("Storage is already borrowed, so the prestate cannot be collected")]
+    StorageBorrowed,
+    /// The execution has finished without the result being stored. The tracers cannot return
+    /// errors to the VM, so the reason is only logged.
+    #[error("Prestate tracer result wasn't stored by the end of the execution")]
+    ResultNotStored,
+    /// The `tracerConfig` is invalid, see [`ConfigError`].
+    #[error("{0}")]
+    InvalidConfig(String),
+    /// The historical state cannot be traced at the genesis, see [`StateHeight::parent()`].
+    #[error("{0:?} has no previous state to execute on top of")]
+    NoParentState(StateHeight),
+    /// [`HistoricalStorage`] has failed to load the state snapshot.
+    #[error("Failed to load state snapshot at {height:?}: {reason}")]
+    SnapshotUnavailable { height: StateHeight, reason: String },
+    /// An L2 block follows the one of the state height, which consists of a single L2 block.
+    #[error("L2 block #{0} doesn't belong to the traced L2 block")]
+    UnexpectedL2Block(MiniblockNumber),
+}
+
+impl From<ConfigError> for PrestateTracerError {
+    fn from(err: ConfigError) -> Self {
+        Self::InvalidConfig(err.to_string())
+    }
+}
+
+/// Invalid `tracerConfig` of the tracer, see [`PrestateTracerConfig::from_json()`].
+#[derive(Debug, Error)]
+#[error("Invalid prestate tracer config: {0}")]
+pub struct ConfigError(#[from] serde_json::Error);
+
+/// Address which isn't a hex string of 20 bytes, see
+/// [`PrestateTracerConfigBuilder::only_hex_addresses()`].
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Invalid address {0:?}, expected 20 hex-encoded bytes")]
+pub struct InvalidAddressError(pub String);
+
+#[derive(Debug, Clone)]
+pub struct PrestateTracerConfig {
+    diff_mode: bool,
+    disable_code: bool,
+    disable_storage: bool,
+    storage_only: bool,
+    include_empty_reads: bool,
+    prune_empty_accounts: bool,
+    track_existence: bool,
+    only_addresses: Option<HashSet<Address>>,
+    tokens: Vec<Address>,
+    slot_preimages: HashMap<H256, SlotPreimage>,
+    slot_types: HashMap<H256, SlotType>,
+    metrics: bool,
+    max_accounts: usize,
+    max_storage_slots: usize,
+    max_slots_per_account: usize,
+    max_code_bytes: usize,
+    protocol_accounts: ProtocolAccounts,
+    paymaster: Option<Address>,
+    fee_payer: Option<Address>,
+    base_token_decimals: Option<u8>,
+    track_refunds: bool,
+    reuse_modified_values: bool,
+    max_depth: usize,
+    gas_attribution: bool,
+    base_token: Address,
+    chain_base_tokens: HashMap<L2ChainId, Address>,
+    balance_slots: BalanceSlotRegistry,
+    slot_hasher: SlotHasher,
+    include_storage_root: bool,
+    exclude_system_contracts: bool,
+    exclude_bootloader_scratch: bool,
+    contracts_only: bool,
+    checksum_addresses: bool,
+    address_formatter: Option<AddressFormatter>,
+    track_code_source: bool,
+    record_touch_order: bool,
+    record_touch_reasons: bool,
+    collect_factory_deps: bool,
+    lazy_pre: bool,
+    access_profile: bool,
+    rollback_reverted_frames: bool,
+    op_counts: bool,
+    block_context: bool,
+    capture_fees: bool,
+    capture_pubdata: bool,
+    detect_storage_collisions: bool,
+    record_modified_keys: bool,
+    post_process: Option<StatePostProcessor>,
+    baseline: Option<Arc<State>>,
+    cancellation_token: Option<CancellationToken>,
+}
+
+impl Default for PrestateTracerConfig {
+    fn default() -> Self {
+        Self {
+            diff_mode: false,
+            disable_code: false,
+            disable_storage: false,
+            storage_only: false,
+            include_empty_reads: true,
+            prune_empty_accounts: false,
+            track_existence: false,
+            only_addresses: None,
+            tokens: Vec::new(),
+            slot_preimages: HashMap::new(),
+            slot_types: HashMap::new(),
+            metrics: false,
+            max_accounts: PrestateTracerConfig::DEFAULT_MAX_ACCOUNTS,
+            max_storage_slots: PrestateTracerConfig::DEFAULT_MAX_STORAGE_SLOTS,
+            max_slots_per_account: usize::MAX,
+            max_code_bytes: usize::MAX,
+            protocol_accounts: ProtocolAccounts::Report,
+            paymaster: None,
+            fee_payer: None,
+            base_token_decimals: None,
+            track_refunds: false,
+            reuse_modified_values: false,
+            max_depth: usize::MAX,
+            gas_attribution: false,
+            base_token: L2_ETH_TOKEN_ADDRESS,
+            chain_base_tokens: HashMap::new(),
+            balance_slots: BalanceSlotRegistry::default(),
+            slot_hasher: SlotHasher::default(),
+            include_storage_root: false,
+            exclude_system_contracts: false,
+            exclude_bootloader_scratch: false,
+            contracts_only: false,
+            checksum_addresses: false,
+            address_formatter: None,
+            track_code_source: false,
+            record_touch_order: false,
+            record_touch_reasons: false,
+            collect_factory_deps: false,
+            lazy_pre: false,
+            access_profile: false,
+            rollback_reverted_frames: false,
+            op_counts: false,
+            block_context: false,
+            capture_fees: false,
+            capture_pubdata: false,
+            detect_storage_collisions: false,
+            record_modified_keys: false,
+            post_process: None,
+            baseline: None,
+            cancellation_token: None,
+        }
+    }
+}
+
+impl PrestateTracerConfig {
+    /// Default limit on the number of reported accounts, far above what normal transactions touch.
+    pub const DEFAULT_MAX_ACCOUNTS: usize = 10_000;
+    /// Default limit on the number of collected storage slots, including the ones
+    /// of the system contracts.
+    pub const DEFAULT_MAX_STORAGE_SLOTS: usize = 100_000;
+
+    pub fn builder() -> PrestateTracerConfigBuilder {
+        PrestateTracerConfigBuilder::default()
+    }
+
+    /// Parses the `tracerConfig` parameter of `debug_traceTransaction`, e.g. `{"diffMode": true}`.
+    /// A missing (`null`) config results in the default one.
+    ///
+    /// The config is validated strictly: unknown options (e.g. a misspelled `diffmode`)
+    /// and options of a wrong type are rejected instead of falling back to the defaults.
+    pub fn from_json(tracer_config: &serde_json::Value) -> Result<Self, ConfigError> {
+        let geth_config: Option<GethTracerConfig> = serde_json::from_value(tracer_config.clone())?;
+        let geth_config = geth_config.unwrap_or_default();
+        Ok(Self::builder()
+            .diff_mode(geth_config.diff_mode)
+            .disable_code(geth_config.disable_code)
+            .disable_storage(geth_config.disable_storage)
+            .build())
+    }
+
+    /// Returns the config for tracing a batch executed with the `system_env`, i.e. with the base
+    /// token of the batch chain if it's set via
+    /// [`PrestateTracerConfigBuilder::chain_base_token()`]. Neither the system nor the batch
+    /// environment contains the base token address, so it's resolved by the chain ID.
+    pub fn for_system_env(&self, system_env: &SystemEnv) -> Self {
+        let base_token = self
+            .chain_base_tokens
+            .get(&system_env.chain_id)
+            .copied()
+            .unwrap_or(self.base_token);
+        Self {
+            base_token,
+            ..self.clone()
+        }
+    }
+
+    /// Checks whether the config captures all the state accessed by the transaction, so that
+    /// the pre-state is complete enough to replay it. That's not the case in diff mode, which
+    /// leaves out the read-only accesses, and with any of the options leaving out some
+    /// of the accounts, code or slots, e.g. `storage_only`, `max_depth`, `max_slots_per_account`
+    /// or `max_code_bytes`. The states rewritten with a [`StatePostProcessor`] aren't deemed
+    /// replay-safe either, since the processor may drop or alter any of the values.
+    ///
+    /// The limits on the numbers of accounts and slots are only checked once the result
+    /// is collected, see [`PrestateResult::truncated`].
+    pub fn is_replay_safe(&self) -> bool {
+        !self.diff_mode
+            && !self.disable_code
+            && !self.disable_storage
+            && !self.storage_only
+            && self.only_addresses.is_none()
+            && !self.exclude_system_contracts
+            && !self.exclude_bootloader_scratch
+            && !self.contracts_only
+            && self.max_depth == usize::MAX
+            && self.max_slots_per_account == usize::MAX
+            && self.max_code_bytes == usize::MAX
+            // The rewritten states can't be told apart from the captured ones.
+            && self.post_process.is_none()
+            // The slots read by the reverted frames are read when replaying as well.
+            && !self.rollback_reverted_frames
+    }
+
+    /// Returns the key of the base token balance of the `account`.
+    pub fn balance_key(&self, account: &AccountTreeId) -> StorageKey {
+        self.token_balance_key(account, self.base_token)
+    }
+
+    fn token_balance_key(&self, account: &AccountTreeId, token: Address) -> StorageKey {
+        self.balance_slots
+            .balance_key_with(account, token, &self.slot_hasher)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation_token
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled)
+    }
+}
+
+/// Stops the collection of the accounts once cancelled, e.g. when the deadline of an RPC request
+/// is reached. Shared by all the clones.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Formats the account addresses in the JSON output, see [`PrestateResult::to_json()`].
+/// The addresses for which the function returns `None` are formatted as usual.
+///
+/// Only the output is affected, the accounts are still keyed by their actual addresses.
+#[derive(Clone)]
+pub struct AddressFormatter(Arc<dyn Fn(&Address) -> Option<String> + Send + Sync>);
+
+impl AddressFormatter {
+    pub fn new(format: impl Fn(&Address) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(format))
+    }
+
+    pub fn format(&self, address: &Address) -> Option<String> {
+        (self.0)(address)
+    }
+}
+
+impl fmt::Debug for AddressFormatter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AddressFormatter").finish_non_exhaustive()
+    }
+}
+
+/// Rewrites the collected states before the result is stored, e.g. to redact the slots
+/// or balances which mustn't leave the process. Applied to both the pre- and the post-state,
+/// after all the annotations of the accounts. The processed result is never
+/// [replay-safe](PrestateTracerConfig::is_replay_safe()).
+#[derive(Clone)]
+pub struct StatePostProcessor(Arc<dyn Fn(&mut State) + Send + Sync>);
+
+impl StatePostProcessor {
+    pub fn new(process: impl Fn(&mut State) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(process))
+    }
+
+    pub fn process(&self, state: &mut State) {
+        (self.0)(state)
+    }
+}
+
+impl fmt::Debug for StatePostProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("StatePostProcessor").finish_non_exhaustive()
+    }
+}
+
+/// Options of Geth's `prestateTracer`. The other options of the tracer are zkSync-specific,
+/// so they can only be set via [`PrestateTracerConfigBuilder`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+struct GethTracerConfig {
+    diff_mode: bool,
+    disable_code: bool,
+    disable_storage: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct PrestateTracerConfigBuilder {
+    config: PrestateTracerConfig,
+}
+
+impl PrestateTracerConfigBuilder {
+    /// Report only the changed state, both before and after the transaction.
+    pub fn diff_mode(mut self, diff_mode: bool) -> Self {
+        self.config.diff_mode = diff_mode;
+        self
+    }
+
+    /// Don't report the code of the accounts.
+    pub fn disable_code(mut self, disable_code: bool) -> Self {
+        self.config.disable_code = disable_code;
+        self
+    }
+
+    /// Don't report the storage slots of the accounts.
+    pub fn disable_storage(mut self, disable_storage: bool) -> Self {
+        self.config.disable_storage = disable_storage;
+        self
+    }
+
+    /// Report only the storage slots of the accounts, without reading their balances, nonces
+    /// and code. The accounts without any reported slots are omitted.
+    pub fn storage_only(mut self, storage_only: bool) -> Self {
+        self.config.storage_only = storage_only;
+        self
+    }
+
+    /// Report the slots which were only read and found to be zero, so that a replay can tell them
+    /// from the untouched ones. Enabled by default, like in Geth. The zero slots which were
+    /// written are always reported.
+    pub fn include_empty_reads(mut self, include_empty_reads: bool) -> Self {
+        self.config.include_empty_reads = include_empty_reads;
+        self
+    }
+
+    /// Treat the touched accounts with zero balance and nonces, no code and only zero slots
+    /// as non-existent, and leave them out, like Geth does for the touched empty accounts
+    /// (EIP-161). Such accounts are e.g. the pruned ones, or the ones that never existed.
+    pub fn prune_empty_accounts(mut self, prune_empty_accounts: bool) -> Self {
+        self.config.prune_empty_accounts = prune_empty_accounts;
+        self
+    }
+
+    /// In diff mode, leave the accounts which become non-empty out of the pre-state,
+    /// and the ones which become empty out of the post-state, like Geth does. The empty accounts
+    /// are the same as for [`Self::prune_empty_accounts()`]. By default, both states are
+    /// reported for them, with the zero values in the empty one.
+    pub fn track_existence(mut self, track_existence: bool) -> Self {
+        self.config.track_existence = track_existence;
+        self
+    }
+
+    /// Report only the given accounts.
+    pub fn only_addresses(mut self, addresses: HashSet<Address>) -> Self {
+        self.config.only_addresses = Some(addresses);
+        self
+    }
+
+    /// Same as [`Self::only_addresses()`], but parses the accounts from hex strings,
+    /// e.g. supplied by the user. The strings may have the `0x` prefix, and the case
+    /// of the digits is ignored, so the same address spelled differently is collected once.
+    /// Fails on the first string which isn't a valid address.
+    pub fn only_hex_addresses<S: AsRef<str>>(
+        self,
+        addresses: impl IntoIterator<Item = S>,
+    ) -> Result<Self, InvalidAddressError> {
+        let addresses = addresses
+            .into_iter()
+            .map(|address| parse_hex_address(address.as_ref()))
+            .collect::<Result<_, _>>()?;
+        Ok(self.only_addresses(addresses))
+    }
+
+    /// Report the balances of the given tokens for every account.
+    pub fn tokens(mut self, tokens: Vec<Address>) -> Self {
+        self.config.tokens = tokens;
+        self
+    }
+
+    /// Don't report the bootloader, the system contracts and the precompiles, i.e. the accounts
+    /// in the kernel address space up to `0xffff`, as well as the base token contract.
+    ///
+    /// They are touched by almost every transaction, so the output becomes much smaller,
+    /// but the prestate is no longer enough to replay the transaction.
+    pub fn exclude_system_contracts(mut self, exclude_system_contracts: bool) -> Self {
+        self.config.exclude_system_contracts = exclude_system_contracts;
+        self
+    }
+
+    /// Don't capture the slots of the bootloader scratch space, see
+    /// [`is_bootloader_scratch_slot()`]. Unlike with `exclude_system_contracts`, the rest
+    /// of the system contract slots are reported.
+    pub fn exclude_bootloader_scratch(mut self, exclude_bootloader_scratch: bool) -> Self {
+        self.config.exclude_bootloader_scratch = exclude_bootloader_scratch;
+        self
+    }
+
+    /// Report only the accounts with code before or after the transaction, dropping the EOAs
+    /// whose balance or nonce was touched, e.g. the sender and the recipient of a transfer.
+    pub fn contracts_only(mut self, contracts_only: bool) -> Self {
+        self.config.contracts_only = contracts_only;
+        self
+    }
+
+    /// Format the account addresses in the JSON output with the EIP-55 checksum,
+    /// see [`to_checksum_address()`]. Geth reports them in lowercase.
+    pub fn checksum_addresses(mut self, checksum_addresses: bool) -> Self {
+        self.config.checksum_addresses = checksum_addresses;
+        self
+    }
+
+    /// Format the account addresses in the JSON output with the `formatter`, e.g. to un-alias
+    /// the L1 addresses or to name the system contracts. See [`AddressFormatter`].
+    pub fn address_formatter(mut self, formatter: AddressFormatter) -> Self {
+        self.config.address_formatter = Some(formatter);
+        self
+    }
+
+    /// Report the commitment to the storage slots of every account, see [`storage_root()`].
+    pub fn include_storage_root(mut self, include_storage_root: bool) -> Self {
+        self.config.include_storage_root = include_storage_root;
+        self
+    }
+
+    /// Read the `balance` of the accounts from the given base token contract. Defaults to
+    /// the `L2EthToken` system contract, which holds the balances on the ETH-based chains.
+    pub fn base_token(mut self, base_token: Address) -> Self {
+        self.config.base_token = base_token;
+        self
+    }
+
+    /// Read the `balance` of the accounts from the `base_token` contract when tracing the batches
+    /// of the chain with the `chain_id`, see [`PrestateTracerConfig::for_system_env()`].
+    /// The other chains use the [`base_token()`](Self::base_token()).
+    pub fn chain_base_token(mut self, chain_id: L2ChainId, base_token: Address) -> Self {
+        self.config.chain_base_tokens.insert(chain_id, base_token);
+        self
+    }
+
+    /// Look up the balances of the base token and the `tokens` using the given storage layouts.
+    pub fn balance_slots(mut self, balance_slots: BalanceSlotRegistry) -> Self {
+        self.config.balance_slots = balance_slots;
+        self
+    }
+
+    /// Derive the balance slots with the `hasher` instead of the built-in `keccak256`.
+    pub fn slot_hasher(mut self, hasher: SlotHasher) -> Self {
+        self.config.slot_hasher = hasher;
+        self
+    }
+
+    /// Annotate the reported storage slots with the given preimages, keyed by the slot.
+    pub fn slot_preimages(mut self, preimages: HashMap<H256, SlotPreimage>) -> Self {
+        self.config.slot_preimages = preimages;
+        self
+    }
+
+    /// Render the reported storage slots with the given types, keyed by the slot,
+    /// see [`Account::storage_decoded`]. The slots without a type are only reported raw.
+    pub fn slot_types(mut self, slot_types: HashMap<H256, SlotType>) -> Self {
+        self.config.slot_types = slot_types;
+        self
+    }
+
+    /// Report the number of collected accounts and slots and the collection time to metrics.
+    pub fn metrics(mut self, metrics: bool) -> Self {
+        self.config.metrics = metrics;
+        self
+    }
+
+    /// Report at most the given number of accounts. The result is marked as truncated
+    /// if more accounts are touched.
+    pub fn max_accounts(mut self, max_accounts: usize) -> Self {
+        self.config.max_accounts = max_accounts;
+        self
+    }
+
+    /// Collect at most the given number of storage slots. The result is marked as truncated
+    /// if more slots are accessed.
+    pub fn max_storage_slots(mut self, max_storage_slots: usize) -> Self {
+        self.config.max_storage_slots = max_storage_slots;
+        self
+    }
+
+    /// Report at most the given number of slots for every account, with the lowest slot keys
+    /// so that the output is deterministic. Unlike `max_storage_slots`, the rest of the slots
+    /// are only counted, see [`Account::omitted_slots`], and the result isn't marked as truncated.
+    pub fn max_slots_per_account(mut self, max_slots_per_account: usize) -> Self {
+        self.config.max_slots_per_account = max_slots_per_account;
+        self
+    }
+
+    /// Report at most the given number of leading bytes of the account bytecode, e.g. if only
+    /// the presence of the code matters. The cut bytecode is marked with
+    /// [`Account::code_truncated`], and the code hash and size are still reported in full.
+    pub fn max_code_bytes(mut self, max_code_bytes: usize) -> Self {
+        self.config.max_code_bytes = max_code_bytes;
+        self
+    }
+
+    /// Tag or exclude the bootloader and the [paymaster](Self::paymaster()) in diff mode,
+    /// see [`ProtocolAccounts`]. By default, they are reported like any other account.
+    pub fn protocol_accounts(mut self, protocol_accounts: ProtocolAccounts) -> Self {
+        self.config.protocol_accounts = protocol_accounts;
+        self
+    }
+
+    /// Paymaster of the traced transaction, treated as a protocol-level account. The tracer
+    /// doesn't see the transaction itself, so the paymaster has to be supplied by the caller.
+    pub fn paymaster(mut self, paymaster: Address) -> Self {
+        self.config.paymaster = Some(paymaster);
+        self
+    }
+
+    /// Report the fee part of the balance change of the `payer` in diff mode separately,
+    /// see [`Account::balance_change`]. The payer is the paymaster of the transaction if it has
+    /// one, and the sender otherwise (see `Transaction::payer()`). Like the paymaster, it has
+    /// to be supplied by the caller. By default, only the combined balance is reported.
+    pub fn fee_payer(mut self, payer: Address) -> Self {
+        self.config.fee_payer = Some(payer);
+        self
+    }
+
+    /// Report the balances normalized to 18 decimals alongside the raw ones, given the `decimals`
+    /// of the base token from the system config, see [`Account::balance_wei_normalized`].
+    pub fn normalize_balances(mut self, decimals: u8) -> Self {
+        self.config.base_token_decimals = Some(decimals);
+        self
+    }
+
+    /// Report the pubdata refunds for the cleared slots in diff mode,
+    /// see [`Account::storage_refunds`].
+    pub fn track_refunds(mut self, track_refunds: bool) -> Self {
+        self.config.track_refunds = track_refunds;
+        self
+    }
+
+    /// Take the balances, nonces and code hashes from the modified storage keys if they are there,
+    /// rather than reading them again, e.g. from a storage backed by a remote database.
+    /// The values of the keys must be the ones the storage would return.
+    pub fn reuse_modified_values(mut self, reuse_modified_values: bool) -> Self {
+        self.config.reuse_modified_values = reuse_modified_values;
+        self
+    }
+
+    /// Don't capture the code executed deeper than `max_depth` levels of nested far calls,
+    /// like the call tracer limited with [`CallTracer::with_max_depth()`]. The storage accessed
+    /// by such code is still reported, so that the prestate is complete.
+    ///
+    /// [`CallTracer::with_max_depth()`]: crate::tracers::CallTracer::with_max_depth()
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.config.max_depth = max_depth;
+        self
+    }
+
+    /// Report the gas remaining when the slots were written and the gas consumed by the code
+    /// of every account, see [`Account::storage_gas_remaining`] and [`Account::gas_used`].
+    pub fn gas_attribution(mut self, gas_attribution: bool) -> Self {
+        self.config.gas_attribution = gas_attribution;
+        self
+    }
+
+    /// Report the contract whose code wrote the storage of every account via a delegate call,
+    /// see [`Account::code_source`].
+    pub fn track_code_source(mut self, track_code_source: bool) -> Self {
+        self.config.track_code_source = track_code_source;
+        self
+    }
+
+    /// Report the order in which the accounts were first touched, see
+    /// [`PrestateResult::touch_order`]. The accounts are touched by executing their code
+    /// or by accessing their storage or code hash.
+    pub fn record_touch_order(mut self, record_touch_order: bool) -> Self {
+        self.config.record_touch_order = record_touch_order;
+        self
+    }
+
+    /// Report why every account was touched, see [`Account::touch_reasons`].
+    pub fn record_touch_reasons(mut self, record_touch_reasons: bool) -> Self {
+        self.config.record_touch_reasons = record_touch_reasons;
+        self
+    }
+
+    /// Report the bytecodes of the touched contracts, see [`PrestateResult::factory_deps`].
+    pub fn collect_factory_deps(mut self, collect_factory_deps: bool) -> Self {
+        self.config.collect_factory_deps = collect_factory_deps;
+        self
+    }
+
+    /// Capture the original value of a slot only when it's first written, instead of on every
+    /// access. Only has an effect in the diff mode, which drops the unchanged values anyway;
+    /// the accounts which are only read aren't touched then.
+    pub fn lazy_pre(mut self, lazy_pre: bool) -> Self {
+        self.config.lazy_pre = lazy_pre;
+        self
+    }
+
+    /// Report the slots read and written by the transaction, see
+    /// [`PrestateResult::access_profile`].
+    pub fn access_profile(mut self, access_profile: bool) -> Self {
+        self.config.access_profile = access_profile;
+        self
+    }
+
+    /// Forget the slots and the code accessed by the reverted call frames, see
+    /// [`PrestateTracer::rollback_to()`], since the changes made in them are rolled back
+    /// by the VM. Like Geth, the tracer reports them by default.
+    pub fn rollback_reverted_frames(mut self, rollback_reverted_frames: bool) -> Self {
+        self.config.rollback_reverted_frames = rollback_reverted_frames;
+        self
+    }
+
+    /// Count the storage operations executed by the transaction, see
+    /// [`PrestateResult::op_counts`].
+    pub fn op_counts(mut self, op_counts: bool) -> Self {
+        self.config.op_counts = op_counts;
+        self
+    }
+
+    /// Report the block properties read by the transaction, see
+    /// [`PrestateResult::block_context`]. The block hashes are taken from the captured reads,
+    /// so they aren't reported with `lazy_pre` in diff mode.
+    pub fn block_context(mut self, block_context: bool) -> Self {
+        self.config.block_context = block_context;
+        self
+    }
+
+    /// Report the effective gas price and the fee credited to its recipient in diff mode, see
+    /// [`PrestateResult::effective_gas_price`] and [`PrestateResult::fee_recipient_delta`].
+    pub fn capture_fees(mut self, capture_fees: bool) -> Self {
+        self.config.capture_fees = capture_fees;
+        self
+    }
+
+    /// Report the price of the pubdata and the bytes published by the transaction, see
+    /// [`PrestateResult::gas_per_pubdata`] and [`PrestateResult::pubdata_published`].
+    pub fn capture_pubdata(mut self, capture_pubdata: bool) -> Self {
+        self.config.capture_pubdata = capture_pubdata;
+        self
+    }
+
+    /// Report the slots of the proxies written by several implementations via delegate calls,
+    /// see [`PrestateResult::storage_collisions`].
+    pub fn detect_storage_collisions(mut self, detect_storage_collisions: bool) -> Self {
+        self.config.detect_storage_collisions = detect_storage_collisions;
+        self
+    }
+
+    /// Report the raw storage keys modified in the VM storage, see
+    /// [`PrestateResult::modified_keys`].
+    pub fn record_modified_keys(mut self, record_modified_keys: bool) -> Self {
+        self.config.record_modified_keys = record_modified_keys;
+        self
+    }
+
+    /// Rewrite the collected states with the `processor` before the result is stored,
+    /// see [`StatePostProcessor`].
+    pub fn post_process(mut self, processor: StatePostProcessor) -> Self {
+        self.config.post_process = Some(processor);
+        self
+    }
+
+    /// Take the values known from the `baseline` state, e.g. the prestate of a prior point
+    /// of an incremental indexer, instead of reading them from the storage. The diff is thus
+    /// relative to the baseline rather than to the start of the transaction.
+    ///
+    /// The slots, balances and code hashes of the baseline accounts are used as is, while
+    /// the nonces are only used if both the nonce and the deployment nonce of an account are set,
+    /// since they are stored in the same slot.
+    pub fn baseline(mut self, baseline: State) -> Self {
+        self.config.baseline = Some(Arc::new(baseline));
+        self
+    }
+
+    /// Stop collecting the accounts once the `token` is cancelled. The accounts which are already
+    /// collected are reported in full, and the result is marked as truncated.
+    ///
+    /// The collection happens at the end of the execution, so the execution itself isn't stopped.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.config.cancellation_token = Some(token);
+        self
+    }
+
+    pub fn build(self) -> PrestateTracerConfig {
+        self.config
+    }
+}
+
+/// Source of the account data that is not covered by the modified storage keys:
+/// balances, nonces, code hashes and bytecodes.
+pub trait StateReader {
+    fn read_value(&mut self, key: &StorageKey) -> H256;
+
+    fn load_bytecode(&mut self, hash: H256) -> Option<Vec<u8>>;
+}
+
+/// Storage which the prestate can be collected from without a VM, e.g. a snapshot of
+/// the historical state. Implemented for all the VM storages.
+///
+/// The methods are named differently from the ones of [`WriteStorage`], so that they don't clash
+/// for the VM storages.
+pub trait PrestateStorageReader {
+    fn read_storage_value(&mut self, key: &StorageKey) -> H256;
+
+    fn load_bytecode(&mut self, hash: H256) -> Option<Vec<u8>>;
+
+    /// Returns the keys touched by the traced code, with their values.
+    fn modified_keys(&self) -> &HashMap<StorageKey, H256>;
+}
+
+impl<S: WriteStorage> PrestateStorageReader for S {
+    fn read_storage_value(&mut self, key: &StorageKey) -> H256 {
+        self.read_value(key)
+    }
+
+    fn load_bytecode(&mut self, hash: H256) -> Option<Vec<u8>> {
+        self.load_factory_dep(hash)
+    }
+
+    fn modified_keys(&self) -> &HashMap<StorageKey, H256> {
+        self.modified_storage_keys()
+    }
+}
+
+/// Adapts a [`PrestateStorageReader`] to [`StateReader`].
+struct StorageStateReader<'a, R>(&'a mut R);
+
+impl<R: PrestateStorageReader> StateReader for StorageStateReader<'_, R> {
+    fn read_value(&mut self, key: &StorageKey) -> H256 {
+        self.0.read_storage_value(key)
+    }
+
+    fn load_bytecode(&mut self, hash: H256) -> Option<Vec<u8>> {
+        self.0.load_bytecode(hash)
+    }
+}
+
+/// Collects the state of every account owning one of the [`PrestateStorageReader::modified_keys()`]
+/// of the `storage`, the same as [`process_modified_storage_keys()`].
+///
+/// The slots are reported with the values of the modified keys, so for a VM storage they are
+/// the values written by the executed transactions rather than the original ones reported
+/// by Geth. [`PrestateTracer`] reports the original values, capturing every slot on its first
+/// access, i.e. before it's written.
+pub fn prestate_from_storage(
+    storage: &mut impl PrestateStorageReader,
+    config: &PrestateTracerConfig,
+) -> State {
+    let modified_keys = storage.modified_keys().clone();
+    process_modified_storage_keys(
+        State::new(),
+        &modified_keys,
+        config,
+        &mut StorageStateReader(storage),
+    )
+}
+
+/// Collects the state of every account owning one of the `modified_keys`, looking up
+/// the balances, nonces and code hashes with `read_value`, without any VM storage.
+/// Bytecodes aren't available, so the code of the accounts isn't reported.
+///
+/// Mostly useful to test the collection in isolation, e.g. with the values from a `HashMap`.
+pub fn prestate_from_modified_keys(
+    modified_keys: &HashMap<StorageKey, StorageValue>,
+    config: &PrestateTracerConfig,
+    read_value: impl FnMut(&StorageKey) -> StorageValue,
+) -> State {
+    process_modified_storage_keys(
+        State::new(),
+        modified_keys,
+        config,
+        &mut FnStateReader(read_value),
+    )
+}
+
+/// Adapts a value lookup closure to [`StateReader`].
+struct FnStateReader<F>(F);
+
+impl<F: FnMut(&StorageKey) -> StorageValue> StateReader for FnStateReader<F> {
+    fn read_value(&mut self, key: &StorageKey) -> H256 {
+        (self.0)(key)
+    }
+
+    fn load_bytecode(&mut self, _hash: H256) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Returns the storage values of the `baseline` accounts, see
+/// [`PrestateTracerConfigBuilder::baseline()`].
+fn baseline_storage_values(
+    baseline: &State,
+    config: &PrestateTracerConfig,
+) -> HashMap<StorageKey, H256> {
+    let mut values = HashMap::new();
+    for (address, account) in baseline {
+        let account_id = AccountTreeId::new(*address);
+        let slots = account.storage.iter().flatten();
+        values.extend(slots.map(|(slot, value)| (StorageKey::new(account_id, *slot), *value)));
+        if let Some(balance) = account.balance {
+            values.insert(config.balance_key(&account_id), u256_to_h256(balance));
+        }
+        if let (Some(nonce), Some(deployment_nonce)) = (account.nonce, account.deployment_nonce) {
+            let full_nonce = nonces_to_full_nonce(nonce, deployment_nonce);
+            values.insert(get_nonce_key(address), u256_to_h256(full_nonce));
+        }
+        if let Some(code_hash) = account.code_hash {
+            values.insert(get_code_key(address), code_hash);
+        }
+    }
+    values
+}
+
+/// Reads the state as seen by the VM. If `initial_values` are provided, they take precedence
+/// over the storage, so that the state before the transaction is read.
+struct VmStateReader<'a, S> {
+    storage: &'a mut S,
+    // Bytecodes deployed by the transaction are only known to the decommitter.
+    known_bytecodes: &'a HashMap<U256, Vec<U256>>,
+    initial_values: Option<&'a HashMap<StorageKey, H256>>,
+}
+
+impl<S: WriteStorage> StateReader for VmStateReader<'_, S> {
+    fn read_value(&mut self, key: &StorageKey) -> H256 {
+        match self.initial_values.and_then(|values| values.get(key)) {
+            Some(value) => *value,
+            None => self.storage.read_value(key),
+        }
+    }
+
+    fn load_bytecode(&mut self, hash: H256) -> Option<Vec<u8>> {
+        match self.known_bytecodes.get(&h256_to_u256(hash)) {
+            Some(words) => Some(be_words_to_bytes(words)),
+            None => self.storage.load_factory_dep(hash),
+        }
+    }
+}
+
+/// Collects the state of every account owning one of the `modified_storage_keys`.
+///
+/// Slot values are taken from `modified_storage_keys` as is, while balances, code and nonces
+/// are looked up via `reader`, once per account. Accounts filtered out by `config` are skipped.
+/// If the cancellation token of the `config` is cancelled, only the accounts read before that
+/// are returned.
+pub fn process_modified_storage_keys(
+    prestate: State,
+    modified_storage_keys: &HashMap<StorageKey, H256>,
+    config: &PrestateTracerConfig,
+    reader: &mut impl StateReader,
+) -> State {
+    let mut sink = CollectingSink::default();
+    process_modified_storage_keys_into(&prestate, modified_storage_keys, config, reader, &mut sink);
+    sink.into_state()
+}
+
+/// Consumer of the accounts collected by [`process_modified_storage_keys_into()`], e.g. a writer
+/// persisting them to a database or a file.
+pub trait PrestateSink {
+    /// Handles a collected account. Every account is passed at most once.
+    fn on_account(&mut self, address: Address, account: Account);
+}
+
+/// Sink collecting the accounts into a [`State`], like [`process_modified_storage_keys()`].
+#[derive(Debug, Clone, Default)]
+pub struct CollectingSink {
+    state: State,
+}
+
+impl CollectingSink {
+    pub fn into_state(self) -> State {
+        self.state
+    }
+}
+
+impl PrestateSink for CollectingSink {
+    fn on_account(&mut self, address: Address, account: Account) {
+        self.state.insert(address, account);
+    }
+}
+
+/// Sink sending the accounts over a channel, e.g. to a thread persisting them while the collection
+/// continues. With a bounded [`mpsc::sync_channel()`], the collection is paused while the channel
+/// is full, so that a slow consumer doesn't let the collected accounts pile up in memory.
+///
+/// The accounts are dropped once the receiver is dropped.
+#[derive(Debug, Clone)]
+pub struct ChannelSink(pub mpsc::SyncSender<(Address, Account)>);
+
+impl PrestateSink for ChannelSink {
+    fn on_account(&mut self, address: Address, account: Account) {
+        if self.0.send((address, account)).is_err() {
+            tracing::debug!("Prestate receiver is dropped, account {address:?} is skipped");
+        }
+    }
+}
+
+/// Version of [`process_modified_storage_keys()`] passing every account to the `sink`
+/// as soon as it's read, so that the collected state doesn't have to be kept in memory.
+/// The accounts already present in `prestate` are skipped.
+pub fn process_modified_storage_keys_into(
+    prestate: &State,
+    modified_storage_keys: &HashMap<StorageKey, H256>,
+    config: &PrestateTracerConfig,
+    reader: &mut impl StateReader,
+    sink: &mut impl PrestateSink,
+) {
+    let started_at = config.metrics.then(Instant::now);
+    tracing::debug!(
+        modified_keys = modified_storage_keys.len(),
+        "Collecting prestate from modified storage keys"
+    );
+    let (mut account_count, mut slot_count) = (0_u64, 0_usize);
+    for (address, account) in iter_touched_accounts(prestate, modified_storage_keys, config, reader)
+    {
+        let account_slots = account.storage.as_ref().map_or(0, HashMap::len);
+        tracing::trace!(
+            ?address,
+            slots = account_slots,
+            "Collected prestate account"
+        );
+        account_count += 1;
+        slot_count += account_slots;
+        sink.on_account(address, account);
+    }
+    tracing::debug!(
+        accounts = account_count,
+        slots = slot_count,
+        "Collected prestate from modified storage keys"
+    );
+
+    if let Some(started_at) = started_at {
+        let metrics = &PRESTATE_TRACER_METRICS;
+        metrics.accounts_processed.inc_by(account_count);
+        metrics.slots_per_transaction.observe(slot_count);
+        metrics.collection_duration.observe(started_at.elapsed());
+    }
+}
+
+/// Lazy version of [`process_modified_storage_keys()`], which reads the accounts one by one
+/// as they are consumed, so that the whole state doesn't have to be kept in memory.
+///
+/// The slots are grouped by account up front, and the accounts already present in `prestate`
+/// are skipped.
+pub fn iter_touched_accounts<'a, R: StateReader>(
+    prestate: &State,
+    modified_storage_keys: &'a HashMap<StorageKey, H256>,
+    config: &'a PrestateTracerConfig,
+    reader: &'a mut R,
+) -> impl Iterator<Item = (Address, Account)> + 'a {
+    let mut slots_by_account: HashMap<Address, HashMap<H256, H256>> = HashMap::new();
+    for (key, value) in modified_storage_keys {
+        let address = key.account().address();
+        if prestate.contains_key(address) || !is_address_included(config, address) {
+            continue;
+        }
+        let slots = slots_by_account.entry(*address).or_default();
+        if !config.disable_storage {
+            slots.insert(*key.key(), *value);
+        }
+    }
+    tracing::debug!(
+        accounts = slots_by_account.len(),
+        "Grouped modified storage keys by account"
+    );
+
+    slots_by_account
+        .into_iter()
+        .map_while(move |(address, storage)| {
+            if config.is_cancelled() {
+                return None;
+            }
+            let account = if config.reuse_modified_values {
+                let mut reader = KnownValuesReader {
+                    known_values: modified_storage_keys,
+                    reader: &mut *reader,
+                };
+                read_account(&address, storage, config, &mut reader)
+            } else {
+                read_account(&address, storage, config, reader)
+            };
+            Some((address, account))
+        })
+        .filter(move |(_, account)| !(config.prune_empty_accounts && is_nonexistent(account)))
+}
+
+/// Checks whether the collected account is indistinguishable from a non-existent one.
+fn is_nonexistent(account: &Account) -> bool {
+    let is_zero = |value: Option<U256>| value.map_or(true, |value| value.is_zero());
+    is_zero(account.balance)
+        && is_zero(account.nonce)
+        && is_zero(account.deployment_nonce)
+        && account.code_hash.is_none()
+        && account
+            .storage
+            .iter()
+            .flat_map(HashMap::values)
+            .all(H256::is_zero)
+}
+
+/// Like in Geth, removes the accounts which didn't exist before the transaction from the `pre`
+/// state, and the ones left empty by it from the `post` state, so that only the existing state
+/// of the accounts is reported. Returns the full prestate of the emptied accounts, which
+/// isn't diffed, like for the deleted accounts.
+fn apply_existence_transitions(pre: &mut State, post: &mut State) -> State {
+    let mut emptied_accounts = State::new();
+    for (address, post_account) in post.iter() {
+        let Some(pre_account) = pre.get(address) else {
+            continue;
+        };
+        match (is_nonexistent(pre_account), is_nonexistent(post_account)) {
+            (true, false) => {
+                pre.remove(address);
+            }
+            (false, true) => {
+                emptied_accounts.insert(*address, pre.remove(address).unwrap());
+            }
+            _ => {}
+        }
+    }
+    post.retain(|address, _| !emptied_accounts.contains_key(address));
+    emptied_accounts
+}
+
+/// Takes the values of the `known_values` keys as is, and reads the rest via `reader`.
+struct KnownValuesReader<'a, R> {
+    known_values: &'a HashMap<StorageKey, H256>,
+    reader: &'a mut R,
+}
+
+impl<R: StateReader> StateReader for KnownValuesReader<'_, R> {
+    fn read_value(&mut self, key: &StorageKey) -> H256 {
+        match self.known_values.get(key) {
+            Some(value) => *value,
+            None => self.reader.read_value(key),
+        }
+    }
+
+    fn load_bytecode(&mut self, hash: H256) -> Option<Vec<u8>> {
+        self.reader.load_bytecode(hash)
+    }
+}
+
+/// Returns the addresses of the accounts owning one of the `modified_storage_keys`, i.e. the ones
+/// [`process_modified_storage_keys()`] would report, without reading anything from the storage.
+///
+/// Outside of diff mode, the keys accessed by the tracer also include the read-only ones.
+pub fn touched_addresses(
+    modified_storage_keys: &HashMap<StorageKey, H256>,
+    config: &PrestateTracerConfig,
+) -> HashSet<Address> {
+    modified_storage_keys
+        .keys()
+        .map(|key| *key.account().address())
+        .filter(|address| is_address_included(config, address))
+        .collect()
+}
+
+/// Returns the slots of the `account` among the `modified_storage_keys`, sorted by the slot key,
+/// e.g. to serialize or diff them deterministically.
+///
+/// All the keys are scanned, so [`iter_touched_accounts()`] is cheaper for many accounts.
+pub fn get_sorted_storage_if_present(
+    account: &AccountTreeId,
+    modified_storage_keys: &HashMap<StorageKey, H256>,
+) -> BTreeMap<H256, H256> {
+    modified_storage_keys
+        .iter()
+        .filter(|(key, _)| key.account() == account)
+        .map(|(key, value)| (*key.key(), *value))
+        .collect()
+}
+
+/// Checks whether the slot is a part of the bootloader scratch space, i.e. the storage
+/// of the bootloader itself and the slots of `SystemContext` which the bootloader sets before
+/// executing every transaction: its origin, its gas price and the rolling hash of the transactions
+/// of the L2 block. They are an implementation detail of the bootloader rather than a part
+/// of the state used by the transaction.
+pub fn is_bootloader_scratch_slot(key: &StorageKey) -> bool {
+    let address = *key.account().address();
+    if address == BOOTLOADER_ADDRESS {
+        return true;
+    }
+    let per_transaction_slots = [
+        SYSTEM_CONTEXT_TX_ORIGIN_POSITION,
+        SYSTEM_CONTEXT_GAS_PRICE_POSITION,
+        SYSTEM_CONTEXT_CURRENT_TX_ROLLING_HASH_POSITION,
+    ];
+    address == SYSTEM_CONTEXT_ADDRESS && per_transaction_slots.contains(key.key())
+}
+
+fn parse_hex_address(address: &str) -> Result<Address, InvalidAddressError> {
+    let digits = address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+        .unwrap_or(address);
+    match hex::decode(digits) {
+        Ok(bytes) if bytes.len() == Address::len_bytes() => Ok(Address::from_slice(&bytes)),
+        _ => Err(InvalidAddressError(address.to_owned())),
+    }
+}
+
+fn is_address_included(config: &PrestateTracerConfig, address: &Address) -> bool {
+    if config.exclude_system_contracts
+        && (is_system_contract(address) || *address == config.base_token)
+    {
+        return false;
+    }
+    config
+        .only_addresses
+        .as_ref()
+        .map_or(true, |addresses| addresses.contains(address))
+}
+
+fn read_account(
+    address: &Address,
+    storage: HashMap<H256, H256>,
+    config: &PrestateTracerConfig,
+    reader: &mut impl StateReader,
+) -> Account {
+    let (storage, omitted_slots) = limit_slots(storage, config.max_slots_per_account);
+    if config.storage_only {
+        return Account {
+            storage_preimages: slot_preimages(&storage, &config.slot_preimages),
+            storage: (!config.disable_storage && !storage.is_empty()).then_some(storage),
+            omitted_slots,
+            ..Account::default()
+        };
+    }
+
+    let account_id = AccountTreeId::new(*address);
+    let token_balances = (!config.tokens.is_empty()).then(|| {
+        config
+            .tokens
+            .iter()
+            .map(|token| {
+                let key = config.token_balance_key(&account_id, *token);
+                (*token, h256_to_u256(reader.read_value(&key)))
+            })
+            .collect()
+    });
+    let raw_nonce = h256_to_u256(reader.read_value(&get_nonce_key(address)));
+    let (tx_nonce, deployment_nonce) = try_decode_nonce(raw_nonce).unwrap_or_else(|err| {
+        tracing::warn!("Nonce of account {address:?} is clamped: {err}");
+        decode_nonce(raw_nonce)
+    });
+    let storage_preimages = slot_preimages(&storage, &config.slot_preimages);
+    let code_hash = Some(reader.read_value(&get_code_key(address)))
+        .filter(|code_hash| *code_hash != H256::zero());
+    // Like Geth, the accounts without code (i.e., EOAs) have neither `code` nor `storage` reported.
+    // Slots are still reported for them if accessed, e.g. by the constructor of a contract
+    // which isn't deployed yet.
+    let has_storage = code_hash.is_some() || !storage.is_empty();
+    let balance_key = config.balance_key(&account_id);
+    let balance = h256_to_u256(reader.read_value(&balance_key));
+    let code = if config.disable_code {
+        None
+    } else {
+        code_hash
+            .and_then(|code_hash| reader.load_bytecode(code_hash))
+            .filter(|code| !code.is_empty())
+    };
+    let code_unavailable = !config.disable_code && code_hash.is_some() && code.is_none();
+    let code_truncated = code
+        .as_ref()
+        .map_or(false, |code| code.len() > config.max_code_bytes);
+    let code = code.map(|mut code| {
+        code.truncate(config.max_code_bytes);
+        code
+    });
+    Account {
+        balance: Some(balance),
+        code,
+        // The length is encoded in the versioned bytecode hash, so the bytecode isn't needed.
+        code_size: code_hash.map(bytecode_len_in_bytes),
+        code_hash,
+        nonce: Some(tx_nonce.into()),
+        deployment_nonce: Some(deployment_nonce.into()),
+        storage: (!config.disable_storage && has_storage).then_some(storage),
+        token_balances,
+        storage_preimages,
+        storage_gas_remaining: None,
+        gas_used: None,
+        storage_root: None,
+        code_source: None,
+        storage_decoded: None,
+        init_code: None,
+        omitted_slots,
+        protocol_role: None,
+        balance_wei_normalized: config
+            .base_token_decimals
+            .map(|decimals| normalize_balance(balance, decimals)),
+        storage_refunds: None,
+        touch_reasons: None,
+        balance_change: None,
+        code_unavailable: code_unavailable.then_some(true),
+        code_truncated: code_truncated.then_some(true),
+    }
+}
+
+/// Scales the `balance` of a token with the given `decimals` to 18 decimals. Saturates
+/// on overflow, and rounds down if the token has more decimals.
+fn normalize_balance(balance: U256, decimals: u8) -> U256 {
+    const WEI_DECIMALS: u8 = 18;
+
+    if decimals <= WEI_DECIMALS {
+        balance.saturating_mul(U256::exp10(usize::from(WEI_DECIMALS - decimals)))
+    } else {
+        // The divisor overflows for the absurd decimals, but then any balance rounds down to zero.
+        U256::from(10)
+            .checked_pow((decimals - WEI_DECIMALS).into())
+            .map_or(U256::zero(), |divisor| balance / divisor)
+    }
+}
+
+/// Annotates the post-state accounts with the refunds for the slots cleared by the transaction.
+fn annotate_refunds(
+    post: &mut State,
+    post_values: &HashMap<StorageKey, H256>,
+    initial_values: &HashMap<StorageKey, H256>,
+) {
+    for (key, value) in post_values {
+        let initial_value = initial_values[key];
+        if !value.is_zero() || initial_value.is_zero() {
+            continue;
+        }
+        if let Some(account) = post.get_mut(key.account().address()) {
+            let refunds = account.storage_refunds.get_or_insert_with(HashMap::new);
+            refunds.insert(*key.key(), clear_refund(initial_value));
+        }
+    }
+}
+
+/// Returns the pubdata bytes refunded for clearing a slot with the `initial_value`, priced like
+/// by the storage oracle of the VM. The user prepays for an initial write, while the slot
+/// with a value is already enumerated, so clearing it is a cheaper repeated write.
+fn clear_refund(initial_value: H256) -> u32 {
+    let compressed_value = compress_with_best_strategy(h256_to_u256(initial_value), U256::zero());
+    let price = u32::from(BYTES_PER_ENUMERATION_INDEX) + compressed_value.len() as u32;
+    (INITIAL_STORAGE_WRITE_PUBDATA_BYTES as u32).saturating_sub(price)
+}
+
+/// Tags or drops the protocol-level accounts according to the config.
+fn handle_protocol_accounts(pre: &mut State, post: &mut State, config: &PrestateTracerConfig) {
+    if config.protocol_accounts == ProtocolAccounts::Report {
+        return;
+    }
+    let paymaster = config
+        .paymaster
+        .map(|address| (address, ProtocolRole::Paymaster));
+    for (address, role) in [(BOOTLOADER_ADDRESS, ProtocolRole::Bootloader)]
+        .into_iter()
+        .chain(paymaster)
+    {
+        for state in [&mut *pre, &mut *post] {
+            if config.protocol_accounts == ProtocolAccounts::Exclude {
+                state.remove(&address);
+            } else if let Some(account) = state.get_mut(&address) {
+                account.protocol_role = Some(role);
+            }
+        }
+    }
+}
+
+/// Leaves at most `max_slots` slots with the lowest keys in the `storage`.
+/// Returns the number of the removed slots, if any.
+fn limit_slots(
+    mut storage: HashMap<H256, H256>,
+    max_slots: usize,
+) -> (HashMap<H256, H256>, Option<usize>) {
+    if storage.len() <= max_slots {
+        return (storage, None);
+    }
+    let mut slots: Vec<_> = storage.keys().copied().collect();
+    slots.sort_unstable();
+    for slot in &slots[max_slots..] {
+        storage.remove(slot);
+    }
+    (storage, Some(slots.len() - max_slots))
+}
+
+/// Returns the known preimages of the `storage` slots, or `None` if there are none.
+fn slot_preimages(
+    storage: &HashMap<H256, H256>,
+    preimages: &HashMap<H256, SlotPreimage>,
+) -> Option<HashMap<H256, SlotPreimage>> {
+    if preimages.is_empty() {
+        return None;
+    }
+    let known: HashMap<_, _> = storage
+        .keys()
+        .filter_map(|slot| Some((*slot, preimages.get(slot)?.clone())))
+        .collect();
+    (!known.is_empty()).then_some(known)
+}
+
+/// Splits the full nonce stored by the `NonceHolder` contract into the transaction nonce
+/// and the deployment nonce.
+///
+/// Each of the nonces takes 128 bits of the full nonce, but they are never expected to exceed
+/// `u64::MAX`. The larger ones are clamped to `u64::MAX`; use [`try_decode_nonce()`] to detect
+/// them.
+pub fn decode_nonce(raw: U256) -> (u64, u64) {
+    let (tx_nonce, deployment_nonce) = decompose_full_nonce(raw);
+    let clamp = |nonce: U256| nonce.min(U256::from(u64::MAX)).as_u64();
+    (clamp(tx_nonce), clamp(deployment_nonce))
+}
+
+/// Same as [`decode_nonce()`], but fails if any of the nonces doesn't fit into `u64`,
+/// e.g. if a contract has stored an arbitrary value in the nonce slot.
+pub fn try_decode_nonce(raw: U256) -> Result<(u64, u64), NonceOverflowError> {
+    let (tx_nonce, deployment_nonce) = decompose_full_nonce(raw);
+    let max_nonce = U256::from(u64::MAX);
+    if tx_nonce > max_nonce || deployment_nonce > max_nonce {
+        return Err(NonceOverflowError(raw));
+    }
+    Ok((tx_nonce.as_u64(), deployment_nonce.as_u64()))
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Full nonce {0:#x} doesn't decode into 64-bit nonces")]
+pub struct NonceOverflowError(pub U256);
+
+/// Leaves only the fields and slots that differ between `pre` and `post`,
+/// dropping the accounts that were not changed at all. The changed slots which are zero
+/// after the change are only reported in `pre`.
+fn retain_changes(pre: &mut State, post: &mut State) {
+    for (address, pre_account) in pre.iter_mut() {
+        let post_account = post.entry(*address).or_default();
+        if pre_account.balance == post_account.balance {
+            pre_account.balance = None;
+            post_account.balance = None;
+            pre_account.balance_wei_normalized = None;
+            post_account.balance_wei_normalized = None;
+        }
+        if pre_account.code == post_account.code {
+            pre_account.code = None;
+            post_account.code = None;
+            pre_account.code_truncated = None;
+            post_account.code_truncated = None;
+        }
+        if pre_account.code_hash == post_account.code_hash {
+            pre_account.code_hash = None;
+            post_account.code_hash = None;
+            pre_account.code_size = None;
+            post_account.code_size = None;
+            pre_account.code_unavailable = None;
+            post_account.code_unavailable = None;
+        }
+        if pre_account.nonce == post_account.nonce {
+            pre_account.nonce = None;
+            post_account.nonce = None;
+        }
+        if pre_account.deployment_nonce == post_account.deployment_nonce {
+            pre_account.deployment_nonce = None;
+            post_account.deployment_nonce = None;
+        }
+
+        let mut pre_balances = pre_account.token_balances.take().unwrap_or_default();
+        let mut post_balances = post_account.token_balances.take().unwrap_or_default();
+        pre_balances.retain(|token, balance| post_balances.get(token) != Some(balance));
+        post_balances.retain(|token, _| pre_balances.contains_key(token));
+        if !pre_balances.is_empty() {
+            pre_account.token_balances = Some(pre_balances);
+            post_account.token_balances = Some(post_balances);
+        }
+
+        let mut pre_storage = pre_account.storage.take().unwrap_or_default();
+        let mut post_storage = post_account.storage.take().unwrap_or_default();
+        pre_storage.retain(|key, value| post_storage.get(key) != Some(value));
+        // Like Geth, the slots set to zero are omitted from the post-state.
+        post_storage.retain(|key, value| pre_storage.contains_key(key) && !value.is_zero());
+        if !pre_storage.is_empty() {
+            pre_account.storage = Some(pre_storage);
+            // Reported even if empty, so that the account isn't mistaken for a deleted one.
+            post_account.storage = Some(post_storage);
+        }
+        retain_preimages(pre_account);
+        retain_preimages(post_account);
+    }
+    // The accounts only present in the post-state are new, so all their fields are changed,
+    // but there are no changed slots in an empty storage.
+    for (address, post_account) in post.iter_mut() {
+        let is_empty_storage = post_account.storage.as_ref().is_some_and(HashMap::is_empty);
+        if !pre.contains_key(address) && is_empty_storage {
+            post_account.storage = None;
+        }
+    }
+
+    pre.retain(|_, account| !account.is_empty_change());
+    // The empty storage is only left in the post-state for the accounts with cleared slots.
+    post.retain(|_, account| !account.is_empty_change() || account.storage.is_some());
+}
+
+/// Annotates the reported slots with the gas remaining when they were last written,
+/// and the accounts with the gas consumed by their code.
+fn annotate_gas(
+    state: &mut State,
+    write_gas_remaining: &HashMap<StorageKey, u32>,
+    gas_by_code_address: &HashMap<Address, u64>,
+) {
+    for (address, account) in state.iter_mut() {
+        let account_id = AccountTreeId::new(*address);
+        let gas_remaining: HashMap<_, _> = account
+            .storage
+            .iter()
+            .flat_map(HashMap::keys)
+            .filter_map(|slot| {
+                let gas = write_gas_remaining.get(&StorageKey::new(account_id, *slot))?;
+                Some((*slot, *gas))
+            })
+            .collect();
+        account.storage_gas_remaining = (!gas_remaining.is_empty()).then_some(gas_remaining);
+        account.gas_used = gas_by_code_address.get(address).copied();
+    }
+}
+
+/// Returns the commitment to the `storage` slots, which is `keccak256` of the concatenated
+/// `slot ++ value` pairs sorted by slot, both encoded as 32 bytes.
+///
+/// Only the given slots are committed to, so the root of a partial storage differs from the root
+/// of the full account storage. It is also unrelated to the root of the zkSync state tree.
+pub fn storage_root(storage: &HashMap<H256, H256>) -> H256 {
+    let mut slots: Vec<_> = storage.iter().collect();
+    slots.sort_unstable();
+    let mut preimage = Vec::with_capacity(slots.len() * 64);
+    for (slot, value) in slots {
+        preimage.extend_from_slice(slot.as_bytes());
+        preimage.extend_from_slice(value.as_bytes());
+    }
+    H256(keccak256(&preimage))
+}
+
+/// Sets the storage root of the accounts with the reported storage.
+fn annotate_storage_roots(state: &mut State) {
+    for account in state.values_mut() {
+        account.storage_root = account.storage.as_ref().map(storage_root);
+    }
+}
+
+/// Renders the reported slots of the accounts which have a type in `slot_types`.
+fn annotate_decoded_storage(state: &mut State, slot_types: &HashMap<H256, SlotType>) {
+    for account in state.values_mut() {
+        let Some(storage) = &account.storage else {
+            continue;
+        };
+        let decoded: HashMap<_, _> = storage
+            .iter()
+            .filter_map(|(slot, value)| Some((*slot, slot_types.get(slot)?.render(*value))))
+            .collect();
+        account.storage_decoded = (!decoded.is_empty()).then_some(decoded);
+    }
+}
+
+/// Leaves at most `max_accounts` accounts in `pre` and `post` combined, keeping the ones with
+/// the lowest addresses so that the output is deterministic. Returns whether any were dropped.
+fn truncate_accounts(pre: &mut State, post: &mut State, max_accounts: usize) -> bool {
+    let mut addresses: Vec<_> = pre.keys().chain(post.keys()).copied().collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+    if addresses.len() <= max_accounts {
+        return false;
+    }
+
+    let kept: HashSet<_> = addresses.into_iter().take(max_accounts).collect();
+    pre.retain(|address, _| kept.contains(address));
+    post.retain(|address, _| kept.contains(address));
+    true
+}
+
+/// Drops the preimages of the slots that are no longer reported for the `account`.
+fn retain_preimages(account: &mut Account) {
+    let mut preimages = account.storage_preimages.take().unwrap_or_default();
+    let storage = account.storage.as_ref();
+    preimages.retain(|slot, _| storage.map_or(false, |storage| storage.contains_key(slot)));
+    if !preimages.is_empty() {
+        account.storage_preimages = Some(preimages);
+    }
+}
+
+/// Returns the key of the slot holding the ETH balance of the `account`.
+///
+/// The balance is stored in the `balance` mapping of the `L2EthToken` system contract,
+/// i.e. under `keccak256(address ++ 0)`. On the chains with another base token, use
+/// [`get_balance_key_for_token()`] with the address of the base token contract.
+///
+/// ```
+/// use multivm::tracers::prestate_tracer::get_balance_key;
+/// use zksync_types::{AccountTreeId, Address, H256, L2_ETH_TOKEN_ADDRESS};
+///
+/// let address: Address = "0x36615cf349d7f6344891b1e7ca7c72883f5dc049".parse().unwrap();
+/// let key = get_balance_key(&AccountTreeId::new(address));
+///
+/// assert_eq!(*key.account().address(), L2_ETH_TOKEN_ADDRESS);
+/// let mut preimage = [0_u8; 64];
+/// preimage[12..32].copy_from_slice(address.as_bytes());
+/// let slot = H256(zksync_types::web3::signing::keccak256(&preimage));
+/// assert_eq!(*key.key(), slot);
+/// ```
+pub fn get_balance_key(account: &AccountTreeId) -> StorageKey {
+    get_balance_key_for_token(account, L2_ETH_TOKEN_ADDRESS)
+}
+
+/// Returns the key of the slot holding the `account` balance in the `token` contract.
+/// The balances are looked up in the default [`BalanceSlotRegistry`].
+pub fn get_balance_key_for_token(account: &AccountTreeId, token: Address) -> StorageKey {
+    BalanceSlotRegistry::default().balance_key(account, token)
+}
+
+/// Slots of the `balance` mappings of the token contracts, i.e. the mappings holding the balances
+/// under `keccak256(address ++ base_slot)`.
+///
+/// The tokens which aren't registered are expected to use the storage layout of the standard
+/// L2 ERC20 bridge token, with the mapping at slot 0. The `L2EthToken` contract is registered
+/// by default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceSlotRegistry {
+    base_slots: HashMap<Address, H256>,
+}
+
+impl Default for BalanceSlotRegistry {
+    fn default() -> Self {
+        Self {
+            base_slots: HashMap::from([(L2_ETH_TOKEN_ADDRESS, H256::zero())]),
+        }
+    }
+}
+
+impl BalanceSlotRegistry {
+    /// Registers the slot of the `balance` mapping of the `token`.
+    pub fn with_token(mut self, token: Address, base_slot: H256) -> Self {
+        self.base_slots.insert(token, base_slot);
+        self
+    }
+
+    /// Returns the slot of the `balance` mapping of the `token`.
+    pub fn base_slot(&self, token: Address) -> H256 {
+        self.base_slots.get(&token).copied().unwrap_or_default()
+    }
+
+    /// Returns the key of the slot holding the `account` balance in the `token` contract.
+    pub fn balance_key(&self, account: &AccountTreeId, token: Address) -> StorageKey {
+        self.balance_key_with(account, token, &SlotHasher::default())
+    }
+
+    /// Same as [`Self::balance_key()`], but hashes the slot with the `hasher`.
+    pub fn balance_key_with(
+        &self,
+        account: &AccountTreeId,
+        token: Address,
+        hasher: &SlotHasher,
+    ) -> StorageKey {
+        let preimage = SlotPreimage {
+            base_slot: self.base_slot(token),
+            key: address_to_h256(account.address()),
+        };
+        StorageKey::new(AccountTreeId::new(token), preimage.slot_with(hasher))
+    }
+}
+
+#[cfg(test)]
+mod tests;
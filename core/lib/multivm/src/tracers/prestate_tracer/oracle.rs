@@ -0,0 +1,115 @@
+//! Captured prestate in the shape consumed by the storage oracle of the witness generator:
+//! the initial values of the storage slots keyed by their enumeration indices in the state tree.
+
+use zksync_state::ReadStorage;
+use zksync_types::{StorageKey, H256};
+
+use crate::tracers::prestate_tracer::{PrestateResult, PrestateTracerConfig};
+
+/// Source of the enumeration indices of the storage slots, i.e. the order the slots were
+/// first written to the state tree in.
+pub trait EnumerationIndexSource {
+    /// Returns `None` if the slot was never written, i.e. it isn't a leaf of the tree yet.
+    fn enumeration_index(&mut self, key: &StorageKey) -> Option<u64>;
+}
+
+impl<S: ReadStorage> EnumerationIndexSource for S {
+    fn enumeration_index(&mut self, key: &StorageKey) -> Option<u64> {
+        self.get_enumeration_index(key)
+    }
+}
+
+/// Initial value of a storage slot read or written by the traced transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OracleStorageEntry {
+    pub key: StorageKey,
+    /// `None` for the slots absent from the tree, the first write of which is an initial one.
+    pub enumeration_index: Option<u64>,
+    pub initial_value: H256,
+}
+
+impl PrestateResult {
+    /// Converts the captured pre-state into the input of the storage oracle, so that
+    /// the prover doesn't have to read the state again.
+    ///
+    /// Like in [`Self::to_witness()`], the balances, nonces and code hashes are converted
+    /// to the slots of the system contracts storing them, and the `config` must be the one
+    /// the result was collected with. The entries are sorted by the enumeration index,
+    /// followed by the slots absent from the tree, sorted by the hashed key.
+    pub fn to_oracle_input(
+        &self,
+        indices: &mut impl EnumerationIndexSource,
+        config: &PrestateTracerConfig,
+    ) -> Vec<OracleStorageEntry> {
+        let mut entries: Vec<_> = self
+            .pre_storage_values(config)
+            .into_iter()
+            .map(|(key, initial_value)| OracleStorageEntry {
+                enumeration_index: indices.enumeration_index(&key),
+                key,
+                initial_value,
+            })
+            .collect();
+        // The sort is stable, so the new slots stay sorted by the hashed key.
+        entries.sort_by_key(|entry| (entry.enumeration_index.is_none(), entry.enumeration_index));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use zksync_types::{get_code_key, AccountTreeId, Address};
+
+    use super::*;
+    use crate::tracers::prestate_tracer::{Account, State};
+
+    struct TestIndices(HashMap<StorageKey, u64>);
+
+    impl EnumerationIndexSource for TestIndices {
+        fn enumeration_index(&mut self, key: &StorageKey) -> Option<u64> {
+            self.0.get(key).copied()
+        }
+    }
+
+    #[test]
+    fn oracle_input_maps_slots_to_enumeration_indices() {
+        let address = Address::repeat_byte(0x01);
+        let slot =
+            |slot: u64| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+        let account = Account {
+            code_hash: Some(H256::repeat_byte(0xcc)),
+            storage: Some(HashMap::from([
+                (*slot(0).key(), H256::from_low_u64_be(1)),
+                (*slot(1).key(), H256::from_low_u64_be(2)),
+                (*slot(2).key(), H256::zero()),
+                (*slot(3).key(), H256::zero()),
+            ])),
+            ..Account::default()
+        };
+        let result = PrestateResult::from((State::from([(address, account)]), State::new()));
+        let mut indices = TestIndices(HashMap::from([
+            (slot(0), 7),
+            (slot(1), 3),
+            (get_code_key(&address), 5),
+        ]));
+
+        let entries = result.to_oracle_input(&mut indices, &PrestateTracerConfig::default());
+        let entry = |key, enumeration_index, initial_value| OracleStorageEntry {
+            key,
+            enumeration_index,
+            initial_value,
+        };
+        let mut new_slots = [slot(2), slot(3)];
+        new_slots.sort_unstable_by_key(StorageKey::hashed_key);
+        let expected = vec![
+            entry(slot(1), Some(3), H256::from_low_u64_be(2)),
+            entry(get_code_key(&address), Some(5), H256::repeat_byte(0xcc)),
+            entry(slot(0), Some(7), H256::from_low_u64_be(1)),
+            entry(new_slots[0], None, H256::zero()),
+            entry(new_slots[1], None, H256::zero()),
+        ];
+        assert_eq!(entries, expected);
+    }
+}
@@ -0,0 +1,251 @@
+//! State overrides applied before tracing, like `stateOverrides` of Geth's `debug_traceCall`.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zksync_state::WriteStorage;
+use zksync_types::{
+    get_code_key, get_known_code_key, get_nonce_key,
+    utils::{decompose_full_nonce, nonces_to_full_nonce},
+    AccountTreeId, Address, StorageKey, H256, U256,
+};
+use zksync_utils::{
+    bytecode::{hash_bytecode, validate_bytecode, InvalidBytecodeError},
+    h256_to_u256, u256_to_h256,
+};
+
+use crate::tracers::prestate_tracer::get_balance_key_for_token;
+
+/// Overrides of the accounts state, keyed by the account address.
+pub type StateOverride = HashMap<Address, AccountOverride>;
+
+/// Override of a single account. The fields which are not set are left intact.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    /// Transaction nonce of the account. The deployment nonce is left intact.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U256>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "super::bytecode_as_hex"
+    )]
+    pub code: Option<Vec<u8>>,
+    /// Replaces the whole storage of the account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<HashMap<H256, H256>>,
+    /// Replaces only the given slots of the account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<HashMap<H256, H256>>,
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum StateOverrideError {
+    #[error("Both `state` and `stateDiff` are overridden for account {0:?}")]
+    StateAndStateDiff(Address),
+    #[error("Invalid code override for account {0:?}: {1}")]
+    InvalidCode(Address, InvalidBytecodeError),
+}
+
+/// Writes the `overrides` to the `storage`, so that the VM and the tracers observe
+/// the overridden state. The balances are written to the `base_token` contract,
+/// which is `L2_ETH_TOKEN_ADDRESS` on the ETH-based chains.
+///
+/// The storage cannot enumerate the slots of an account, so a full `state` override only resets
+/// the slots which were already written to the `storage`. The rest of the slots have to be unset
+/// in the underlying storage.
+///
+/// Returns the overridden bytecodes keyed by their hash. They aren't a part of the storage,
+/// so they have to be supplied to the VM separately, e.g. as factory dependencies.
+pub fn apply_state_override<S: WriteStorage>(
+    storage: &mut S,
+    overrides: &StateOverride,
+    base_token: Address,
+) -> Result<HashMap<H256, Vec<u8>>, StateOverrideError> {
+    let mut bytecodes = HashMap::new();
+    for (address, account) in overrides {
+        if account.state.is_some() && account.state_diff.is_some() {
+            return Err(StateOverrideError::StateAndStateDiff(*address));
+        }
+
+        if let Some(balance) = account.balance {
+            let key = get_balance_key_for_token(&AccountTreeId::new(*address), base_token);
+            storage.set_value(key, u256_to_h256(balance));
+        }
+        if let Some(nonce) = account.nonce {
+            let key = get_nonce_key(address);
+            // The deployment nonce is kept as is, even if it doesn't fit into the decoded nonces.
+            let (_, deployment_nonce) =
+                decompose_full_nonce(h256_to_u256(storage.read_value(&key)));
+            let full_nonce = nonces_to_full_nonce(nonce, deployment_nonce);
+            storage.set_value(key, u256_to_h256(full_nonce));
+        }
+        if let Some(code) = &account.code {
+            validate_bytecode(code)
+                .map_err(|err| StateOverrideError::InvalidCode(*address, err))?;
+            let code_hash = hash_bytecode(code);
+            storage.set_value(get_code_key(address), code_hash);
+            storage.set_value(get_known_code_key(&code_hash), H256::from_low_u64_be(1));
+            bytecodes.insert(code_hash, code.clone());
+        }
+
+        let account_id = AccountTreeId::new(*address);
+        if let Some(state) = &account.state {
+            let written_slots: Vec<_> = storage
+                .modified_storage_keys()
+                .keys()
+                .filter(|key| *key.account() == account_id && !state.contains_key(key.key()))
+                .copied()
+                .collect();
+            for key in written_slots {
+                storage.set_value(key, H256::zero());
+            }
+        }
+        for (slot, value) in account.state.iter().chain(&account.state_diff).flatten() {
+            storage.set_value(StorageKey::new(account_id, *slot), *value);
+        }
+    }
+    Ok(bytecodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_state::{InMemoryStorage, ReadStorage, StorageView};
+    use zksync_types::L2_ETH_TOKEN_ADDRESS;
+
+    use super::*;
+
+    fn slot_key(address: Address, slot: u64) -> StorageKey {
+        StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot))
+    }
+
+    #[test]
+    fn overrides_are_written_to_storage() {
+        let address = Address::repeat_byte(0x01);
+        let nonce_key = get_nonce_key(&address);
+        let mut raw_storage = InMemoryStorage::default();
+        raw_storage.set_value(
+            nonce_key,
+            u256_to_h256(nonces_to_full_nonce(3.into(), 2.into())),
+        );
+        raw_storage.set_value(slot_key(address, 1), H256::from_low_u64_be(1));
+        let mut storage = StorageView::new(raw_storage);
+
+        let code = vec![0; 32];
+        let overrides = StateOverride::from([(
+            address,
+            AccountOverride {
+                balance: Some(100.into()),
+                nonce: Some(5.into()),
+                code: Some(code.clone()),
+                state_diff: Some(HashMap::from([(
+                    H256::from_low_u64_be(2),
+                    H256::from_low_u64_be(2),
+                )])),
+                ..AccountOverride::default()
+            },
+        )]);
+        let bytecodes =
+            apply_state_override(&mut storage, &overrides, L2_ETH_TOKEN_ADDRESS).unwrap();
+
+        let balance_key =
+            get_balance_key_for_token(&AccountTreeId::new(address), L2_ETH_TOKEN_ADDRESS);
+        assert_eq!(h256_to_u256(storage.read_value(&balance_key)), 100.into());
+        let full_nonce = h256_to_u256(storage.read_value(&nonce_key));
+        assert_eq!(decompose_full_nonce(full_nonce), (5.into(), 2.into()));
+        let code_hash = hash_bytecode(&code);
+        assert_eq!(storage.read_value(&get_code_key(&address)), code_hash);
+        assert_eq!(bytecodes, HashMap::from([(code_hash, code)]));
+        // Slots which aren't overridden are left intact.
+        assert_eq!(
+            storage.read_value(&slot_key(address, 1)),
+            H256::from_low_u64_be(1)
+        );
+        assert_eq!(
+            storage.read_value(&slot_key(address, 2)),
+            H256::from_low_u64_be(2)
+        );
+    }
+
+    #[test]
+    fn full_state_override_resets_written_slots() {
+        let address = Address::repeat_byte(0x01);
+        let mut storage = StorageView::new(InMemoryStorage::default());
+        storage.set_value(slot_key(address, 1), H256::from_low_u64_be(1));
+
+        let overrides = StateOverride::from([(
+            address,
+            AccountOverride {
+                state: Some(HashMap::from([(
+                    H256::from_low_u64_be(2),
+                    H256::from_low_u64_be(2),
+                )])),
+                ..AccountOverride::default()
+            },
+        )]);
+        apply_state_override(&mut storage, &overrides, L2_ETH_TOKEN_ADDRESS).unwrap();
+
+        assert_eq!(storage.read_value(&slot_key(address, 1)), H256::zero());
+        assert_eq!(
+            storage.read_value(&slot_key(address, 2)),
+            H256::from_low_u64_be(2)
+        );
+    }
+
+    #[test]
+    fn invalid_overrides_are_rejected() {
+        let address = Address::repeat_byte(0x01);
+        let mut storage = StorageView::new(InMemoryStorage::default());
+
+        let overrides = StateOverride::from([(
+            address,
+            AccountOverride {
+                state: Some(HashMap::new()),
+                state_diff: Some(HashMap::new()),
+                ..AccountOverride::default()
+            },
+        )]);
+        assert_eq!(
+            apply_state_override(&mut storage, &overrides, L2_ETH_TOKEN_ADDRESS),
+            Err(StateOverrideError::StateAndStateDiff(address))
+        );
+
+        let overrides = StateOverride::from([(
+            address,
+            AccountOverride {
+                code: Some(vec![0; 31]),
+                ..AccountOverride::default()
+            },
+        )]);
+        assert!(matches!(
+            apply_state_override(&mut storage, &overrides, L2_ETH_TOKEN_ADDRESS),
+            Err(StateOverrideError::InvalidCode(..))
+        ));
+    }
+
+    #[test]
+    fn override_is_deserialized_like_geth() {
+        let json = r#"{
+            "balance": "0x64",
+            "code": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "stateDiff": {
+                "0x0000000000000000000000000000000000000000000000000000000000000001":
+                "0x0000000000000000000000000000000000000000000000000000000000000002"
+            }
+        }"#;
+        let account: AccountOverride = serde_json::from_str(json).unwrap();
+        assert_eq!(account.balance, Some(100.into()));
+        assert_eq!(account.code, Some(vec![0; 32]));
+        assert_eq!(
+            account.state_diff,
+            Some(HashMap::from([(
+                H256::from_low_u64_be(1),
+                H256::from_low_u64_be(2)
+            )]))
+        );
+    }
+}
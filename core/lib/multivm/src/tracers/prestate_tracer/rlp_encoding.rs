@@ -0,0 +1,149 @@
+//! Deterministic RLP encoding of the prestate for the proof pipelines.
+//!
+//! The state maps are unordered, so the accounts are sorted by address and the storage slots
+//! by key before encoding. Logically equal results thus always produce the same bytes.
+
+use rlp::RlpStream;
+use zksync_types::{H256, U256};
+
+use crate::tracers::prestate_tracer::{Account, PrestateResult, State};
+
+impl PrestateResult {
+    /// Encodes the result as an RLP list `[pre, post]`. The post state is encoded as an empty
+    /// list if it isn't reported.
+    ///
+    /// Each state is a list of `[address, balance, nonce, codeHash, [[slot, value], ..]]` items
+    /// sorted by address, with the storage pairs sorted by slot. The fields which weren't
+    /// collected are encoded as empty strings.
+    pub fn rlp_encode(&self) -> Vec<u8> {
+        let mut rlp = RlpStream::new_list(2);
+        rlp_append_state(&mut rlp, &self.pre);
+        match &self.post {
+            Some(post) => rlp_append_state(&mut rlp, post),
+            None => {
+                rlp.begin_list(0);
+            }
+        }
+        rlp.out().to_vec()
+    }
+}
+
+fn rlp_append_state(rlp: &mut RlpStream, state: &State) {
+    let mut accounts: Vec<_> = state.iter().collect();
+    accounts.sort_unstable_by_key(|(address, _)| **address);
+
+    rlp.begin_list(accounts.len());
+    for (address, account) in accounts {
+        rlp.begin_list(5);
+        rlp.append(&address.as_bytes());
+        rlp_append_account(rlp, account);
+    }
+}
+
+fn rlp_append_account(rlp: &mut RlpStream, account: &Account) {
+    rlp_opt_u256(rlp, account.balance);
+    rlp_opt_u256(rlp, account.nonce);
+    match &account.code_hash {
+        Some(hash) => rlp.append(&hash.as_bytes()),
+        None => rlp.append(&""),
+    };
+
+    let mut storage: Vec<(&H256, &H256)> = account.storage.iter().flatten().collect();
+    storage.sort_unstable();
+    rlp.begin_list(storage.len());
+    for (slot, value) in storage {
+        rlp.begin_list(2);
+        rlp.append(&slot.as_bytes());
+        rlp.append(&value.as_bytes());
+    }
+}
+
+fn rlp_opt_u256(rlp: &mut RlpStream, value: Option<U256>) {
+    match value {
+        Some(value) => rlp.append(&value),
+        None => rlp.append(&""),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use zksync_types::Address;
+
+    use super::*;
+
+    fn account(seed: u8, slots: &[(H256, H256)]) -> Account {
+        Account {
+            balance: Some(U256::from(seed) * 1_000),
+            nonce: Some(seed.into()),
+            code_hash: Some(H256::repeat_byte(seed)),
+            storage: Some(slots.iter().copied().collect()),
+            ..Account::default()
+        }
+    }
+
+    #[test]
+    fn rlp_encoding_does_not_depend_on_map_order() {
+        let slots: Vec<_> = (0..32)
+            .map(|i| (H256::from_low_u64_be(i), H256::from_low_u64_be(i * 7)))
+            .collect();
+        let reversed_slots: Vec<_> = slots.iter().rev().copied().collect();
+        let addresses: Vec<_> = (1..=16).map(Address::repeat_byte).collect();
+
+        let state: State = addresses
+            .iter()
+            .map(|&address| (address, account(address[0], &slots)))
+            .collect();
+        // Grow the map from a different capacity and in the opposite order, so that
+        // the iteration order of both the accounts and the slots differs.
+        let mut other_state = HashMap::with_capacity(1_024);
+        for &address in addresses.iter().rev() {
+            other_state.insert(address, account(address[0], &reversed_slots));
+        }
+        assert_eq!(state, other_state);
+
+        let result = PrestateResult::from((state.clone(), state));
+        let other_result = PrestateResult::from((other_state.clone(), other_state));
+        assert_eq!(result.rlp_encode(), other_result.rlp_encode());
+    }
+
+    #[test]
+    fn rlp_encoding_of_missing_fields() {
+        let result = PrestateResult {
+            pre: State::from([(Address::zero(), Account::default())]).into(),
+            post: None,
+            truncated: false,
+            failed: false,
+            revert_reason: None,
+            return_data: None,
+            tx_hash: None,
+            tx_index: None,
+            vm_version: None,
+            touch_order: None,
+            factory_deps: None,
+            access_profile: None,
+            op_counts: None,
+            replay_safe: false,
+            block_context: None,
+            effective_gas_price: None,
+            fee_recipient_delta: None,
+            gas_per_pubdata: None,
+            pubdata_published: None,
+            storage_collisions: None,
+            modified_keys: None,
+            created_addresses: Vec::new(),
+            counts: Default::default(),
+        };
+        let encoded = result.rlp_encode();
+
+        let rlp = rlp::Rlp::new(&encoded);
+        assert_eq!(rlp.item_count().unwrap(), 2);
+        assert_eq!(rlp.at(1).unwrap().item_count().unwrap(), 0);
+        let account = rlp.at(0).unwrap().at(0).unwrap();
+        assert_eq!(account.item_count().unwrap(), 5);
+        assert!(account.at(1).unwrap().is_empty());
+        assert!(account.at(3).unwrap().is_empty());
+        assert_eq!(account.at(4).unwrap().item_count().unwrap(), 0);
+    }
+}
@@ -0,0 +1,196 @@
+//! JSON Schema of the prestate output, e.g. to generate the types deserializing the traces
+//! in other languages.
+//!
+//! The schema of an account is derived from the serialization of a sample account with all
+//! the fields set, so it follows the serde attributes of [`Account`]: the fields are listed
+//! under their JSON names, with the types of their serialized values. The sample is built
+//! without the defaults, so a new field has to be added to it to compile.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::{json, Map, Value};
+use zksync_types::{Address, H256, U256};
+
+use crate::tracers::prestate_tracer::{
+    Account, BalanceChange, BalanceDelta, PrestateResult, ProtocolRole, SlotPreimage, TouchReason,
+};
+
+/// Pattern of the hex-encoded values, i.e. the quantities, hashes, addresses and bytecodes.
+/// The digits are matched regardless of the case, since the addresses may be checksummed.
+const HEX_PATTERN: &str = "^0x[0-9a-fA-F]*$";
+
+impl PrestateResult {
+    /// Returns the JSON Schema (draft 2020-12) of the output of [`Self::to_json()`]: the state
+    /// keyed by the account addresses, or both `pre` and `post` states in diff mode.
+    ///
+    /// All the account fields are optional, since the ones which weren't collected are omitted.
+    /// The accounts are defined once, under `$defs/account`.
+    pub fn json_schema(diff_mode: bool) -> Value {
+        let state = json!({
+            "type": "object",
+            "additionalProperties": { "$ref": "#/$defs/account" },
+        });
+        let mut schema = if diff_mode {
+            json!({
+                "type": "object",
+                "properties": { "pre": state, "post": state },
+                "required": ["pre", "post"],
+                "additionalProperties": false,
+            })
+        } else {
+            state
+        };
+        let fields = schema.as_object_mut().expect("schema is an object");
+        fields.insert(
+            "$schema".to_owned(),
+            "https://json-schema.org/draft/2020-12/schema".into(),
+        );
+        fields.insert("title".to_owned(), "PrestateTracerResult".into());
+        fields.insert("$defs".to_owned(), json!({ "account": account_schema() }));
+        schema
+    }
+}
+
+fn account_schema() -> Value {
+    let account = serde_json::to_value(sample_account()).expect("failed to serialize account");
+    let mut schema = schema_of(&account);
+    // Unlike the nested structs, none of the account fields is always reported.
+    schema
+        .as_object_mut()
+        .expect("account schema is an object")
+        .remove("required");
+    schema
+}
+
+/// Returns the schema of the `value` serialized from a Rust type. The objects keyed
+/// by hex values are the maps, e.g. the storage, and the rest of them are the structs.
+fn schema_of(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({ "type": "null" }),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(number) if number.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer", "minimum": 0 }),
+        Value::String(string) if is_hex(string) => {
+            json!({ "type": "string", "pattern": HEX_PATTERN })
+        }
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(items) => {
+            let items = items.first().map_or_else(|| json!({}), schema_of);
+            json!({ "type": "array", "items": items, "uniqueItems": true })
+        }
+        Value::Object(fields) if fields.keys().all(|key| is_hex(key)) => {
+            let values = fields.values().next().map_or_else(|| json!({}), schema_of);
+            json!({
+                "type": "object",
+                "propertyNames": { "pattern": HEX_PATTERN },
+                "additionalProperties": values,
+            })
+        }
+        Value::Object(fields) => {
+            let properties: Map<_, _> = fields
+                .iter()
+                .map(|(name, value)| (name.clone(), schema_of(value)))
+                .collect();
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": fields.keys().collect::<Vec<_>>(),
+                "additionalProperties": false,
+            })
+        }
+    }
+}
+
+fn is_hex(string: &str) -> bool {
+    string.strip_prefix("0x").map_or(false, |digits| {
+        digits.bytes().all(|b| b.is_ascii_hexdigit())
+    })
+}
+
+/// Account with all the fields set, and with a single entry in every map and set.
+fn sample_account() -> Account {
+    let slot = H256::from_low_u64_be(1);
+    Account {
+        balance: Some(U256::one()),
+        code: Some(vec![0; 32]),
+        code_hash: Some(H256::zero()),
+        code_size: Some(32),
+        nonce: Some(U256::one()),
+        deployment_nonce: Some(U256::one()),
+        storage: Some(HashMap::from([(slot, H256::zero())])),
+        token_balances: Some(HashMap::from([(Address::zero(), U256::one())])),
+        storage_preimages: Some(HashMap::from([(
+            slot,
+            SlotPreimage {
+                base_slot: H256::zero(),
+                key: H256::zero(),
+            },
+        )])),
+        storage_gas_remaining: Some(HashMap::from([(slot, 1)])),
+        gas_used: Some(1),
+        storage_root: Some(H256::zero()),
+        code_source: Some(Address::zero()),
+        storage_decoded: Some(HashMap::from([(slot, "true".to_owned())])),
+        init_code: Some(vec![0; 32]),
+        omitted_slots: Some(1),
+        protocol_role: Some(ProtocolRole::Bootloader),
+        balance_wei_normalized: Some(U256::one()),
+        storage_refunds: Some(HashMap::from([(slot, 1)])),
+        touch_reasons: Some(BTreeSet::from([TouchReason::Written])),
+        balance_change: Some(BalanceChange {
+            value_delta: BalanceDelta::increase(U256::one()),
+            fee_delta: BalanceDelta::decrease(U256::one()),
+        }),
+        code_unavailable: Some(false),
+        code_truncated: Some(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_schema_has_geth_fields() {
+        let schema = PrestateResult::json_schema(false);
+        assert_eq!(schema["additionalProperties"]["$ref"], "#/$defs/account");
+        let account = &schema["$defs"]["account"];
+        assert_eq!(account["type"], "object");
+        assert_eq!(account.get("required"), None);
+
+        let properties = &account["properties"];
+        let hex_string = json!({ "type": "string", "pattern": HEX_PATTERN });
+        assert_eq!(properties["balance"], hex_string);
+        assert_eq!(
+            properties["nonce"],
+            json!({ "type": "integer", "minimum": 0 })
+        );
+        assert_eq!(properties["code"], hex_string);
+        assert_eq!(properties["storage"]["type"], "object");
+        assert_eq!(properties["storage"]["additionalProperties"], hex_string);
+        assert_eq!(
+            properties["storagePreimages"]["additionalProperties"]["type"],
+            "object"
+        );
+        assert_eq!(
+            properties["touchReasons"]["items"],
+            json!({ "type": "string" })
+        );
+
+        // Every serialized field is described.
+        let serialized = serde_json::to_value(sample_account()).unwrap();
+        let serialized = serialized.as_object().unwrap();
+        assert_eq!(properties.as_object().unwrap().len(), serialized.len());
+    }
+
+    #[test]
+    fn diff_mode_schema_has_both_states() {
+        let schema = PrestateResult::json_schema(true);
+        assert_eq!(schema["required"], json!(["pre", "post"]));
+        for state in ["pre", "post"] {
+            let accounts = &schema["properties"][state]["additionalProperties"];
+            assert_eq!(accounts["$ref"], "#/$defs/account");
+        }
+        assert!(schema["$defs"]["account"]["properties"]["balance"].is_object());
+    }
+}
@@ -0,0 +1,344 @@
+//! Assertions on the captured states for the tests of the contracts, e.g. comparing a prestate
+//! with an expected fixture. Only available with the `test-util` feature.
+//!
+//! The fixtures shared by the unit tests of the tracer are defined here as well.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write as _,
+};
+
+use zksync_types::H256;
+
+use crate::tracers::prestate_tracer::{
+    divergence::compare_states, flat::FLAT_FIELDS, Account, Divergence, DivergencePath,
+    DivergentState, PrestateResult, State,
+};
+
+/// Asserts that the `actual` state equals the `expected` one.
+///
+/// On mismatch, the panic message lists the differing accounts, fields and slots one per line,
+/// rather than dumping both states.
+#[track_caller]
+pub fn assert_state_eq(actual: &State, expected: &State) {
+    let mut divergences = vec![];
+    compare_states(DivergentState::Pre, actual, expected, &mut divergences);
+    if divergences.is_empty() {
+        return;
+    }
+    divergences.sort_by(|a, b| (a.address, &a.path).cmp(&(b.address, &b.path)));
+
+    let mut message = "states differ:".to_owned();
+    for divergence in &divergences {
+        write!(message, "\n  {}", format_divergence(divergence)).unwrap();
+    }
+    panic!("{message}");
+}
+
+/// Asserts that the `diff` mode result of a transaction is consistent with the `default` mode
+/// result of the same transaction: every account and slot of the diff is reported by the default
+/// mode, and the values of the diff pre-state are the same as in the default mode prestate.
+/// The annotations of the accounts aren't compared.
+///
+/// On mismatch, the panic message lists the inconsistent accounts, fields and slots one per line.
+#[track_caller]
+pub fn assert_diff_subset_of_default(diff: &PrestateResult, default: &PrestateResult) {
+    let diff_post = diff
+        .post
+        .as_ref()
+        .expect("diff result has no post-state, i.e. it wasn't collected in diff mode");
+    let mut inconsistencies = vec![];
+    let addresses: BTreeSet<_> = diff.pre.keys().chain(diff_post.keys()).collect();
+    for address in addresses {
+        let Some(default_account) = default.pre.get(address) else {
+            inconsistencies.push(format!("{address:?} account: missing in default mode"));
+            continue;
+        };
+        if let Some(diff_account) = diff.pre.get(address) {
+            let (diff_fields, default_fields) = (fields(diff_account), fields(default_account));
+            for name in FLAT_FIELDS {
+                let Some(value) = diff_fields.get(name) else {
+                    continue;
+                };
+                if default_fields.get(name) != Some(value) {
+                    inconsistencies.push(format!(
+                        "{address:?} field `{name}`: diff pre {value}, default {}",
+                        format_json(default_fields.get(name))
+                    ));
+                }
+            }
+        }
+
+        let empty = HashMap::new();
+        let storage = |state: &State| {
+            state
+                .get(address)
+                .and_then(|account| account.storage.as_ref())
+                .unwrap_or(&empty)
+        };
+        let (pre_storage, post_storage) = (storage(&diff.pre), storage(diff_post));
+        let default_storage = default_account.storage.as_ref().unwrap_or(&empty);
+        let slots: BTreeSet<_> = pre_storage.keys().chain(post_storage.keys()).collect();
+        for slot in slots {
+            let default_value = default_storage.get(slot);
+            // The slots only in the post-state have to be reported, but their values differ.
+            let is_consistent = match pre_storage.get(slot) {
+                Some(pre_value) => default_value == Some(pre_value),
+                None => default_value.is_some(),
+            };
+            if !is_consistent {
+                let format_value = |value: Option<&H256>| {
+                    value.map_or_else(|| "<missing>".to_owned(), |value| format!("{value:?}"))
+                };
+                inconsistencies.push(format!(
+                    "{address:?} slot {slot:?}: diff pre {}, default {}",
+                    format_value(pre_storage.get(slot)),
+                    format_value(default_value)
+                ));
+            }
+        }
+    }
+    if !inconsistencies.is_empty() {
+        panic!(
+            "diff is inconsistent with default mode:\n  {}",
+            inconsistencies.join("\n  ")
+        );
+    }
+}
+
+fn fields(account: &Account) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::to_value(account).expect("failed to serialize account") {
+        serde_json::Value::Object(fields) => fields,
+        _ => unreachable!("account is serialized as an object"),
+    }
+}
+
+fn format_json(value: Option<&serde_json::Value>) -> String {
+    value.map_or_else(|| "<missing>".to_owned(), ToString::to_string)
+}
+
+fn format_divergence(divergence: &Divergence) -> String {
+    let path = match &divergence.path {
+        DivergencePath::Account => "account".to_owned(),
+        DivergencePath::Field(name) => format!("field `{name}`"),
+        DivergencePath::Slot(slot) => format!("slot {slot:?}"),
+    };
+    let format_value = |value: &Option<serde_json::Value>| {
+        value
+            .as_ref()
+            .map_or_else(|| "<missing>".to_owned(), ToString::to_string)
+    };
+    format!(
+        "{:?} {path}: actual {}, expected {}",
+        divergence.address,
+        format_value(&divergence.this),
+        format_value(&divergence.other)
+    )
+}
+
+/// Storages and readers the unit tests of the tracer collect the state from.
+#[cfg(test)]
+pub(super) mod fixtures {
+    use std::collections::HashMap;
+
+    use zksync_state::{InMemoryStorage, StoragePtr, StorageView, WriteStorage};
+    use zksync_types::{StorageKey, H256, U256};
+    use zksync_utils::{bytecode::hash_bytecode, bytes_to_be_words, h256_to_u256};
+
+    use crate::tracers::prestate_tracer::{PrestateStorageReader, PrestateTracer, StateReader};
+
+    pub type TestStorage = StoragePtr<StorageView<InMemoryStorage>>;
+
+    /// Returns a storage with the `values` set before the traced transaction.
+    pub fn storage_with(values: &[(StorageKey, H256)]) -> TestStorage {
+        let mut raw_storage = InMemoryStorage::default();
+        for (key, value) in values {
+            raw_storage.set_value(*key, *value);
+        }
+        StorageView::new(raw_storage).to_rc_ptr()
+    }
+
+    /// Writes the `value` like a storage opcode executed by the traced transaction.
+    pub fn write_value(
+        tracer: &mut PrestateTracer,
+        storage: &TestStorage,
+        key: StorageKey,
+        value: H256,
+    ) {
+        tracer.record_storage_access(key, true, storage);
+        storage.borrow_mut().set_value(key, value);
+    }
+
+    /// Returns the factory deps with the `code`, keyed like the known bytecodes of the VM.
+    pub fn known_bytecodes(code: &[u8]) -> HashMap<U256, Vec<U256>> {
+        HashMap::from([(
+            h256_to_u256(hash_bytecode(code)),
+            bytes_to_be_words(code.to_vec()),
+        )])
+    }
+
+    /// Reader of a state snapshot, independent of any VM. The keys without a value are read
+    /// as zero, and no bytecode is available. The reads are counted.
+    #[derive(Default)]
+    pub struct MockReader {
+        values: HashMap<StorageKey, H256>,
+        modified_keys: HashMap<StorageKey, H256>,
+        on_read: Option<Box<dyn FnMut(&StorageKey) + Send>>,
+        pub reads: usize,
+        pub bytecode_loads: usize,
+    }
+
+    impl MockReader {
+        pub fn new(values: HashMap<StorageKey, H256>) -> Self {
+            Self {
+                values,
+                ..Self::default()
+            }
+        }
+
+        /// Sets the keys modified by the transaction, see
+        /// [`PrestateStorageReader::modified_keys()`].
+        pub fn with_modified_keys(mut self, modified_keys: HashMap<StorageKey, H256>) -> Self {
+            self.modified_keys = modified_keys;
+            self
+        }
+
+        /// Calls the `hook` on every read, e.g. to observe the reads from another thread.
+        pub fn on_read(mut self, hook: impl FnMut(&StorageKey) + Send + 'static) -> Self {
+            self.on_read = Some(Box::new(hook));
+            self
+        }
+
+        fn read(&mut self, key: &StorageKey) -> H256 {
+            self.reads += 1;
+            if let Some(hook) = &mut self.on_read {
+                hook(key);
+            }
+            self.values.get(key).copied().unwrap_or_default()
+        }
+    }
+
+    impl StateReader for MockReader {
+        fn read_value(&mut self, key: &StorageKey) -> H256 {
+            self.read(key)
+        }
+
+        fn load_bytecode(&mut self, _hash: H256) -> Option<Vec<u8>> {
+            self.bytecode_loads += 1;
+            None
+        }
+    }
+
+    impl PrestateStorageReader for MockReader {
+        fn read_storage_value(&mut self, key: &StorageKey) -> H256 {
+            self.read(key)
+        }
+
+        fn load_bytecode(&mut self, _hash: H256) -> Option<Vec<u8>> {
+            self.bytecode_loads += 1;
+            None
+        }
+
+        fn modified_keys(&self) -> &HashMap<StorageKey, H256> {
+            &self.modified_keys
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use zksync_types::{Address, H256};
+
+    use super::*;
+    use crate::tracers::prestate_tracer::Account;
+
+    #[test]
+    fn failure_message_lists_differing_slot() {
+        let address = Address::repeat_byte(0x01);
+        let slot = |slot: u64| H256::from_low_u64_be(slot);
+        let account = |values: [u64; 3]| Account {
+            balance: Some(100.into()),
+            storage: Some(
+                (0..3)
+                    .map(|i| (slot(i), slot(values[i as usize])))
+                    .collect(),
+            ),
+            ..Account::default()
+        };
+        let actual = State::from([(address, account([1, 2, 3]))]);
+        let expected = State::from([(address, account([1, 5, 3]))]);
+        assert_state_eq(&actual, &actual);
+
+        let panic = panic::catch_unwind(|| assert_state_eq(&actual, &expected)).unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        let expected_line = format!(
+            "{address:?} slot {:?}: actual \"{:?}\", expected \"{:?}\"",
+            slot(1),
+            slot(2),
+            slot(5)
+        );
+        assert_eq!(*message, format!("states differ:\n  {expected_line}"));
+
+        let panic = panic::catch_unwind(|| assert_state_eq(&actual, &State::new())).unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(
+            message.contains(&format!("{address:?} account: actual {{")),
+            "{message}"
+        );
+        assert!(message.ends_with("expected <missing>"), "{message}");
+    }
+
+    #[test]
+    fn diff_is_checked_against_default_mode() {
+        let address = Address::repeat_byte(0x01);
+        let slot = |slot: u64| H256::from_low_u64_be(slot);
+        let account = |balance: u64, slots: &[(u64, u64)]| Account {
+            balance: Some(balance.into()),
+            storage: Some(
+                slots
+                    .iter()
+                    .map(|&(key, value)| (slot(key), slot(value)))
+                    .collect(),
+            ),
+            ..Account::default()
+        };
+        let default = PrestateResult::from((
+            State::from([(address, account(100, &[(0, 1), (1, 2)]))]),
+            State::new(),
+        ));
+        let diff = PrestateResult::from((
+            State::from([(address, account(100, &[(0, 1)]))]),
+            State::from([(address, account(50, &[(0, 3)]))]),
+        ));
+        assert_diff_subset_of_default(&diff, &default);
+
+        let inconsistent = PrestateResult::from((
+            State::from([(address, account(100, &[(0, 5)]))]),
+            State::from([(address, account(50, &[(0, 3), (2, 1)]))]),
+        ));
+        let panic = panic::catch_unwind(|| assert_diff_subset_of_default(&inconsistent, &default))
+            .unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        let expected_lines = [
+            format!(
+                "{address:?} slot {:?}: diff pre {:?}, default {:?}",
+                slot(0),
+                slot(5),
+                slot(1)
+            ),
+            format!(
+                "{address:?} slot {:?}: diff pre <missing>, default <missing>",
+                slot(2)
+            ),
+        ];
+        assert_eq!(
+            *message,
+            format!(
+                "diff is inconsistent with default mode:\n  {}",
+                expected_lines.join("\n  ")
+            )
+        );
+    }
+}
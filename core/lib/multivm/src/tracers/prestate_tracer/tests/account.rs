@@ -0,0 +1,250 @@
+use crate::tracers::prestate_tracer::*;
+
+#[test]
+fn account_serializes_like_geth() {
+    let account = Account {
+        balance: Some(U256::from(2_000_000_000_000_000_000u64)),
+        nonce: Some(U256::from(5)),
+        storage: Some(HashMap::from([(
+            H256::from_low_u64_be(0),
+            H256::from_low_u64_be(1),
+        )])),
+        ..Account::default()
+    };
+    // Output of Geth's `prestateTracer` for the same account.
+    let expected = concat!(
+        r#"{"balance":"0x1bc16d674ec80000","nonce":5,"storage":{"#,
+        r#""0x0000000000000000000000000000000000000000000000000000000000000000":"#,
+        r#""0x0000000000000000000000000000000000000000000000000000000000000001"}}"#
+    );
+
+    let serialized = serde_json::to_string(&account).unwrap();
+    assert_eq!(serialized, expected);
+    let deserialized: Account = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, account);
+}
+
+#[test]
+fn account_builder() {
+    let (slot, value) = (H256::repeat_byte(1), H256::repeat_byte(2));
+    let account = Account::builder()
+        .balance(100)
+        .code(vec![0xfe; 32])
+        .nonce(1)
+        .storage(HashMap::from([(slot, value)]))
+        .slot(H256::repeat_byte(3), H256::zero())
+        .slot(slot, value)
+        .build()
+        .unwrap();
+
+    let expected = Account {
+        balance: Some(100.into()),
+        code: Some(vec![0xfe; 32]),
+        nonce: Some(1.into()),
+        storage: Some(HashMap::from([
+            (slot, value),
+            (H256::repeat_byte(3), H256::zero()),
+        ])),
+        ..Account::default()
+    };
+    assert_eq!(account, expected);
+    assert_eq!(Account::builder().build().unwrap(), Account::empty());
+
+    let err = Account::builder()
+        .slot(slot, value)
+        .slot(slot, H256::zero())
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ConflictingSlotError {
+            slot,
+            value,
+            other_value: H256::zero(),
+        }
+    );
+}
+
+#[test]
+fn account_is_displayed_as_json() {
+    let account = Account {
+        balance: Some(U256::from(1_000)),
+        code: Some(vec![0x60, 0x80, 0x60, 0x40]),
+        code_hash: Some(H256::repeat_byte(0x01)),
+        code_size: Some(4),
+        nonce: Some(U256::from(5)),
+        deployment_nonce: Some(U256::from(1)),
+        storage: Some(HashMap::from([(
+            H256::from_low_u64_be(0),
+            H256::from_low_u64_be(1),
+        )])),
+        token_balances: Some(HashMap::from([(Address::repeat_byte(0x02), U256::MAX)])),
+        storage_preimages: Some(HashMap::from([(
+            H256::from_low_u64_be(0),
+            SlotPreimage {
+                base_slot: H256::zero(),
+                key: H256::repeat_byte(0x03),
+            },
+        )])),
+        storage_gas_remaining: Some(HashMap::from([(H256::from_low_u64_be(0), 1_000)])),
+        gas_used: Some(500),
+        storage_root: Some(H256::repeat_byte(0x04)),
+        code_source: Some(Address::repeat_byte(0x05)),
+        storage_decoded: Some(HashMap::from([(H256::zero(), "true".to_owned())])),
+        init_code: Some(vec![0x60, 0x80]),
+        omitted_slots: Some(3),
+        protocol_role: Some(ProtocolRole::Paymaster),
+        balance_wei_normalized: Some(U256::exp10(15)),
+        storage_refunds: Some(HashMap::from([(H256::from_low_u64_be(1), 59)])),
+        touch_reasons: Some(BTreeSet::from([TouchReason::Written, TouchReason::Sender])),
+        balance_change: Some(BalanceChange {
+            value_delta: BalanceDelta::decrease(100.into()),
+            fee_delta: BalanceDelta::decrease(5.into()),
+        }),
+        code_unavailable: Some(true),
+        ..Account::default()
+    };
+
+    let displayed = account.to_string();
+    let parsed: serde_json::Value = serde_json::from_str(&displayed).unwrap();
+    assert_eq!(parsed["code"], "0x60806040");
+    assert_eq!(parsed["nonce"], 5);
+    assert_eq!(serde_json::from_value::<Account>(parsed).unwrap(), account);
+}
+
+#[test]
+fn empty_change_is_detected() {
+    assert!(Account::default().is_empty_change());
+    let account = Account {
+        storage: Some(HashMap::new()),
+        touch_reasons: Some(BTreeSet::from([TouchReason::ReadOnly])),
+        ..Account::default()
+    };
+    assert!(account.is_empty_change());
+
+    let account = Account {
+        storage: Some(HashMap::from([(H256::zero(), H256::repeat_byte(1))])),
+        ..Account::default()
+    };
+    assert!(!account.is_empty_change());
+    let account = Account {
+        code_hash: Some(H256::repeat_byte(0xcc)),
+        ..Account::default()
+    };
+    assert!(!account.is_empty_change());
+}
+
+#[test]
+fn missing_and_empty_storage_are_semantically_equal() {
+    let account = Account {
+        nonce: Some(U256::one()),
+        ..Account::default()
+    };
+    let with_empty_storage = Account {
+        balance: Some(U256::zero()),
+        storage: Some(HashMap::new()),
+        ..account.clone()
+    };
+    assert_ne!(account, with_empty_storage);
+    assert!(account.semantically_eq(&with_empty_storage));
+    assert!(with_empty_storage.semantically_eq(&account));
+
+    let with_slot = Account {
+        storage: Some(HashMap::from([(H256::zero(), H256::zero())])),
+        ..account.clone()
+    };
+    assert!(!account.semantically_eq(&with_slot));
+    let with_balance = Account {
+        balance: Some(U256::one()),
+        ..account.clone()
+    };
+    assert!(!account.semantically_eq(&with_balance));
+}
+
+#[test]
+fn storage_deltas_are_applied_in_order() {
+    let slot = |slot: u64| H256::from_low_u64_be(slot);
+    let mut account = Account::empty();
+    account.apply_storage_delta(&HashMap::from([(slot(0), slot(1)), (slot(1), slot(2))]));
+    account.apply_storage_delta(&HashMap::from([(slot(1), slot(3)), (slot(2), slot(4))]));
+    account.apply_balance(10.into());
+    account.apply_nonce(2.into());
+    account.apply_balance(5.into());
+
+    let expected_storage =
+        HashMap::from([(slot(0), slot(1)), (slot(1), slot(3)), (slot(2), slot(4))]);
+    assert_eq!(account.storage, Some(expected_storage));
+    assert_eq!(account.balance, Some(5.into()));
+    assert_eq!(account.nonce, Some(2.into()));
+    assert_eq!(account.deployment_nonce, None);
+}
+
+#[test]
+fn code_serializes_as_hex() {
+    let account = Account {
+        code: Some(vec![0x60, 0x80, 0x60, 0x40]),
+        ..Account::default()
+    };
+
+    let serialized = serde_json::to_string(&account).unwrap();
+    assert_eq!(serialized, r#"{"code":"0x60806040"}"#);
+    let deserialized: Account = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, account);
+}
+
+#[test]
+fn state_serializes_accounts_by_address() {
+    let address = Address::repeat_byte(0x11);
+    let state = State::from([(
+        address,
+        Account {
+            balance: Some(U256::zero()),
+            ..Account::default()
+        },
+    )]);
+
+    let serialized = serde_json::to_string(&state).unwrap();
+    assert_eq!(
+        serialized,
+        r#"{"0x1111111111111111111111111111111111111111":{"balance":"0x0"}}"#
+    );
+    let deserialized: State = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, state);
+}
+
+#[test]
+fn nonce_is_decoded() {
+    // Full nonce of an account that has sent 26 transactions and deployed 3 contracts.
+    let raw = U256::from_dec_str("1020847100762815390390123822295304634394").unwrap();
+    assert_eq!(raw, nonces_to_full_nonce(26.into(), 3.into()));
+    assert_eq!(decode_nonce(raw), (26, 3));
+    assert_eq!(decode_nonce(U256::from(7)), (7, 0));
+    assert_eq!(decode_nonce(U256::one() << 128), (0, 1));
+    assert_eq!(try_decode_nonce(raw), Ok((26, 3)));
+}
+
+#[test]
+fn oversized_nonce_is_clamped() {
+    let raw = h256_to_u256(H256::repeat_byte(0xff));
+    assert_eq!(try_decode_nonce(raw), Err(NonceOverflowError(raw)));
+    assert_eq!(decode_nonce(raw), (u64::MAX, u64::MAX));
+
+    let raw = nonces_to_full_nonce(U256::from(u64::MAX) + 1, 3.into());
+    assert_eq!(try_decode_nonce(raw), Err(NonceOverflowError(raw)));
+    assert_eq!(decode_nonce(raw), (u64::MAX, 3));
+    let raw = nonces_to_full_nonce(u64::MAX.into(), u64::MAX.into());
+    assert_eq!(try_decode_nonce(raw), Ok((u64::MAX, u64::MAX)));
+}
+
+#[test]
+fn slot_values_are_rendered_by_type() {
+    let value = H256::from_low_u64_be(1);
+    assert_eq!(SlotType::Uint256.render(value), "1");
+    assert_eq!(SlotType::Bool.render(value), "true");
+    assert_eq!(SlotType::Bool.render(H256::zero()), "false");
+    assert_eq!(SlotType::Bytes32.render(value), format!("{:?}", value));
+    // Values which aren't valid for the type are left raw.
+    let packed = H256::repeat_byte(0x01);
+    assert_eq!(SlotType::Address.render(packed), format!("{:?}", packed));
+    assert_eq!(SlotType::Bool.render(packed), format!("{:?}", packed));
+}
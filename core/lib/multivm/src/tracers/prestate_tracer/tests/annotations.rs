@@ -0,0 +1,608 @@
+use zksync_types::utils::storage_key_for_standard_token_balance;
+
+use crate::tracers::prestate_tracer::{
+    testing::fixtures::{storage_with, write_value},
+    *,
+};
+
+#[test]
+fn access_profile_separates_reads_from_writes() {
+    let address = Address::repeat_byte(0x01);
+    let slot =
+        |slot: u64| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+    let storage = storage_with(&[(slot(0), H256::from_low_u64_be(1))]);
+
+    for access_profile in [false, true] {
+        let config = PrestateTracerConfig::builder()
+            .access_profile(access_profile)
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        tracer.record_storage_access(slot(0), false, &storage);
+        write_value(&mut tracer, &storage, slot(1), H256::from_low_u64_be(2));
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let profile = tracer.take_result().unwrap().access_profile;
+        if !access_profile {
+            assert_eq!(profile, None);
+            continue;
+        }
+        let accesses = &profile.unwrap()[&address];
+        assert_eq!(accesses.read_slots, HashSet::from([*slot(0).key()]));
+        assert_eq!(accesses.write_slots, HashSet::from([*slot(1).key()]));
+    }
+}
+
+#[test]
+fn storage_ops_are_counted() {
+    let address = Address::repeat_byte(0x01);
+    let slot =
+        |slot: u64| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+    let storage = storage_with(&[(slot(0), H256::from_low_u64_be(1))]);
+
+    let config = PrestateTracerConfig::builder().op_counts(true).build();
+    let mut tracer = PrestateTracer::from_config(config);
+    let mut execute = |slot: u64, is_write| {
+        let slot = U256::from(slot);
+        tracer.on_storage_opcode(address, address, slot, is_write, 1_000, &storage);
+    };
+    // Two loads of different slots, and a store to the first of them.
+    execute(0, false);
+    execute(1, false);
+    execute(0, true);
+    storage
+        .borrow_mut()
+        .set_value(slot(0), H256::from_low_u64_be(2));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let expected = StorageOpCounts {
+        sloads: 2,
+        sstores: 1,
+        cold_accesses: 2,
+        warm_accesses: 1,
+        changed_slots: 1,
+    };
+    assert_eq!(tracer.take_result().unwrap().op_counts, Some(expected));
+}
+
+#[test]
+fn read_block_hash_is_recorded_in_block_context() {
+    let system_context = AccountTreeId::new(SYSTEM_CONTEXT_ADDRESS);
+    let block_hash_slot = SlotPreimage {
+        base_slot: SYSTEM_CONTEXT_BLOCK_HASH_POSITION,
+        key: H256::from_low_u64_be(9),
+    }
+    .slot();
+    let block_hash = H256::repeat_byte(0x09);
+    let storage = storage_with(&[
+        (
+            StorageKey::new(system_context, CURRENT_VIRTUAL_BLOCK_INFO_POSITION),
+            u256_to_h256(zksync_types::block::pack_block_info(10, 1_000)),
+        ),
+        (
+            StorageKey::new(system_context, SYSTEM_CONTEXT_BASE_FEE_POSITION),
+            H256::from_low_u64_be(250_000_000),
+        ),
+        (StorageKey::new(system_context, block_hash_slot), block_hash),
+        (
+            StorageKey::new(system_context, u256_to_h256(8.into())),
+            H256::repeat_byte(0x08),
+        ),
+    ]);
+
+    let config = PrestateTracerConfig::builder().block_context(true).build();
+    let mut tracer = PrestateTracer::from_config(config);
+    // `SystemContext.getBlockHashEIP209(9)` called by the `BLOCKHASH` of the contract.
+    let slot = h256_to_u256(block_hash_slot);
+    let address = SYSTEM_CONTEXT_ADDRESS;
+    tracer.on_storage_opcode(address, address, slot, false, 1_000, &storage);
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let expected = BlockContext {
+        number: 10,
+        timestamp: 1_000,
+        base_fee: 250_000_000.into(),
+        // The hash of the block 8 isn't read, so it isn't reported.
+        block_hashes: BTreeMap::from([(9, block_hash)]),
+    };
+    let result = tracer.take_result().unwrap();
+    assert_eq!(result.block_context, Some(expected));
+}
+
+#[test]
+fn created_addresses_are_in_deployment_order() {
+    let factory = Address::repeat_byte(0x01);
+    let children = [Address::repeat_byte(0x03), Address::repeat_byte(0x02)];
+    let storage = storage_with(&[]);
+
+    let mut tracer = PrestateTracer::default();
+    tracer.on_step(factory, 1, 1_000);
+    // `CREATE2` marks the code hash of each child as constructing, and replaces it
+    // with the deployed one once the constructor returns.
+    for child in children {
+        let code_key = get_code_key(&child);
+        write_value(&mut tracer, &storage, code_key, H256::repeat_byte(0xcc));
+        write_value(&mut tracer, &storage, code_key, H256::repeat_byte(0xdd));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let result = tracer.take_result().unwrap();
+    assert_eq!(result.created_addresses, children);
+}
+
+#[test]
+fn touch_reasons_distinguish_reads_from_writes() {
+    let reader = Address::repeat_byte(0x01);
+    let writer = Address::repeat_byte(0x02);
+    let read_key = StorageKey::new(AccountTreeId::new(reader), H256::zero());
+    let write_key = StorageKey::new(AccountTreeId::new(writer), H256::zero());
+    let storage = storage_with(&[(read_key, H256::from_low_u64_be(1))]);
+
+    for record_touch_reasons in [false, true] {
+        let config = PrestateTracerConfig::builder()
+            .record_touch_reasons(record_touch_reasons)
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        tracer.on_step(writer, 1, 1_000);
+        tracer.record_storage_access(read_key, false, &storage);
+        write_value(&mut tracer, &storage, write_key, H256::from_low_u64_be(2));
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let pre = tracer.take_result().unwrap().pre;
+        if record_touch_reasons {
+            assert_eq!(
+                pre[&reader].touch_reasons,
+                Some(BTreeSet::from([TouchReason::ReadOnly]))
+            );
+            assert_eq!(
+                pre[&writer].touch_reasons,
+                Some(BTreeSet::from([
+                    TouchReason::Written,
+                    TouchReason::CallTarget
+                ]))
+            );
+        } else {
+            assert_eq!(pre[&reader].touch_reasons, None);
+            assert_eq!(pre[&writer].touch_reasons, None);
+        }
+    }
+}
+
+#[test]
+fn cleared_slots_are_annotated_with_refunds() {
+    let address = Address::repeat_byte(0x01);
+    let key = |slot| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+    let storage = storage_with(&[
+        (key(0), H256::from_low_u64_be(5)),
+        (key(1), H256::from_low_u64_be(6)),
+    ]);
+
+    for track_refunds in [false, true] {
+        let config = PrestateTracerConfig::builder()
+            .diff_mode(true)
+            .track_refunds(track_refunds)
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        write_value(&mut tracer, &storage, key(0), H256::zero());
+        write_value(&mut tracer, &storage, key(1), H256::from_low_u64_be(7));
+        // Slots which were already zero aren't refunded.
+        write_value(&mut tracer, &storage, key(2), H256::zero());
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let post = tracer.take_result().unwrap().post.unwrap();
+        let refunds = post[&address].storage_refunds.as_ref();
+        if !track_refunds {
+            assert_eq!(refunds, None);
+            continue;
+        }
+        let refunds = refunds.unwrap();
+        assert_eq!(refunds.keys().collect::<Vec<_>>(), [key(0).key()]);
+        assert!(refunds[key(0).key()] > 0);
+        let expected = INITIAL_STORAGE_WRITE_PUBDATA_BYTES as u32
+            - u32::from(BYTES_PER_ENUMERATION_INDEX)
+            - compress_with_best_strategy(5.into(), U256::zero()).len() as u32;
+        assert_eq!(refunds[key(0).key()], expected);
+    }
+}
+
+#[test]
+fn balances_are_normalized_to_wei() {
+    let address = Address::repeat_byte(0x01);
+    let account_id = AccountTreeId::new(address);
+    let slot = StorageKey::new(account_id, H256::zero());
+    // 1.5 of a base token with 6 decimals, like USDC.
+    let storage = storage_with(&[(get_balance_key(&account_id), u256_to_h256(1_500_000.into()))]);
+
+    let config = PrestateTracerConfig::builder()
+        .normalize_balances(6)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+    tracer.record_storage_access(slot, false, &storage);
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let account = &tracer.get_result().unwrap().pre[&address];
+    assert_eq!(account.balance, Some(1_500_000.into()));
+    let expected = U256::from(15) * U256::exp10(17);
+    assert_eq!(account.balance_wei_normalized, Some(expected));
+    let json = serde_json::to_value(account).unwrap();
+    assert_eq!(json["balanceWeiNormalized"], format!("{expected:#x}"));
+
+    assert_eq!(normalize_balance(1_500_000.into(), 18), 1_500_000.into());
+    assert_eq!(normalize_balance(U256::exp10(20), 20), U256::exp10(18));
+    assert_eq!(normalize_balance(U256::MAX, 0), U256::MAX);
+    assert_eq!(normalize_balance(U256::MAX, u8::MAX), U256::zero());
+}
+
+#[test]
+fn protocol_accounts_are_tagged_or_excluded() {
+    let user = Address::repeat_byte(0x01);
+    let paymaster = Address::repeat_byte(0x02);
+    let balance_key = |address| get_balance_key(&AccountTreeId::new(address));
+    let nonce_key = get_nonce_key(&user);
+    let storage = storage_with(&[
+        (balance_key(user), u256_to_h256(100.into())),
+        (balance_key(paymaster), u256_to_h256(50.into())),
+    ]);
+
+    let trace = |protocol_accounts| {
+        let config = PrestateTracerConfig::builder()
+            .diff_mode(true)
+            .protocol_accounts(protocol_accounts)
+            .paymaster(paymaster)
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        // The paymaster pays the fee to the bootloader, while the user transfers some value.
+        tracer.on_step(BOOTLOADER_ADDRESS, 0, 10_000);
+        tracer.on_step(user, 1, 1_000);
+        let new_nonce = u256_to_h256(nonces_to_full_nonce(1.into(), 0.into()));
+        write_value(&mut tracer, &storage, nonce_key, new_nonce);
+        write_value(
+            &mut tracer,
+            &storage,
+            balance_key(user),
+            u256_to_h256(90.into()),
+        );
+        tracer.on_step(paymaster, 1, 900);
+        write_value(
+            &mut tracer,
+            &storage,
+            balance_key(paymaster),
+            u256_to_h256(45.into()),
+        );
+        let bootloader_balance = u256_to_h256(5.into());
+        write_value(
+            &mut tracer,
+            &storage,
+            balance_key(BOOTLOADER_ADDRESS),
+            bootloader_balance,
+        );
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+        tracer.take_result().unwrap().post.unwrap()
+    };
+
+    let post = trace(ProtocolAccounts::Report);
+    assert_eq!(post.len(), 3);
+    assert!(post.values().all(|account| account.protocol_role.is_none()));
+
+    let post = trace(ProtocolAccounts::Tag);
+    assert_eq!(post[&user].protocol_role, None);
+    assert_eq!(
+        post[&paymaster].protocol_role,
+        Some(ProtocolRole::Paymaster)
+    );
+    assert_eq!(post[&paymaster].balance, Some(45.into()));
+    let bootloader = &post[&BOOTLOADER_ADDRESS];
+    assert_eq!(bootloader.protocol_role, Some(ProtocolRole::Bootloader));
+    let json = serde_json::to_value(bootloader).unwrap();
+    assert_eq!(json["protocolRole"], "bootloader");
+
+    let post = trace(ProtocolAccounts::Exclude);
+    assert_eq!(post.keys().collect::<Vec<_>>(), [&user]);
+    assert_eq!(post[&user].balance, Some(90.into()));
+}
+
+#[test]
+fn token_balances_are_reported() {
+    let address = Address::repeat_byte(0x01);
+    let token = Address::repeat_byte(0x22);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let token_balance_key =
+        storage_key_for_standard_token_balance(AccountTreeId::new(token), &address);
+    let storage = storage_with(&[(token_balance_key, u256_to_h256(1_000.into()))]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone()).with_token_balances(vec![token]);
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    write_value(
+        &mut tracer,
+        &storage,
+        token_balance_key,
+        u256_to_h256(400.into()),
+    );
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert_eq!(
+        pre[&address].token_balances,
+        Some(HashMap::from([(token, 1_000.into())]))
+    );
+    assert_eq!(
+        post[&address].token_balances,
+        Some(HashMap::from([(token, 400.into())]))
+    );
+}
+
+#[test]
+fn allowance_slot_is_annotated_with_preimage() {
+    let token = Address::repeat_byte(0x22);
+    let owner = Address::repeat_byte(0x01);
+    let spender = Address::repeat_byte(0x02);
+    // `allowance` is the second mapping of the OpenZeppelin ERC-20 contract.
+    let owner_allowances = SlotPreimage {
+        base_slot: H256::from_low_u64_be(1),
+        key: address_to_h256(&owner),
+    };
+    let preimage = SlotPreimage {
+        base_slot: owner_allowances.slot(),
+        key: address_to_h256(&spender),
+    };
+    let allowance_slot = preimage.slot();
+    let allowance_key = StorageKey::new(AccountTreeId::new(token), allowance_slot);
+    let other_key = StorageKey::new(AccountTreeId::new(token), H256::zero());
+    let storage = storage_with(&[]);
+
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .slot_preimages(HashMap::from([(allowance_slot, preimage.clone())]))
+        .build();
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::with_config(config, result.clone());
+    write_value(
+        &mut tracer,
+        &storage,
+        allowance_key,
+        H256::from_low_u64_be(100),
+    );
+    write_value(&mut tracer, &storage, other_key, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    let expected = Some(HashMap::from([(allowance_slot, preimage)]));
+    assert_eq!(pre[&token].storage_preimages, expected);
+    assert_eq!(post[&token].storage_preimages, expected);
+
+    let json = serde_json::to_value(&pre[&token]).unwrap();
+    assert_eq!(
+        json["storagePreimages"][format!("{:?}", allowance_slot)],
+        serde_json::json!({
+            "baseSlot": format!("{:?}", owner_allowances.slot()),
+            "key": format!("{:?}", address_to_h256(&spender)),
+        })
+    );
+}
+
+#[test]
+fn slots_are_not_annotated_without_preimages() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let storage = storage_with(&[]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(false, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, _) = result.get().unwrap();
+    assert_eq!(pre[&address].storage_preimages, None);
+    let json = serde_json::to_value(&pre[&address]).unwrap();
+    assert!(json.get("storagePreimages").is_none());
+}
+
+#[test]
+fn hinted_slots_are_decoded() {
+    let address = Address::repeat_byte(0x01);
+    let owner = Address::repeat_byte(0x02);
+    let owner_key = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let raw_key = StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(1));
+    let storage = storage_with(&[
+        (owner_key, address_to_h256(&owner)),
+        (raw_key, H256::from_low_u64_be(5)),
+    ]);
+
+    let config = PrestateTracerConfig::builder()
+        .slot_types(HashMap::from([(*owner_key.key(), SlotType::Address)]))
+        .build();
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::with_config(config, result.clone());
+    write_value(&mut tracer, &storage, owner_key, H256::zero());
+    write_value(&mut tracer, &storage, raw_key, H256::zero());
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, _) = result.get().unwrap();
+    assert_eq!(pre[&address].storage.as_ref().unwrap().len(), 2);
+    let expected = HashMap::from([(*owner_key.key(), format!("{:?}", owner))]);
+    assert_eq!(pre[&address].storage_decoded, Some(expected));
+    assert_eq!(
+        format!("{:?}", owner),
+        "0x0202020202020202020202020202020202020202"
+    );
+}
+
+#[test]
+fn delegate_call_writes_are_attributed_to_code_source() {
+    let proxy = Address::repeat_byte(0x01);
+    let implementation = Address::repeat_byte(0x02);
+    let storage = storage_with(&[
+        (get_code_key(&proxy), H256::repeat_byte(0xcc)),
+        (get_code_key(&implementation), H256::repeat_byte(0xdd)),
+    ]);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .track_code_source(true)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+
+    // The proxy delegates to the implementation, which writes to the proxy storage,
+    // and then the implementation writes to its own storage in a regular call.
+    for (address, code_address) in [(proxy, implementation), (implementation, implementation)] {
+        let key = StorageKey::new(AccountTreeId::new(address), H256::zero());
+        tracer.on_storage_opcode(address, code_address, U256::zero(), true, 0, &storage);
+        storage
+            .borrow_mut()
+            .set_value(key, H256::from_low_u64_be(1));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let post = tracer.take_result().unwrap().post.unwrap();
+    assert_eq!(post[&proxy].code_source, Some(implementation));
+    assert_eq!(post[&implementation].code_source, None);
+    assert!(post[&proxy].storage.is_some());
+}
+
+#[test]
+fn proxy_slot_written_by_two_implementations_is_collision() {
+    let proxy = Address::repeat_byte(0x01);
+    let implementations = [Address::repeat_byte(0x02), Address::repeat_byte(0x03)];
+    let storage = storage_with(&[]);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .detect_storage_collisions(true)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+
+    // Both implementations write the slot 0 of the proxy, and only the first one the slot 1.
+    let writes = [
+        (implementations[0], 0),
+        (implementations[1], 0),
+        (implementations[0], 1),
+        (proxy, 1),
+    ];
+    for (code_address, slot) in writes {
+        let key = StorageKey::new(AccountTreeId::new(proxy), H256::from_low_u64_be(slot));
+        tracer.on_storage_opcode(proxy, code_address, slot.into(), true, 0, &storage);
+        storage
+            .borrow_mut()
+            .set_value(key, H256::from_low_u64_be(slot + 1));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let collisions = tracer.take_result().unwrap().storage_collisions.unwrap();
+    let expected = StorageCollision {
+        address: proxy,
+        slot: H256::zero(),
+        implementations: implementations.to_vec(),
+    };
+    assert_eq!(collisions, [expected]);
+}
+
+#[test]
+fn raw_modified_keys_are_recorded() {
+    let address = Address::repeat_byte(0x01);
+    let slot =
+        |slot: u64| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+    let storage = storage_with(&[(slot(0), H256::from_low_u64_be(1))]);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .record_modified_keys(true)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+
+    write_value(&mut tracer, &storage, slot(0), H256::from_low_u64_be(2));
+    write_value(&mut tracer, &storage, slot(1), H256::from_low_u64_be(3));
+    // Modifications aren't filtered, even if the value is restored.
+    write_value(&mut tracer, &storage, slot(0), H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let modified_keys = tracer.take_result().unwrap().modified_keys.unwrap();
+    assert_eq!(&modified_keys, storage.borrow().modified_storage_keys());
+    assert_eq!(modified_keys.len(), 2);
+    assert_eq!(modified_keys[&slot(1)], H256::from_low_u64_be(3));
+}
+
+#[test]
+fn storage_root_is_stable() {
+    let slot = H256::from_low_u64_be;
+    let storage = HashMap::from([(slot(3), slot(4)), (slot(1), slot(2))]);
+    let expected: H256 = "0x392791df626408017a264f53fde61065d5a93a32b60171df9d8a46afdf82992d"
+        .parse()
+        .unwrap();
+    assert_eq!(storage_root(&storage), expected);
+
+    let address = Address::repeat_byte(0x01);
+    let key = StorageKey::new(AccountTreeId::new(address), slot(1));
+    let storage = storage_with(&[(get_code_key(&address), H256::repeat_byte(0xcc))]);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .include_storage_root(true)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+    write_value(&mut tracer, &storage, key, slot(2));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let result = tracer.take_result().unwrap();
+    let post = result.post.unwrap();
+    assert_eq!(
+        post[&address].storage_root,
+        Some(storage_root(&HashMap::from([(slot(1), slot(2))])))
+    );
+    assert_eq!(
+        result.pre[&address].storage_root,
+        Some(storage_root(&HashMap::from([(slot(1), H256::zero())])))
+    );
+}
+
+#[test]
+fn touch_order_is_recorded() {
+    let [sender, a, b, c] = [1, 2, 3, 4].map(Address::repeat_byte);
+    let storage = storage_with(&[]);
+    let config = PrestateTracerConfig::builder()
+        .record_touch_order(true)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+
+    // The account of the sender validates the transaction, and then A calls B, which calls C.
+    for (depth, address) in [sender, a, b, c].into_iter().enumerate() {
+        tracer.on_step(address, depth, 1_000);
+        tracer.on_storage_opcode(address, address, U256::zero(), true, 1_000, &storage);
+    }
+    // Returns and repeated accesses don't change the order.
+    tracer.on_step(b, 2, 900);
+    tracer.on_storage_opcode(a, a, U256::one(), true, 900, &storage);
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let result = tracer.get_result().unwrap();
+    assert_eq!(result.touch_order, Some(vec![sender, a, b, c]));
+}
+
+#[test]
+fn gas_is_attributed_to_slots_and_accounts() {
+    let address = Address::repeat_byte(0x01);
+    let callee = Address::repeat_byte(0x02);
+    let storage = storage_with(&[(get_code_key(&address), H256::repeat_byte(0xcc))]);
+
+    for gas_attribution in [false, true] {
+        let config = PrestateTracerConfig::builder()
+            .gas_attribution(gas_attribution)
+            .build();
+        let mut tracer = PrestateTracer::with_config(config, Arc::new(OnceCell::new()));
+        tracer.on_step(address, 1, 1_000);
+        tracer.on_storage_opcode(address, address, U256::zero(), true, 1_000, &storage);
+        // The gas passed to the subcall isn't attributed to either of the accounts.
+        tracer.on_step(callee, 2, 300);
+        tracer.on_step(callee, 2, 250);
+        tracer.on_step(address, 1, 800);
+        tracer.on_step(address, 1, 600);
+        tracer.on_storage_opcode(address, address, U256::one(), true, 600, &storage);
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let pre = tracer.get_result().unwrap().pre;
+        let account = &pre[&address];
+        if !gas_attribution {
+            assert_eq!(account.storage_gas_remaining, None);
+            assert_eq!(account.gas_used, None);
+            continue;
+        }
+        let gas_remaining = account.storage_gas_remaining.as_ref().unwrap();
+        assert!(gas_remaining[&H256::zero()] > gas_remaining[&H256::from_low_u64_be(1)]);
+        assert_eq!(account.gas_used, Some(200));
+    }
+}
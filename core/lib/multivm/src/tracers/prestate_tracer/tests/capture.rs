@@ -0,0 +1,543 @@
+use zksync_system_constants::SYSTEM_CONTEXT_CHAIN_ID_POSITION;
+use zksync_utils::bytecode::hash_bytecode;
+
+use crate::tracers::prestate_tracer::{
+    testing::fixtures::{known_bytecodes, storage_with, write_value},
+    *,
+};
+
+#[test]
+fn storage_only_reports_just_storage() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let balance_key = get_balance_key(&AccountTreeId::new(address));
+    let storage = storage_with(&[
+        (get_code_key(&address), hash_bytecode(&[0xaa; 32])),
+        (balance_key, u256_to_h256(100.into())),
+        (slot, H256::from_low_u64_be(1)),
+    ]);
+
+    let config = PrestateTracerConfig::builder().storage_only(true).build();
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::with_config(config, result.clone());
+    tracer.on_step(address, 1, 1_000);
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(2));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, _) = result.get().unwrap();
+    let expected = Account {
+        storage: Some(HashMap::from([(*slot.key(), H256::from_low_u64_be(1))])),
+        ..Account::default()
+    };
+    assert_eq!(*pre, State::from([(address, expected)]));
+}
+
+#[test]
+fn factory_deps_include_called_contract() {
+    let address = Address::repeat_byte(0x01);
+    let code = vec![0xaa; 64];
+    let code_hash = hash_bytecode(&code);
+    let storage = storage_with(&[(get_code_key(&address), code_hash)]);
+
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .collect_factory_deps(true)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+    tracer.on_step(address, 1, 1_000);
+    tracer
+        .store_result(&storage, &known_bytecodes(&code))
+        .unwrap();
+
+    let result = tracer.take_result().unwrap();
+    // Nothing has changed, so the contract itself isn't reported.
+    assert!(result.pre.is_empty());
+    assert_eq!(
+        result.factory_deps,
+        Some(HashMap::from([(code_hash, code)]))
+    );
+}
+
+#[test]
+fn empty_accounts_are_pruned() {
+    let empty = Address::repeat_byte(0x01);
+    let funded = Address::repeat_byte(0x02);
+    let empty_slot = StorageKey::new(AccountTreeId::new(empty), H256::zero());
+    let funded_slot = StorageKey::new(AccountTreeId::new(funded), H256::zero());
+
+    for prune_empty_accounts in [false, true] {
+        let storage = storage_with(&[(
+            get_balance_key(&AccountTreeId::new(funded)),
+            u256_to_h256(100.into()),
+        )]);
+        let config = PrestateTracerConfig::builder()
+            .prune_empty_accounts(prune_empty_accounts)
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        tracer.record_storage_access(empty_slot, false, &storage);
+        tracer.record_storage_access(funded_slot, false, &storage);
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let pre = tracer.take_result().unwrap().pre;
+        assert_eq!(pre.contains_key(&empty), !prune_empty_accounts);
+        // Zero slots don't make an account with a balance empty.
+        assert_eq!(pre[&funded].balance, Some(100.into()));
+    }
+}
+
+#[test]
+fn empty_reads_are_reported_as_zero() {
+    let address = Address::repeat_byte(0x01);
+    let read_slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let written_slot = StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(1));
+
+    for include_empty_reads in [true, false] {
+        let storage = storage_with(&[]);
+        let config = PrestateTracerConfig::builder()
+            .include_empty_reads(include_empty_reads)
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        tracer.record_storage_access(read_slot, false, &storage);
+        write_value(
+            &mut tracer,
+            &storage,
+            written_slot,
+            H256::from_low_u64_be(1),
+        );
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let result = tracer.take_result().unwrap();
+        let account_storage = result.pre[&address].storage.clone().unwrap();
+        assert_eq!(account_storage[written_slot.key()], H256::zero());
+        if include_empty_reads {
+            assert_eq!(account_storage[read_slot.key()], H256::zero());
+            let json = serde_json::to_value(&result.pre[&address]).unwrap();
+            assert_eq!(
+                json["storage"][format!("{:?}", read_slot.key())],
+                format!("{:?}", H256::zero())
+            );
+        } else {
+            assert!(!account_storage.contains_key(read_slot.key()));
+        }
+    }
+}
+
+#[test]
+fn code_hash_is_reported_byte_for_byte() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let code_hash = hash_bytecode(&[0xaa; 32]);
+    let storage = storage_with(&[(get_code_key(&address), code_hash)]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(false, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, _) = result.get().unwrap();
+    assert_eq!(pre[&address].code_hash, Some(code_hash));
+    // Unlike a number, the hash keeps its leading zero nibble when serialized.
+    let json = serde_json::to_value(&pre[&address]).unwrap();
+    let expected = format!("0x{}", hex::encode(code_hash.as_bytes()));
+    assert!(expected.starts_with("0x01"));
+    assert_eq!(json["codeHash"], expected);
+}
+
+#[test]
+fn executed_code_is_reported_without_storage_accesses() {
+    let caller = Address::repeat_byte(0x01);
+    let implementation = Address::repeat_byte(0x02);
+    let code = vec![0xaa; 32];
+    let code_hash = hash_bytecode(&code);
+    let storage = storage_with(&[
+        (get_code_key(&caller), H256::repeat_byte(0xcc)),
+        (get_code_key(&implementation), code_hash),
+    ]);
+
+    let mut tracer = PrestateTracer::default();
+    // The caller delegates to the implementation, which doesn't access any storage.
+    let slot = StorageKey::new(AccountTreeId::new(caller), H256::zero());
+    tracer.on_step(caller, 1, 1_000);
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.on_step(implementation, 2, 900);
+    tracer.on_step(implementation, 2, 800);
+    tracer.on_step(caller, 1, 700);
+    tracer
+        .store_result(&storage, &known_bytecodes(&code))
+        .unwrap();
+
+    let pre = tracer.take_result().unwrap().pre;
+    let account = &pre[&implementation];
+    assert_eq!(account.code, Some(code));
+    assert_eq!(account.code_hash, Some(code_hash));
+    assert_eq!(account.storage, Some(HashMap::new()));
+    assert!(pre.contains_key(&caller));
+}
+
+#[test]
+fn code_is_not_captured_beyond_max_depth() {
+    let contract = Address::repeat_byte(0x01);
+    let callee = Address::repeat_byte(0x02);
+    let storage = storage_with(&[
+        (get_code_key(&contract), H256::repeat_byte(0xcc)),
+        (get_code_key(&callee), H256::repeat_byte(0xdd)),
+    ]);
+
+    let config = PrestateTracerConfig::builder().max_depth(2).build();
+    let mut tracer = PrestateTracer::from_config(config);
+    // The contract recursively calls itself, calling the callee at the deepest level.
+    for depth in 1..=4 {
+        tracer.on_frame_step(contract, depth, || depth, 1_000);
+    }
+    tracer.on_frame_step(callee, 5, || 5, 900);
+    // The storage accessed beyond the limit is still reported.
+    let slot = StorageKey::new(AccountTreeId::new(callee), H256::zero());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    assert_eq!(tracer.executed_code_addresses, HashSet::from([contract]));
+
+    let pre = tracer.take_result().unwrap().pre;
+    assert!(pre.contains_key(&contract));
+    assert_eq!(
+        pre[&callee].storage,
+        Some(HashMap::from([(*slot.key(), H256::zero())]))
+    );
+
+    // The depth isn't computed if it's not limited.
+    let mut tracer = PrestateTracer::default();
+    tracer.on_frame_step(callee, 5, || unreachable!(), 900);
+    assert!(tracer.executed_code_addresses.contains(&callee));
+}
+
+#[test]
+fn code_is_resolved_from_factory_deps() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let code = vec![0xaa; 32];
+    let code_hash = hash_bytecode(&code);
+    let storage = storage_with(&[(get_code_key(&address), code_hash)]);
+
+    for known_bytecodes in [known_bytecodes(&code), HashMap::new()] {
+        let mut tracer = PrestateTracer::from_config(PrestateTracerConfig::default());
+        tracer.record_storage_access(slot, false, &storage);
+        tracer.store_result(&storage, &known_bytecodes).unwrap();
+
+        let account = tracer.take_result().unwrap().pre[&address].clone();
+        assert_eq!(account.code_hash, Some(code_hash));
+        if known_bytecodes.is_empty() {
+            assert_eq!(account.code, None);
+            assert_eq!(account.code_unavailable, Some(true));
+        } else {
+            assert_eq!(account.code, Some(code.clone()));
+            assert_eq!(account.code_unavailable, None);
+        }
+    }
+}
+
+#[test]
+fn init_code_is_reported_for_created_contracts() {
+    let created = Address::repeat_byte(0x01);
+    let existing = Address::repeat_byte(0x02);
+    let code = vec![0xaa; 32];
+    let existing_code = vec![0xbb; 32];
+    let existing_slot = StorageKey::new(AccountTreeId::new(existing), H256::zero());
+    let storage = storage_with(&[(get_code_key(&existing), hash_bytecode(&existing_code))]);
+    let mut bytecodes = known_bytecodes(&code);
+    bytecodes.extend(known_bytecodes(&existing_code));
+
+    for diff_mode in [false, true] {
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = PrestateTracer::new(diff_mode, result.clone());
+        write_value(
+            &mut tracer,
+            &storage,
+            existing_slot,
+            H256::from_low_u64_be(1),
+        );
+        // The existing contract deploys a new one.
+        write_value(
+            &mut tracer,
+            &storage,
+            get_code_key(&created),
+            hash_bytecode(&code),
+        );
+        tracer.store_result(&storage, &bytecodes).unwrap();
+
+        let (pre, post) = result.get().unwrap();
+        let state = if diff_mode { post } else { pre };
+        assert_eq!(state[&created].init_code, Some(code.clone()));
+        // The deployed code is only a part of the post-state.
+        let deployed_code = diff_mode.then(|| code.clone());
+        assert_eq!(state[&created].code, deployed_code);
+        assert_eq!(state[&existing].init_code, None);
+    }
+}
+
+#[test]
+fn eoas_have_no_code_and_storage() {
+    let eoa = Address::repeat_byte(0x01);
+    let contract = Address::repeat_byte(0x02);
+    let code = vec![0xaa; 32];
+    let balance_key = get_balance_key(&AccountTreeId::new(eoa));
+    let storage = storage_with(&[
+        (balance_key, u256_to_h256(100.into())),
+        (get_code_key(&contract), hash_bytecode(&code)),
+    ]);
+    let factory_deps = known_bytecodes(&code);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(false, result.clone());
+    tracer.record_storage_access(get_code_key(&eoa), false, &storage);
+    tracer.record_storage_access(get_code_key(&contract), false, &storage);
+    tracer.store_result(&storage, &factory_deps).unwrap();
+
+    let (pre, _) = result.get().unwrap();
+    assert_eq!(pre[&eoa].balance, Some(100.into()));
+    assert_eq!(pre[&eoa].code, None);
+    assert_eq!(pre[&eoa].code_hash, None);
+    assert_eq!(pre[&eoa].storage, None);
+    assert_eq!(pre[&contract].code, Some(code));
+    assert_eq!(pre[&contract].storage, Some(HashMap::new()));
+}
+
+#[test]
+fn code_hash_and_size_are_reported() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let code_key = get_code_key(&address);
+    let code = vec![0xaa; 64];
+    let storage = storage_with(&[(code_key, hash_bytecode(&code))]);
+    let stored_code_hash = storage.borrow_mut().read_value(&code_key);
+
+    for disable_code in [false, true] {
+        let config = PrestateTracerConfig::builder()
+            .disable_code(disable_code)
+            .build();
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = PrestateTracer::with_config(config, result.clone());
+        write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+        tracer
+            .store_result(&storage, &known_bytecodes(&code))
+            .unwrap();
+
+        let account = &result.get().unwrap().0[&address];
+        assert_eq!(account.code_hash, Some(stored_code_hash));
+        assert_eq!(account.code_size, Some(code.len()));
+        assert_eq!(account.code.is_some(), !disable_code);
+
+        let json = serde_json::to_value(account).unwrap();
+        assert_eq!(json["codeHash"], format!("{:?}", stored_code_hash));
+        assert_eq!(json["codeSize"], code.len());
+    }
+}
+
+#[test]
+fn only_addresses_filters_accounts() {
+    let addresses = [
+        Address::repeat_byte(0x01),
+        Address::repeat_byte(0x02),
+        Address::repeat_byte(0x03),
+    ];
+    let storage = storage_with(&[]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone())
+        .with_only_addresses(HashSet::from([addresses[0]]));
+    for address in addresses {
+        let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+        write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert_eq!(pre.keys().collect::<Vec<_>>(), [&addresses[0]]);
+    assert_eq!(post.keys().collect::<Vec<_>>(), [&addresses[0]]);
+}
+
+#[test]
+fn tracer_reports_transaction_nonce() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let nonce_key = get_nonce_key(&address);
+    let full_nonce = nonces_to_full_nonce(26.into(), 3.into());
+    let storage = storage_with(&[(nonce_key, u256_to_h256(full_nonce))]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(false, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, _) = result.get().unwrap();
+    assert_eq!(pre[&address].nonce, Some(26.into()));
+    assert_eq!(pre[&address].deployment_nonce, Some(3.into()));
+}
+
+#[test]
+fn balance_is_read_from_base_token() {
+    let address = Address::repeat_byte(0x01);
+    let base_token = Address::repeat_byte(0xbb);
+    let account_id = AccountTreeId::new(address);
+    let storage = storage_with(&[
+        (get_balance_key(&account_id), u256_to_h256(100.into())),
+        (
+            get_balance_key_for_token(&account_id, base_token),
+            u256_to_h256(7.into()),
+        ),
+    ]);
+    let slot = StorageKey::new(account_id, H256::zero());
+
+    for (config, expected_balance) in [
+        (PrestateTracerConfig::default(), 100),
+        (
+            PrestateTracerConfig::builder()
+                .base_token(base_token)
+                .build(),
+            7,
+        ),
+    ] {
+        let mut tracer = PrestateTracer::with_config(config, Arc::new(OnceCell::new()));
+        write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let pre = tracer.get_result().unwrap().pre;
+        assert_eq!(pre[&address].balance, Some(expected_balance.into()));
+    }
+}
+
+#[test]
+fn default_mode_reports_full_prestate() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let nonce_key = get_nonce_key(&address);
+    let storage = storage_with(&[(nonce_key, u256_to_h256(1.into()))]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(false, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert_eq!(
+        pre[&address],
+        Account {
+            balance: Some(U256::zero()),
+            nonce: Some(1.into()),
+            deployment_nonce: Some(U256::zero()),
+            storage: Some(HashMap::from([(H256::zero(), H256::zero())])),
+            ..Account::default()
+        }
+    );
+    assert!(post.is_empty());
+}
+
+#[test]
+fn default_mode_reports_original_slot_values() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let storage = storage_with(&[(slot, H256::from_low_u64_be(5))]);
+
+    let mut tracer = PrestateTracer::default();
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(7));
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(9));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    // Like in Geth, the slot has the value from before the transaction rather than
+    // the one written by it.
+    let pre = tracer.take_result().unwrap().pre;
+    assert_eq!(
+        pre[&address].storage,
+        Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(5))]))
+    );
+    assert_eq!(
+        storage.borrow().modified_storage_keys()[&slot],
+        H256::from_low_u64_be(9)
+    );
+}
+
+#[test]
+fn bootloader_scratch_slots_are_optionally_excluded() {
+    let user_contract = Address::repeat_byte(0x01);
+    let user_slot = StorageKey::new(AccountTreeId::new(user_contract), H256::zero());
+    let system_context = AccountTreeId::new(SYSTEM_CONTEXT_ADDRESS);
+    let gas_price_slot = StorageKey::new(system_context, SYSTEM_CONTEXT_GAS_PRICE_POSITION);
+    let chain_id_slot = StorageKey::new(system_context, SYSTEM_CONTEXT_CHAIN_ID_POSITION);
+    let bootloader_slot = StorageKey::new(AccountTreeId::new(BOOTLOADER_ADDRESS), H256::zero());
+    assert!(is_bootloader_scratch_slot(&gas_price_slot));
+    assert!(is_bootloader_scratch_slot(&bootloader_slot));
+    assert!(!is_bootloader_scratch_slot(&chain_id_slot));
+    assert!(!is_bootloader_scratch_slot(&user_slot));
+
+    for exclude_bootloader_scratch in [false, true] {
+        let storage = storage_with(&[
+            (user_slot, H256::from_low_u64_be(1)),
+            (gas_price_slot, H256::from_low_u64_be(2)),
+            (chain_id_slot, H256::from_low_u64_be(270)),
+            (bootloader_slot, H256::from_low_u64_be(4)),
+        ]);
+        let config = PrestateTracerConfig::builder()
+            .exclude_bootloader_scratch(exclude_bootloader_scratch)
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        for key in [user_slot, chain_id_slot, bootloader_slot] {
+            tracer.record_storage_access(key, false, &storage);
+        }
+        write_value(
+            &mut tracer,
+            &storage,
+            gas_price_slot,
+            H256::from_low_u64_be(3),
+        );
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let pre = tracer.take_result().unwrap().pre;
+        let system_context_slots = pre[&SYSTEM_CONTEXT_ADDRESS].storage.clone().unwrap();
+        assert!(system_context_slots.contains_key(&SYSTEM_CONTEXT_CHAIN_ID_POSITION));
+        assert_eq!(
+            system_context_slots.contains_key(&SYSTEM_CONTEXT_GAS_PRICE_POSITION),
+            !exclude_bootloader_scratch
+        );
+        assert_eq!(
+            pre.contains_key(&BOOTLOADER_ADDRESS),
+            !exclude_bootloader_scratch
+        );
+        assert!(pre[&user_contract]
+            .storage
+            .as_ref()
+            .unwrap()
+            .contains_key(&H256::zero()));
+    }
+}
+
+#[test]
+fn read_only_accesses_are_reported_in_default_mode() {
+    let address = Address::repeat_byte(0x01);
+    let called_address = Address::repeat_byte(0x02);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let code_key = get_code_key(&called_address);
+    let storage = storage_with(&[
+        (slot, H256::from_low_u64_be(3)),
+        (code_key, H256::repeat_byte(0xcc)),
+    ]);
+
+    for diff_mode in [false, true] {
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = PrestateTracer::new(diff_mode, result.clone());
+        tracer.record_storage_access(slot, false, &storage);
+        tracer.record_storage_access(code_key, false, &storage);
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let (pre, _) = result.get().unwrap();
+        if diff_mode {
+            assert!(pre.is_empty(), "{:?}", pre);
+            continue;
+        }
+        assert_eq!(
+            pre[&address].storage,
+            Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(3))]))
+        );
+        assert_eq!(
+            pre[&called_address].code_hash,
+            Some(H256::repeat_byte(0xcc))
+        );
+    }
+}
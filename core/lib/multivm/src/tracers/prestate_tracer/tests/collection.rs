@@ -0,0 +1,473 @@
+use std::{io, sync::Mutex};
+
+use zksync_state::{InMemoryStorage, StorageView};
+use zksync_utils::bytecode::hash_bytecode;
+
+use crate::tracers::prestate_tracer::{
+    testing::fixtures::{storage_with, write_value, MockReader},
+    *,
+};
+
+/// Buffer capturing the output of a `tracing` subscriber.
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn collection_is_logged() {
+    let modified_storage_keys: HashMap<_, _> = [(0x01, 0), (0x01, 1), (0x02, 0)]
+        .into_iter()
+        .map(|(account, slot)| {
+            let account_id = AccountTreeId::new(Address::repeat_byte(account));
+            let key = StorageKey::new(account_id, H256::from_low_u64_be(slot));
+            (key, H256::from_low_u64_be(slot + 1))
+        })
+        .collect();
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+
+    let config = PrestateTracerConfig::default();
+    let state = tracing::subscriber::with_default(subscriber, || {
+        let mut reader = MockReader::default();
+        process_modified_storage_keys(State::new(), &modified_storage_keys, &config, &mut reader)
+    });
+    assert_eq!(state.len(), 2);
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("modified_keys=3"), "{logs}");
+    assert!(
+        logs.contains("Grouped modified storage keys by account accounts=2"),
+        "{logs}"
+    );
+    let account_address = format!("{:?}", Address::repeat_byte(0x01));
+    let account_line = logs
+        .lines()
+        .find(|line| line.contains(&account_address))
+        .unwrap();
+    assert!(account_line.contains("slots=2"), "{logs}");
+    assert!(logs.contains("accounts=2 slots=3"), "{logs}");
+}
+
+#[test]
+fn account_fields_are_read_once_per_account() {
+    let modified_storage_keys: HashMap<_, _> = (1..=5)
+        .flat_map(|account| {
+            (0..10).map(move |slot| {
+                let key = StorageKey::new(
+                    AccountTreeId::new(Address::repeat_byte(account)),
+                    H256::from_low_u64_be(slot),
+                );
+                (key, H256::from_low_u64_be(slot + 1))
+            })
+        })
+        .collect();
+
+    let mut reader = MockReader::default();
+    let state = process_modified_storage_keys(
+        State::new(),
+        &modified_storage_keys,
+        &PrestateTracerConfig::default(),
+        &mut reader,
+    );
+
+    assert_eq!(state.len(), 5);
+    assert!(state
+        .values()
+        .all(|account| account.storage.as_ref().unwrap().len() == 10));
+    // Balance, code hash and nonce for each account.
+    assert_eq!(reader.reads, 5 * 3);
+    // None of the accounts has code.
+    assert_eq!(reader.bytecode_loads, 0);
+}
+
+#[test]
+fn modified_values_are_reused() {
+    let address = Address::repeat_byte(0x01);
+    let nonce = u256_to_h256(nonces_to_full_nonce(3.into(), 1.into()));
+    let modified_storage_keys = HashMap::from([
+        (
+            StorageKey::new(AccountTreeId::new(address), H256::zero()),
+            H256::from_low_u64_be(1),
+        ),
+        (get_nonce_key(&address), nonce),
+    ]);
+
+    for reuse_modified_values in [false, true] {
+        // The nonce holder owning the nonce key isn't of interest.
+        let config = PrestateTracerConfig::builder()
+            .only_addresses(HashSet::from([address]))
+            .reuse_modified_values(reuse_modified_values)
+            .build();
+        let mut reader = MockReader::default();
+        let state = process_modified_storage_keys(
+            State::new(),
+            &modified_storage_keys,
+            &config,
+            &mut reader,
+        );
+
+        // The slots are never read, and the nonce is only read if it's not reused.
+        if reuse_modified_values {
+            assert_eq!(reader.reads, 2);
+            assert_eq!(state[&address].nonce, Some(3.into()));
+            assert_eq!(state[&address].deployment_nonce, Some(U256::one()));
+        } else {
+            assert_eq!(reader.reads, 3);
+        }
+        let slot_value = state[&address].storage.as_ref().unwrap()[&H256::zero()];
+        assert_eq!(slot_value, H256::from_low_u64_be(1));
+    }
+}
+
+#[test]
+fn touched_accounts_are_read_lazily() {
+    let modified_storage_keys: HashMap<_, _> = (1..=3)
+        .map(|account| {
+            let key = StorageKey::new(
+                AccountTreeId::new(Address::repeat_byte(account)),
+                H256::zero(),
+            );
+            (key, H256::from_low_u64_be(account.into()))
+        })
+        .collect();
+    let config = PrestateTracerConfig::default();
+
+    let mut reader = MockReader::default();
+    let first_account =
+        iter_touched_accounts(&State::new(), &modified_storage_keys, &config, &mut reader).next();
+    assert!(first_account.is_some());
+    // Only the consumed account is read.
+    assert_eq!(reader.reads, 3);
+
+    let mut reader = MockReader::default();
+    let accounts: State =
+        iter_touched_accounts(&State::new(), &modified_storage_keys, &config, &mut reader)
+            .collect();
+    let state = process_modified_storage_keys(
+        State::new(),
+        &modified_storage_keys,
+        &config,
+        &mut MockReader::default(),
+    );
+    assert_eq!(accounts.len(), 3);
+    assert_eq!(accounts, state);
+}
+
+#[test]
+fn accounts_are_streamed_to_sink() {
+    #[derive(Default)]
+    struct CountingSink {
+        addresses: Vec<Address>,
+        slot_count: usize,
+    }
+
+    impl PrestateSink for CountingSink {
+        fn on_account(&mut self, address: Address, account: Account) {
+            self.addresses.push(address);
+            self.slot_count += account.storage.map_or(0, |storage| storage.len());
+        }
+    }
+
+    let modified_storage_keys: HashMap<_, _> = (1..=4)
+        .flat_map(|account| {
+            (0..2).map(move |slot| {
+                let key = StorageKey::new(
+                    AccountTreeId::new(Address::repeat_byte(account)),
+                    H256::from_low_u64_be(slot),
+                );
+                (key, H256::from_low_u64_be(slot + 1))
+            })
+        })
+        .collect();
+    let config = PrestateTracerConfig::default();
+    // The accounts already in the prestate aren't passed to the sink.
+    let prestate = State::from([(Address::repeat_byte(1), Account::default())]);
+
+    let mut sink = CountingSink::default();
+    process_modified_storage_keys_into(
+        &prestate,
+        &modified_storage_keys,
+        &config,
+        &mut MockReader::default(),
+        &mut sink,
+    );
+    assert_eq!(sink.addresses.len(), 3);
+    assert!(!sink.addresses.contains(&Address::repeat_byte(1)));
+    assert_eq!(sink.slot_count, 3 * 2);
+
+    let mut collecting_sink = CollectingSink::default();
+    process_modified_storage_keys_into(
+        &prestate,
+        &modified_storage_keys,
+        &config,
+        &mut MockReader::default(),
+        &mut collecting_sink,
+    );
+    let state = process_modified_storage_keys(
+        prestate,
+        &modified_storage_keys,
+        &config,
+        &mut MockReader::default(),
+    );
+    assert_eq!(collecting_sink.into_state(), state);
+}
+
+#[test]
+fn accounts_are_streamed_over_bounded_channel() {
+    use std::sync::atomic::AtomicUsize;
+
+    const BOUND: usize = 1;
+
+    let modified_storage_keys: HashMap<_, _> = (1..=20)
+        .map(|account| {
+            let key = StorageKey::new(
+                AccountTreeId::new(Address::repeat_byte(account)),
+                H256::zero(),
+            );
+            (key, H256::from_low_u64_be(account.into()))
+        })
+        .collect();
+    let produced = Arc::<AtomicUsize>::default();
+    let (sender, receiver) = mpsc::sync_channel(BOUND);
+    let producer = std::thread::spawn({
+        let modified_storage_keys = modified_storage_keys.clone();
+        // Counts the accounts read by the collection, i.e. produced for the channel.
+        let produced = produced.clone();
+        let mut reader = MockReader::default().on_read(move |key| {
+            // The nonce is read once per account.
+            if *key.account().address() == zksync_system_constants::NONCE_HOLDER_ADDRESS {
+                produced.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        move || {
+            process_modified_storage_keys_into(
+                &State::new(),
+                &modified_storage_keys,
+                &PrestateTracerConfig::default(),
+                &mut reader,
+                &mut ChannelSink(sender),
+            );
+        }
+    });
+
+    let mut received = State::new();
+    for (address, account) in receiver {
+        // Besides the received accounts and the ones in the channel, the producer can only
+        // have read the account it's blocked on sending.
+        assert!(produced.load(Ordering::SeqCst) <= received.len() + 1 + BOUND + 1);
+        received.insert(address, account);
+    }
+    producer.join().unwrap();
+
+    let expected = process_modified_storage_keys(
+        State::new(),
+        &modified_storage_keys,
+        &PrestateTracerConfig::default(),
+        &mut MockReader::default(),
+    );
+    assert_eq!(received.len(), 20);
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn prestate_is_collected_from_snapshot() {
+    let address = Address::repeat_byte(0x01);
+    let account_id = AccountTreeId::new(address);
+    let slot = StorageKey::new(account_id, H256::zero());
+    let mut snapshot = MockReader::new(HashMap::from([
+        (get_balance_key(&account_id), u256_to_h256(100.into())),
+        (
+            get_nonce_key(&address),
+            u256_to_h256(nonces_to_full_nonce(2.into(), 1.into())),
+        ),
+    ]))
+    .with_modified_keys(HashMap::from([(slot, H256::from_low_u64_be(5))]));
+
+    let config = PrestateTracerConfig::default();
+    let state = prestate_from_storage(&mut snapshot, &config);
+    let account = &state[&address];
+    assert_eq!(account.balance, Some(100.into()));
+    assert_eq!(account.nonce, Some(2.into()));
+    assert_eq!(
+        account.storage,
+        Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(5))]))
+    );
+
+    // VM storages are readers as well.
+    let mut storage = StorageView::new(InMemoryStorage::default());
+    storage.set_value(slot, H256::from_low_u64_be(5));
+    let state = prestate_from_storage(&mut storage, &config);
+    assert_eq!(state[&address].storage, account.storage);
+}
+
+#[test]
+fn touched_addresses_match_collected_accounts() {
+    let modified_storage_keys: HashMap<_, _> = (1..=3)
+        .flat_map(|account| {
+            (0..2).map(move |slot| {
+                let key = StorageKey::new(
+                    AccountTreeId::new(Address::repeat_byte(account)),
+                    H256::from_low_u64_be(slot),
+                );
+                (key, H256::from_low_u64_be(account.into()))
+            })
+        })
+        .collect();
+    let config = PrestateTracerConfig::builder()
+        .only_addresses(HashSet::from([
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+        ]))
+        .build();
+
+    let mut reader = MockReader::default();
+    let addresses = touched_addresses(&modified_storage_keys, &config);
+    let state =
+        process_modified_storage_keys(State::new(), &modified_storage_keys, &config, &mut reader);
+    assert_eq!(addresses, state.keys().copied().collect());
+    assert_eq!(addresses.len(), 2);
+}
+
+#[test]
+fn sorted_storage_is_in_ascending_slot_order() {
+    let account = AccountTreeId::new(Address::repeat_byte(0x01));
+    let other_account = AccountTreeId::new(Address::repeat_byte(0x02));
+    let modified_storage_keys: HashMap<_, _> = [5, 1, 0xff, 3]
+        .into_iter()
+        .map(|slot| {
+            let key = StorageKey::new(account, H256::from_low_u64_be(slot));
+            (key, H256::from_low_u64_be(slot + 1))
+        })
+        .chain([(StorageKey::new(other_account, H256::zero()), H256::zero())])
+        .collect();
+
+    let storage = get_sorted_storage_if_present(&account, &modified_storage_keys);
+    let slots: Vec<_> = storage.keys().map(|slot| slot.to_low_u64_be()).collect();
+    assert_eq!(slots, [1, 3, 5, 0xff]);
+    assert_eq!(storage[&H256::from_low_u64_be(3)], H256::from_low_u64_be(4));
+}
+
+#[test]
+fn prestate_is_built_from_modified_keys() {
+    let contract = Address::repeat_byte(0x01);
+    let eoa = Address::repeat_byte(0x02);
+    let slot =
+        |address, slot| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+    let modified_keys = HashMap::from([
+        (slot(contract, 0), H256::from_low_u64_be(1)),
+        (slot(contract, 1), H256::from_low_u64_be(2)),
+        (slot(eoa, 0), H256::zero()),
+    ]);
+    let code_hash = hash_bytecode(&[0xaa; 32]);
+    let values = HashMap::from([
+        (get_code_key(&contract), code_hash),
+        (
+            get_nonce_key(&eoa),
+            u256_to_h256(nonces_to_full_nonce(7.into(), 0.into())),
+        ),
+        (
+            get_balance_key(&AccountTreeId::new(eoa)),
+            u256_to_h256(100.into()),
+        ),
+    ]);
+
+    let state =
+        prestate_from_modified_keys(&modified_keys, &PrestateTracerConfig::default(), |key| {
+            values.get(key).copied().unwrap_or_default()
+        });
+    assert_eq!(state.len(), 2);
+    let contract_account = &state[&contract];
+    assert_eq!(contract_account.code_hash, Some(code_hash));
+    assert_eq!(contract_account.code, None);
+    assert_eq!(contract_account.storage.as_ref().unwrap().len(), 2);
+    let eoa_account = &state[&eoa];
+    assert_eq!(eoa_account.nonce, Some(7.into()));
+    assert_eq!(eoa_account.balance, Some(100.into()));
+    assert_eq!(
+        eoa_account.storage,
+        Some(HashMap::from([(H256::zero(), H256::zero())]))
+    );
+}
+
+#[test]
+fn collection_stops_when_cancelled() {
+    let modified_storage_keys: HashMap<_, _> = (1..=3)
+        .map(|account| {
+            let key = StorageKey::new(
+                AccountTreeId::new(Address::repeat_byte(account)),
+                H256::zero(),
+            );
+            (key, H256::from_low_u64_be(account.into()))
+        })
+        .collect();
+    let token = CancellationToken::new();
+    let config = PrestateTracerConfig::builder()
+        .cancellation_token(token.clone())
+        .build();
+
+    // Cancels the token on the first read, i.e. while the first account is being read.
+    let mut reader = MockReader::default().on_read({
+        let token = token.clone();
+        move |_| token.cancel()
+    });
+    let state =
+        process_modified_storage_keys(State::new(), &modified_storage_keys, &config, &mut reader);
+    assert!(token.is_cancelled());
+    // The account being read when cancelled is still reported in full.
+    assert_eq!(state.len(), 1);
+    let (address, account) = state.into_iter().next().unwrap();
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    assert_eq!(
+        account.storage,
+        Some(HashMap::from([(
+            H256::zero(),
+            modified_storage_keys[&slot]
+        )]))
+    );
+
+    // The tracer marks the cancelled result as truncated.
+    let storage = storage_with(&[]);
+    let mut tracer = PrestateTracer::from_config(config);
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    let result = tracer.take_result().unwrap();
+    assert!(result.truncated);
+    assert!(result.pre.is_empty());
+}
+
+#[test]
+fn disabled_fields_are_not_read() {
+    let address = Address::repeat_byte(0x01);
+    let modified_storage_keys = HashMap::from([(
+        StorageKey::new(AccountTreeId::new(address), H256::zero()),
+        H256::from_low_u64_be(1),
+    )]);
+    let config = PrestateTracerConfig::builder()
+        .disable_code(true)
+        .disable_storage(true)
+        .build();
+
+    let mut reader = MockReader::default();
+    let state =
+        process_modified_storage_keys(State::new(), &modified_storage_keys, &config, &mut reader);
+
+    assert_eq!(state[&address].code, None);
+    assert_eq!(state[&address].storage, None);
+    // Only balance, code hash and nonce are read.
+    assert_eq!(reader.reads, 3);
+    assert_eq!(reader.bytecode_loads, 0);
+}
@@ -0,0 +1,116 @@
+use std::sync::Mutex;
+
+use zksync_types::utils::storage_key_for_standard_token_balance;
+
+use crate::tracers::prestate_tracer::{
+    testing::fixtures::{storage_with, write_value},
+    *,
+};
+
+#[test]
+fn balance_key_is_hashed_with_injected_hasher() {
+    let preimages = Arc::new(Mutex::new(vec![]));
+    let hasher = SlotHasher::new({
+        let preimages = preimages.clone();
+        move |preimage| {
+            preimages.lock().unwrap().push(preimage.to_vec());
+            H256(keccak256(preimage))
+        }
+    });
+    let config = PrestateTracerConfig::builder().slot_hasher(hasher).build();
+    let account = AccountTreeId::new(Address::repeat_byte(0x01));
+
+    let key = config.balance_key(&account);
+    assert_eq!(key, get_balance_key(&account));
+    let mut expected_preimage = address_to_h256(account.address()).as_bytes().to_vec();
+    expected_preimage.extend([0; 32]);
+    assert_eq!(*preimages.lock().unwrap(), [expected_preimage]);
+}
+
+#[test]
+fn balance_key_uses_registered_slot() {
+    let account = AccountTreeId::new(Address::repeat_byte(0x01));
+    let token = Address::repeat_byte(0x02);
+    let registry = BalanceSlotRegistry::default().with_token(token, H256::from_low_u64_be(2));
+
+    let key = registry.balance_key(&account, token);
+    let slot_0_key = get_balance_key_for_token(&account, token);
+    assert_ne!(key, slot_0_key);
+    assert_eq!(
+        slot_0_key,
+        storage_key_for_standard_token_balance(AccountTreeId::new(token), account.address())
+    );
+    let mut preimage = [0_u8; 64];
+    preimage[12..32].copy_from_slice(account.address().as_bytes());
+    preimage[63] = 2;
+    assert_eq!(*key.key(), H256(keccak256(&preimage)));
+    assert_eq!(
+        registry.balance_key(&account, L2_ETH_TOKEN_ADDRESS),
+        get_balance_key(&account)
+    );
+
+    // The tracer reads the balance from the registered slot.
+    let address = *account.address();
+    let storage = storage_with(&[(key, u256_to_h256(100.into()))]);
+    let config = PrestateTracerConfig::builder()
+        .base_token(token)
+        .balance_slots(registry)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+    let slot = StorageKey::new(account, H256::zero());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    let result = tracer.take_result().unwrap();
+    assert_eq!(result.pre[&address].balance, Some(100.into()));
+}
+
+#[test]
+fn config_is_parsed_from_tracer_config() {
+    let config = PrestateTracerConfig::from_json(&serde_json::json!({ "diffMode": true })).unwrap();
+    assert!(config.diff_mode);
+    assert!(!config.disable_code);
+
+    let config = PrestateTracerConfig::from_json(&serde_json::Value::Null).unwrap();
+    assert!(!config.diff_mode);
+    let config = PrestateTracerConfig::from_json(&serde_json::json!({
+        "disableCode": true,
+        "disableStorage": true,
+    }))
+    .unwrap();
+    assert!(config.disable_code && config.disable_storage);
+
+    let err = PrestateTracerConfig::from_json(&serde_json::json!({ "diffMode": 1 }));
+    assert!(err.unwrap_err().to_string().contains("invalid type"));
+}
+
+#[test]
+fn unknown_tracer_config_options_are_rejected() {
+    let err = PrestateTracerConfig::from_json(&serde_json::json!({ "diffmode": true }));
+    let err = err.unwrap_err().to_string();
+    assert!(err.contains("unknown field `diffmode`"), "{err}");
+
+    let err = PrestateTracerConfig::from_json(&serde_json::json!(true)).unwrap_err();
+    assert!(err.to_string().contains("invalid type"), "{err}");
+}
+
+#[test]
+fn only_addresses_are_parsed_from_hex() {
+    let address = Address::repeat_byte(0xab);
+    let config = PrestateTracerConfig::builder()
+        .only_hex_addresses([
+            "0xabababababababababababababababababababab",
+            "0xABABABABABABABABABABABABABABABABABABABAB",
+            "0XaBaBaBaBaBaBaBaBaBaBaBaBaBaBaBaBaBaBaBaB",
+            "abABabABabABabABabABabABabABabABabABabAB",
+        ])
+        .unwrap()
+        .build();
+    assert_eq!(config.only_addresses, Some(HashSet::from([address])));
+
+    for malformed in ["0xabab", "0xzzababababababababababababababababababab", ""] {
+        let err = PrestateTracerConfig::builder()
+            .only_hex_addresses(["0xabababababababababababababababababababab", malformed])
+            .unwrap_err();
+        assert_eq!(err, InvalidAddressError(malformed.to_owned()));
+    }
+}
@@ -0,0 +1,597 @@
+use zksync_utils::bytecode::hash_bytecode;
+
+use crate::tracers::prestate_tracer::{
+    testing::fixtures::{known_bytecodes, storage_with, write_value},
+    *,
+};
+
+#[test]
+fn diff_mode_omits_unchanged_fields() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let balance_key = get_balance_key(&AccountTreeId::new(address));
+    let nonce_key = get_nonce_key(&address);
+    let storage = storage_with(&[
+        (balance_key, u256_to_h256(100.into())),
+        (nonce_key, u256_to_h256(1.into())),
+    ]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    write_value(&mut tracer, &storage, balance_key, u256_to_h256(50.into()));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert_eq!(
+        pre[&address],
+        Account {
+            balance: Some(100.into()),
+            storage: Some(HashMap::from([(H256::zero(), H256::zero())])),
+            ..Account::default()
+        }
+    );
+    assert_eq!(
+        post[&address],
+        Account {
+            balance: Some(50.into()),
+            storage: Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(1))])),
+            ..Account::default()
+        }
+    );
+}
+
+fn trace_existence_transition(balances: (u64, u64)) -> (PreState, PostState) {
+    let address = Address::repeat_byte(0x01);
+    let balance_key = get_balance_key(&AccountTreeId::new(address));
+    let storage = storage_with(&[(balance_key, u256_to_h256(balances.0.into()))]);
+
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .track_existence(true)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+    // The code of the recipient is checked by the transfer.
+    tracer.record_storage_access(get_code_key(&address), false, &storage);
+    write_value(
+        &mut tracer,
+        &storage,
+        balance_key,
+        u256_to_h256(balances.1.into()),
+    );
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    let result = tracer.take_result().unwrap();
+    (result.pre, result.post.unwrap())
+}
+
+#[test]
+fn account_becoming_existent_is_absent_from_pre() {
+    let address = Address::repeat_byte(0x01);
+    let (pre, post) = trace_existence_transition((0, 100));
+    assert!(!pre.contains_key(&address));
+    assert_eq!(
+        post[&address],
+        Account {
+            balance: Some(100.into()),
+            nonce: Some(U256::zero()),
+            deployment_nonce: Some(U256::zero()),
+            ..Account::default()
+        }
+    );
+}
+
+#[test]
+fn account_becoming_empty_is_absent_from_post() {
+    let address = Address::repeat_byte(0x01);
+    let (pre, post) = trace_existence_transition((100, 0));
+    assert_eq!(
+        pre[&address],
+        Account {
+            balance: Some(100.into()),
+            nonce: Some(U256::zero()),
+            deployment_nonce: Some(U256::zero()),
+            ..Account::default()
+        }
+    );
+    assert!(!post.contains_key(&address));
+}
+
+#[test]
+fn repeated_writes_report_net_change() {
+    let address = Address::repeat_byte(0x01);
+    let slot =
+        |slot: u64| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+    let storage = storage_with(&[
+        (slot(0), H256::from_low_u64_be(1)),
+        (slot(1), H256::from_low_u64_be(1)),
+    ]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone());
+    // The first slot ends up with its original value, the second one doesn't.
+    for value in [2, 3, 1] {
+        write_value(&mut tracer, &storage, slot(0), H256::from_low_u64_be(value));
+    }
+    for value in [2, 3] {
+        write_value(&mut tracer, &storage, slot(1), H256::from_low_u64_be(value));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    let slot_1 = *slot(1).key();
+    assert_eq!(
+        pre[&address].storage,
+        Some(HashMap::from([(slot_1, H256::from_low_u64_be(1))]))
+    );
+    assert_eq!(
+        post[&address].storage,
+        Some(HashMap::from([(slot_1, H256::from_low_u64_be(3))]))
+    );
+}
+
+#[test]
+fn diff_mode_drops_unchanged_accounts() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let storage = storage_with(&[(slot, H256::from_low_u64_be(1))]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert!(pre.is_empty());
+    assert!(post.is_empty());
+}
+
+#[test]
+fn lazy_pre_produces_same_diff() {
+    let address = Address::repeat_byte(0x01);
+    let unchanged = Address::repeat_byte(0x02);
+    let written = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let read = StorageKey::new(AccountTreeId::new(unchanged), H256::zero());
+
+    let trace = |lazy_pre| {
+        let config = PrestateTracerConfig::builder()
+            .diff_mode(true)
+            .lazy_pre(lazy_pre)
+            .build();
+        let storage = storage_with(&[
+            (written, H256::from_low_u64_be(1)),
+            (read, H256::from_low_u64_be(5)),
+        ]);
+        let mut tracer = PrestateTracer::from_config(config);
+        tracer.record_storage_access(read, false, &storage);
+        tracer.record_storage_access(written, false, &storage);
+        write_value(&mut tracer, &storage, written, H256::from_low_u64_be(2));
+        tracer.record_storage_access(read, false, &storage);
+        assert_eq!(tracer.initial_values.contains_key(&read), !lazy_pre);
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+        tracer.take_result().unwrap()
+    };
+    let (eager, lazy) = (trace(false), trace(true));
+    assert_eq!(lazy.pre, eager.pre);
+    assert_eq!(lazy.post, eager.post);
+    let pre_storage = lazy.pre[&address].storage.as_ref().unwrap();
+    assert_eq!(pre_storage[written.key()], H256::from_low_u64_be(1));
+    assert!(!lazy.pre.contains_key(&unchanged));
+}
+
+#[test]
+fn diff_mode_reports_deployment_nonce_of_factory() {
+    let factory = Address::repeat_byte(0x01);
+    let children = [Address::repeat_byte(0x02), Address::repeat_byte(0x03)];
+    let nonce_key = get_nonce_key(&factory);
+    let full_nonce = |deployment_nonce: u64| {
+        u256_to_h256(nonces_to_full_nonce(0.into(), deployment_nonce.into()))
+    };
+    let storage = storage_with(&[(nonce_key, full_nonce(1))]);
+
+    let mut tracer = PrestateTracer::diff();
+    tracer.on_step(factory, 1, 1_000);
+    // Every deployment bumps the deployment nonce of the factory, sharing the nonce slot
+    // with its transaction nonce.
+    for (deployment_nonce, child) in (2..).zip(children) {
+        write_value(
+            &mut tracer,
+            &storage,
+            nonce_key,
+            full_nonce(deployment_nonce),
+        );
+        let code_key = get_code_key(&child);
+        write_value(&mut tracer, &storage, code_key, H256::repeat_byte(0xcc));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let result = tracer.take_result().unwrap();
+    let post = result.post.unwrap();
+    assert_eq!(result.pre[&factory].deployment_nonce, Some(1.into()));
+    assert_eq!(post[&factory].deployment_nonce, Some(3.into()));
+    // The transaction nonce isn't changed, so it's not reported.
+    assert_eq!(result.pre[&factory].nonce, None);
+    assert_eq!(post[&factory].nonce, None);
+    for child in children {
+        assert_eq!(post[&child].code_hash, Some(H256::repeat_byte(0xcc)));
+    }
+}
+
+#[test]
+fn eoas_are_dropped_with_contracts_only() {
+    let sender = Address::repeat_byte(0x01);
+    let recipient = Address::repeat_byte(0x02);
+    let contract = Address::repeat_byte(0x03);
+    let balance_key = |address| get_balance_key(&AccountTreeId::new(address));
+    let slot = StorageKey::new(AccountTreeId::new(contract), H256::zero());
+    let storage = storage_with(&[
+        (balance_key(sender), u256_to_h256(100.into())),
+        (get_code_key(&contract), H256::repeat_byte(0xcc)),
+        (get_code_key(&L2_ETH_TOKEN_ADDRESS), H256::repeat_byte(0xee)),
+    ]);
+
+    for contracts_only in [false, true] {
+        let config = PrestateTracerConfig::builder()
+            .diff_mode(true)
+            .contracts_only(contracts_only)
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        // The sender transfers to the recipient and calls the contract.
+        tracer.on_step(sender, 1, 1_000);
+        write_value(
+            &mut tracer,
+            &storage,
+            balance_key(sender),
+            u256_to_h256(90.into()),
+        );
+        let recipient_balance = u256_to_h256(10.into());
+        write_value(
+            &mut tracer,
+            &storage,
+            balance_key(recipient),
+            recipient_balance,
+        );
+        tracer.on_step(recipient, 1, 900);
+        tracer.on_step(contract, 1, 800);
+        write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let result = tracer.take_result().unwrap();
+        let post = result.post.unwrap();
+        let addresses: HashSet<_> = result.pre.keys().chain(post.keys()).copied().collect();
+        if contracts_only {
+            // The base token contract holding the balances has code as well.
+            assert_eq!(addresses, HashSet::from([contract, L2_ETH_TOKEN_ADDRESS]));
+        } else {
+            assert!(addresses.contains(&sender) && addresses.contains(&recipient));
+            assert!(addresses.contains(&contract));
+        }
+    }
+}
+
+#[test]
+fn diff_mode_omits_storage_of_value_only_recipient() {
+    let recipient = Address::repeat_byte(0x01);
+    let balance_key = get_balance_key(&AccountTreeId::new(recipient));
+    let storage = storage_with(&[
+        (get_code_key(&recipient), hash_bytecode(&[0xaa; 32])),
+        (balance_key, u256_to_h256(10.into())),
+    ]);
+
+    let mut tracer = PrestateTracer::diff();
+    // The recipient's code is called with the transferred value, but doesn't write its storage.
+    tracer.on_step(recipient, 1, 1_000);
+    write_value(&mut tracer, &storage, balance_key, u256_to_h256(15.into()));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let result = tracer.take_result().unwrap();
+    let post = result.post.unwrap();
+    for (account, balance) in [(&result.pre[&recipient], 10), (&post[&recipient], 15)] {
+        assert_eq!(account.balance, Some(balance.into()));
+        assert_eq!(account.storage, None);
+        assert_eq!(account.code_hash, None);
+        assert_eq!(account.nonce, None);
+    }
+    let json = serde_json::to_value(&post[&recipient]).unwrap();
+    assert_eq!(json, serde_json::json!({ "balance": "0xf" }));
+}
+
+#[test]
+fn new_accounts_omit_empty_storage() {
+    let address = Address::repeat_byte(0x01);
+    let mut pre = State::new();
+    let mut post = State::from([(
+        address,
+        Account {
+            balance: Some(1.into()),
+            storage: Some(HashMap::new()),
+            ..Account::default()
+        },
+    )]);
+
+    retain_changes(&mut pre, &mut post);
+    assert_eq!(post[&address].balance, Some(1.into()));
+    assert_eq!(post[&address].storage, None);
+}
+
+#[test]
+fn diff_mode_omits_deleted_accounts_from_post() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let code_key = get_code_key(&address);
+    let balance_key = get_balance_key(&AccountTreeId::new(address));
+    let code = vec![0xaa; 32];
+    let code_hash = hash_bytecode(&code);
+    let storage = storage_with(&[
+        (code_key, code_hash),
+        (balance_key, u256_to_h256(10.into())),
+        (slot, H256::from_low_u64_be(1)),
+    ]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::zero());
+    write_value(&mut tracer, &storage, code_key, H256::zero());
+    write_value(&mut tracer, &storage, balance_key, H256::zero());
+    assert!(tracer.is_account_deleted(&storage, &address));
+    tracer
+        .store_result(&storage, &known_bytecodes(&code))
+        .unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert_eq!(
+        pre[&address],
+        Account {
+            balance: Some(10.into()),
+            code: Some(code),
+            code_hash: Some(code_hash),
+            code_size: Some(32),
+            nonce: Some(U256::zero()),
+            deployment_nonce: Some(U256::zero()),
+            storage: Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(1))])),
+            ..Account::default()
+        }
+    );
+    assert!(!post.contains_key(&address));
+}
+
+#[test]
+fn diff_mode_reports_beneficiary_of_destructed_account() {
+    let destructed = Address::repeat_byte(0x01);
+    let beneficiary = Address::repeat_byte(0x02);
+    let code_key = get_code_key(&destructed);
+    let balance_key = |address| get_balance_key(&AccountTreeId::new(address));
+    let storage = storage_with(&[
+        (code_key, hash_bytecode(&[0xaa; 32])),
+        (balance_key(destructed), u256_to_h256(100.into())),
+        (balance_key(beneficiary), u256_to_h256(5.into())),
+    ]);
+    let mut calldata = TRANSFER_FROM_TO_SELECTOR.to_vec();
+    calldata.extend_from_slice(address_to_h256(&destructed).as_bytes());
+    calldata.extend_from_slice(address_to_h256(&beneficiary).as_bytes());
+
+    for report_transfer in [false, true] {
+        let mut tracer = PrestateTracer::diff();
+        tracer.on_step(destructed, 1, 1_000);
+        // The balance is swept by the base token contract, without calling the beneficiary.
+        if report_transfer {
+            tracer.on_base_token_call(&calldata[..BASE_TOKEN_CALLDATA_LEN]);
+        }
+        write_value(&mut tracer, &storage, code_key, H256::zero());
+        let value = u256_to_h256(105.into());
+        write_value(&mut tracer, &storage, balance_key(beneficiary), value);
+        write_value(&mut tracer, &storage, balance_key(destructed), H256::zero());
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let result = tracer.take_result().unwrap();
+        let post = result.post.unwrap();
+        assert!(result.pre.contains_key(&destructed));
+        assert!(!post.contains_key(&destructed));
+        if !report_transfer {
+            // The beneficiary can't be recovered from its hashed balance key.
+            assert!(!post.contains_key(&beneficiary));
+            continue;
+        }
+        assert_eq!(result.pre[&beneficiary].balance, Some(5.into()));
+        assert_eq!(post[&beneficiary].balance, Some(105.into()));
+    }
+}
+
+#[test]
+fn diff_mode_reports_created_and_called_contract() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let code_key = get_code_key(&address);
+    let code = vec![0xaa; 32];
+    let code_hash = hash_bytecode(&code);
+    let storage = storage_with(&[]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone());
+    // The contract is deployed and then called within the same transaction.
+    write_value(&mut tracer, &storage, code_key, code_hash);
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(5));
+    tracer
+        .store_result(&storage, &known_bytecodes(&code))
+        .unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert_eq!(
+        pre[&address],
+        Account {
+            storage: Some(HashMap::from([(H256::zero(), H256::zero())])),
+            ..Account::default()
+        }
+    );
+    assert_eq!(
+        post[&address],
+        Account {
+            code: Some(code),
+            code_hash: Some(code_hash),
+            code_size: Some(32),
+            storage: Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(5))])),
+            init_code: Some(code),
+            ..Account::default()
+        }
+    );
+}
+
+#[test]
+fn short_lived_accounts_are_omitted_from_diff() {
+    let factory = Address::repeat_byte(0x01);
+    let child = Address::repeat_byte(0x02);
+    let factory_slot = StorageKey::new(AccountTreeId::new(factory), H256::zero());
+    let child_slot = StorageKey::new(AccountTreeId::new(child), H256::zero());
+    let child_balance_key = get_balance_key(&AccountTreeId::new(child));
+    let storage = storage_with(&[]);
+
+    let mut tracer = PrestateTracer::diff();
+    tracer.on_step(factory, 1, 1_000);
+    write_value(
+        &mut tracer,
+        &storage,
+        factory_slot,
+        H256::from_low_u64_be(1),
+    );
+    // The factory deploys the child, which writes its storage and receives some value,
+    // and then destroys it.
+    let code_key = get_code_key(&child);
+    write_value(&mut tracer, &storage, code_key, hash_bytecode(&[0xaa; 32]));
+    tracer.on_step(child, 2, 900);
+    write_value(&mut tracer, &storage, child_slot, H256::from_low_u64_be(5));
+    write_value(
+        &mut tracer,
+        &storage,
+        child_balance_key,
+        u256_to_h256(10.into()),
+    );
+    write_value(&mut tracer, &storage, code_key, H256::zero());
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let result = tracer.take_result().unwrap();
+    let post = result.post.unwrap();
+    assert!(!result.pre.contains_key(&child));
+    assert!(!post.contains_key(&child));
+    assert_eq!(
+        post[&factory].storage,
+        Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(1))]))
+    );
+}
+
+#[test]
+fn diff_mode_reports_code_hash_change() {
+    let address = Address::repeat_byte(0x01);
+    let code_key = get_code_key(&address);
+    let (old_code, new_code) = (vec![0xaa; 32], vec![0xbb; 96]);
+    let (old_code_hash, new_code_hash) = (hash_bytecode(&old_code), hash_bytecode(&new_code));
+    let storage = storage_with(&[(code_key, old_code_hash)]);
+    let mut bytecodes = known_bytecodes(&old_code);
+    bytecodes.extend(known_bytecodes(&new_code));
+
+    // Only the code hash is overwritten, the contract itself isn't called.
+    let mut tracer = PrestateTracer::diff();
+    write_value(&mut tracer, &storage, code_key, new_code_hash);
+    tracer.store_result(&storage, &bytecodes).unwrap();
+
+    let result = tracer.take_result().unwrap();
+    let pre = &result.pre[&address];
+    assert_eq!(pre.code_hash, Some(old_code_hash));
+    assert_eq!(pre.code, Some(old_code));
+    let post = result.post.unwrap();
+    let post = &post[&address];
+    assert_eq!(post.code_hash, Some(new_code_hash));
+    assert_eq!(post.code_size, Some(new_code.len()));
+    assert_eq!(post.code, Some(new_code));
+    assert!(result.created_addresses.is_empty());
+}
+
+#[test]
+fn slots_written_back_or_to_zero_are_omitted_from_diff() {
+    let address = Address::repeat_byte(0x01);
+    let restored = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let zeroed = StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(1));
+    let storage = storage_with(&[
+        (restored, H256::from_low_u64_be(1)),
+        (zeroed, H256::from_low_u64_be(3)),
+    ]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone());
+    write_value(&mut tracer, &storage, restored, H256::from_low_u64_be(2));
+    write_value(&mut tracer, &storage, restored, H256::from_low_u64_be(1));
+    write_value(&mut tracer, &storage, zeroed, H256::zero());
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert_eq!(
+        pre[&address].storage,
+        Some(HashMap::from([(*zeroed.key(), H256::from_low_u64_be(3))]))
+    );
+    assert_eq!(post[&address].storage, Some(HashMap::new()));
+
+    // A slot written back to its original value isn't reported at all.
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone());
+    write_value(&mut tracer, &storage, restored, H256::from_low_u64_be(5));
+    write_value(&mut tracer, &storage, restored, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let (pre, post) = result.get().unwrap();
+    assert!(pre.is_empty());
+    assert!(post.is_empty());
+}
+
+#[test]
+fn diff_is_relative_to_baseline() {
+    let address = Address::repeat_byte(0x01);
+    let key = |slot| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+    let balance_key = get_balance_key(&AccountTreeId::new(address));
+    let baseline = State::from([(
+        address,
+        Account {
+            balance: Some(50.into()),
+            storage: Some(HashMap::from([
+                (*key(0).key(), H256::from_low_u64_be(5)),
+                (*key(1).key(), H256::from_low_u64_be(3)),
+            ])),
+            ..Account::default()
+        },
+    )]);
+
+    for with_baseline in [false, true] {
+        let storage = storage_with(&[
+            (key(0), H256::from_low_u64_be(1)),
+            (key(1), H256::from_low_u64_be(1)),
+            (balance_key, u256_to_h256(10.into())),
+        ]);
+        let mut config = PrestateTracerConfig::builder().diff_mode(true);
+        if with_baseline {
+            config = config.baseline(baseline.clone());
+        }
+        let mut tracer = PrestateTracer::from_config(config.build());
+        tracer.on_step(address, 1, 1_000);
+        write_value(&mut tracer, &storage, key(0), H256::from_low_u64_be(7));
+        write_value(&mut tracer, &storage, key(1), H256::from_low_u64_be(3));
+        write_value(&mut tracer, &storage, balance_key, u256_to_h256(50.into()));
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let result = tracer.take_result().unwrap();
+        let post = result.post.unwrap();
+        let (pre, post) = (&result.pre[&address], &post[&address]);
+        if !with_baseline {
+            let slots = HashMap::from([
+                (*key(0).key(), H256::from_low_u64_be(1)),
+                (*key(1).key(), H256::from_low_u64_be(1)),
+            ]);
+            assert_eq!(pre.storage, Some(slots));
+            assert_eq!(pre.balance, Some(10.into()));
+            continue;
+        }
+        // The slot and the balance left as in the baseline aren't changed.
+        let slots = HashMap::from([(*key(0).key(), H256::from_low_u64_be(5))]);
+        assert_eq!(pre.storage, Some(slots));
+        assert_eq!(pre.balance, None);
+        let slots = HashMap::from([(*key(0).key(), H256::from_low_u64_be(7))]);
+        assert_eq!(post.storage, Some(slots));
+        assert_eq!(post.balance, None);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::tracers::prestate_tracer::*;
+
+#[test]
+fn result_json_matches_geth() {
+    let address: Address = "0x0000000000000000000000000000000000000001"
+        .parse()
+        .unwrap();
+    let account = |nonce: u64, value: u64| Account {
+        balance: Some(U256::from(1_000)),
+        nonce: Some(nonce.into()),
+        storage: Some(HashMap::from([(
+            H256::zero(),
+            H256::from_low_u64_be(value),
+        )])),
+        ..Account::default()
+    };
+    let slot = "0x0000000000000000000000000000000000000000000000000000000000000000";
+    let mut result = PrestateResult::from((
+        State::from([(address, account(1, 1))]),
+        State::from([(address, account(2, 2))]),
+    ));
+
+    // Geth output for the same transaction with `{"diffMode": true}`.
+    let expected = serde_json::json!({
+        "pre": {
+            "0x0000000000000000000000000000000000000001": {
+                "balance": "0x3e8",
+                "nonce": 1,
+                "storage": {
+                    slot: "0x0000000000000000000000000000000000000000000000000000000000000001"
+                }
+            }
+        },
+        "post": {
+            "0x0000000000000000000000000000000000000001": {
+                "balance": "0x3e8",
+                "nonce": 2,
+                "storage": {
+                    slot: "0x0000000000000000000000000000000000000000000000000000000000000002"
+                }
+            }
+        }
+    });
+    let config = PrestateTracerConfig::default();
+    assert_eq!(result.to_json(&config), expected);
+
+    // Geth output without the tracer config.
+    result.post = None;
+    assert_eq!(result.to_json(&config), expected["pre"]);
+}
+
+#[test]
+fn json_is_padded_and_optionally_checksummed() {
+    let address: Address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"
+        .parse()
+        .unwrap();
+    let account = Account {
+        storage: Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(1))])),
+        ..Account::default()
+    };
+    let result = PrestateResult::from((State::from([(address, account)]), State::new()));
+
+    let json = result.to_json(&PrestateTracerConfig::default());
+    let storage = &json["pre"]["0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"]["storage"];
+    let value = storage[format!("0x{}", "0".repeat(64))].as_str().unwrap();
+    assert_eq!(value, format!("0x{}1", "0".repeat(63)));
+
+    // Checksum from the EIP-55 test vectors.
+    let checksummed = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    assert_eq!(to_checksum_address(&address), checksummed);
+    let config = PrestateTracerConfig::builder()
+        .checksum_addresses(true)
+        .build();
+    let json = result.to_json(&config);
+    assert!(json["pre"][checksummed].is_object());
+    assert!(json["pre"]
+        .get("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed")
+        .is_none());
+}
+
+#[test]
+fn json_is_written_with_hex_address_keys() {
+    let state: State = (1..=3_u8)
+        .map(|seed| (Address::repeat_byte(seed * 0x1a), Account::default()))
+        .collect();
+    let result = PrestateResult::from((state.clone(), state));
+
+    let config = PrestateTracerConfig::default();
+    let mut buffer = vec![];
+    result.write_json(&mut buffer, &config).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+    assert_eq!(json, result.to_json(&config));
+    for key in json["pre"].as_object().unwrap().keys() {
+        let hex = key.strip_prefix("0x").unwrap();
+        assert_eq!(hex.len(), 40);
+        assert!(
+            hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')),
+            "{key}"
+        );
+    }
+}
+
+#[test]
+fn estimated_json_size_is_close_to_serialized_size() {
+    let state: State = (0..10_u8)
+        .map(|seed| {
+            let slots =
+                (0..u64::from(seed)).map(|slot| (H256::from_low_u64_be(slot), H256::random()));
+            let code = vec![seed; 32 * usize::from(seed)];
+            let account = Account {
+                balance: Some(U256::from(seed) << (8 * usize::from(seed))),
+                nonce: Some(seed.into()),
+                deployment_nonce: (seed % 2 == 0).then(|| U256::from(seed) * 1_000),
+                code_hash: (!code.is_empty()).then(|| H256::repeat_byte(seed)),
+                code_size: (!code.is_empty()).then_some(code.len()),
+                code: (!code.is_empty()).then_some(code),
+                storage: (seed % 3 != 0).then(|| slots.collect()),
+                ..Account::default()
+            };
+            (Address::repeat_byte(seed), account)
+        })
+        .collect();
+    let result = PrestateResult::from((state.clone(), state));
+    let without_post = PrestateResult {
+        post: None,
+        ..result.clone()
+    };
+
+    let config = PrestateTracerConfig::default();
+    for result in [result, without_post] {
+        let actual = result.to_json(&config).to_string().len();
+        let estimated = result.estimated_json_size();
+        assert!(
+            actual.abs_diff(estimated) <= actual / 20,
+            "{estimated} vs {actual}"
+        );
+    }
+}
+
+#[test]
+fn sorted_json_is_byte_stable() {
+    let accounts: Vec<_> = (1..=20_u8)
+        .map(|seed| {
+            let slots = (0..20).map(|slot| (H256::from_low_u64_be(slot), H256::random()));
+            let account = Account {
+                balance: Some(seed.into()),
+                storage: Some(slots.collect()),
+                ..Account::default()
+            };
+            (Address::repeat_byte(seed), account)
+        })
+        .collect();
+    let state: State = accounts.iter().cloned().collect();
+    // Same state with the accounts and slots inserted in the reverse order.
+    let reversed: State = accounts
+        .into_iter()
+        .rev()
+        .map(|(address, account)| {
+            let mut slots: Vec<_> = account.storage.clone().unwrap().into_iter().collect();
+            slots.reverse();
+            let account = Account {
+                storage: Some(slots.into_iter().collect()),
+                ..account
+            };
+            (address, account)
+        })
+        .collect();
+    let result = |state: State| PrestateResult::from((state.clone(), state));
+
+    let config = PrestateTracerConfig::builder()
+        .checksum_addresses(true)
+        .build();
+    let json = result(state).to_sorted_json(&config);
+    assert_eq!(json, result(reversed).to_sorted_json(&config));
+    let first_slot = format!("{:?}", H256::zero());
+    let second_slot = format!("{:?}", H256::from_low_u64_be(1));
+    assert!(json.find(&first_slot).unwrap() < json.find(&second_slot).unwrap());
+}
+
+#[test]
+fn addresses_are_formatted_with_formatter() {
+    let named = L2_ETH_TOKEN_ADDRESS;
+    let other = Address::repeat_byte(0x11);
+    let state = State::from([(named, Account::empty()), (other, Account::empty())]);
+    let result = PrestateResult::from((state.clone(), state));
+
+    let formatter =
+        AddressFormatter::new(move |address| (*address == named).then(|| "L2EthToken".into()));
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .address_formatter(formatter)
+        .build();
+    let json = result.to_json(&config);
+    assert_eq!(
+        json["pre"],
+        serde_json::json!({
+            "L2EthToken": {},
+            "0x1111111111111111111111111111111111111111": {},
+        })
+    );
+    // The state itself is still keyed by the addresses.
+    assert!(result.pre.contains_key(&named));
+}
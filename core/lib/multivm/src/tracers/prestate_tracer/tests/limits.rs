@@ -0,0 +1,122 @@
+use zksync_utils::bytecode::hash_bytecode;
+
+use crate::tracers::prestate_tracer::{
+    testing::fixtures::{known_bytecodes, storage_with, write_value},
+    *,
+};
+
+#[test]
+fn large_code_is_truncated() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let code_key = get_code_key(&address);
+    let code: Vec<u8> = (0..32 * 1_001).map(|i| i as u8).collect();
+    let storage = storage_with(&[(code_key, hash_bytecode(&code))]);
+    let stored_code_hash = storage.borrow_mut().read_value(&code_key);
+
+    let config = PrestateTracerConfig::builder().max_code_bytes(64).build();
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::with_config(config, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer
+        .store_result(&storage, &known_bytecodes(&code))
+        .unwrap();
+
+    let account = &result.get().unwrap().0[&address];
+    assert_eq!(account.code.as_deref(), Some(&code[..64]));
+    assert_eq!(account.code_truncated, Some(true));
+    assert_eq!(account.code_size, Some(code.len()));
+    assert_eq!(account.code_hash, Some(stored_code_hash));
+    let json = serde_json::to_value(account).unwrap();
+    assert_eq!(json["codeTruncated"], true);
+
+    // The code within the limit is reported as is.
+    let config = PrestateTracerConfig::builder()
+        .max_code_bytes(code.len())
+        .build();
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::with_config(config, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(2));
+    tracer
+        .store_result(&storage, &known_bytecodes(&code))
+        .unwrap();
+    let account = &result.get().unwrap().0[&address];
+    assert_eq!(account.code.as_ref(), Some(&code));
+    assert_eq!(account.code_truncated, None);
+}
+
+#[test]
+fn result_is_truncated_when_limits_are_exceeded() {
+    let keys: Vec<_> = (1..=5)
+        .map(|i| StorageKey::new(AccountTreeId::new(Address::repeat_byte(i)), H256::zero()))
+        .collect();
+
+    let config = PrestateTracerConfig::builder().max_accounts(2).build();
+    let mut tracer = PrestateTracer::with_config(config, Arc::new(OnceCell::new()));
+    let storage = storage_with(&[]);
+    for key in &keys {
+        write_value(&mut tracer, &storage, *key, H256::from_low_u64_be(1));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    let result = tracer.get_result().unwrap();
+    assert!(result.truncated);
+    assert!(result.pre.len() <= 2, "{:?}", result.pre);
+
+    let config = PrestateTracerConfig::builder().max_storage_slots(3).build();
+    let mut tracer = PrestateTracer::with_config(config, Arc::new(OnceCell::new()));
+    let storage = storage_with(&[]);
+    for key in &keys {
+        write_value(&mut tracer, &storage, *key, H256::from_low_u64_be(1));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    let result = tracer.get_result().unwrap();
+    assert!(result.truncated);
+    let slot_count: usize = result
+        .pre
+        .values()
+        .filter_map(|account| account.storage.as_ref().map(HashMap::len))
+        .sum();
+    assert_eq!(slot_count, 3);
+}
+
+#[test]
+fn slots_are_limited_per_account() {
+    let address = Address::repeat_byte(0x01);
+    let other_address = Address::repeat_byte(0x02);
+    let storage = storage_with(&[]);
+    let config = PrestateTracerConfig::builder()
+        .max_slots_per_account(3)
+        .build();
+    let mut tracer = PrestateTracer::with_config(config, Arc::new(OnceCell::new()));
+    for i in 0..10 {
+        let key = StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(i));
+        write_value(&mut tracer, &storage, key, H256::from_low_u64_be(1));
+    }
+    let other_key = StorageKey::new(AccountTreeId::new(other_address), H256::zero());
+    write_value(&mut tracer, &storage, other_key, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let result = tracer.get_result().unwrap();
+    assert!(!result.truncated);
+    let account = &result.pre[&address];
+    let mut slots: Vec<_> = account.storage.as_ref().unwrap().keys().copied().collect();
+    slots.sort_unstable();
+    assert_eq!(slots, (0..3).map(H256::from_low_u64_be).collect::<Vec<_>>());
+    assert_eq!(account.omitted_slots, Some(7));
+    let other_account = &result.pre[&other_address];
+    assert_eq!(other_account.storage.as_ref().unwrap().len(), 1);
+    assert_eq!(other_account.omitted_slots, None);
+}
+
+#[test]
+fn default_limits_do_not_truncate() {
+    let address = Address::repeat_byte(0x01);
+    let storage = storage_with(&[]);
+    let mut tracer = PrestateTracer::new(false, Arc::new(OnceCell::new()));
+    for i in 0..100 {
+        let key = StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(i));
+        write_value(&mut tracer, &storage, key, H256::from_low_u64_be(1));
+    }
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    assert!(!tracer.get_result().unwrap().truncated);
+}
@@ -0,0 +1,9 @@
+mod account;
+mod annotations;
+mod capture;
+mod collection;
+mod config;
+mod diff_mode;
+mod json;
+mod limits;
+mod tracer;
@@ -0,0 +1,368 @@
+use crate::tracers::prestate_tracer::{
+    testing::fixtures::{storage_with, write_value},
+    *,
+};
+
+#[test]
+fn collection_does_not_panic_if_storage_is_borrowed() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let storage = storage_with(&[]);
+    let mut tracer = PrestateTracer::default();
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+
+    let shared_borrow = storage.borrow();
+    assert_eq!(
+        tracer.store_result(&storage, &HashMap::new()),
+        Err(PrestateTracerError::StorageBorrowed)
+    );
+    drop(shared_borrow);
+
+    // Another component holding the storage borrow can collect the result itself.
+    let mut borrowed_storage = storage.borrow_mut();
+    tracer
+        .store_result_with_storage(&mut *borrowed_storage, &HashMap::new())
+        .unwrap();
+    drop(borrowed_storage);
+    let result = tracer.take_result().unwrap();
+    assert!(result.pre.contains_key(&address));
+}
+
+#[test]
+fn result_counts_accounts_and_slots() {
+    let first = AccountTreeId::new(Address::repeat_byte(0x01));
+    let second = AccountTreeId::new(Address::repeat_byte(0x02));
+    let unchanged = AccountTreeId::new(Address::repeat_byte(0x03));
+    let slot = |account, slot| StorageKey::new(account, H256::from_low_u64_be(slot));
+    let writes = [
+        (slot(first, 0), 1, 2),
+        (slot(first, 1), 3, 4),
+        (slot(second, 0), 1, 7),
+    ];
+    let read = slot(unchanged, 0);
+
+    for diff_mode in [false, true] {
+        let mut values: Vec<_> = writes
+            .iter()
+            .map(|&(key, value, _)| (key, H256::from_low_u64_be(value)))
+            .collect();
+        values.push((read, H256::from_low_u64_be(5)));
+        let storage = storage_with(&values);
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = PrestateTracer::new(diff_mode, result);
+        tracer.record_storage_access(read, false, &storage);
+        for &(key, _, value) in &writes {
+            write_value(&mut tracer, &storage, key, H256::from_low_u64_be(value));
+        }
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let result = tracer.take_result().unwrap();
+        if diff_mode {
+            // The changed accounts and slots are reported in both states, but counted once.
+            assert_eq!(result.post.as_ref().unwrap().len(), 2);
+            assert_eq!((result.account_count(), result.slot_count()), (2, 3));
+        } else {
+            assert_eq!((result.account_count(), result.slot_count()), (3, 4));
+        }
+    }
+}
+
+#[test]
+fn replay_safety_depends_on_config() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let storage = storage_with(&[(slot, H256::from_low_u64_be(1))]);
+
+    let configs = [
+        (PrestateTracerConfig::default(), true),
+        // The read slots aren't captured in diff mode.
+        (
+            PrestateTracerConfig::builder().diff_mode(true).build(),
+            false,
+        ),
+        (
+            PrestateTracerConfig::builder().storage_only(true).build(),
+            false,
+        ),
+        (
+            PrestateTracerConfig::builder().max_storage_slots(0).build(),
+            false,
+        ),
+        // The omitted slots aren't reported, even though the result isn't truncated.
+        (
+            PrestateTracerConfig::builder()
+                .max_slots_per_account(1)
+                .build(),
+            false,
+        ),
+        (
+            PrestateTracerConfig::builder().max_code_bytes(0).build(),
+            false,
+        ),
+        (
+            PrestateTracerConfig::builder()
+                .post_process(StatePostProcessor::new(|_| {}))
+                .build(),
+            false,
+        ),
+    ];
+    for (config, replay_safe) in configs {
+        let mut tracer = PrestateTracer::from_config(config);
+        tracer.record_storage_access(slot, false, &storage);
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+        assert_eq!(tracer.take_result().unwrap().replay_safe, replay_safe);
+    }
+}
+
+#[test]
+fn reverted_frames_are_rolled_back() {
+    let address = Address::repeat_byte(0x01);
+    let slot =
+        |slot: u64| StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(slot));
+    let storage = storage_with(&[(slot(0), H256::from_low_u64_be(1))]);
+
+    let config = PrestateTracerConfig::builder()
+        .rollback_reverted_frames(true)
+        .build();
+    let mut tracer = PrestateTracer::from_config(config);
+    tracer.on_step(address, 1, 1_000);
+    write_value(&mut tracer, &storage, slot(0), H256::from_low_u64_be(2));
+    // The subcall writes a slot and reverts, so the VM rolls back the write.
+    tracer.on_frame_entry();
+    tracer.on_step(Address::repeat_byte(0x02), 2, 900);
+    write_value(&mut tracer, &storage, slot(1), H256::from_low_u64_be(3));
+    // The base token minted in the subcall is burned by the rollback as well.
+    let minted_to = Address::repeat_byte(0x03);
+    let mut calldata = MINT_SELECTOR.to_vec();
+    calldata.extend_from_slice(address_to_h256(&minted_to).as_bytes());
+    tracer.on_base_token_call(&calldata);
+    tracer.on_frame_exit(true);
+    storage.borrow_mut().set_value(slot(1), H256::zero());
+    // A successful subcall is kept.
+    tracer.on_frame_entry();
+    write_value(&mut tracer, &storage, slot(2), H256::from_low_u64_be(4));
+    tracer.on_frame_exit(false);
+    // Manual snapshots are rolled back the same way.
+    let snapshot = tracer.snapshot();
+    write_value(&mut tracer, &storage, slot(3), H256::from_low_u64_be(5));
+    tracer.rollback_to(snapshot);
+    storage.borrow_mut().set_value(slot(3), H256::zero());
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let pre = tracer.take_result().unwrap().pre;
+    let account_storage = pre[&address].storage.as_ref().unwrap();
+    let mut slots: Vec<_> = account_storage.keys().copied().collect();
+    slots.sort_unstable();
+    assert_eq!(slots, [*slot(0).key(), *slot(2).key()]);
+    assert!(!pre.contains_key(&Address::repeat_byte(0x02)));
+    assert!(!pre.contains_key(&minted_to));
+}
+
+#[test]
+fn post_processor_redacts_balances() {
+    let sender = Address::repeat_byte(0x01);
+    let balance_key = get_balance_key(&AccountTreeId::new(sender));
+    let zero_balances = StatePostProcessor::new(|state| {
+        for account in state.values_mut() {
+            account.balance = account.balance.map(|_| U256::zero());
+        }
+    });
+
+    for diff_mode in [false, true] {
+        let storage = storage_with(&[(balance_key, u256_to_h256(100.into()))]);
+        let config = PrestateTracerConfig::builder()
+            .diff_mode(diff_mode)
+            .post_process(zero_balances.clone())
+            .build();
+        let mut tracer = PrestateTracer::from_config(config);
+        tracer.on_step(sender, 1, 1_000);
+        write_value(&mut tracer, &storage, balance_key, u256_to_h256(90.into()));
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let result = tracer.take_result().unwrap();
+        assert_eq!(result.pre[&sender].balance, Some(U256::zero()));
+        if diff_mode {
+            assert_eq!(result.post.unwrap()[&sender].balance, Some(U256::zero()));
+        }
+    }
+}
+
+#[test]
+fn reset_tracer_traces_transactions_independently() {
+    let first = Address::repeat_byte(0x01);
+    let second = Address::repeat_byte(0x02);
+    let storage = storage_with(&[
+        (get_code_key(&first), H256::repeat_byte(0xcc)),
+        (get_code_key(&second), H256::repeat_byte(0xdd)),
+    ]);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .max_storage_slots(1)
+        .build();
+    let first_cell = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::with_config(config, first_cell.clone());
+
+    // The first transaction exceeds the slot limit.
+    tracer.on_storage_opcode(first, first, U256::zero(), true, 0, &storage);
+    tracer.on_storage_opcode(first, first, U256::one(), true, 0, &storage);
+    storage.borrow_mut().set_value(
+        StorageKey::new(AccountTreeId::new(first), H256::zero()),
+        H256::from_low_u64_be(1),
+    );
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    let first_result = tracer.get_result().unwrap();
+    assert!(first_result.truncated);
+    assert!(first_result.pre.contains_key(&first));
+
+    let second_cell = Arc::new(OnceCell::new());
+    tracer.reset(second_cell.clone());
+    tracer.on_storage_opcode(second, second, U256::zero(), true, 0, &storage);
+    storage.borrow_mut().set_value(
+        StorageKey::new(AccountTreeId::new(second), H256::zero()),
+        H256::from_low_u64_be(2),
+    );
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    let second_result = tracer.get_result().unwrap();
+    assert!(!second_result.truncated);
+    assert!(!second_result.pre.contains_key(&first));
+    let post = second_result.post.unwrap();
+    assert!(!post.contains_key(&first));
+    assert_eq!(
+        post[&second].storage,
+        Some(HashMap::from([(H256::zero(), H256::from_low_u64_be(2))]))
+    );
+    // The result of the first transaction is left intact.
+    let (_, first_post) = first_cell.get().unwrap();
+    assert_eq!(first_result.post.unwrap(), *first_post);
+    assert!(!first_post.contains_key(&second));
+}
+
+#[test]
+fn convenience_constructors() {
+    let tracer = PrestateTracer::diff();
+    assert!(tracer.config.diff_mode);
+    assert!(tracer.result.get().is_none());
+    assert!(tracer.get_result().is_none());
+
+    let tracer = PrestateTracer::default();
+    assert!(!tracer.config.diff_mode);
+    assert!(tracer.result.get().is_none());
+
+    assert_eq!(
+        serde_json::to_value(Account::empty()).unwrap(),
+        serde_json::json!({})
+    );
+    assert!(State::new().is_empty());
+}
+
+#[test]
+fn result_is_taken_from_tracer() {
+    let address = Address::repeat_byte(0x01);
+    let key = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let storage = storage_with(&[]);
+
+    let mut tracer = PrestateTracer::from_config(PrestateTracerConfig::default());
+    write_value(&mut tracer, &storage, key, H256::from_low_u64_be(1));
+    let vm_tracer = tracer.clone();
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+    // The result is cloned while it's shared with another tracer.
+    assert!(tracer.clone().take_result().is_some());
+
+    drop(vm_tracer);
+    let result = tracer.take_result().unwrap();
+    assert!(result.pre.contains_key(&address));
+    assert_eq!(result.post, None);
+}
+
+#[test]
+fn tracer_is_built_from_config() {
+    let address = Address::repeat_byte(0x01);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .disable_code(true)
+        .disable_storage(true)
+        .only_addresses(HashSet::from([address]))
+        .build();
+    let tracer = PrestateTracer::with_config(config, Arc::new(OnceCell::new()));
+
+    assert!(tracer.config.diff_mode);
+    assert!(tracer.config.disable_code);
+    assert!(tracer.config.disable_storage);
+    assert_eq!(tracer.config.only_addresses, Some(HashSet::from([address])));
+    assert!(tracer.config.tokens.is_empty());
+
+    let tracer = PrestateTracer::new(false, Arc::new(OnceCell::new()));
+    assert!(!tracer.config.diff_mode);
+    assert!(!tracer.config.disable_code);
+    assert!(!tracer.config.disable_storage);
+}
+
+#[test]
+fn storing_result_twice_fails() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+    let storage = storage_with(&[]);
+
+    let result = Arc::new(OnceCell::new());
+    let mut tracer = PrestateTracer::new(true, result.clone());
+    write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+    tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+    assert_eq!(
+        tracer.store_result(&storage, &HashMap::new()),
+        Err(PrestateTracerError::ResultAlreadySet)
+    );
+    assert!(result.get().is_some());
+}
+
+#[test]
+fn result_has_no_post_state_in_default_mode() {
+    let address = Address::repeat_byte(0x01);
+    let slot = StorageKey::new(AccountTreeId::new(address), H256::zero());
+
+    for diff_mode in [false, true] {
+        let storage = storage_with(&[]);
+        let mut tracer = PrestateTracer::new(diff_mode, Arc::new(OnceCell::new()));
+        assert_eq!(tracer.get_result(), None);
+        write_value(&mut tracer, &storage, slot, H256::from_low_u64_be(1));
+        tracer.store_result(&storage, &HashMap::new()).unwrap();
+
+        let result = tracer.get_result().unwrap();
+        assert!(result.pre.contains_key(&address));
+        assert_eq!(result.post.is_some(), diff_mode);
+    }
+}
+
+#[test]
+fn execution_result_is_recorded() {
+    let result = PrestateResult::from((State::new(), State::new()));
+    assert!(!result.failed);
+
+    let reverted = result
+        .clone()
+        .with_execution_result(&ExecutionResult::Revert {
+            output: VmRevertReason::General {
+                msg: "short".to_owned(),
+                data: vec![],
+            },
+        });
+    assert!(reverted.failed);
+    assert_eq!(reverted.revert_reason.as_deref(), Some("short"));
+
+    let reverted = result
+        .clone()
+        .with_execution_result(&ExecutionResult::Revert {
+            output: VmRevertReason::VmError,
+        });
+    assert!(reverted.failed);
+    assert_eq!(reverted.revert_reason, None);
+    assert_eq!(reverted.return_data, Some(vec![]));
+
+    let output = u256_to_h256(42.into()).as_bytes().to_vec();
+    let succeeded = result.with_execution_result(&ExecutionResult::Success {
+        output: output.clone(),
+    });
+    assert!(!succeeded.failed);
+    assert_eq!(succeeded.return_data, Some(output));
+}
@@ -0,0 +1,222 @@
+//! Storage layouts of the standard token contracts, deriving the slots of their mappings,
+//! e.g. to annotate the reported slots of the tokens with their preimages.
+
+use std::collections::HashMap;
+
+use zksync_types::{Address, H256, U256};
+use zksync_utils::{address_to_h256, u256_to_h256};
+
+use crate::tracers::prestate_tracer::{SlotHasher, SlotPreimage};
+
+/// Token standard with the slots of the mappings of its implementation. The constructors use
+/// the layouts of the OpenZeppelin implementations, which other contracts may not follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenStandard {
+    /// `balanceOf[holder]` and `allowance[owner][spender]`.
+    Erc20 {
+        balances_slot: H256,
+        allowances_slot: H256,
+    },
+    /// `ownerOf[tokenId]`, `balanceOf[holder]`, `getApproved[tokenId]`
+    /// and `isApprovedForAll[owner][operator]`.
+    Erc721 {
+        owners_slot: H256,
+        balances_slot: H256,
+        token_approvals_slot: H256,
+        operator_approvals_slot: H256,
+    },
+    /// `balanceOf[tokenId][holder]` and `isApprovedForAll[owner][operator]`.
+    Erc1155 {
+        balances_slot: H256,
+        operator_approvals_slot: H256,
+    },
+}
+
+/// Entry of a mapping of a token contract, see [`TokenStandard::preimage()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSlot {
+    /// Balance of an ERC-20 or ERC-721 token.
+    Balance {
+        holder: Address,
+    },
+    /// Balance of an ERC-1155 token.
+    TokenBalance {
+        token_id: U256,
+        holder: Address,
+    },
+    Allowance {
+        owner: Address,
+        spender: Address,
+    },
+    Owner {
+        token_id: U256,
+    },
+    TokenApproval {
+        token_id: U256,
+    },
+    OperatorApproval {
+        owner: Address,
+        operator: Address,
+    },
+}
+
+impl TokenStandard {
+    pub fn erc20() -> Self {
+        Self::Erc20 {
+            balances_slot: H256::from_low_u64_be(0),
+            allowances_slot: H256::from_low_u64_be(1),
+        }
+    }
+
+    /// The name and the symbol are stored in the slots 0 and 1.
+    pub fn erc721() -> Self {
+        Self::Erc721 {
+            owners_slot: H256::from_low_u64_be(2),
+            balances_slot: H256::from_low_u64_be(3),
+            token_approvals_slot: H256::from_low_u64_be(4),
+            operator_approvals_slot: H256::from_low_u64_be(5),
+        }
+    }
+
+    pub fn erc1155() -> Self {
+        Self::Erc1155 {
+            balances_slot: H256::from_low_u64_be(0),
+            operator_approvals_slot: H256::from_low_u64_be(1),
+        }
+    }
+
+    /// Returns the preimage of the `slot`, or `None` if the standard has no such mapping.
+    pub fn preimage(&self, slot: TokenSlot) -> Option<SlotPreimage> {
+        self.preimage_with(slot, &SlotHasher::default())
+    }
+
+    /// Same as [`Self::preimage()`], but hashes the slots of the outer mappings with the `hasher`.
+    pub fn preimage_with(&self, slot: TokenSlot, hasher: &SlotHasher) -> Option<SlotPreimage> {
+        let mapping = |base_slot, key| SlotPreimage { base_slot, key };
+        // The preimage of a nested mapping has the slot of the inner mapping as the base slot.
+        let nested = |base_slot, outer_key, key| SlotPreimage {
+            base_slot: mapping(base_slot, outer_key).slot_with(hasher),
+            key,
+        };
+        let preimage = match (*self, slot) {
+            (
+                Self::Erc20 { balances_slot, .. } | Self::Erc721 { balances_slot, .. },
+                TokenSlot::Balance { holder },
+            ) => mapping(balances_slot, address_to_h256(&holder)),
+            (
+                Self::Erc20 {
+                    allowances_slot, ..
+                },
+                TokenSlot::Allowance { owner, spender },
+            ) => nested(
+                allowances_slot,
+                address_to_h256(&owner),
+                address_to_h256(&spender),
+            ),
+            (Self::Erc721 { owners_slot, .. }, TokenSlot::Owner { token_id }) => {
+                mapping(owners_slot, u256_to_h256(token_id))
+            }
+            (
+                Self::Erc721 {
+                    token_approvals_slot,
+                    ..
+                },
+                TokenSlot::TokenApproval { token_id },
+            ) => mapping(token_approvals_slot, u256_to_h256(token_id)),
+            (
+                Self::Erc721 {
+                    operator_approvals_slot,
+                    ..
+                }
+                | Self::Erc1155 {
+                    operator_approvals_slot,
+                    ..
+                },
+                TokenSlot::OperatorApproval { owner, operator },
+            ) => nested(
+                operator_approvals_slot,
+                address_to_h256(&owner),
+                address_to_h256(&operator),
+            ),
+            (Self::Erc1155 { balances_slot, .. }, TokenSlot::TokenBalance { token_id, holder }) => {
+                nested(
+                    balances_slot,
+                    u256_to_h256(token_id),
+                    address_to_h256(&holder),
+                )
+            }
+            _ => return None,
+        };
+        Some(preimage)
+    }
+
+    /// Returns the preimages of the `slots` keyed by the slot, which can be passed to
+    /// [`PrestateTracerConfigBuilder::slot_preimages()`]. The slots which the standard
+    /// has no mapping for are skipped.
+    ///
+    /// [`PrestateTracerConfigBuilder::slot_preimages()`]:
+    /// crate::tracers::prestate_tracer::PrestateTracerConfigBuilder::slot_preimages()
+    pub fn slot_preimages(
+        &self,
+        slots: impl IntoIterator<Item = TokenSlot>,
+    ) -> HashMap<H256, SlotPreimage> {
+        slots
+            .into_iter()
+            .filter_map(|slot| self.preimage(slot))
+            .map(|preimage| (preimage.slot(), preimage))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_types::utils::storage_key_for_eth_balance;
+
+    use super::*;
+
+    fn slot_of(standard: TokenStandard, slot: TokenSlot) -> H256 {
+        standard.preimage(slot).unwrap().slot()
+    }
+
+    #[test]
+    fn erc20_balance_and_allowance_slots() {
+        let (owner, spender) = (Address::repeat_byte(0x01), Address::repeat_byte(0x02));
+
+        let balance = slot_of(TokenStandard::erc20(), TokenSlot::Balance { holder: owner });
+        let expected: H256 = "0xf5b49cc25a440729f9b5120177e19949750dedfd4dd402c87843c9b78946cd68"
+            .parse()
+            .unwrap();
+        assert_eq!(balance, expected);
+        // `L2EthToken` keeps the balances at the slot 0 as well.
+        assert_eq!(balance, *storage_key_for_eth_balance(&owner).key());
+
+        let allowance = slot_of(
+            TokenStandard::erc20(),
+            TokenSlot::Allowance { owner, spender },
+        );
+        let expected: H256 = "0xc7130c6e1c3c748794ed11bed222c39a370711fc0a5b0069f5432c68545b76ed"
+            .parse()
+            .unwrap();
+        assert_eq!(allowance, expected);
+
+        // ERC-20 has no token ids.
+        let owner_slot = TokenSlot::Owner { token_id: 1.into() };
+        assert_eq!(TokenStandard::erc20().preimage(owner_slot), None);
+        let preimages = TokenStandard::erc20()
+            .slot_preimages([TokenSlot::Allowance { owner, spender }, owner_slot]);
+        assert_eq!(preimages.len(), 1);
+        assert_eq!(preimages[&allowance].key, address_to_h256(&spender));
+    }
+
+    #[test]
+    fn erc1155_balance_slot() {
+        let slot = TokenSlot::TokenBalance {
+            token_id: 1.into(),
+            holder: Address::repeat_byte(0x01),
+        };
+        let expected: H256 = "0x8c33eb6823cbb658c72d064d915b53511af554fec9fb35a1a8d53c8dc4603843"
+            .parse()
+            .unwrap();
+        assert_eq!(slot_of(TokenStandard::erc1155(), slot), expected);
+    }
+}
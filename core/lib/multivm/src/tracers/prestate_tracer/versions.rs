@@ -0,0 +1,267 @@
+//! Implementations of the tracer traits of every VM version for [`PrestateTracer`].
+//!
+//! The implementations only forward the VM callbacks to the version-independent entry points
+//! of the tracer, so they are generated by a macro to keep the versions in lockstep. The legacy
+//! versions which can be disabled by the crate features are only implemented if they are enabled.
+
+use crate::tracers::prestate_tracer::{PrestateTracer, TracerVmVersion, BASE_TOKEN_CALLDATA_LEN};
+
+/// Implements the tracer traits of a VM version for [`PrestateTracer`].
+///
+/// Takes the version module, the corresponding [`TracerVmVersion`] variant, the `zk_evm` crate
+/// used by the version, and the path to its `DynTracer` trait relative to the crate root.
+/// The VMs which split their tracer trait into `ExecutionEndTracer`, `ExecutionProcessing`
+/// and `VmTracer` are marked with `execution_processing`.
+macro_rules! impl_prestate_tracer {
+    ($vm:ident, $version:ident, $zk_evm:ident, $($dyn_tracer:ident)::+) => {
+        impl_prestate_tracer!(@dyn_tracer $vm, $zk_evm, $($dyn_tracer)::+);
+
+        impl<S: zksync_state::WriteStorage, H: crate::$vm::HistoryMode> crate::$vm::VmTracer<S, H>
+            for PrestateTracer
+        {
+            fn after_vm_execution(
+                &mut self,
+                state: &mut crate::$vm::ZkSyncVmState<S, H>,
+                _bootloader_state: &crate::$vm::BootloaderState,
+                _stop_reason: crate::interface::tracer::VmExecutionStopReason,
+            ) {
+                self.on_execution_end(
+                    &state.storage.storage.get_ptr(),
+                    state.decommittment_processor.known_bytecodes.inner(),
+                    TracerVmVersion::$version,
+                );
+            }
+        }
+    };
+    ($vm:ident, $version:ident, $zk_evm:ident, $($dyn_tracer:ident)::+, execution_processing) => {
+        impl_prestate_tracer!(@dyn_tracer $vm, $zk_evm, $($dyn_tracer)::+);
+
+        impl<H: crate::$vm::HistoryMode> crate::$vm::ExecutionEndTracer<H> for PrestateTracer {}
+
+        impl<S: zksync_state::WriteStorage, H: crate::$vm::HistoryMode>
+            crate::$vm::ExecutionProcessing<S, H> for PrestateTracer
+        {
+            fn after_vm_execution(
+                &mut self,
+                state: &mut crate::$vm::ZkSyncVmState<S, H>,
+                _bootloader_state: &crate::$vm::BootloaderState,
+                _stop_reason: crate::interface::tracer::VmExecutionStopReason,
+            ) {
+                self.on_execution_end(
+                    &state.storage.storage.get_ptr(),
+                    state.decommittment_processor.known_bytecodes.inner(),
+                    TracerVmVersion::$version,
+                );
+            }
+        }
+
+        impl<S: zksync_state::WriteStorage, H: crate::$vm::HistoryMode> crate::$vm::VmTracer<S, H>
+            for PrestateTracer
+        {
+        }
+    };
+    (@dyn_tracer $vm:ident, $zk_evm:ident, $($dyn_tracer:ident)::+) => {
+        impl<S: zksync_state::WriteStorage, H: crate::$vm::HistoryMode>
+            crate::$($dyn_tracer)::+<S, crate::$vm::SimpleMemory<H>> for PrestateTracer
+        {
+            fn before_execution(
+                &mut self,
+                state: $zk_evm::tracing::VmLocalStateData<'_>,
+                data: $zk_evm::tracing::BeforeExecutionData,
+                _memory: &crate::$vm::SimpleMemory<H>,
+                storage: zksync_state::StoragePtr<S>,
+            ) {
+                use $zk_evm::zkevm_opcode_defs::{LogOpcode, Opcode, RetOpcode};
+                use zksync_system_constants::BOOTLOADER_ADDRESS;
+
+                self.on_pubdata_price(state.vm_local_state.current_ergs_per_pubdata_byte);
+                let callstack = &state.vm_local_state.callstack;
+                let current = &callstack.current;
+                // The far calls are counted from the bootloader frame, like in the call tracer.
+                let far_call_depth = || {
+                    let frames = callstack.inner.iter().chain([current]);
+                    frames
+                        .skip_while(|frame| frame.this_address != BOOTLOADER_ADDRESS)
+                        .filter(|frame| !frame.is_local_frame)
+                        .count()
+                        .saturating_sub(1)
+                };
+                self.on_frame_step(
+                    current.code_address,
+                    callstack.inner.len(),
+                    far_call_depth,
+                    current.ergs_remaining,
+                );
+
+                let is_write = match data.opcode.variant.opcode {
+                    Opcode::Log(LogOpcode::StorageRead) => false,
+                    Opcode::Log(LogOpcode::StorageWrite) => true,
+                    Opcode::NearCall(_) | Opcode::FarCall(_) => {
+                        self.on_frame_entry();
+                        return;
+                    }
+                    Opcode::Ret(ret) => {
+                        self.on_frame_exit(ret != RetOpcode::Ok);
+                        return;
+                    }
+                    _ => return,
+                };
+                self.on_storage_opcode(
+                    current.this_address,
+                    current.code_address,
+                    data.src0_value.value,
+                    is_write,
+                    current.ergs_remaining,
+                    &storage,
+                );
+            }
+
+            fn after_execution(
+                &mut self,
+                state: $zk_evm::tracing::VmLocalStateData<'_>,
+                data: $zk_evm::tracing::AfterExecutionData,
+                memory: &crate::$vm::SimpleMemory<H>,
+                _storage: zksync_state::StoragePtr<S>,
+            ) {
+                use $zk_evm::zkevm_opcode_defs::{
+                    FarCallABI, Opcode, CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER,
+                };
+
+                // The calldata is only available in the callee frame, i.e. after the far call.
+                let callee = state.vm_local_state.callstack.current.this_address;
+                if !matches!(data.opcode.variant.opcode, Opcode::FarCall(_))
+                    || !self.is_base_token(&callee)
+                {
+                    return;
+                }
+                let registers = &state.vm_local_state.registers;
+                let packed_abi = registers[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize];
+                if !packed_abi.is_pointer {
+                    return;
+                }
+                let calldata_ptr = FarCallABI::from_u256(packed_abi.value);
+                let calldata_ptr = calldata_ptr.memory_quasi_fat_pointer;
+                let calldata = memory.read_unaligned_bytes(
+                    calldata_ptr.memory_page as usize,
+                    calldata_ptr.start as usize,
+                    (calldata_ptr.length as usize).min(BASE_TOKEN_CALLDATA_LEN),
+                );
+                self.on_base_token_call(&calldata);
+            }
+        }
+    };
+}
+
+impl_prestate_tracer!(
+    vm_latest,
+    Latest,
+    zk_evm_1_4_1,
+    interface::traits::tracers::dyn_tracers::vm_1_4_1::DynTracer
+);
+impl_prestate_tracer!(
+    vm_1_4_1,
+    Vm1_4_1,
+    zk_evm_1_4_1,
+    interface::traits::tracers::dyn_tracers::vm_1_4_1::DynTracer
+);
+impl_prestate_tracer!(
+    vm_boojum_integration,
+    BoojumIntegration,
+    zk_evm_1_4_0,
+    interface::traits::tracers::dyn_tracers::vm_1_4_0::DynTracer
+);
+#[cfg(feature = "vm-refunds-enhancement")]
+impl_prestate_tracer!(
+    vm_refunds_enhancement,
+    RefundsEnhancement,
+    zk_evm_1_3_3,
+    interface::traits::tracers::dyn_tracers::vm_1_3_3::DynTracer
+);
+#[cfg(feature = "vm-virtual-blocks")]
+impl_prestate_tracer!(
+    vm_virtual_blocks,
+    VirtualBlocks,
+    zk_evm_1_3_3,
+    interface::dyn_tracers::vm_1_3_3::DynTracer,
+    execution_processing
+);
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use once_cell::sync::OnceCell;
+    use zksync_state::{InMemoryStorage, StorageView, WriteStorage};
+    use zksync_types::{AccountTreeId, Address, StorageKey, H256, U256};
+    use zksync_utils::u256_to_h256;
+
+    use super::*;
+    use crate::{
+        glue::tracers::{IntoLatestTracer, IntoVm1_4_1IntegrationTracer},
+        tracers::prestate_tracer::testing::fixtures::storage_with,
+        vm_latest::HistoryEnabled,
+        MultiVMTracer, MultiVmTracerPointer,
+    };
+
+    type Storage = StorageView<InMemoryStorage>;
+
+    fn assert_tracer_for_two_versions<T>(_: &T)
+    where
+        T: crate::vm_latest::VmTracer<Storage, HistoryEnabled>
+            + crate::vm_1_4_1::VmTracer<Storage, crate::vm_1_4_1::HistoryEnabled>,
+    {
+    }
+
+    #[test]
+    fn macro_implements_version_tracers() {
+        let tracer = PrestateTracer::new(false, Arc::new(OnceCell::new()));
+        assert_tracer_for_two_versions(&tracer);
+
+        let tracer: MultiVmTracerPointer<Storage, HistoryEnabled> = tracer.into_tracer_pointer();
+        let _latest = tracer.latest();
+        let _vm_1_4_1 = tracer.vm_1_4_1();
+    }
+
+    #[test]
+    fn version_entry_points_collect_prestate() {
+        // Every VM version forwards its callbacks to these two methods,
+        // so they produce the same output for the same accesses.
+        let address = Address::repeat_byte(0x01);
+        let slot = U256::from(5);
+        let key = StorageKey::new(AccountTreeId::new(address), u256_to_h256(slot));
+        let storage = storage_with(&[(key, H256::from_low_u64_be(1))]);
+
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = PrestateTracer::new(true, result.clone());
+        tracer.on_step(address, 1, 1_000);
+        tracer.on_storage_opcode(address, address, slot, false, 1_000, &storage);
+        tracer.on_step(address, 1, 900);
+        tracer.on_storage_opcode(address, address, slot, true, 900, &storage);
+        storage
+            .borrow_mut()
+            .set_value(key, H256::from_low_u64_be(2));
+        tracer.on_execution_end(&storage, &HashMap::new(), TracerVmVersion::Latest);
+        // Storing the result again is logged rather than propagated to the VM.
+        tracer.on_execution_end(&storage, &HashMap::new(), TracerVmVersion::Vm1_4_1);
+        assert_eq!(
+            tracer.get_result().unwrap().vm_version,
+            Some(TracerVmVersion::Latest)
+        );
+
+        let (pre, post) = result.get().unwrap();
+        assert_eq!(
+            pre[&address].storage,
+            Some(HashMap::from([(
+                u256_to_h256(slot),
+                H256::from_low_u64_be(1)
+            )]))
+        );
+        assert_eq!(
+            post[&address].storage,
+            Some(HashMap::from([(
+                u256_to_h256(slot),
+                H256::from_low_u64_be(2)
+            )]))
+        );
+    }
+}
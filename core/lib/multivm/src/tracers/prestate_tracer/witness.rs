@@ -0,0 +1,470 @@
+//! Witness of the prestate for stateless execution: the captured values together with the proofs
+//! of their inclusion into the state tree.
+//!
+//! All the state is stored in the storage slots on zkSync, including the balances, nonces and
+//! code hashes of the accounts, so the witness consists of storage proofs only.
+//!
+//! The proofs are provided by a [`StorageProver`]. The prover backed by the zkSync state tree
+//! and the verification of the proofs are only available with the `merkle_tree` feature,
+//! so that the VM doesn't depend on the tree storage otherwise.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+#[cfg(feature = "merkle_tree")]
+use zksync_merkle_tree::{Database, HashTree, MerkleTree, NoVersionError};
+use zksync_types::{
+    get_code_key, get_nonce_key, utils::nonces_to_full_nonce, AccountTreeId, Address, StorageKey,
+    H256, U256,
+};
+use zksync_utils::{h256_to_u256, u256_to_h256};
+
+use crate::tracers::prestate_tracer::{PrestateResult, PrestateTracerConfig};
+
+/// Depth of the zkSync state tree, i.e. the number of bits in the hashed storage keys.
+#[cfg(feature = "merkle_tree")]
+const TREE_DEPTH: usize = 256;
+
+/// Source of the proofs of the storage slots in the zkSync state tree, e.g. a version
+/// of the tree before the traced transaction, see [`TreeProver`].
+pub trait StorageProver {
+    type Error;
+
+    /// Returns the root hash of the proven state.
+    fn root_hash(&self) -> Result<H256, Self::Error>;
+
+    /// Returns the proofs of the `keys`, in the same order.
+    fn prove(&self, keys: &[StorageKey]) -> Result<Vec<LeafProof>, Self::Error>;
+}
+
+/// Proof of a storage slot returned by a [`StorageProver`], see [`StorageProof`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafProof {
+    pub leaf_index: u64,
+    pub merkle_path: Vec<H256>,
+}
+
+/// [`StorageProver`] loading the proofs from a `version` of the state `tree`.
+#[cfg(feature = "merkle_tree")]
+pub struct TreeProver<'a, DB, H> {
+    tree: &'a MerkleTree<DB, H>,
+    version: u64,
+}
+
+#[cfg(feature = "merkle_tree")]
+impl<'a, DB: Database, H: HashTree> TreeProver<'a, DB, H> {
+    pub fn new(tree: &'a MerkleTree<DB, H>, version: u64) -> Self {
+        Self { tree, version }
+    }
+}
+
+#[cfg(feature = "merkle_tree")]
+impl<DB: Database, H: HashTree> StorageProver for TreeProver<'_, DB, H> {
+    type Error = NoVersionError;
+
+    fn root_hash(&self) -> Result<H256, NoVersionError> {
+        let missing_version = || NoVersionError {
+            missing_version: self.version,
+            version_count: self.tree.latest_version().map_or(0, |version| version + 1),
+        };
+        self.tree
+            .root_hash(self.version)
+            .ok_or_else(missing_version)
+    }
+
+    fn prove(&self, keys: &[StorageKey]) -> Result<Vec<LeafProof>, NoVersionError> {
+        let tree_keys: Vec<_> = keys.iter().map(StorageKey::hashed_key_u256).collect();
+        let entries = self.tree.entries_with_proofs(self.version, &tree_keys)?;
+        let proofs = entries.into_iter().map(|entry| LeafProof {
+            leaf_index: entry.base.leaf_index,
+            merkle_path: entry.merkle_path,
+        });
+        Ok(proofs.collect())
+    }
+}
+
+/// Proof of the `value` of a storage slot in the zkSync state tree, in the format
+/// of `TreeEntryWithProof` of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageProof {
+    pub key: StorageKey,
+    pub value: H256,
+    /// Enumeration index of the slot, which is hashed into its leaf. Zero if the slot
+    /// isn't in the tree, in which case the value must be zero as well.
+    pub leaf_index: u64,
+    /// Sibling hashes from the leaf level to the root. The hashes of the empty subtrees
+    /// at the bottom of the path are omitted, like in the proofs of the tree.
+    pub merkle_path: Vec<H256>,
+}
+
+impl StorageProof {
+    /// Checks that the tree with the `root_hash` has the `value` at the `key`. The `i`-th bit
+    /// of the hashed key, starting from the least significant one, tells whether the node
+    /// on the `i`-th level is the right child of its parent.
+    #[cfg(feature = "merkle_tree")]
+    pub fn verify(&self, root_hash: H256, hasher: &dyn HashTree) -> bool {
+        if (self.leaf_index == 0 && !self.value.is_zero()) || self.merkle_path.len() > TREE_DEPTH {
+            return false;
+        }
+        let index = self.key.hashed_key_u256();
+        let empty_levels = TREE_DEPTH - self.merkle_path.len();
+        let siblings = (0..empty_levels)
+            .map(|depth| hasher.empty_subtree_hash(depth))
+            .chain(self.merkle_path.iter().copied());
+        let computed_root = siblings.enumerate().fold(
+            hasher.hash_leaf(&self.value, self.leaf_index),
+            |hash, (level, sibling)| {
+                if index.bit(level) {
+                    hasher.hash_branch(&sibling, &hash)
+                } else {
+                    hasher.hash_branch(&hash, &sibling)
+                }
+            },
+        );
+        computed_root == root_hash
+    }
+}
+
+/// Captured prestate with the proofs of all its values, which can be verified
+/// by anyone knowing only the state root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrestateWitness {
+    pub root_hash: H256,
+    pub proofs: Vec<StorageProof>,
+}
+
+impl PrestateWitness {
+    /// Checks all the proofs against the root hash of the witness.
+    #[cfg(feature = "merkle_tree")]
+    pub fn verify(&self, hasher: &dyn HashTree) -> bool {
+        self.proofs
+            .iter()
+            .all(|proof| proof.verify(self.root_hash, hasher))
+    }
+}
+
+/// Proofs of an account in the shape of an `eth_getProof` response, see
+/// [`PrestateResult::to_eth_proofs()`].
+///
+/// There are no account leaves in the zkSync state tree, so the proofs are the paths
+/// of the storage slots, and the `storage_hash` is the root of the whole tree.
+/// The `account_proof` is the path of the slot storing the code hash of the account.
+///
+/// The paths are compacted like in [`StorageProof`], and the leaves of the tree commit
+/// to the enumeration indices of the slots, so the indices are reported alongside the paths,
+/// like in `zks_getProof`. They aren't a part of `eth_getProof`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EthAccountProof {
+    pub address: Address,
+    pub account_proof: Vec<H256>,
+    /// Enumeration index of the code hash slot, or zero if it isn't proven.
+    pub account_proof_index: u64,
+    pub balance: U256,
+    pub code_hash: H256,
+    pub nonce: U256,
+    pub storage_hash: H256,
+    pub storage_proof: Vec<EthStorageProof>,
+}
+
+/// Proof of a storage slot in an `eth_getProof` response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EthStorageProof {
+    pub key: H256,
+    pub value: U256,
+    pub proof: Vec<H256>,
+    /// Enumeration index of the slot, see [`StorageProof::leaf_index`].
+    pub index: u64,
+}
+
+impl PrestateResult {
+    /// Reshapes the captured pre-state and its `witness` into `eth_getProof` responses for all
+    /// the reported accounts, sorted by the address, with the storage proofs sorted by the slot.
+    ///
+    /// The proofs are taken from the `witness` produced by [`Self::to_witness()`] rather than
+    /// computed, and the slots without a proof are left out. The fields missing from
+    /// the pre-state are reported as zero.
+    pub fn to_eth_proofs(&self, witness: &PrestateWitness) -> Vec<EthAccountProof> {
+        let proofs: HashMap<_, _> = witness
+            .proofs
+            .iter()
+            .map(|proof| (proof.key, proof))
+            .collect();
+        let mut accounts: Vec<_> = self.pre.iter().collect();
+        accounts.sort_unstable_by_key(|(address, _)| **address);
+
+        accounts
+            .into_iter()
+            .map(|(address, account)| {
+                let account_id = AccountTreeId::new(*address);
+                let mut storage: Vec<_> = account.storage.iter().flatten().collect();
+                storage.sort_unstable();
+                let storage_proof = storage
+                    .into_iter()
+                    .filter_map(|(slot, value)| {
+                        let proof = proofs.get(&StorageKey::new(account_id, *slot))?;
+                        Some(EthStorageProof {
+                            key: *slot,
+                            value: h256_to_u256(*value),
+                            proof: proof.merkle_path.clone(),
+                            index: proof.leaf_index,
+                        })
+                    })
+                    .collect();
+                // Only proven if the code hash is reported.
+                let account_proof = proofs.get(&get_code_key(address));
+                EthAccountProof {
+                    address: *address,
+                    account_proof: account_proof
+                        .map_or_else(Vec::new, |proof| proof.merkle_path.clone()),
+                    account_proof_index: account_proof.map_or(0, |proof| proof.leaf_index),
+                    balance: account.balance.unwrap_or_default(),
+                    code_hash: account.code_hash.unwrap_or_default(),
+                    nonce: account.nonce.unwrap_or_default(),
+                    storage_hash: witness.root_hash,
+                    storage_proof,
+                }
+            })
+            .collect()
+    }
+
+    /// Proves the captured pre-state of the transaction with the `prover` of the state before it.
+    /// The proofs are loaded from the prover, while the values are the captured ones, so
+    /// the proofs of the values which don't match the state don't verify.
+    ///
+    /// Besides the reported slots, the balances, nonces and code hashes of the accounts are proven
+    /// by the slots of the system contracts storing them. The `config` must be the one
+    /// the result was collected with, so that the balances are looked up in the same slots.
+    /// A nonce is only proven if both the transaction and the deployment nonces are reported,
+    /// since they are stored in the same slot.
+    ///
+    /// Fails if the prover fails, e.g. if the tree doesn't have the proven version.
+    pub fn to_witness<P: StorageProver>(
+        &self,
+        prover: &P,
+        config: &PrestateTracerConfig,
+    ) -> Result<PrestateWitness, P::Error> {
+        let values = self.pre_storage_values(config);
+        let keys: Vec<_> = values.iter().map(|(key, _)| *key).collect();
+        let leaf_proofs = prover.prove(&keys)?;
+        let root_hash = prover.root_hash()?;
+        let proofs = values
+            .into_iter()
+            .zip(leaf_proofs)
+            .map(|((key, value), proof)| StorageProof {
+                key,
+                value,
+                leaf_index: proof.leaf_index,
+                merkle_path: proof.merkle_path,
+            })
+            .collect();
+        Ok(PrestateWitness { root_hash, proofs })
+    }
+
+    /// Returns the values of the storage slots making up the captured pre-state, sorted
+    /// by the hashed key, see [`Self::to_witness()`].
+    pub(super) fn pre_storage_values(
+        &self,
+        config: &PrestateTracerConfig,
+    ) -> Vec<(StorageKey, H256)> {
+        let mut values = vec![];
+        for (address, account) in &self.pre {
+            let account_id = AccountTreeId::new(*address);
+            if let Some(balance) = account.balance {
+                let key = config.balance_key(&account_id);
+                values.push((key, u256_to_h256(balance)));
+            }
+            if let (Some(nonce), Some(deployment_nonce)) = (account.nonce, account.deployment_nonce)
+            {
+                let full_nonce = nonces_to_full_nonce(nonce, deployment_nonce);
+                values.push((get_nonce_key(address), u256_to_h256(full_nonce)));
+            }
+            if let Some(code_hash) = account.code_hash {
+                values.push((get_code_key(address), code_hash));
+            }
+            for (slot, value) in account.storage.iter().flatten() {
+                values.push((StorageKey::new(account_id, *slot), *value));
+            }
+        }
+        // Sorted so that the witness doesn't depend on the iteration order of the state.
+        values.sort_unstable_by_key(|(key, _)| key.hashed_key());
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracers::prestate_tracer::{Account, State};
+
+    /// Prover of a flat state, with the enumeration indices assigned in the order of the values
+    /// and the keys as the only path items.
+    struct FlatProver(Vec<(StorageKey, H256)>);
+
+    impl StorageProver for FlatProver {
+        type Error = ();
+
+        fn root_hash(&self) -> Result<H256, ()> {
+            Ok(H256::repeat_byte(0xaa))
+        }
+
+        fn prove(&self, keys: &[StorageKey]) -> Result<Vec<LeafProof>, ()> {
+            let proofs = keys.iter().map(|key| {
+                let position = self.0.iter().position(|(tree_key, _)| tree_key == key);
+                LeafProof {
+                    leaf_index: position.map_or(0, |position| position as u64 + 1),
+                    merkle_path: vec![key.hashed_key()],
+                }
+            });
+            Ok(proofs.collect())
+        }
+    }
+
+    fn account_with_slots(slots: &[(H256, H256)]) -> Account {
+        Account {
+            balance: Some(100.into()),
+            nonce: Some(3.into()),
+            deployment_nonce: Some(U256::one()),
+            code_hash: Some(H256::repeat_byte(0xcc)),
+            storage: Some(slots.iter().copied().collect()),
+            ..Account::default()
+        }
+    }
+
+    /// Returns the values of the account slots, the balance, nonce and code hash as stored
+    /// in the state, except for the last slot, which is empty.
+    fn state_values(
+        address: Address,
+        slots: &[(H256, H256)],
+        config: &PrestateTracerConfig,
+    ) -> Vec<(StorageKey, H256)> {
+        let account_id = AccountTreeId::new(address);
+        let mut values: Vec<_> = slots[..slots.len() - 1]
+            .iter()
+            .map(|(slot, value)| (StorageKey::new(account_id, *slot), *value))
+            .collect();
+        values.push((config.balance_key(&account_id), u256_to_h256(100.into())));
+        let full_nonce = nonces_to_full_nonce(3.into(), U256::one());
+        values.push((get_nonce_key(&address), u256_to_h256(full_nonce)));
+        values.push((get_code_key(&address), H256::repeat_byte(0xcc)));
+        values
+    }
+
+    #[test]
+    fn witness_proves_captured_values() {
+        let address = Address::repeat_byte(0x01);
+        let config = PrestateTracerConfig::default();
+        let account_id = AccountTreeId::new(address);
+        let slots = [(0, 1), (1, 2), (2, 0)]
+            .map(|(slot, value)| (H256::from_low_u64_be(slot), H256::from_low_u64_be(value)));
+        let state_values = state_values(address, &slots, &config);
+        let prover = FlatProver(state_values.clone());
+
+        let result = PrestateResult::from((
+            State::from([(address, account_with_slots(&slots))]),
+            State::new(),
+        ));
+        let witness = result.to_witness(&prover, &config).unwrap();
+        assert_eq!(witness.root_hash, H256::repeat_byte(0xaa));
+        assert_eq!(witness.proofs.len(), 6);
+        let state_values: HashMap<_, _> = state_values.into_iter().collect();
+        for proof in &witness.proofs {
+            let value = state_values.get(&proof.key).copied().unwrap_or_default();
+            assert_eq!(proof.value, value);
+            assert_eq!(proof.leaf_index == 0, value.is_zero());
+            assert_eq!(proof.merkle_path, [proof.key.hashed_key()]);
+        }
+        // The proofs don't depend on the iteration order of the state.
+        let hashed_keys: Vec<_> = witness.proofs.iter().map(|p| p.key.hashed_key()).collect();
+        assert!(hashed_keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let eth_proofs = result.to_eth_proofs(&witness);
+        assert_eq!(eth_proofs.len(), 1);
+        let eth_proof = &eth_proofs[0];
+        assert_eq!(eth_proof.address, address);
+        assert_eq!(eth_proof.storage_hash, witness.root_hash);
+        let code_key = get_code_key(&address);
+        assert_eq!(eth_proof.account_proof, [code_key.hashed_key()]);
+        assert_eq!(eth_proof.account_proof_index, 5);
+        let expected_storage_proofs: Vec<_> = slots
+            .iter()
+            .zip([1, 2, 0])
+            .map(|((slot, value), index)| EthStorageProof {
+                key: *slot,
+                value: h256_to_u256(*value),
+                proof: vec![StorageKey::new(account_id, *slot).hashed_key()],
+                index,
+            })
+            .collect();
+        assert_eq!(eth_proof.storage_proof, expected_storage_proofs);
+        let json = serde_json::to_value(eth_proof).unwrap();
+        assert_eq!(
+            json["storageProof"][1]["key"],
+            serde_json::json!(slots[1].0)
+        );
+        assert_eq!(json["storageProof"][1]["value"], "0x2");
+        assert_eq!(json["storageProof"][1]["index"], 2);
+        assert_eq!(json["storageProof"][2]["index"], 0);
+        assert_eq!(json["nonce"], "0x3");
+    }
+
+    #[cfg(feature = "merkle_tree")]
+    #[test]
+    fn witness_proofs_verify_against_tree_root() {
+        use zksync_crypto::hasher::blake2::Blake2Hasher;
+        use zksync_merkle_tree::{PatchSet, TreeEntry};
+
+        let address = Address::repeat_byte(0x01);
+        let config = PrestateTracerConfig::default();
+        // The slot 2 is empty, so it's proven to be missing from the tree.
+        let slots = [(0, 1), (1, 2), (2, 0)]
+            .map(|(slot, value)| (H256::from_low_u64_be(slot), H256::from_low_u64_be(value)));
+        let mut tree_values = state_values(address, &slots, &config);
+        // Slots which weren't touched by the transaction are committed to by the root as well.
+        let other_key = StorageKey::new(AccountTreeId::new(Address::zero()), H256::zero());
+        tree_values.push((other_key, H256::repeat_byte(0xff)));
+
+        let mut tree = MerkleTree::new(PatchSet::default());
+        let entries = tree_values
+            .iter()
+            .zip(1..)
+            .map(|((key, value), leaf_index)| {
+                TreeEntry::new(key.hashed_key_u256(), leaf_index, *value)
+            })
+            .collect();
+        let output = tree.extend(entries);
+
+        let result = PrestateResult::from((
+            State::from([(address, account_with_slots(&slots))]),
+            State::new(),
+        ));
+        let witness = result
+            .to_witness(&TreeProver::new(&tree, 0), &config)
+            .unwrap();
+        assert_eq!(witness.root_hash, output.root_hash);
+        assert_eq!(witness.proofs.len(), 6);
+        for proof in &witness.proofs {
+            let tree_entry = tree
+                .entries_with_proofs(0, &[proof.key.hashed_key_u256()])
+                .unwrap()
+                .remove(0);
+            assert_eq!(proof.leaf_index, tree_entry.base.leaf_index);
+            assert_eq!(proof.merkle_path, tree_entry.merkle_path);
+            assert!(proof.verify(witness.root_hash, &Blake2Hasher));
+        }
+        assert!(witness.verify(&Blake2Hasher));
+        let err = result
+            .to_witness(&TreeProver::new(&tree, 1), &config)
+            .unwrap_err();
+        assert_eq!(err.missing_version, 1);
+
+        // A captured value which doesn't match the tree isn't proven, and neither is
+        // the value at another leaf index.
+        let mut forged = witness.proofs[0].clone();
+        forged.value = H256::repeat_byte(0xee);
+        assert!(!forged.verify(witness.root_hash, &Blake2Hasher));
+        let mut forged = witness.proofs[0].clone();
+        forged.leaf_index += 100;
+        assert!(!forged.verify(witness.root_hash, &Blake2Hasher));
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use zksync_state::WriteStorage;
+use zksync_types::{
+    api::ResultDebugCall,
+    debug_flat_call::{flatten_debug_calls, DebugCallFlat},
+    vm_trace::Call,
+};
+
+use crate::{
+    tracers::{
+        prestate_tracer::PrestateTracerConfig, CallTracer, FourByteTracer, NoopTracer,
+        PrestateTracer,
+    },
+    HistoryMode, MultiVMTracer, MultiVmTracerPointer,
+};
+
+/// Tracer that can be selected by its name, like the `tracer` parameter of
+/// `debug_traceTransaction`, and reports its result as JSON.
+pub trait NamedTracer<S: WriteStorage, H: HistoryMode> {
+    /// Returns the tracers to pass to the VM. They share the result with `self`.
+    fn tracer_pointers(&self) -> Vec<MultiVmTracerPointer<S, H>>;
+
+    /// Returns the result of the tracer, or `None` if it hasn't been stored yet.
+    fn json_result(&self) -> Option<serde_json::Value>;
+}
+
+/// [`CallTracer`] reporting its result as the flat traces of the `debug_traceBlock*` methods
+/// with the `flatCallTracer`, see [`flatten_debug_calls()`].
+#[derive(Debug, Clone)]
+pub struct FlatCallTracer {
+    call_tracer: CallTracer,
+    result: Arc<OnceCell<Vec<Call>>>,
+}
+
+impl FlatCallTracer {
+    pub fn new(result: Arc<OnceCell<Vec<Call>>>) -> Self {
+        Self {
+            call_tracer: CallTracer::new(result.clone()),
+            result,
+        }
+    }
+
+    /// Reports at most `max_depth` levels of nested calls, see [`CallTracer::with_max_depth()`].
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.call_tracer = self.call_tracer.with_max_depth(max_depth);
+        self
+    }
+
+    /// Returns the flat calls, if the result has already been stored. Every top-level call
+    /// is the root of its own tree, with its index as the trace address.
+    pub fn get_result(&self) -> Option<Vec<DebugCallFlat>> {
+        let calls = self.result.get()?.iter().map(|call| ResultDebugCall {
+            result: call.clone().into(),
+        });
+        Some(flatten_debug_calls(calls.collect()))
+    }
+}
+
+impl<S: WriteStorage, H: HistoryMode> NamedTracer<S, H> for FlatCallTracer {
+    fn tracer_pointers(&self) -> Vec<MultiVmTracerPointer<S, H>> {
+        vec![self.call_tracer.clone().into_tracer_pointer()]
+    }
+
+    fn json_result(&self) -> Option<serde_json::Value> {
+        let calls = self.get_result()?;
+        Some(serde_json::to_value(calls).expect("failed to serialize flat call tracer result"))
+    }
+}
+
+/// Creates the tracer with the given Geth name and the default config,
+/// or returns `None` if there is no such tracer.
+pub fn tracer_from_name<S: WriteStorage, H: HistoryMode>(
+    name: &str,
+) -> Option<Box<dyn NamedTracer<S, H>>> {
+    tracer_from_name_with_max_depth(name, usize::MAX)
+}
+
+/// Same as [`tracer_from_name()`], but the call tracers report at most `max_depth` levels
+/// of nested calls, see [`CallTracer::with_max_depth()`], and the prestate tracer doesn't capture
+/// the code executed deeper than that, see the `max_depth` option of [`PrestateTracerConfig`].
+pub fn tracer_from_name_with_max_depth<S: WriteStorage, H: HistoryMode>(
+    name: &str,
+    max_depth: usize,
+) -> Option<Box<dyn NamedTracer<S, H>>> {
+    let tracer: Box<dyn NamedTracer<S, H>> = match name {
+        "noopTracer" => Box::new(NoopTracer),
+        "prestateTracer" => Box::new(PrestateTracer::from_config(
+            PrestateTracerConfig::builder().max_depth(max_depth).build(),
+        )),
+        "callTracer" => {
+            Box::new(CallTracer::new(Arc::new(OnceCell::new())).with_max_depth(max_depth))
+        }
+        "flatCallTracer" => {
+            Box::new(FlatCallTracer::new(Arc::new(OnceCell::new())).with_max_depth(max_depth))
+        }
+        "4byteTracer" => Box::new(FourByteTracer::new(Arc::new(OnceCell::new()))),
+        _ => return None,
+    };
+    Some(tracer)
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_state::{InMemoryStorage, StorageView};
+
+    use super::*;
+    use crate::vm_latest::HistoryEnabled;
+
+    type Tracer = Box<dyn NamedTracer<StorageView<InMemoryStorage>, HistoryEnabled>>;
+
+    #[test]
+    fn noop_tracer_is_found_by_name() {
+        let tracer: Tracer = tracer_from_name("noopTracer").unwrap();
+        assert_eq!(tracer.json_result(), Some(serde_json::json!({})));
+    }
+
+    #[test]
+    fn flat_call_tracer_flattens_every_top_level_call() {
+        let calls = vec![Call::default(), Call::default()];
+        let tracer = FlatCallTracer::new(Arc::new(OnceCell::new()));
+        tracer.result.set(calls).unwrap();
+
+        let flat_calls = tracer.get_result().unwrap();
+        let trace_addresses: Vec<_> = flat_calls
+            .into_iter()
+            .map(|call| call.traceaddress)
+            .collect();
+        assert_eq!(trace_addresses, [vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn unknown_tracer_name() {
+        assert!(tracer_from_name::<StorageView<InMemoryStorage>, HistoryEnabled>("foo").is_none());
+    }
+}
@@ -3,7 +3,9 @@ use crate::{glue::tracers::IntoOldVmTracer, tracers::old_tracers::OldTracers};
 pub mod vm_1_4_1;
 pub mod vm_boojum_integration;
 pub mod vm_latest;
+#[cfg(feature = "vm-refunds-enhancement")]
 pub mod vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
 pub mod vm_virtual_blocks;
 
 /// Tracer responsible for calculating the number of storage invocations and
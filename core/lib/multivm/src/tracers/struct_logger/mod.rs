@@ -0,0 +1,360 @@
+use std::{collections::HashMap, sync::Arc};
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use zksync_state::{StoragePtr, WriteStorage};
+use zksync_system_constants::BOOTLOADER_ADDRESS;
+use zksync_types::{web3::types::Bytes, AccountTreeId, Address, StorageKey, H256, U256};
+use zksync_utils::u256_to_h256;
+
+use crate::{
+    glue::tracers::IntoOldVmTracer,
+    interface::{ExecutionResult, VmExecutionResultAndLogs},
+    tracers::{is_system_contract, NamedTracer},
+    HistoryMode, MultiVMTracer, MultiVmTracerPointer,
+};
+
+pub mod vm_1_4_1;
+pub mod vm_boojum_integration;
+pub mod vm_latest;
+#[cfg(feature = "vm-refunds-enhancement")]
+pub mod vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
+pub mod vm_virtual_blocks;
+
+/// Offset of the heap page of a call frame from its base memory page.
+const HEAP_PAGE_OFFSET: u32 = 2;
+
+/// Single VM step in the format of Geth's struct logger.
+///
+/// zkEVM is a register machine, so the `stack` holds the values of the registers `r1`-`r15`,
+/// and the `memory` holds the heap of the current frame. The `depth` counts the far calls
+/// from the bootloader frame, so the near calls don't change it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StructLog {
+    pub pc: u16,
+    pub op: String,
+    /// Gas remaining in the frame before the step.
+    pub gas: u64,
+    /// Base price of the opcode, excluding e.g. the gas passed to the calls.
+    pub gas_cost: u64,
+    pub depth: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<Vec<U256>>,
+    /// Heap contents split into 32-byte words, hex-encoded without the `0x` prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Vec<String>>,
+    /// Slots of the current contract accessed so far. Only reported for the storage opcodes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<HashMap<H256, H256>>,
+}
+
+/// Result of the struct logger, the default output of Geth's `debug_traceTransaction`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StructLogResult {
+    /// Gas used by the transaction.
+    pub gas: u64,
+    /// Whether the transaction has failed.
+    pub failed: bool,
+    /// Output of the transaction.
+    pub return_value: Bytes,
+    pub struct_logs: Vec<StructLog>,
+}
+
+impl StructLogResult {
+    /// Combines the logged steps with the outcome of the traced transaction, which is only
+    /// known to the VM caller.
+    pub fn new(struct_logs: Vec<StructLog>, execution: &VmExecutionResultAndLogs) -> Self {
+        let return_value = match &execution.result {
+            ExecutionResult::Success { output } => output.clone().into(),
+            ExecutionResult::Revert { output } => output.encoded_data().into(),
+            ExecutionResult::Halt { .. } => Bytes::default(),
+        };
+        Self {
+            gas: execution.statistics.gas_used.into(),
+            failed: execution.result.is_failed(),
+            return_value,
+            struct_logs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StructLoggerConfig {
+    disable_stack: bool,
+    enable_memory: bool,
+    disable_storage: bool,
+    limit: usize,
+}
+
+impl StructLoggerConfig {
+    pub fn builder() -> StructLoggerConfigBuilder {
+        StructLoggerConfigBuilder::default()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StructLoggerConfigBuilder {
+    config: StructLoggerConfig,
+}
+
+impl StructLoggerConfigBuilder {
+    /// Don't report the registers.
+    pub fn disable_stack(mut self, disable_stack: bool) -> Self {
+        self.config.disable_stack = disable_stack;
+        self
+    }
+
+    /// Report the heap, like Geth's `enableMemory`. It is copied on every step, so it is
+    /// by far the largest part of the output.
+    pub fn enable_memory(mut self, enable_memory: bool) -> Self {
+        self.config.enable_memory = enable_memory;
+        self
+    }
+
+    /// Don't report the storage slots.
+    pub fn disable_storage(mut self, disable_storage: bool) -> Self {
+        self.config.disable_storage = disable_storage;
+        self
+    }
+
+    /// Log at most `limit` steps. Zero means no limit.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.config.limit = limit;
+        self
+    }
+
+    pub fn build(self) -> StructLoggerConfig {
+        self.config
+    }
+}
+
+/// Logs every step executed by the transaction code, like Geth's struct logger.
+///
+/// The steps of the bootloader and the system contracts are not logged.
+#[derive(Debug, Clone)]
+pub struct StructLogger {
+    config: StructLoggerConfig,
+    logs: Vec<StructLog>,
+    // Slots accessed by every contract, with their last seen values.
+    storage: HashMap<Address, HashMap<H256, H256>>,
+    pub result: Arc<OnceCell<Vec<StructLog>>>,
+}
+
+impl StructLogger {
+    pub fn new(result: Arc<OnceCell<Vec<StructLog>>>) -> Self {
+        Self::with_config(StructLoggerConfig::default(), result)
+    }
+
+    pub fn with_config(config: StructLoggerConfig, result: Arc<OnceCell<Vec<StructLog>>>) -> Self {
+        Self {
+            config,
+            logs: Vec::new(),
+            storage: HashMap::new(),
+            result,
+        }
+    }
+
+    /// Returns the logged steps, if they have already been stored. The outcome of the
+    /// transaction is added by the caller, see [`StructLogResult::new()`].
+    pub fn get_result(&self) -> Option<Vec<StructLog>> {
+        self.result.get().cloned()
+    }
+
+    /// Returns whether a step executing the code of `code_address` has to be logged.
+    fn should_log(&self, code_address: Address) -> bool {
+        let below_limit = self.config.limit == 0 || self.logs.len() < self.config.limit;
+        // The system contracts are not a part of the transaction code.
+        below_limit && !is_system_contract(&code_address)
+    }
+
+    /// Logs a step. The storage of the `address` contract is attached to the step
+    /// if it accesses the `slot`, writing the `written_value` if it is a write.
+    fn record_step<S: WriteStorage>(
+        &mut self,
+        mut log: StructLog,
+        address: Address,
+        storage_access: Option<(U256, Option<U256>)>,
+        storage: &StoragePtr<S>,
+    ) {
+        if let Some((slot, written_value)) = storage_access {
+            let slot = u256_to_h256(slot);
+            let value = match written_value {
+                Some(value) => u256_to_h256(value),
+                None => {
+                    let key = StorageKey::new(AccountTreeId::new(address), slot);
+                    storage.borrow_mut().read_value(&key)
+                }
+            };
+            let contract_storage = self.storage.entry(address).or_default();
+            contract_storage.insert(slot, value);
+            if !self.config.disable_storage {
+                log.storage = Some(contract_storage.clone());
+            }
+        }
+        self.logs.push(log);
+    }
+
+    fn store_result(&mut self) {
+        if self.result.set(std::mem::take(&mut self.logs)).is_err() {
+            tracing::error!("Struct logger result is already set");
+        }
+    }
+}
+
+/// Returns the number of far calls from the bootloader frame to the current one, i.e. the last
+/// of the `frames`, given their addresses and whether they are near call frames. The top-level
+/// call of the transaction is at depth 1, like in Geth.
+fn far_call_depth(frames: impl Iterator<Item = (Address, bool)>) -> usize {
+    frames
+        .skip_while(|&(address, _)| address != BOOTLOADER_ADDRESS)
+        .filter(|&(_, is_local_frame)| !is_local_frame)
+        .count()
+        .saturating_sub(1)
+}
+
+/// Splits the `memory` into 32-byte words, padding the last one with zeros.
+fn memory_words(memory: &[u8]) -> Vec<String> {
+    memory
+        .chunks(32)
+        .map(|chunk| {
+            let mut word = [0_u8; 32];
+            word[..chunk.len()].copy_from_slice(chunk);
+            hex::encode(word)
+        })
+        .collect()
+}
+
+impl IntoOldVmTracer for StructLogger {}
+
+impl<S: WriteStorage, H: HistoryMode> NamedTracer<S, H> for StructLogger {
+    fn tracer_pointers(&self) -> Vec<MultiVmTracerPointer<S, H>> {
+        vec![self.clone().into_tracer_pointer()]
+    }
+
+    /// Only reports the `structLogs`, since the outcome of the transaction isn't known
+    /// to the tracer; see [`StructLogResult`] for the full output.
+    fn json_result(&self) -> Option<serde_json::Value> {
+        let struct_logs = self.result.get()?;
+        let struct_logs =
+            serde_json::to_value(struct_logs).expect("failed to serialize struct logger result");
+        Some(serde_json::json!({ "structLogs": struct_logs }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zksync_state::{InMemoryStorage, StorageView};
+
+    use super::*;
+
+    fn log(pc: u16, op: &str) -> StructLog {
+        StructLog {
+            pc,
+            op: op.to_owned(),
+            gas: 1_000,
+            gas_cost: 4,
+            depth: 1,
+            stack: Some(vec![U256::one()]),
+            memory: None,
+            storage: None,
+        }
+    }
+
+    #[test]
+    fn storage_is_attached_to_storage_steps() {
+        let address = Address::repeat_byte(0x01);
+        let key = StorageKey::new(AccountTreeId::new(address), H256::from_low_u64_be(1));
+        let mut raw_storage = InMemoryStorage::default();
+        raw_storage.set_value(key, H256::from_low_u64_be(5));
+        let storage = StorageView::new(raw_storage).to_rc_ptr();
+
+        let result = Arc::new(OnceCell::new());
+        let mut tracer = StructLogger::new(result.clone());
+        assert!(tracer.should_log(address));
+        assert!(!tracer.should_log(BOOTLOADER_ADDRESS));
+
+        tracer.record_step(log(0, "Add"), address, None, &storage);
+        tracer.record_step(log(1, "Read"), address, Some((1.into(), None)), &storage);
+        tracer.record_step(
+            log(2, "Write"),
+            address,
+            Some((2.into(), Some(7.into()))),
+            &storage,
+        );
+        tracer.store_result();
+
+        let logs = result.get().unwrap();
+        assert_eq!(logs[0].storage, None);
+        assert_eq!(
+            logs[1].storage,
+            Some(HashMap::from([(
+                H256::from_low_u64_be(1),
+                H256::from_low_u64_be(5)
+            )]))
+        );
+        assert_eq!(logs[2].storage.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn steps_above_limit_are_not_logged() {
+        let address = Address::repeat_byte(0x01);
+        let storage = StorageView::new(InMemoryStorage::default()).to_rc_ptr();
+        let config = StructLoggerConfig::builder().limit(1).build();
+        let mut tracer = StructLogger::with_config(config, Arc::new(OnceCell::new()));
+
+        tracer.record_step(log(0, "Add"), address, None, &storage);
+        assert!(!tracer.should_log(address));
+    }
+
+    #[test]
+    fn depth_counts_far_calls_from_bootloader() {
+        let caller = Address::repeat_byte(0x01);
+        let callee = Address::repeat_byte(0x02);
+        // The frames below the bootloader and the near calls aren't counted.
+        let frames = [
+            (Address::zero(), false),
+            (BOOTLOADER_ADDRESS, false),
+            (BOOTLOADER_ADDRESS, true),
+            (caller, false),
+            (caller, true),
+        ];
+        assert_eq!(far_call_depth(frames.into_iter()), 1);
+        let frames = frames.into_iter().chain([(callee, false)]);
+        assert_eq!(far_call_depth(frames), 2);
+        assert_eq!(far_call_depth([(BOOTLOADER_ADDRESS, false)].into_iter()), 0);
+    }
+
+    #[test]
+    fn result_is_serialized_like_geth() {
+        let mut step = log(3, "Add");
+        step.memory = Some(memory_words(&[0xab; 33]));
+        let result = StructLogResult {
+            gas: 21_000,
+            failed: false,
+            return_value: Bytes(vec![0x01]),
+            struct_logs: vec![step],
+        };
+
+        let expected = serde_json::json!({
+            "gas": 21_000,
+            "failed": false,
+            "returnValue": "0x01",
+            "structLogs": [{
+                "pc": 3,
+                "op": "Add",
+                "gas": 1_000,
+                "gasCost": 4,
+                "depth": 1,
+                "stack": ["0x1"],
+                "memory": [
+                    "abababababababababababababababababababababababababababababababab",
+                    "ab00000000000000000000000000000000000000000000000000000000000000"
+                ]
+            }]
+        });
+        assert_eq!(serde_json::to_value(&result).unwrap(), expected);
+    }
+}
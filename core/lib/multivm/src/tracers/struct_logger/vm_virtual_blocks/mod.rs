@@ -0,0 +1,87 @@
+use zk_evm_1_3_3::{
+    tracing::{BeforeExecutionData, VmLocalStateData},
+    zkevm_opcode_defs::{LogOpcode, Opcode},
+};
+use zksync_state::{StoragePtr, WriteStorage};
+
+use crate::{
+    interface::{dyn_tracers::vm_1_3_3::DynTracer, tracer::VmExecutionStopReason},
+    tracers::struct_logger::{
+        far_call_depth, memory_words, StructLog, StructLogger, HEAP_PAGE_OFFSET,
+    },
+    vm_virtual_blocks::{
+        BootloaderState, ExecutionEndTracer, ExecutionProcessing, HistoryMode, SimpleMemory,
+        VmTracer, ZkSyncVmState,
+    },
+};
+
+impl<S: WriteStorage, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for StructLogger {
+    fn before_execution(
+        &mut self,
+        state: VmLocalStateData<'_>,
+        data: BeforeExecutionData,
+        memory: &SimpleMemory<H>,
+        storage: StoragePtr<S>,
+    ) {
+        let callstack = &state.vm_local_state.callstack;
+        let current = &callstack.current;
+        if !self.should_log(current.code_address) {
+            return;
+        }
+
+        let opcode = data.opcode.variant.opcode;
+        let log = StructLog {
+            pc: current.pc,
+            op: format!("{:?}", opcode),
+            gas: current.ergs_remaining.into(),
+            gas_cost: data.opcode.inner.variant.ergs_price().into(),
+            depth: far_call_depth(
+                callstack
+                    .inner
+                    .iter()
+                    .chain([current])
+                    .map(|frame| (frame.this_address, frame.is_local_frame)),
+            ),
+            stack: (!self.config.disable_stack).then(|| {
+                state
+                    .vm_local_state
+                    .registers
+                    .iter()
+                    .map(|register| register.value)
+                    .collect()
+            }),
+            memory: self.config.enable_memory.then(|| {
+                let heap = memory.read_unaligned_bytes(
+                    (current.base_memory_page.0 + HEAP_PAGE_OFFSET) as usize,
+                    0,
+                    current.heap_bound as usize,
+                );
+                memory_words(&heap)
+            }),
+            storage: None,
+        };
+        let storage_access = match opcode {
+            Opcode::Log(LogOpcode::StorageRead) => Some((data.src0_value.value, None)),
+            Opcode::Log(LogOpcode::StorageWrite) => {
+                Some((data.src0_value.value, Some(data.src1_value.value)))
+            }
+            _ => None,
+        };
+        self.record_step(log, current.this_address, storage_access, &storage);
+    }
+}
+
+impl<H: HistoryMode> ExecutionEndTracer<H> for StructLogger {}
+
+impl<S: WriteStorage, H: HistoryMode> ExecutionProcessing<S, H> for StructLogger {
+    fn after_vm_execution(
+        &mut self,
+        _state: &mut ZkSyncVmState<S, H>,
+        _bootloader_state: &BootloaderState,
+        _stop_reason: VmExecutionStopReason,
+    ) {
+        self.store_result();
+    }
+}
+
+impl<S: WriteStorage, H: HistoryMode> VmTracer<S, H> for StructLogger {}
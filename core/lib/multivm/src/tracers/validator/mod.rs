@@ -22,7 +22,9 @@ mod types;
 mod vm_1_4_1;
 mod vm_boojum_integration;
 mod vm_latest;
+#[cfg(feature = "vm-refunds-enhancement")]
 mod vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
 mod vm_virtual_blocks;
 
 /// Tracer that is used to ensure that the validation adheres to all the rules
@@ -5,6 +5,12 @@ use zksync_types::{
 
 use crate::vm_latest::L1BatchEnv;
 
+/// Panics for a VM `version` which is disabled by the features of the crate.
+#[cfg(not(all(feature = "vm-virtual-blocks", feature = "vm-refunds-enhancement")))]
+pub(crate) fn disabled_vm_version(version: VmVersion) -> ! {
+    panic!("VM version {version:?} is disabled by the features of the `multivm` crate")
+}
+
 /// Calculates the base fee and gas per pubdata for the given L1 gas price.
 pub fn derive_base_fee_and_gas_per_pubdata(
     batch_fee_input: BatchFeeInput,
@@ -26,16 +32,22 @@ pub fn derive_base_fee_and_gas_per_pubdata(
                 batch_fee_input.into_l1_pegged(),
             )
         }
+        #[cfg(feature = "vm-virtual-blocks")]
         VmVersion::VmVirtualBlocks => {
             crate::vm_virtual_blocks::utils::fee::derive_base_fee_and_gas_per_pubdata(
                 batch_fee_input.into_l1_pegged(),
             )
         }
+        #[cfg(not(feature = "vm-virtual-blocks"))]
+        VmVersion::VmVirtualBlocks => disabled_vm_version(vm_version),
+        #[cfg(feature = "vm-refunds-enhancement")]
         VmVersion::VmVirtualBlocksRefundsEnhancement => {
             crate::vm_refunds_enhancement::utils::fee::derive_base_fee_and_gas_per_pubdata(
                 batch_fee_input.into_l1_pegged(),
             )
         }
+        #[cfg(not(feature = "vm-refunds-enhancement"))]
+        VmVersion::VmVirtualBlocksRefundsEnhancement => disabled_vm_version(vm_version),
         VmVersion::VmBoojumIntegration => {
             crate::vm_boojum_integration::utils::fee::derive_base_fee_and_gas_per_pubdata(
                 batch_fee_input.into_l1_pegged(),
@@ -59,12 +71,18 @@ pub fn get_batch_base_fee(l1_batch_env: &L1BatchEnv, vm_version: VmVersion) -> u
             crate::vm_m6::vm_with_bootloader::get_batch_base_fee(l1_batch_env)
         }
         VmVersion::Vm1_3_2 => crate::vm_1_3_2::vm_with_bootloader::get_batch_base_fee(l1_batch_env),
+        #[cfg(feature = "vm-virtual-blocks")]
         VmVersion::VmVirtualBlocks => {
             crate::vm_virtual_blocks::utils::fee::get_batch_base_fee(l1_batch_env)
         }
+        #[cfg(not(feature = "vm-virtual-blocks"))]
+        VmVersion::VmVirtualBlocks => disabled_vm_version(vm_version),
+        #[cfg(feature = "vm-refunds-enhancement")]
         VmVersion::VmVirtualBlocksRefundsEnhancement => {
             crate::vm_refunds_enhancement::utils::fee::get_batch_base_fee(l1_batch_env)
         }
+        #[cfg(not(feature = "vm-refunds-enhancement"))]
+        VmVersion::VmVirtualBlocksRefundsEnhancement => disabled_vm_version(vm_version),
         VmVersion::VmBoojumIntegration => {
             crate::vm_boojum_integration::utils::fee::get_batch_base_fee(l1_batch_env)
         }
@@ -167,12 +185,16 @@ pub fn derive_overhead(
             encoded_len,
             crate::vm_1_3_2::transaction_data::OverheadCoefficients::from_tx_type(tx_type),
         ),
+        #[cfg(feature = "vm-virtual-blocks")]
         VmVersion::VmVirtualBlocks => crate::vm_virtual_blocks::utils::overhead::derive_overhead(
             gas_limit,
             gas_price_per_pubdata,
             encoded_len,
             crate::vm_virtual_blocks::utils::overhead::OverheadCoefficients::from_tx_type(tx_type),
         ),
+        #[cfg(not(feature = "vm-virtual-blocks"))]
+        VmVersion::VmVirtualBlocks => disabled_vm_version(vm_version),
+        #[cfg(feature = "vm-refunds-enhancement")]
         VmVersion::VmVirtualBlocksRefundsEnhancement => {
             crate::vm_refunds_enhancement::utils::overhead::derive_overhead(
                 gas_limit,
@@ -183,6 +205,8 @@ pub fn derive_overhead(
                 ),
             )
         }
+        #[cfg(not(feature = "vm-refunds-enhancement"))]
+        VmVersion::VmVirtualBlocksRefundsEnhancement => disabled_vm_version(vm_version),
         VmVersion::VmBoojumIntegration => {
             crate::vm_boojum_integration::utils::overhead::derive_overhead(
                 gas_limit,
@@ -207,12 +231,18 @@ pub fn get_bootloader_encoding_space(version: VmVersion) -> u32 {
             crate::vm_m6::vm_with_bootloader::BOOTLOADER_TX_ENCODING_SPACE
         }
         VmVersion::Vm1_3_2 => crate::vm_1_3_2::vm_with_bootloader::BOOTLOADER_TX_ENCODING_SPACE,
+        #[cfg(feature = "vm-virtual-blocks")]
         VmVersion::VmVirtualBlocks => {
             crate::vm_virtual_blocks::constants::BOOTLOADER_TX_ENCODING_SPACE
         }
+        #[cfg(not(feature = "vm-virtual-blocks"))]
+        VmVersion::VmVirtualBlocks => disabled_vm_version(version),
+        #[cfg(feature = "vm-refunds-enhancement")]
         VmVersion::VmVirtualBlocksRefundsEnhancement => {
             crate::vm_refunds_enhancement::constants::BOOTLOADER_TX_ENCODING_SPACE
         }
+        #[cfg(not(feature = "vm-refunds-enhancement"))]
+        VmVersion::VmVirtualBlocksRefundsEnhancement => disabled_vm_version(version),
         VmVersion::VmBoojumIntegration => {
             crate::vm_boojum_integration::constants::BOOTLOADER_TX_ENCODING_SPACE
         }
@@ -230,10 +260,16 @@ pub fn get_bootloader_max_txs_in_batch(version: VmVersion) -> usize {
             crate::vm_m6::vm_with_bootloader::MAX_TXS_IN_BLOCK
         }
         VmVersion::Vm1_3_2 => crate::vm_1_3_2::vm_with_bootloader::MAX_TXS_IN_BLOCK,
+        #[cfg(feature = "vm-virtual-blocks")]
         VmVersion::VmVirtualBlocks => crate::vm_virtual_blocks::constants::MAX_TXS_IN_BLOCK,
+        #[cfg(not(feature = "vm-virtual-blocks"))]
+        VmVersion::VmVirtualBlocks => disabled_vm_version(version),
+        #[cfg(feature = "vm-refunds-enhancement")]
         VmVersion::VmVirtualBlocksRefundsEnhancement => {
             crate::vm_refunds_enhancement::constants::MAX_TXS_IN_BLOCK
         }
+        #[cfg(not(feature = "vm-refunds-enhancement"))]
+        VmVersion::VmVirtualBlocksRefundsEnhancement => disabled_vm_version(version),
         VmVersion::VmBoojumIntegration => crate::vm_boojum_integration::constants::MAX_TXS_IN_BLOCK,
         VmVersion::Vm1_4_1 => crate::vm_1_4_1::constants::MAX_TXS_IN_BATCH,
         VmVersion::Vm1_4_2 => crate::vm_latest::constants::MAX_TXS_IN_BATCH,
@@ -269,10 +305,16 @@ pub fn get_max_gas_per_pubdata_byte(version: VmVersion) -> u64 {
             crate::vm_m6::vm_with_bootloader::MAX_GAS_PER_PUBDATA_BYTE
         }
         VmVersion::Vm1_3_2 => crate::vm_1_3_2::vm_with_bootloader::MAX_GAS_PER_PUBDATA_BYTE,
+        #[cfg(feature = "vm-virtual-blocks")]
         VmVersion::VmVirtualBlocks => crate::vm_virtual_blocks::constants::MAX_GAS_PER_PUBDATA_BYTE,
+        #[cfg(not(feature = "vm-virtual-blocks"))]
+        VmVersion::VmVirtualBlocks => disabled_vm_version(version),
+        #[cfg(feature = "vm-refunds-enhancement")]
         VmVersion::VmVirtualBlocksRefundsEnhancement => {
             crate::vm_refunds_enhancement::constants::MAX_GAS_PER_PUBDATA_BYTE
         }
+        #[cfg(not(feature = "vm-refunds-enhancement"))]
+        VmVersion::VmVirtualBlocksRefundsEnhancement => disabled_vm_version(version),
         VmVersion::VmBoojumIntegration => {
             crate::vm_boojum_integration::constants::MAX_GAS_PER_PUBDATA_BYTE
         }
@@ -290,12 +332,18 @@ pub fn get_used_bootloader_memory_bytes(version: VmVersion) -> usize {
             crate::vm_m6::vm_with_bootloader::USED_BOOTLOADER_MEMORY_BYTES
         }
         VmVersion::Vm1_3_2 => crate::vm_1_3_2::vm_with_bootloader::USED_BOOTLOADER_MEMORY_BYTES,
+        #[cfg(feature = "vm-virtual-blocks")]
         VmVersion::VmVirtualBlocks => {
             crate::vm_virtual_blocks::constants::USED_BOOTLOADER_MEMORY_BYTES
         }
+        #[cfg(not(feature = "vm-virtual-blocks"))]
+        VmVersion::VmVirtualBlocks => disabled_vm_version(version),
+        #[cfg(feature = "vm-refunds-enhancement")]
         VmVersion::VmVirtualBlocksRefundsEnhancement => {
             crate::vm_refunds_enhancement::constants::USED_BOOTLOADER_MEMORY_BYTES
         }
+        #[cfg(not(feature = "vm-refunds-enhancement"))]
+        VmVersion::VmVirtualBlocksRefundsEnhancement => disabled_vm_version(version),
         VmVersion::VmBoojumIntegration => {
             crate::vm_boojum_integration::constants::USED_BOOTLOADER_MEMORY_BYTES
         }
@@ -313,12 +361,18 @@ pub fn get_used_bootloader_memory_words(version: VmVersion) -> usize {
             crate::vm_m6::vm_with_bootloader::USED_BOOTLOADER_MEMORY_WORDS
         }
         VmVersion::Vm1_3_2 => crate::vm_1_3_2::vm_with_bootloader::USED_BOOTLOADER_MEMORY_WORDS,
+        #[cfg(feature = "vm-virtual-blocks")]
         VmVersion::VmVirtualBlocks => {
             crate::vm_virtual_blocks::constants::USED_BOOTLOADER_MEMORY_WORDS
         }
+        #[cfg(not(feature = "vm-virtual-blocks"))]
+        VmVersion::VmVirtualBlocks => disabled_vm_version(version),
+        #[cfg(feature = "vm-refunds-enhancement")]
         VmVersion::VmVirtualBlocksRefundsEnhancement => {
             crate::vm_refunds_enhancement::constants::USED_BOOTLOADER_MEMORY_WORDS
         }
+        #[cfg(not(feature = "vm-refunds-enhancement"))]
+        VmVersion::VmVirtualBlocksRefundsEnhancement => disabled_vm_version(version),
         VmVersion::VmBoojumIntegration => {
             crate::vm_boojum_integration::constants::USED_BOOTLOADER_MEMORY_WORDS
         }
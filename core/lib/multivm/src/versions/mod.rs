@@ -4,5 +4,7 @@ pub mod vm_boojum_integration;
 pub mod vm_latest;
 pub mod vm_m5;
 pub mod vm_m6;
+#[cfg(feature = "vm-refunds-enhancement")]
 pub mod vm_refunds_enhancement;
+#[cfg(feature = "vm-virtual-blocks")]
 pub mod vm_virtual_blocks;
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use ethabi::Token;
+use once_cell::sync::OnceCell;
+use zksync_types::{web3::signing::keccak256, Address, Execute, H256};
+
+use crate::{
+    interface::{TxExecutionMode, VmExecutionMode, VmInterface},
+    tracers::{access_list_tracer::AccessListTracerConfig, AccessListTracer},
+    vm_latest::{
+        constants::BLOCK_GAS_LIMIT,
+        tests::{tester::VmTesterBuilder, utils::read_expensive_contract},
+        HistoryEnabled, ToTracerPointer,
+    },
+};
+
+#[test]
+fn test_access_list_tracer() {
+    let (contract, abi) = read_expensive_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    // `expensive(1)` accesses the length of the array in slot 0
+    // and its only element in slot `keccak256(0)`.
+    let calldata = abi
+        .function("expensive")
+        .unwrap()
+        .encode_input(&[Token::Uint(1.into())])
+        .unwrap();
+    let account = &mut vm.rich_accounts[0];
+    let sender = account.address;
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata,
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+    vm.vm.push_transaction(tx);
+
+    let config = AccessListTracerConfig {
+        from: Some(sender),
+        to: None,
+        exclude_precompiles: true,
+    };
+    let result = Arc::new(OnceCell::new());
+    let access_list_tracer = AccessListTracer::new(config, result.clone()).into_tracer_pointer();
+    let res = vm
+        .vm
+        .inspect(access_list_tracer.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+
+    let access_list = result.get().unwrap();
+    assert!(access_list.iter().all(|item| item.address != sender));
+    let item = access_list
+        .iter()
+        .find(|item| item.address == address)
+        .unwrap();
+    assert_eq!(
+        item.storage_keys,
+        vec![H256::zero(), H256(keccak256(H256::zero().as_bytes()))]
+    );
+}
@@ -1,11 +1,14 @@
 use std::sync::Arc;
 
 use once_cell::sync::OnceCell;
-use zksync_types::{Address, Execute};
+use zksync_types::{
+    vm_trace::{Call, CallType},
+    Address, Execute,
+};
 
 use crate::{
     interface::{TxExecutionMode, VmExecutionMode, VmInterface},
-    tracers::CallTracer,
+    tracers::{call_tracer::MAX_DEPTH_EXCEEDED_ERROR, CallTracer},
     vm_latest::{
         constants::BLOCK_GAS_LIMIT,
         tests::{
@@ -90,3 +93,119 @@ fn test_basic_behavior() {
     assert!(subcall.len() > 10);
     assert!(!res.result.is_failed());
 }
+
+#[test]
+fn test_nested_calls_tree() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+
+    let account = &mut vm.rich_accounts[0];
+    let sender = account.address;
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = Arc::new(OnceCell::new());
+    let call_tracer = CallTracer::new(result.clone()).into_tracer_pointer();
+    vm.vm.push_transaction(tx);
+    let res = vm.vm.inspect(call_tracer.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+
+    // Returns the call that has a direct subcall to the `to` address, along with the subcall.
+    fn find_parent(calls: &[Call], to: Address) -> Option<(&Call, &Call)> {
+        calls.iter().find_map(|call| {
+            call.calls
+                .iter()
+                .find(|subcall| subcall.to == to)
+                .map(|subcall| (call, subcall))
+                .or_else(|| find_parent(&call.calls, to))
+        })
+    }
+
+    fn assert_gas_accounting(call: &Call) {
+        for subcall in &call.calls {
+            assert!(subcall.gas_used <= call.gas_used);
+            assert_gas_accounting(subcall);
+        }
+    }
+
+    let calls = result.get().unwrap();
+    // The bootloader calls the account, which in turn calls the counter contract.
+    let (account_call, counter_call) = find_parent(calls, address).unwrap();
+    assert_eq!(account_call.to, sender);
+    assert_eq!(counter_call.from, sender);
+    assert!(matches!(counter_call.r#type, CallType::Call(_)));
+    assert_eq!(
+        counter_call.input,
+        hex::decode(increment_by_6_calldata).unwrap()
+    );
+    assert!(counter_call.gas_used > 0);
+    assert!(counter_call.gas_used <= account_call.gas_used);
+    assert_eq!(counter_call.revert_reason, None);
+    calls.iter().for_each(assert_gas_accounting);
+}
+
+#[test]
+fn test_call_depth_limit() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+
+    let account = &mut vm.rich_accounts[0];
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = Arc::new(OnceCell::new());
+    let call_tracer = CallTracer::new(result.clone())
+        .with_max_depth(1)
+        .into_tracer_pointer();
+    vm.vm.push_transaction(tx);
+    let res = vm.vm.inspect(call_tracer.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+
+    // Only the calls made by the bootloader are reported with their subcalls, which are truncated.
+    let calls = result.get().unwrap();
+    let subcalls: Vec<_> = calls.iter().flat_map(|call| &call.calls).collect();
+    for call in &subcalls {
+        assert_eq!(call.error.as_deref(), Some(MAX_DEPTH_EXCEEDED_ERROR));
+        assert!(call.calls.is_empty());
+    }
+    // The account calls the counter contract on the second level of nesting.
+    let counter_call = subcalls.iter().find(|call| call.to == address).unwrap();
+    assert!(counter_call.input.is_empty());
+    assert!(counter_call.gas_used > 0);
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use zksync_types::{Address, Execute};
+
+use crate::{
+    interface::{TxExecutionMode, VmExecutionMode, VmInterface},
+    tracers::FourByteTracer,
+    vm_latest::{
+        constants::BLOCK_GAS_LIMIT,
+        tests::{tester::VmTesterBuilder, utils::read_test_contract},
+        HistoryEnabled, ToTracerPointer,
+    },
+};
+
+#[test]
+fn test_four_byte_tracer() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let get_calldata = "6d4ce63c";
+
+    let account = &mut vm.rich_accounts[0];
+    let txs: Vec<_> = [
+        increment_by_6_calldata,
+        increment_by_6_calldata,
+        get_calldata,
+    ]
+    .iter()
+    .map(|calldata| {
+        account.get_l2_tx_for_execute(
+            Execute {
+                contract_address: address,
+                calldata: hex::decode(calldata).unwrap(),
+                value: Default::default(),
+                factory_deps: None,
+            },
+            None,
+        )
+    })
+    .collect();
+    for tx in txs {
+        vm.vm.push_transaction(tx);
+    }
+
+    let result = Arc::new(OnceCell::new());
+    let four_byte_tracer = FourByteTracer::new(result.clone()).into_tracer_pointer();
+    let res = vm
+        .vm
+        .inspect(four_byte_tracer.into(), VmExecutionMode::Batch);
+    assert!(!res.result.is_failed());
+
+    let counts = result.get().unwrap();
+    assert_eq!(counts["0x7cf5dab0-32"], 2);
+    assert_eq!(counts["0x6d4ce63c-0"], 1);
+}
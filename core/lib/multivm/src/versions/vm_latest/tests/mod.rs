@@ -1,3 +1,4 @@
+mod access_list_tracer;
 mod bootloader;
 mod default_aa;
 // TODO - fix this test
@@ -6,17 +7,21 @@ mod block_tip;
 mod bytecode_publishing;
 mod call_tracer;
 mod circuits;
+mod four_byte_tracer;
 mod gas_limit;
 mod get_used_contracts;
 mod is_write_initial;
 mod l1_tx_execution;
 mod l2_blocks;
+mod mux_tracer;
 mod nonce_holder;
 mod precompiles;
+mod prestate_tracer;
 mod refunds;
 mod require_eip712;
 mod rollbacks;
 mod simple_execution;
+mod struct_logger;
 mod tester;
 mod tracing_execution_error;
 mod upgrade;
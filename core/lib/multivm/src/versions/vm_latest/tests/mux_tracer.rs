@@ -0,0 +1,69 @@
+use zksync_types::{Address, Execute, Transaction};
+
+use crate::{
+    interface::{TxExecutionMode, VmExecutionMode, VmInterface, VmInterfaceHistoryEnabled},
+    tracers::{tracer_from_name, MuxTracer, NamedTracer, TracerDispatcher},
+    vm_latest::{
+        constants::BLOCK_GAS_LIMIT,
+        tests::{
+            tester::{InMemoryStorageView, VmTester, VmTesterBuilder},
+            utils::read_test_contract,
+        },
+        HistoryEnabled,
+    },
+};
+
+/// Executes `tx` with the `tracer` and rolls the VM back, so that the same transaction
+/// can be traced again.
+fn trace_tx(
+    vm: &mut VmTester<HistoryEnabled>,
+    tx: &Transaction,
+    tracer: &dyn NamedTracer<InMemoryStorageView, HistoryEnabled>,
+) -> serde_json::Value {
+    vm.vm.make_snapshot();
+    vm.vm.push_transaction(tx.clone());
+    let tracers: TracerDispatcher<_, HistoryEnabled> = tracer.tracer_pointers().into();
+    let res = vm.vm.inspect(tracers.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+    vm.vm.rollback_to_the_latest_snapshot();
+    tracer.json_result().unwrap()
+}
+
+#[test]
+fn test_mux_tracer() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let tx = vm.rich_accounts[0].get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let mux_tracer = MuxTracer::from_names(["prestateTracer", "4byteTracer"]).unwrap();
+    let mux_result = trace_tx(&mut vm, &tx, &mux_tracer);
+
+    for name in ["prestateTracer", "4byteTracer"] {
+        let tracer = tracer_from_name(name).unwrap();
+        let result = trace_tx(&mut vm, &tx, tracer.as_ref());
+        assert_eq!(mux_result[name], result, "{}", name);
+    }
+    assert_eq!(mux_result["4byteTracer"]["0x7cf5dab0-32"], 1);
+    assert!(mux_result["prestateTracer"]
+        .get(format!("{:?}", address))
+        .is_some());
+}
@@ -0,0 +1,1170 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ethabi::Token;
+use once_cell::sync::OnceCell;
+use zksync_contracts::{load_contract, BaseSystemContracts};
+use zksync_state::{InMemoryStorage, StorageView};
+use zksync_test_account::{DeployContractsTx, TxType};
+use zksync_types::{
+    block::MiniblockExecutionData, get_code_key, utils::storage_key_for_eth_balance, AccountTreeId,
+    Address, Execute, L1BatchNumber, L2ChainId, MiniblockNumber, ProtocolVersionId, StorageKey,
+    Transaction, H256, L2_ETH_TOKEN_ADDRESS, U256,
+};
+use zksync_utils::{bytecode::hash_bytecode, u256_to_h256};
+
+use crate::{
+    interface::{SystemEnv, TxExecutionMode, VmExecutionMode, VmInterface, VmRevertReason},
+    tracers::{
+        prestate_tracer::{
+            apply_state_override, assert_diff_subset_of_default, debug_trace_transaction_prestate,
+            get_balance_key_for_token, merge_prestate_results, trace_block_prestate,
+            trace_block_prestate_per_transaction, trace_call_prestate,
+            trace_historical_transaction_prestate, trace_prestate, trace_prestate_with_version,
+            trace_transaction_prestate, AccountOverride, BalanceDelta, CallMessage,
+            HistoricalStorage, PrestateTracerConfig, PrestateTracerError, StateHeight,
+            StateOverride, TracerVmVersion,
+        },
+        PrestateTracer,
+    },
+    vm_latest::{
+        constants::BLOCK_GAS_LIMIT,
+        tests::{
+            tester::{default_l1_batch, get_empty_storage, Account, VmTesterBuilder},
+            utils::{get_execute_error_calldata, read_error_contract, read_test_contract},
+        },
+        utils::fee::get_batch_base_fee,
+        HistoryEnabled, ToTracerPointer, Vm,
+    },
+};
+
+#[test]
+fn test_prestate_tracer_diff_mode() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+
+    let account = &mut vm.rich_accounts[0];
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = Arc::new(OnceCell::new());
+    let prestate_tracer = PrestateTracer::new(true, result.clone()).into_tracer_pointer();
+    vm.vm.push_transaction(tx);
+    let res = vm
+        .vm
+        .inspect(prestate_tracer.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+
+    let (pre, post) = result.get().unwrap();
+    let pre_account = &pre[&address];
+    let post_account = &post[&address];
+    assert_eq!(
+        pre_account.storage.as_ref().unwrap()[&H256::zero()],
+        H256::zero()
+    );
+    assert_eq!(
+        post_account.storage.as_ref().unwrap()[&H256::zero()],
+        H256::from_low_u64_be(6)
+    );
+    // Neither the balance nor the nonce of the contract is changed by the call.
+    assert_eq!(pre_account.balance, None);
+    assert_eq!(post_account.balance, None);
+    assert_eq!(pre_account.nonce, None);
+    assert_eq!(post_account.nonce, None);
+}
+
+#[test]
+fn test_prestate_tracer_contract_deployment() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    let counter = read_test_contract();
+    let account = &mut vm.rich_accounts[0];
+    let DeployContractsTx {
+        tx,
+        address,
+        bytecode_hash,
+    } = account.get_deploy_tx(&counter, None, TxType::L2);
+
+    let result = Arc::new(OnceCell::new());
+    let prestate_tracer = PrestateTracer::new(true, result.clone());
+    vm.vm.push_transaction(tx);
+    let res = vm.vm.inspect(
+        prestate_tracer.clone().into_tracer_pointer().into(),
+        VmExecutionMode::OneTx,
+    );
+    assert!(!res.result.is_failed());
+    assert_eq!(
+        prestate_tracer.get_result().unwrap().created_addresses,
+        [address]
+    );
+
+    let (pre, post) = result.get().unwrap();
+    // The contract had no code before the deployment, so nothing is reported for it in diff mode.
+    assert!(pre
+        .get(&address)
+        .map_or(true, |account| account.code.is_none()));
+    assert_eq!(post[&address].code_hash, Some(bytecode_hash));
+    assert_eq!(post[&address].code_size, Some(counter.len()));
+    assert_eq!(post[&address].code, Some(counter));
+}
+
+#[test]
+fn test_prestate_tracer_reports_read_only_slots() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    // `get()` only reads the counter value.
+    let get_calldata = "6d4ce63c";
+    let account = &mut vm.rich_accounts[0];
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(get_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = Arc::new(OnceCell::new());
+    let prestate_tracer = PrestateTracer::new(false, result.clone()).into_tracer_pointer();
+    vm.vm.push_transaction(tx);
+    let res = vm
+        .vm
+        .inspect(prestate_tracer.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+
+    let (pre, _) = result.get().unwrap();
+    let storage = pre[&address].storage.as_ref().unwrap();
+    assert_eq!(storage[&H256::zero()], H256::zero());
+    assert!(pre[&address].code_hash.is_some());
+}
+
+#[test]
+fn test_block_prestate_of_created_contract() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    let counter = read_test_contract();
+    let account = &mut vm.rich_accounts[0];
+    let DeployContractsTx {
+        tx: deploy_tx,
+        address,
+        ..
+    } = account.get_deploy_tx(&counter, None, TxType::L2);
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let call_tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let config = PrestateTracerConfig::default();
+    let result =
+        trace_block_prestate::<_, HistoryEnabled, _>(&mut vm.vm, [deploy_tx, call_tx], &config);
+
+    // The second transaction sees the deployed contract, but it didn't exist at the block start.
+    let contract = &result.pre[&address];
+    assert_eq!(contract.code_hash, None);
+    assert_eq!(contract.code, None);
+    assert_eq!(
+        contract.storage.as_ref().unwrap()[&H256::zero()],
+        H256::zero()
+    );
+}
+
+#[test]
+fn test_block_prestate_shares_factory_deps() {
+    let contract = read_test_contract();
+    let code_hash = hash_bytecode(&contract);
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract.clone(), address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let account = &mut vm.rich_accounts[0];
+    let txs: Vec<_> = (0..2)
+        .map(|_| {
+            account.get_l2_tx_for_execute(
+                Execute {
+                    contract_address: address,
+                    calldata: hex::decode(increment_by_6_calldata).unwrap(),
+                    value: Default::default(),
+                    factory_deps: None,
+                },
+                None,
+            )
+        })
+        .collect();
+
+    let config = PrestateTracerConfig::default();
+    let block_results =
+        trace_block_prestate_per_transaction::<_, HistoryEnabled, _>(&mut vm.vm, txs, &config);
+
+    assert_eq!(block_results.results.len(), 2);
+    assert_eq!(block_results.factory_deps[&code_hash], contract);
+    let copies = block_results
+        .factory_deps
+        .values()
+        .filter(|bytecode| **bytecode == contract)
+        .count();
+    assert_eq!(copies, 1);
+    for (result, hashes) in block_results
+        .results
+        .iter()
+        .zip(&block_results.factory_dep_hashes)
+    {
+        assert!(hashes.contains(&code_hash));
+        assert_eq!(result.factory_deps, None);
+        assert_eq!(result.pre[&address].code_hash, Some(code_hash));
+        assert_eq!(result.pre[&address].code, None);
+    }
+}
+
+#[test]
+fn test_block_prestate_results_identify_transactions() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    let account = &mut vm.rich_accounts[0];
+    let txs: Vec<_> = (0..2)
+        .map(|_| {
+            account.get_l2_tx_for_execute(
+                Execute {
+                    contract_address: Address::random(),
+                    calldata: vec![],
+                    value: U256::from(1_000),
+                    factory_deps: None,
+                },
+                None,
+            )
+        })
+        .collect();
+    let tx_hashes: Vec<_> = txs.iter().map(Transaction::hash).collect();
+
+    let config = PrestateTracerConfig::default();
+    let block_results =
+        trace_block_prestate_per_transaction::<_, HistoryEnabled, _>(&mut vm.vm, txs, &config);
+
+    assert_eq!(block_results.results.len(), 2);
+    for (i, result) in block_results.results.iter().enumerate() {
+        assert_eq!(result.tx_hash, Some(tx_hashes[i]));
+        assert_eq!(result.tx_index, Some(i));
+    }
+    let merged = merge_prestate_results(block_results.results);
+    assert_eq!((merged.tx_hash, merged.tx_index), (None, None));
+}
+
+#[test]
+fn test_prestate_tracer_reports_revert_reason() {
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(read_error_contract(), address, false)])
+        .build();
+
+    let account = &mut vm.rich_accounts[0];
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: get_execute_error_calldata(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = Arc::new(OnceCell::new());
+    let prestate_tracer = PrestateTracer::new(true, result.clone());
+    vm.vm.push_transaction(tx);
+    let res = vm.vm.inspect(
+        prestate_tracer.clone().into_tracer_pointer().into(),
+        VmExecutionMode::OneTx,
+    );
+    assert!(res.result.is_failed());
+
+    let result = prestate_tracer
+        .get_result()
+        .unwrap()
+        .with_execution_result(&res.result);
+    assert!(result.failed);
+    assert_eq!(result.revert_reason.as_deref(), Some("short"));
+    // The revert payload is the ABI-encoded `Error("short")` returned by the contract.
+    let return_data = result.return_data.unwrap();
+    assert_eq!(return_data[..4], [0x08, 0xc3, 0x79, 0xa0]);
+    assert!(matches!(
+        VmRevertReason::from(return_data.as_slice()),
+        VmRevertReason::General { msg, .. } if msg == "short"
+    ));
+    // The contract storage isn't changed by the reverted transaction.
+    assert!(!result.post.unwrap().contains_key(&address));
+}
+
+#[test]
+fn test_prestate_tracer_rolls_back_reverted_frames() {
+    let counter = load_contract(
+        "etc/contracts-test-data/artifacts-zk/contracts/counter/counter.sol/Counter.json",
+    );
+    // `incrementWithRevert(6, true)` writes the counter slot and reverts.
+    let calldata = counter
+        .function("incrementWithRevert")
+        .unwrap()
+        .encode_input(&[Token::Uint(6.into()), Token::Bool(true)])
+        .unwrap();
+    let trace = |rollback_reverted_frames: bool| {
+        let address = Address::random();
+        let mut vm = VmTesterBuilder::new(HistoryEnabled)
+            .with_empty_in_memory_storage()
+            .with_random_rich_accounts(1)
+            .with_deployer()
+            .with_gas_limit(BLOCK_GAS_LIMIT)
+            .with_execution_mode(TxExecutionMode::VerifyExecute)
+            .with_custom_contracts(vec![(read_test_contract(), address, true)])
+            .build();
+        let tx = vm.rich_accounts[0].get_l2_tx_for_execute(
+            Execute {
+                contract_address: address,
+                calldata: calldata.clone(),
+                value: Default::default(),
+                factory_deps: None,
+            },
+            None,
+        );
+
+        let config = PrestateTracerConfig::builder()
+            .rollback_reverted_frames(rollback_reverted_frames)
+            .build();
+        let prestate_tracer = PrestateTracer::from_config(config);
+        vm.vm.push_transaction(tx);
+        let res = vm.vm.inspect(
+            prestate_tracer.clone().into_tracer_pointer().into(),
+            VmExecutionMode::OneTx,
+        );
+        assert!(res.result.is_failed());
+        let mut pre = prestate_tracer.get_result().unwrap().pre;
+        pre.remove(&address).and_then(|account| account.storage)
+    };
+
+    // Like Geth, the tracer reports the slot written by the reverted call by default.
+    assert!(trace(false).unwrap().contains_key(&H256::zero()));
+    assert!(trace(true).map_or(true, |storage| storage.is_empty()));
+}
+
+#[test]
+fn test_prestate_tracer_distinguishes_eoas_from_contracts() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract.clone(), address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let account = &mut vm.rich_accounts[0];
+    let eoa = account.address;
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = Arc::new(OnceCell::new());
+    let prestate_tracer = PrestateTracer::new(false, result.clone()).into_tracer_pointer();
+    vm.vm.push_transaction(tx);
+    let res = vm
+        .vm
+        .inspect(prestate_tracer.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+
+    let (pre, _) = result.get().unwrap();
+    // The sender is a plain EOA.
+    assert_eq!(pre[&eoa].code, None);
+    assert_eq!(pre[&eoa].storage, None);
+    assert!(pre[&eoa].balance.is_some());
+    assert_eq!(pre[&address].code, Some(contract));
+    assert!(pre[&address].storage.is_some());
+}
+
+#[test]
+fn test_prestate_tracer_with_state_override() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    let account = &mut vm.rich_accounts[0];
+    let sender = account.address;
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: Address::random(),
+            calldata: vec![],
+            value: 1_000.into(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let balance = U256::exp10(30) + 12_345;
+    let overrides = StateOverride::from([(
+        sender,
+        AccountOverride {
+            balance: Some(balance),
+            ..AccountOverride::default()
+        },
+    )]);
+    apply_state_override(
+        &mut *vm.storage.borrow_mut(),
+        &overrides,
+        L2_ETH_TOKEN_ADDRESS,
+    )
+    .unwrap();
+
+    let result = Arc::new(OnceCell::new());
+    let prestate_tracer = PrestateTracer::new(false, result.clone()).into_tracer_pointer();
+    vm.vm.push_transaction(tx);
+    let res = vm
+        .vm
+        .inspect(prestate_tracer.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+
+    let (pre, _) = result.get().unwrap();
+    assert_eq!(pre[&sender].balance, Some(balance));
+}
+
+#[test]
+fn test_prestate_result_is_returned_by_runner() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let account = &mut vm.rich_accounts[0];
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    vm.vm.push_transaction(tx);
+    let config = PrestateTracerConfig::builder().diff_mode(true).build();
+    let (execution, result) =
+        trace_transaction_prestate::<_, HistoryEnabled, _>(&mut vm.vm, &config).unwrap();
+    assert!(!execution.result.is_failed());
+    assert!(!result.failed);
+    // The transaction is traced by the adapter of the latest VM.
+    assert_eq!(result.vm_version, Some(TracerVmVersion::Latest));
+
+    let post = result.post.unwrap();
+    assert_eq!(
+        post[&address].storage.as_ref().unwrap()[&H256::zero()],
+        H256::from_low_u64_be(6)
+    );
+}
+
+#[test]
+fn test_prestate_tracer_excludes_system_contracts() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    let recipient = Address::random();
+    let account = &mut vm.rich_accounts[0];
+    let sender = account.address;
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: recipient,
+            calldata: vec![],
+            value: U256::from(1_000),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    vm.vm.push_transaction(tx);
+    let config = PrestateTracerConfig::builder()
+        .exclude_system_contracts(true)
+        .build();
+    let (execution, result) =
+        trace_transaction_prestate::<_, HistoryEnabled, _>(&mut vm.vm, &config).unwrap();
+    assert!(!execution.result.is_failed());
+
+    assert!(result.pre.contains_key(&sender));
+    assert!(result.pre.contains_key(&recipient));
+    let system_contracts_boundary = Address::from_low_u64_be(0xffff);
+    assert!(
+        result
+            .pre
+            .keys()
+            .all(|address| *address > system_contracts_boundary),
+        "{:?}",
+        result.pre.keys().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_prestate_tracer_rpc_response() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let account = &mut vm.rich_accounts[0];
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    vm.vm.push_transaction(tx);
+    let tracer_config = serde_json::json!({ "diffMode": true });
+    let response =
+        debug_trace_transaction_prestate::<_, HistoryEnabled, _>(&mut vm.vm, &tracer_config)
+            .unwrap();
+
+    let address = format!("{address:?}");
+    let slot = format!("{:?}", H256::zero());
+    assert_eq!(
+        response["post"][&address]["storage"][&slot],
+        serde_json::json!(format!("{:?}", H256::from_low_u64_be(6)))
+    );
+    assert!(response["pre"].is_object());
+}
+
+#[test]
+fn test_prestate_tracer_reports_sender_of_transfer() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    let recipient = Address::random();
+    let account = &mut vm.rich_accounts[0];
+    let sender = account.address;
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: recipient,
+            calldata: vec![],
+            value: U256::from(1_000),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    vm.vm.push_transaction(tx);
+    let config = PrestateTracerConfig::builder().diff_mode(true).build();
+    let (execution, result) =
+        trace_transaction_prestate::<_, HistoryEnabled, _>(&mut vm.vm, &config).unwrap();
+    assert!(!execution.result.is_failed());
+
+    let post = result.post.unwrap();
+    let pre_nonce = result.pre[&sender].nonce.unwrap();
+    assert_eq!(post[&sender].nonce, Some(pre_nonce + 1));
+    let (pre_balance, post_balance) = (result.pre[&sender].balance, post[&sender].balance);
+    assert!(post_balance.unwrap() + 1_000 <= pre_balance.unwrap());
+    assert_eq!(post[&recipient].balance, Some(U256::from(1_000)));
+}
+
+#[test]
+fn test_prestate_tracer_splits_fee_from_transferred_value() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    let recipient = Address::random();
+    let account = &mut vm.rich_accounts[0];
+    let sender = account.address;
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: recipient,
+            calldata: vec![],
+            value: U256::from(1_000),
+            factory_deps: None,
+        },
+        None,
+    );
+    let gas_limit = tx.gas_limit();
+
+    vm.vm.push_transaction(tx);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .fee_payer(sender)
+        .build();
+    let (execution, result) =
+        trace_transaction_prestate::<_, HistoryEnabled, _>(&mut vm.vm, &config).unwrap();
+    assert!(!execution.result.is_failed());
+
+    let base_fee = U256::from(get_batch_base_fee(&vm.vm.batch_env));
+    let expected_fee = (gas_limit - U256::from(execution.refunds.gas_refunded)) * base_fee;
+    let post = result.post.unwrap();
+    let change = post[&sender].balance_change.unwrap();
+    assert!(!change.fee_delta.amount.is_zero());
+    assert_eq!(change.fee_delta, BalanceDelta::decrease(expected_fee));
+    assert_eq!(change.value_delta, BalanceDelta::decrease(1_000.into()));
+    // Only the fee payer is annotated.
+    assert_eq!(post[&recipient].balance_change, None);
+}
+
+#[test]
+fn test_prestate_tracer_fee_recipient_credit_matches_sender_debit() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    let account = &mut vm.rich_accounts[0];
+    let sender = account.address;
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: Address::random(),
+            calldata: vec![],
+            value: U256::from(1_000),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    vm.vm.push_transaction(tx);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .fee_payer(sender)
+        .capture_fees(true)
+        .build();
+    let (execution, result) =
+        trace_transaction_prestate::<_, HistoryEnabled, _>(&mut vm.vm, &config).unwrap();
+    assert!(!execution.result.is_failed());
+
+    let base_fee = U256::from(get_batch_base_fee(&vm.vm.batch_env));
+    assert_eq!(result.effective_gas_price, Some(base_fee));
+    let fee_debit = result.post.unwrap()[&sender]
+        .balance_change
+        .unwrap()
+        .fee_delta;
+    let fee_credit = result.fee_recipient_delta.unwrap();
+    assert!(!fee_credit.amount.is_zero());
+    assert_eq!(fee_credit, BalanceDelta::increase(fee_debit.amount));
+}
+
+#[test]
+fn test_prestate_tracer_captures_pubdata() {
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .build();
+
+    // The transfer to a new account writes its balance slot for the first time,
+    // so the slot is published with its full key.
+    let recipient = Address::random();
+    let tx = vm.rich_accounts[0].get_l2_tx_for_execute(
+        Execute {
+            contract_address: recipient,
+            calldata: vec![],
+            value: U256::from(1_000),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    vm.vm.push_transaction(tx);
+    let config = PrestateTracerConfig::builder()
+        .diff_mode(true)
+        .capture_pubdata(true)
+        .build();
+    let (execution, result) =
+        trace_transaction_prestate::<_, HistoryEnabled, _>(&mut vm.vm, &config).unwrap();
+    assert!(!execution.result.is_failed());
+
+    let pubdata_published = result.pubdata_published.unwrap();
+    assert!(pubdata_published > 0);
+    assert_eq!(pubdata_published, execution.statistics.pubdata_published);
+    assert!(result.gas_per_pubdata.unwrap() > 0);
+    assert!(result.post.unwrap().contains_key(&recipient));
+}
+
+/// Historical storage with the snapshots kept in memory.
+struct MockHistoricalStorage(HashMap<StateHeight, InMemoryStorage>);
+
+impl HistoricalStorage for MockHistoricalStorage {
+    type Snapshot = InMemoryStorage;
+
+    fn snapshot_at(&self, height: StateHeight) -> anyhow::Result<InMemoryStorage> {
+        self.0
+            .get(&height)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no snapshot at {height:?}"))
+    }
+}
+
+#[test]
+fn test_prestate_tracer_reads_historical_state() {
+    let mut account = Account::random();
+    let sender = account.address;
+    let balance_key = storage_key_for_eth_balance(&sender);
+    let snapshot_with_balance = |balance: u64| {
+        let mut storage = get_empty_storage();
+        storage.set_value(balance_key, u256_to_h256(U256::from(balance)));
+        storage
+    };
+    // The sender has spent most of its balance after the traced batch.
+    let historical_balance = 10_u64.pow(19);
+    let source = MockHistoricalStorage(HashMap::from([
+        (
+            StateHeight::L1Batch(L1BatchNumber(0)),
+            snapshot_with_balance(historical_balance),
+        ),
+        (
+            StateHeight::L1Batch(L1BatchNumber(1)),
+            snapshot_with_balance(10_u64.pow(15)),
+        ),
+    ]));
+    let system_env = SystemEnv {
+        zk_porter_available: false,
+        version: ProtocolVersionId::latest(),
+        base_system_smart_contracts: BaseSystemContracts::playground(),
+        gas_limit: BLOCK_GAS_LIMIT,
+        execution_mode: TxExecutionMode::VerifyExecute,
+        default_validation_computational_gas_limit: BLOCK_GAS_LIMIT,
+        chain_id: L2ChainId::from(270),
+    };
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: Address::random(),
+            calldata: vec![],
+            value: U256::from(1_000),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = trace_historical_transaction_prestate::<
+        _,
+        HistoryEnabled,
+        Vm<StorageView<InMemoryStorage>, HistoryEnabled>,
+    >(
+        &source,
+        StateHeight::L1Batch(L1BatchNumber(1)),
+        vec![],
+        tx,
+        default_l1_batch(L1BatchNumber(1)),
+        system_env,
+        &PrestateTracerConfig::default(),
+    )
+    .unwrap();
+    assert!(!result.failed);
+    assert_eq!(result.pre[&sender].balance, Some(historical_balance.into()));
+    assert_eq!(result.pre[&sender].nonce, Some(U256::zero()));
+}
+
+#[test]
+fn test_historical_l2_block_has_no_following_l2_blocks() {
+    let source = MockHistoricalStorage(HashMap::from([(
+        StateHeight::L2Block(MiniblockNumber(1)),
+        get_empty_storage(),
+    )]));
+    let system_env = SystemEnv {
+        zk_porter_available: false,
+        version: ProtocolVersionId::latest(),
+        base_system_smart_contracts: BaseSystemContracts::playground(),
+        gas_limit: BLOCK_GAS_LIMIT,
+        execution_mode: TxExecutionMode::VerifyExecute,
+        default_validation_computational_gas_limit: BLOCK_GAS_LIMIT,
+        chain_id: L2ChainId::from(270),
+    };
+    let batch_env = default_l1_batch(L1BatchNumber(1));
+    let l2_block = |number: u32| MiniblockExecutionData {
+        number: MiniblockNumber(number),
+        timestamp: batch_env.first_l2_block.timestamp + u64::from(number),
+        prev_block_hash: H256::zero(),
+        virtual_blocks: 1,
+        txs: vec![],
+    };
+    let preceding_l2_blocks = vec![l2_block(2), l2_block(3)];
+    let tx = Account::random().get_l2_tx_for_execute(
+        Execute {
+            contract_address: Address::random(),
+            calldata: vec![],
+            value: U256::zero(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let err = trace_historical_transaction_prestate::<
+        _,
+        HistoryEnabled,
+        Vm<StorageView<InMemoryStorage>, HistoryEnabled>,
+    >(
+        &source,
+        StateHeight::L2Block(MiniblockNumber(2)),
+        preceding_l2_blocks,
+        tx,
+        batch_env,
+        system_env,
+        &PrestateTracerConfig::default(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        PrestateTracerError::UnexpectedL2Block(MiniblockNumber(3))
+    );
+}
+
+#[test]
+fn test_prestate_tracer_resolves_base_token_per_chain() {
+    let system_env = |chain_id: u64| SystemEnv {
+        zk_porter_available: false,
+        version: ProtocolVersionId::latest(),
+        base_system_smart_contracts: BaseSystemContracts::playground(),
+        gas_limit: BLOCK_GAS_LIMIT,
+        execution_mode: TxExecutionMode::VerifyExecute,
+        default_validation_computational_gas_limit: BLOCK_GAS_LIMIT,
+        chain_id: L2ChainId::from(chain_id),
+    };
+    let custom_token = Address::repeat_byte(0xbb);
+    let config = PrestateTracerConfig::builder()
+        .chain_base_token(L2ChainId::from(271), custom_token)
+        .build();
+
+    let account = AccountTreeId::new(Address::random());
+    let eth_chain_config = config.for_system_env(&system_env(270));
+    assert_eq!(
+        eth_chain_config.balance_key(&account),
+        get_balance_key_for_token(&account, L2_ETH_TOKEN_ADDRESS)
+    );
+    let custom_chain_config = config.for_system_env(&system_env(271));
+    assert_eq!(
+        custom_chain_config.balance_key(&account),
+        get_balance_key_for_token(&account, custom_token)
+    );
+}
+
+#[test]
+fn test_trace_prestate_runs_transaction_end_to_end() {
+    let contract = read_test_contract();
+    let contract_address = Address::random();
+    let recipient = Address::random();
+    let system_env = SystemEnv {
+        zk_porter_available: false,
+        version: ProtocolVersionId::latest(),
+        base_system_smart_contracts: BaseSystemContracts::playground(),
+        gas_limit: BLOCK_GAS_LIMIT,
+        execution_mode: TxExecutionMode::VerifyExecute,
+        default_validation_computational_gas_limit: BLOCK_GAS_LIMIT,
+        chain_id: L2ChainId::from(270),
+    };
+    let trace = |execute: Execute| {
+        let mut account = Account::random();
+        let mut storage = get_empty_storage();
+        storage.set_value(
+            storage_key_for_eth_balance(&account.address),
+            u256_to_h256(U256::from(10_u64.pow(19))),
+        );
+        storage.set_value(get_code_key(&contract_address), hash_bytecode(&contract));
+        storage.store_factory_dep(hash_bytecode(&contract), contract.clone());
+        let tx = account.get_l2_tx_for_execute(execute, None);
+        let tx_hash = tx.hash();
+        let config = PrestateTracerConfig::builder().diff_mode(true).build();
+        let result = trace_prestate(
+            tx,
+            StorageView::new(storage).to_rc_ptr(),
+            default_l1_batch(L1BatchNumber(1)),
+            system_env.clone(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!((result.tx_hash, result.tx_index), (Some(tx_hash), Some(0)));
+        (account.address, result)
+    };
+
+    let (sender, transfer) = trace(Execute {
+        contract_address: recipient,
+        calldata: vec![],
+        value: U256::from(1_000),
+        factory_deps: None,
+    });
+    assert!(!transfer.failed);
+    assert_eq!(transfer.vm_version, Some(TracerVmVersion::Latest));
+    let post = transfer.post.as_ref().unwrap();
+    assert_eq!(transfer.pre[&sender].nonce, Some(U256::zero()));
+    assert_eq!(post[&sender].nonce, Some(U256::one()));
+    assert_eq!(post[&recipient].balance, Some(U256::from(1_000)));
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let (_, call) = trace(Execute {
+        contract_address,
+        calldata: hex::decode(increment_by_6_calldata).unwrap(),
+        value: U256::zero(),
+        factory_deps: None,
+    });
+    assert!(!call.failed);
+    let post = call.post.as_ref().unwrap();
+    assert_eq!(
+        call.pre[&contract_address].storage.as_ref().unwrap()[&H256::zero()],
+        H256::zero()
+    );
+    assert_eq!(
+        post[&contract_address].storage.as_ref().unwrap()[&H256::zero()],
+        H256::from_low_u64_be(6)
+    );
+}
+
+#[test]
+fn test_trace_prestate_with_version_runs_selected_vm() {
+    let base_system_smart_contracts = |version| match version {
+        TracerVmVersion::Latest => BaseSystemContracts::playground(),
+        TracerVmVersion::Vm1_4_1 => BaseSystemContracts::playground_post_1_4_1(),
+        TracerVmVersion::BoojumIntegration => {
+            BaseSystemContracts::playground_post_allowlist_removal()
+        }
+        TracerVmVersion::RefundsEnhancement | TracerVmVersion::VirtualBlocks => {
+            BaseSystemContracts::playground_post_virtual_blocks_finish_upgrade_fix()
+        }
+    };
+
+    for vm_version in TracerVmVersion::ALL {
+        let mut account = Account::random();
+        let mut storage = get_empty_storage();
+        storage.set_value(
+            storage_key_for_eth_balance(&account.address),
+            u256_to_h256(U256::from(10_u64.pow(19))),
+        );
+        let system_env = SystemEnv {
+            zk_porter_available: false,
+            // The protocol version doesn't select the VM, so it's left the latest one.
+            version: ProtocolVersionId::latest(),
+            base_system_smart_contracts: base_system_smart_contracts(vm_version),
+            gas_limit: BLOCK_GAS_LIMIT,
+            execution_mode: TxExecutionMode::VerifyExecute,
+            default_validation_computational_gas_limit: BLOCK_GAS_LIMIT,
+            chain_id: L2ChainId::from(270),
+        };
+        let tx = account.get_l2_tx_for_execute(
+            Execute {
+                contract_address: Address::random(),
+                calldata: vec![],
+                value: U256::from(1_000),
+                factory_deps: None,
+            },
+            None,
+        );
+        let result = trace_prestate_with_version(
+            tx,
+            StorageView::new(storage).to_rc_ptr(),
+            default_l1_batch(L1BatchNumber(1)),
+            system_env,
+            vm_version,
+            &PrestateTracerConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(result.vm_version, Some(vm_version));
+    }
+}
+
+#[test]
+fn test_trace_call_prestate_does_not_touch_sender_nonce() {
+    let contract = read_test_contract();
+    let contract_address = Address::random();
+    // The sender has neither a balance nor a nonce.
+    let sender = Address::random();
+    let mut storage = get_empty_storage();
+    storage.set_value(get_code_key(&contract_address), hash_bytecode(&contract));
+    storage.store_factory_dep(hash_bytecode(&contract), contract);
+    let value_key = StorageKey::new(AccountTreeId::new(contract_address), H256::zero());
+    storage.set_value(value_key, H256::from_low_u64_be(5));
+    let system_env = SystemEnv {
+        zk_porter_available: false,
+        version: ProtocolVersionId::latest(),
+        base_system_smart_contracts: BaseSystemContracts::playground(),
+        gas_limit: BLOCK_GAS_LIMIT,
+        execution_mode: TxExecutionMode::VerifyExecute,
+        default_validation_computational_gas_limit: BLOCK_GAS_LIMIT,
+        chain_id: L2ChainId::from(270),
+    };
+
+    let get_calldata = "6d4ce63c";
+    let call = CallMessage {
+        from: sender,
+        to: contract_address,
+        data: hex::decode(get_calldata).unwrap(),
+        ..CallMessage::default()
+    };
+    let config = PrestateTracerConfig::builder().diff_mode(true).build();
+    let result = trace_call_prestate(
+        call,
+        StorageView::new(storage).to_rc_ptr(),
+        default_l1_batch(L1BatchNumber(1)),
+        system_env,
+        &config,
+    )
+    .unwrap();
+    assert!(!result.failed);
+    assert_eq!((result.tx_hash, result.tx_index), (None, None));
+    assert_eq!(
+        result.pre[&contract_address].storage.as_ref().unwrap()[&H256::zero()],
+        H256::from_low_u64_be(5)
+    );
+    let post = result.post.as_ref().unwrap();
+    assert!(!post.contains_key(&contract_address));
+    assert_eq!(post.get(&sender).and_then(|account| account.nonce), None);
+}
+
+#[test]
+fn test_prestate_tracer_diff_is_consistent_with_default_mode() {
+    let contract = read_test_contract();
+    let contract_address = Address::random();
+    let mut account = Account::random();
+    let mut storage = get_empty_storage();
+    storage.set_value(
+        storage_key_for_eth_balance(&account.address),
+        u256_to_h256(U256::from(10_u64.pow(19))),
+    );
+    storage.set_value(get_code_key(&contract_address), hash_bytecode(&contract));
+    storage.store_factory_dep(hash_bytecode(&contract), contract);
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: U256::zero(),
+            factory_deps: None,
+        },
+        None,
+    );
+    let system_env = SystemEnv {
+        zk_porter_available: false,
+        version: ProtocolVersionId::latest(),
+        base_system_smart_contracts: BaseSystemContracts::playground(),
+        gas_limit: BLOCK_GAS_LIMIT,
+        execution_mode: TxExecutionMode::VerifyExecute,
+        default_validation_computational_gas_limit: BLOCK_GAS_LIMIT,
+        chain_id: L2ChainId::from(270),
+    };
+    let batch_env = default_l1_batch(L1BatchNumber(1));
+    let trace = |diff_mode: bool| {
+        let config = PrestateTracerConfig::builder().diff_mode(diff_mode).build();
+        trace_prestate(
+            tx.clone(),
+            StorageView::new(storage.clone()).to_rc_ptr(),
+            batch_env.clone(),
+            system_env.clone(),
+            &config,
+        )
+        .unwrap()
+    };
+
+    let (default, diff) = (trace(false), trace(true));
+    assert!(!diff.failed && !default.failed);
+    assert!(diff.pre.contains_key(&contract_address));
+    assert_diff_subset_of_default(&diff, &default);
+}
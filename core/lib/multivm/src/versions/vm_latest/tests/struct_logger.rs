@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use zksync_types::{Address, Execute, H256};
+
+use crate::{
+    interface::{TxExecutionMode, VmExecutionMode, VmInterface},
+    tracers::{
+        struct_logger::{StructLogResult, StructLoggerConfig},
+        StructLogger,
+    },
+    vm_latest::{
+        constants::BLOCK_GAS_LIMIT,
+        tests::{tester::VmTesterBuilder, utils::read_test_contract},
+        HistoryEnabled, ToTracerPointer,
+    },
+};
+
+#[test]
+fn test_struct_logger() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let increment_by_6_calldata =
+        "7cf5dab00000000000000000000000000000000000000000000000000000000000000006";
+    let account = &mut vm.rich_accounts[0];
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode(increment_by_6_calldata).unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = Arc::new(OnceCell::new());
+    let tracer = StructLogger::new(result.clone());
+    vm.vm.push_transaction(tx);
+    let res = vm.vm.inspect(
+        tracer.clone().into_tracer_pointer().into(),
+        VmExecutionMode::OneTx,
+    );
+    assert!(!res.result.is_failed());
+
+    let result = StructLogResult::new(tracer.get_result().unwrap(), &res);
+    assert!(!result.failed);
+    assert!(result.gas > 0);
+    let logs = &result.struct_logs;
+    // The memory is only captured on request.
+    assert!(logs.iter().all(|log| log.memory.is_none()));
+    assert!(logs.iter().all(|log| log.depth >= 1));
+
+    // The counter is read and then written, with the written value reported in the storage.
+    let ops: Vec<_> = logs.iter().map(|log| log.op.as_str()).collect();
+    let read_idx = ops.iter().position(|op| *op == "Log(StorageRead)").unwrap();
+    let write_idx = ops
+        .iter()
+        .position(|op| *op == "Log(StorageWrite)")
+        .unwrap();
+    assert!(read_idx < write_idx);
+    // Both accesses are made by the contract, regardless of the near calls in between.
+    assert_eq!(logs[read_idx].depth, logs[write_idx].depth);
+    let write = &logs[write_idx];
+    assert_eq!(
+        write.storage.as_ref().unwrap()[&H256::zero()],
+        H256::from_low_u64_be(6)
+    );
+
+    let last_step = logs.last().unwrap();
+    assert!(last_step.op.starts_with("Ret"), "{:?}", last_step);
+    assert_eq!(last_step.stack.as_ref().unwrap().len(), 15);
+}
+
+#[test]
+fn test_struct_logger_limit_and_disabled_stack() {
+    let contract = read_test_contract();
+    let address = Address::random();
+    let mut vm = VmTesterBuilder::new(HistoryEnabled)
+        .with_empty_in_memory_storage()
+        .with_random_rich_accounts(1)
+        .with_deployer()
+        .with_gas_limit(BLOCK_GAS_LIMIT)
+        .with_execution_mode(TxExecutionMode::VerifyExecute)
+        .with_custom_contracts(vec![(contract, address, true)])
+        .build();
+
+    let account = &mut vm.rich_accounts[0];
+    let tx = account.get_l2_tx_for_execute(
+        Execute {
+            contract_address: address,
+            calldata: hex::decode("6d4ce63c").unwrap(),
+            value: Default::default(),
+            factory_deps: None,
+        },
+        None,
+    );
+
+    let result = Arc::new(OnceCell::new());
+    let config = StructLoggerConfig::builder()
+        .disable_stack(true)
+        .limit(5)
+        .build();
+    let tracer = StructLogger::with_config(config, result.clone()).into_tracer_pointer();
+    vm.vm.push_transaction(tx);
+    let res = vm.vm.inspect(tracer.into(), VmExecutionMode::OneTx);
+    assert!(!res.result.is_failed());
+
+    let logs = result.get().unwrap();
+    assert_eq!(logs.len(), 5);
+    assert!(logs.iter().all(|log| log.stack.is_none()));
+}
@@ -110,6 +110,12 @@ pub(crate) fn read_precompiles_contract() -> Vec<u8> {
     )
 }
 
+pub(crate) fn read_expensive_contract() -> (Vec<u8>, Contract) {
+    let path =
+        "etc/contracts-test-data/artifacts-zk/contracts/expensive/expensive.sol/Expensive.json";
+    (read_bytecode(path), load_contract(path))
+}
+
 pub(crate) fn read_complex_upgrade() -> Vec<u8> {
     read_bytecode("etc/contracts-test-data/artifacts-zk/contracts/complex-upgrade/complex-upgrade.sol/ComplexUpgrade.json")
 }
@@ -17,7 +17,9 @@ pub enum VmInstance<S: WriteStorage, H: HistoryMode> {
     VmM5(crate::vm_m5::Vm<S, H>),
     VmM6(crate::vm_m6::Vm<S, H>),
     Vm1_3_2(crate::vm_1_3_2::Vm<S, H>),
+    #[cfg(feature = "vm-virtual-blocks")]
     VmVirtualBlocks(crate::vm_virtual_blocks::Vm<S, H>),
+    #[cfg(feature = "vm-refunds-enhancement")]
     VmVirtualBlocksRefundsEnhancement(crate::vm_refunds_enhancement::Vm<S, H>),
     VmBoojumIntegration(crate::vm_boojum_integration::Vm<S, H>),
     Vm1_4_1(crate::vm_1_4_1::Vm<S, H>),
@@ -30,7 +32,9 @@ macro_rules! dispatch_vm {
             VmInstance::VmM5(vm) => vm.$function($($params)*),
             VmInstance::VmM6(vm) => vm.$function($($params)*),
             VmInstance::Vm1_3_2(vm) => vm.$function($($params)*),
+            #[cfg(feature = "vm-virtual-blocks")]
             VmInstance::VmVirtualBlocks(vm) => vm.$function($($params)*),
+            #[cfg(feature = "vm-refunds-enhancement")]
             VmInstance::VmVirtualBlocksRefundsEnhancement(vm) => vm.$function($($params)*),
             VmInstance::VmBoojumIntegration(vm) => vm.$function($($params)*),
             VmInstance::Vm1_4_1(vm) => vm.$function($($params)*),
@@ -192,15 +196,23 @@ impl<S: WriteStorage, H: HistoryMode> VmInstance<S, H> {
                 let vm = crate::vm_1_3_2::Vm::new(l1_batch_env, system_env, storage_view);
                 VmInstance::Vm1_3_2(vm)
             }
+            #[cfg(feature = "vm-virtual-blocks")]
             VmVersion::VmVirtualBlocks => {
                 let vm = crate::vm_virtual_blocks::Vm::new(l1_batch_env, system_env, storage_view);
                 VmInstance::VmVirtualBlocks(vm)
             }
+            #[cfg(not(feature = "vm-virtual-blocks"))]
+            VmVersion::VmVirtualBlocks => crate::utils::disabled_vm_version(vm_version),
+            #[cfg(feature = "vm-refunds-enhancement")]
             VmVersion::VmVirtualBlocksRefundsEnhancement => {
                 let vm =
                     crate::vm_refunds_enhancement::Vm::new(l1_batch_env, system_env, storage_view);
                 VmInstance::VmVirtualBlocksRefundsEnhancement(vm)
             }
+            #[cfg(not(feature = "vm-refunds-enhancement"))]
+            VmVersion::VmVirtualBlocksRefundsEnhancement => {
+                crate::utils::disabled_vm_version(vm_version)
+            }
             VmVersion::VmBoojumIntegration => {
                 let vm =
                     crate::vm_boojum_integration::Vm::new(l1_batch_env, system_env, storage_view);
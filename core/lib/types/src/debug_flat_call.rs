@@ -86,7 +86,8 @@ mod test {
     use super::*;
     use crate::{
         api::{DebugCall, DebugCallType, ResultDebugCall},
-        vm_trace::Call,
+        vm_trace::{Call, CallType},
+        zk_evm_types::FarCallOpcode,
         Address, BOOTLOADER_ADDRESS,
     };
 
@@ -103,6 +104,66 @@ mod test {
         assert_eq!(debug_call_flat, expected_debug_call_flat);
     }
 
+    #[test]
+    fn test_flatten_nested_calls() {
+        let call = |r#type, from: u8, to: u8, calls| Call {
+            r#type,
+            from: Address::repeat_byte(from),
+            to: Address::repeat_byte(to),
+            gas: 1_000,
+            gas_used: 100,
+            input: vec![from],
+            output: vec![to],
+            calls,
+            ..Call::default()
+        };
+        // 1 -> 2 -> (create 3 -> 4, delegatecall 5), where the call to 4 is reverted.
+        let mut reverted = call(CallType::Call(FarCallOpcode::Normal), 3, 4, vec![]);
+        reverted.revert_reason = Some("nope".to_owned());
+        let create = call(CallType::Create, 2, 3, vec![reverted]);
+        let delegate = call(CallType::Call(FarCallOpcode::Delegate), 2, 5, vec![]);
+        let inner = call(
+            CallType::Call(FarCallOpcode::Mimic),
+            1,
+            2,
+            vec![create, delegate],
+        );
+        let root = call(CallType::Call(FarCallOpcode::Normal), 0, 1, vec![inner]);
+
+        let flat_calls = flatten_debug_calls(vec![ResultDebugCall {
+            result: root.into(),
+        }]);
+        let flat_call =
+            |r#type, from: u8, to: u8, subtraces, traceaddress: &[usize]| DebugCallFlat {
+                action: Action {
+                    r#type,
+                    from: Address::repeat_byte(from),
+                    to: Address::repeat_byte(to),
+                    gas: 1_000.into(),
+                    value: 0.into(),
+                    input: vec![from].into(),
+                },
+                result: CallResult {
+                    output: vec![to].into(),
+                    gas_used: 100.into(),
+                },
+                subtraces,
+                traceaddress: traceaddress.to_vec(),
+                error: None,
+                revert_reason: None,
+            };
+        let mut expected_reverted = flat_call(DebugCallType::Call, 3, 4, 0, &[0, 0, 0, 0]);
+        expected_reverted.revert_reason = Some("nope".to_owned());
+        let expected = vec![
+            flat_call(DebugCallType::Call, 0, 1, 1, &[0]),
+            flat_call(DebugCallType::Call, 1, 2, 2, &[0, 0]),
+            flat_call(DebugCallType::Create, 2, 3, 1, &[0, 0, 0]),
+            expected_reverted,
+            flat_call(DebugCallType::Call, 2, 5, 0, &[0, 0, 1]),
+        ];
+        assert_eq!(flat_calls, expected);
+    }
+
     fn new_testing_debug_call() -> DebugCall {
         DebugCall {
             r#type: DebugCallType::Call,